@@ -0,0 +1,133 @@
+//! Running a [VirtualMachine] inside an async service, with `tokio`'s [AsyncRead] and
+//! [AsyncWrite] as its input and output, so that waiting for input doesn't block the runtime.
+//! Only available with the `async` feature.
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{CellKind, FlushPolicy, HaltReason, IoRequest, VMError, VirtualMachine};
+
+impl<'a, T: CellKind> VirtualMachine<'a, T> {
+    /// As [VirtualMachine::interpret], but awaiting each read and write, so the task yields to the
+    /// runtime whenever the program waits for input or its output can't be taken yet. Between
+    /// `,`s and `.`s the program runs without yielding, so programs that compute for a long time
+    /// between them should be given [crate::Limits] or run on a blocking thread instead.
+    ///
+    /// ```
+    ///# use bft_interp::VirtualMachine;
+    ///# use bft_types::BfProgram;
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    ///# runtime.block_on(async {
+    ///  let program = BfProgram::new("next.bf", ",+.")?;
+    ///  let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+    ///  let mut output = Vec::new();
+    ///  vm.interpret_async(&mut &b"a"[..], &mut output).await?;
+    ///  assert_eq!(output, b"b");
+    ///# Ok(())
+    ///# })
+    ///# }
+    /// ```
+    pub async fn interpret_async<R, W>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+    ) -> Result<HaltReason, VMError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        loop {
+            match self.run_until_io()? {
+                IoRequest::Input => {
+                    if self.flush_policy == FlushPolicy::Line {
+                        output
+                            .flush()
+                            .await
+                            .map_err(|error| VMError::WriteError(self.current(), error))?;
+                    }
+                    let mut buffer = [0];
+                    match input.read(&mut buffer).await {
+                        Ok(0) => self.provide_eof(),
+                        Ok(_) => self.provide_input(buffer[0]),
+                        Err(error) => return Err(VMError::ReadError(self.current(), error)),
+                    }
+                }
+                IoRequest::Output(byte) => {
+                    let flush = match self.flush_policy {
+                        FlushPolicy::EveryByte => true,
+                        FlushPolicy::Line => byte == b'\n',
+                        FlushPolicy::Manual => false,
+                    };
+                    let written = match output.write_all(&[byte]).await {
+                        Ok(()) if flush => output.flush().await,
+                        written => written,
+                    };
+                    // the `.` has already been run, so the error belongs to the instruction before
+                    written.map_err(|error| VMError::WriteError(self.previous(), error))?;
+                }
+                IoRequest::Halted(halt_reason) => {
+                    if halt_reason == HaltReason::Completed
+                        && self.flush_policy == FlushPolicy::Line
+                    {
+                        output
+                            .flush()
+                            .await
+                            .map_err(|error| VMError::WriteError(self.previous(), error))?;
+                    }
+                    return Ok(halt_reason);
+                }
+            }
+        }
+    }
+
+    /// The instruction at the program counter
+    fn current(&self) -> bft_types::LocalisedInstruction {
+        self.program.localised_instructions()[self.program_counter]
+    }
+
+    /// The instruction before the program counter, which was the last to run unless it jumped
+    fn previous(&self) -> bft_types::LocalisedInstruction {
+        let instructions = self.program.localised_instructions();
+        instructions[self.program_counter.clamp(1, instructions.len()) - 1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EofBehavior;
+    use bft_types::BfProgram;
+
+    // Does a program read from and write to a pipe that is only fed while it waits?
+    #[test]
+    fn test_interpret_async() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let program = BfProgram::new("echo.bf", ",[.,]").unwrap();
+        let (mut ours, mut theirs) = tokio::io::duplex(1);
+
+        let output = runtime.block_on(async {
+            let mut vm: VirtualMachine<u8> =
+                VirtualMachine::new(&program, None, false).with_eof_behavior(EofBehavior::SetZero);
+            let (mut input, mut output) = tokio::io::split(&mut theirs);
+            let machine = vm.interpret_async(&mut input, &mut output);
+            let client = async {
+                ours.write_all(b"hi").await.unwrap();
+                let mut echoed = [0; 2];
+                ours.read_exact(&mut echoed).await.unwrap();
+                ours.shutdown().await.unwrap();
+                echoed
+            };
+            let (halt_reason, echoed) = tokio::join!(machine, client);
+            assert_eq!(halt_reason.unwrap(), HaltReason::Completed);
+            echoed
+        });
+        assert_eq!(&output, b"hi");
+
+        // the end of input is an error unless the machine is told otherwise
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        let result = runtime.block_on(vm.interpret_async(&mut &b""[..], &mut Vec::new()));
+        assert!(matches!(result, Err(VMError::ReadError(..))));
+    }
+}
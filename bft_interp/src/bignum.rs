@@ -0,0 +1,208 @@
+//! Cells that never overflow, for programs that count past what any fixed width can hold. Enabled
+//! with the `bignum` feature.
+//!
+//! A [BigCell] holds any integer, positive or negative, and grows as needed, so `+` and `-` never
+//! wrap. Input and output are still a byte at a time: a byte that is read in is stored as its
+//! value, from 0 to 255, and a cell that is written out gives its value modulo 256, always counted
+//! upwards from zero, so -1 outputs 255 and 321 outputs 65 (`'A'`). This matches the signed and
+//! unsigned fixed-width cells for every value they can hold.
+
+use std::fmt::Display;
+
+use crate::CellKind;
+
+/// How many bits one limb of a [BigCell] holds
+const LIMB_BITS: u32 = u32::BITS;
+
+/// An integer cell of unlimited size
+///
+/// ```
+///# use bft_interp::bignum::BigCell;
+///# use bft_interp::VirtualMachine;
+///# use bft_types::BfProgram;
+///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+///  // 8 * 8 * 8 is 512, which a u8 cell would have wrapped to 0, and 0 - 1 is -1
+///  let program = BfProgram::new("big.bf", "++++++++[>++++++++<-]>[<++++++++>-]>-")?;
+///  let mut vm: VirtualMachine<BigCell> = VirtualMachine::new(&program, None, false);
+///  vm.interpret(&mut std::io::empty(), &mut std::io::sink())?;
+///  assert_eq!(vm.tape()[0].to_string(), "512");
+///  assert_eq!(vm.tape()[2].to_string(), "-1");
+///# Ok(())
+///# }
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct BigCell {
+    /// Whether the value is below zero. Never set for zero itself.
+    negative: bool,
+    /// The size of the value, least significant limb first, with no trailing zero limbs. Empty
+    /// for zero.
+    magnitude: Vec<u32>,
+}
+
+impl BigCell {
+    /// Add one to the magnitude
+    fn grow(&mut self) {
+        for limb in self.magnitude.iter_mut() {
+            let (sum, carry) = limb.overflowing_add(1);
+            *limb = sum;
+            if !carry {
+                return;
+            }
+        }
+        self.magnitude.push(1);
+    }
+
+    /// Take one from the magnitude, which must not be zero
+    fn shrink(&mut self) {
+        for limb in self.magnitude.iter_mut() {
+            let (difference, borrow) = limb.overflowing_sub(1);
+            *limb = difference;
+            if !borrow {
+                break;
+            }
+        }
+        if self.magnitude.last() == Some(&0) {
+            self.magnitude.pop();
+        }
+        if self.magnitude.is_empty() {
+            self.negative = false;
+        }
+    }
+}
+
+impl From<i64> for BigCell {
+    fn from(value: i64) -> Self {
+        let mut size = value.unsigned_abs();
+        let mut magnitude = Vec::new();
+        while size != 0 {
+            magnitude.push(size as u32);
+            size >>= LIMB_BITS;
+        }
+        Self {
+            negative: value < 0,
+            magnitude,
+        }
+    }
+}
+
+impl CellKind for BigCell {
+    fn wrapping_increment(&mut self) {
+        if self.negative {
+            self.shrink();
+        } else {
+            self.grow();
+        }
+    }
+
+    fn wrapping_decrement(&mut self) {
+        if self.negative || self.magnitude.is_empty() {
+            self.negative = true;
+            self.grow();
+        } else {
+            self.shrink();
+        }
+    }
+
+    fn checked_increment(&mut self) -> bool {
+        self.wrapping_increment();
+        true
+    }
+
+    fn checked_decrement(&mut self) -> bool {
+        self.wrapping_decrement();
+        true
+    }
+
+    fn set_value(&mut self, value: u8) {
+        *self = Self::from(i64::from(value));
+    }
+
+    fn get_value(&self) -> u8 {
+        let low_byte = self.magnitude.first().map_or(0, |limb| *limb as u8);
+        if self.negative {
+            low_byte.wrapping_neg()
+        } else {
+            low_byte
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+}
+
+/// In decimal
+impl Display for BigCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        /// The largest power of ten that fits in a limb, for peeling off nine digits at a time
+        const CHUNK: u64 = 1_000_000_000;
+
+        let mut remaining = self.magnitude.clone();
+        let mut chunks = Vec::new();
+        while !remaining.is_empty() {
+            let mut remainder = 0u64;
+            for limb in remaining.iter_mut().rev() {
+                let value = (remainder << LIMB_BITS) | u64::from(*limb);
+                *limb = (value / CHUNK) as u32;
+                remainder = value % CHUNK;
+            }
+            while remaining.last() == Some(&0) {
+                remaining.pop();
+            }
+            chunks.push(remainder);
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        match chunks.split_last() {
+            None => write!(f, "0"),
+            Some((most_significant, rest)) => {
+                write!(f, "{}", most_significant)?;
+                rest.iter()
+                    .rev()
+                    .try_for_each(|chunk| write!(f, "{:09}", chunk))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Does counting carry across limbs and through zero without wrapping?
+    #[test]
+    fn test_counting() {
+        let mut cell = BigCell::from(u32::MAX as i64);
+        cell.wrapping_increment();
+        assert_eq!(cell, BigCell::from(1 << 32));
+        assert_eq!(cell.to_string(), "4294967296");
+        cell.wrapping_decrement();
+        assert_eq!(cell, BigCell::from(u32::MAX as i64));
+
+        let mut cell = BigCell::from(1);
+        cell.wrapping_decrement();
+        assert!(cell.is_zero());
+        cell.wrapping_decrement();
+        assert_eq!(cell, BigCell::from(-1));
+        assert_eq!(cell.to_string(), "-1");
+        cell.wrapping_increment();
+        assert_eq!(cell, BigCell::default());
+        assert_eq!(BigCell::from(-1_000_000_007).to_string(), "-1000000007");
+    }
+
+    // Is output the value modulo 256, counted upwards from zero?
+    #[test]
+    fn test_output_modulo() {
+        assert_eq!(BigCell::from(321).get_value(), 65);
+        assert_eq!(BigCell::from(-1).get_value(), 255);
+        assert_eq!(BigCell::from(-256).get_value(), 0);
+        assert_eq!(BigCell::from(-191).get_value(), 65);
+        assert_eq!(BigCell::from(1 << 40).get_value(), 0);
+
+        let mut cell = BigCell::from(-5);
+        cell.set_value(200);
+        assert_eq!(cell, BigCell::from(200));
+    }
+}
@@ -0,0 +1,131 @@
+//! Breakpoints: places in a program where [crate::VirtualMachine::interpret] stops with
+//! [crate::HaltReason::BreakpointHit] before running the instruction there, so a debugger can look
+//! at the machine and then carry on.
+
+use std::fmt::Display;
+
+use bft_types::messages::{self, Localise, Message};
+use bft_types::LocalisedInstruction;
+use thiserror::Error;
+
+/// Where to stop, given to [crate::VirtualMachine::add_breakpoint]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Breakpoint {
+    /// Before the instruction with this index in the program
+    Instruction(usize),
+    /// Before the first instruction at or after this place in the source, so that a breakpoint
+    /// on a comment or a blank line stops at the next instruction. Both are 1-indexed.
+    Location { line_num: usize, column_num: usize },
+}
+
+/// A [Breakpoint] that is not in the program
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BreakpointError {
+    /// The index is past the last instruction
+    OutOfRange { index: usize, instructions: usize },
+    /// There are no instructions at or after the location
+    NoInstruction { line_num: usize, column_num: usize },
+}
+
+impl Breakpoint {
+    /// The index of the instruction this breakpoint stops before
+    pub(crate) fn resolve(
+        &self,
+        instructions: &[LocalisedInstruction],
+    ) -> Result<usize, BreakpointError> {
+        match *self {
+            Breakpoint::Instruction(index) if index < instructions.len() => Ok(index),
+            Breakpoint::Instruction(index) => Err(BreakpointError::OutOfRange {
+                index,
+                instructions: instructions.len(),
+            }),
+            Breakpoint::Location {
+                line_num,
+                column_num,
+            } => instructions
+                .iter()
+                .position(|instruction| {
+                    instruction.source() == 0
+                        && (instruction.line_num(), instruction.column_num())
+                            >= (line_num, column_num)
+                })
+                .ok_or(BreakpointError::NoInstruction {
+                    line_num,
+                    column_num,
+                }),
+        }
+    }
+}
+
+impl Localise for BreakpointError {
+    fn message(&self) -> Message {
+        match self {
+            BreakpointError::OutOfRange {
+                index,
+                instructions,
+            } => Message::new(
+                messages::BREAKPOINT_OUT_OF_RANGE,
+                vec![
+                    ("index", index.to_string()),
+                    ("instructions", instructions.to_string()),
+                ],
+            ),
+            BreakpointError::NoInstruction {
+                line_num,
+                column_num,
+            } => Message::new(
+                messages::BREAKPOINT_NO_INSTRUCTION,
+                vec![
+                    ("line", line_num.to_string()),
+                    ("column", column_num.to_string()),
+                ],
+            ),
+        }
+    }
+}
+
+impl Display for BreakpointError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::BfProgram;
+
+    // Do locations stop at the next instruction, and are breakpoints outside the program refused?
+    #[test]
+    fn test_resolve() {
+        let program = BfProgram::new("test.bf", "+ comment\n\n  >.").unwrap();
+        let instructions = program.localised_instructions();
+        let at = |line_num, column_num| {
+            Breakpoint::Location {
+                line_num,
+                column_num,
+            }
+            .resolve(instructions)
+        };
+
+        assert_eq!(at(1, 1), Ok(0));
+        assert_eq!(at(1, 2), Ok(1));
+        assert_eq!(at(2, 1), Ok(1));
+        assert_eq!(at(3, 4), Ok(2));
+        assert_eq!(
+            at(3, 5),
+            Err(BreakpointError::NoInstruction {
+                line_num: 3,
+                column_num: 5
+            })
+        );
+        assert_eq!(Breakpoint::Instruction(2).resolve(instructions), Ok(2));
+        assert_eq!(
+            Breakpoint::Instruction(3).resolve(instructions),
+            Err(BreakpointError::OutOfRange {
+                index: 3,
+                instructions: 3
+            })
+        );
+    }
+}
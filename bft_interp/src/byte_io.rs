@@ -0,0 +1,135 @@
+//! Where a [crate::VirtualMachine] gets its input from and sends its output to. The machine only
+//! ever reads and writes one byte at a time, so [InputProvider] and [OutputSink] ask for no more
+//! than that. Every [Read] is an [InputProvider] and every [Write] is an [OutputSink], so files,
+//! stdio and buffers can be given to the machine as they are; channels, callbacks and the like can
+//! be given with [FnInput] and [FnOutput] rather than being dressed up as streams.
+//!
+//! ```
+//!# use std::sync::mpsc;
+//!# use bft_interp::{EofBehavior, FnInput, FnOutput, VirtualMachine};
+//!# use bft_types::BfProgram;
+//!# fn main() -> Result<(), Box<dyn std::error::Error>>{
+//!  let (keys, typed) = mpsc::channel();
+//!  keys.send(b'a')?;
+//!  keys.send(b'b')?;
+//!  drop(keys);
+//!
+//!  let program = BfProgram::new("upper.bf", &format!(",[{}.,]", "-".repeat(32)))?;
+//!  let mut vm: VirtualMachine<u8> =
+//!      VirtualMachine::new(&program, None, false).with_eof_behavior(EofBehavior::SetZero);
+//!  let mut shouted = String::new();
+//!  vm.interpret(
+//!      // the input ends once the sender has gone
+//!      &mut FnInput(|| Ok(typed.recv().ok())),
+//!      &mut FnOutput(|byte| {
+//!          shouted.push(byte as char);
+//!          Ok(())
+//!      }),
+//!  )?;
+//!  assert_eq!(shouted, "AB");
+//!# Ok(())
+//!# }
+//! ```
+
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Something a machine can read its input from
+pub trait InputProvider {
+    /// The next byte of input, or `None` once the input has ended. Fails with
+    /// [ErrorKind::WouldBlock] if there is no byte yet but there may be later, in which case the
+    /// machine pauses with [crate::HaltReason::NeedsInput].
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+}
+
+/// Something a machine can write its output to
+pub trait OutputSink {
+    /// Write one byte of output
+    fn write_byte(&mut self, byte: u8) -> io::Result<()>;
+
+    /// Pass on anything written so far, as asked for by the machine's [crate::FlushPolicy]
+    fn flush_output(&mut self) -> io::Result<()>;
+}
+
+impl<R: Read + ?Sized> InputProvider for R {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut buffer = [0];
+        loop {
+            match self.read(&mut buffer) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(buffer[0])),
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+impl<W: Write + ?Sized> OutputSink for W {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.write_all(&[byte])
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+/// Input from a function, called for each byte as [InputProvider::read_byte] is
+#[derive(Debug, Clone, Copy)]
+pub struct FnInput<F>(pub F);
+
+impl<F: FnMut() -> io::Result<Option<u8>>> InputProvider for FnInput<F> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        (self.0)()
+    }
+}
+
+/// Output to a function, called with each byte. Flushing does nothing.
+#[derive(Debug, Clone, Copy)]
+pub struct FnOutput<F>(pub F);
+
+impl<F: FnMut(u8) -> io::Result<()>> OutputSink for FnOutput<F> {
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        (self.0)(byte)
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // Does a reader give its bytes one at a time and then the end of input, retrying interrupts?
+    #[test]
+    fn test_read_byte() {
+        struct Interrupting(bool, VecDeque<u8>);
+        impl Read for Interrupting {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.0 = !self.0;
+                match self.0 {
+                    true => Err(ErrorKind::Interrupted.into()),
+                    false => self.1.read(buf),
+                }
+            }
+        }
+        let mut input = Interrupting(false, VecDeque::from(vec![1, 2]));
+        assert_eq!(input.read_byte().unwrap(), Some(1));
+        assert_eq!(input.read_byte().unwrap(), Some(2));
+        assert_eq!(input.read_byte().unwrap(), None);
+
+        let mut counting = FnInput({
+            let mut next = 0;
+            move || {
+                next += 1;
+                Ok((next < 3).then_some(next))
+            }
+        });
+        assert_eq!(counting.read_byte().unwrap(), Some(1));
+        assert_eq!(counting.read_byte().unwrap(), Some(2));
+        assert_eq!(counting.read_byte().unwrap(), None);
+    }
+}
@@ -0,0 +1,35 @@
+//! Cross-thread cancellation of a running [crate::VirtualMachine].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A handle that can be used, from any thread, to ask a [crate::VirtualMachine] to stop. The
+/// machine checks for a request before each instruction, and when it sees one it stops with
+/// [crate::HaltReason::Interrupted] and clears the request, so it can be resumed afterwards by
+/// calling [crate::VirtualMachine::interpret] again.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    requested: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Create a new token with no cancellation requested
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the machine to stop at the next instruction boundary
+    pub fn cancel(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a cancellation has been requested and not yet acted upon
+    pub fn is_cancelled(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+
+    /// Clear any outstanding request, returning whether there was one
+    pub(crate) fn take_request(&self) -> bool {
+        self.requested.load(Ordering::Relaxed) && self.requested.swap(false, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,188 @@
+//! A journal of the most recent changes a [crate::VirtualMachine] made to its tape, for seeing how
+//! data flows through a program.
+
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::num::NonZeroUsize;
+
+use bft_types::LocalisedInstruction;
+
+/// A single change to a cell
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CellChange {
+    /// The machine's clock once the change was made (see [crate::VirtualMachine::clock])
+    pub clock: u64,
+    /// Index of the cell that changed in [crate::VirtualMachine::tape]
+    pub cell: usize,
+    /// The value before the change
+    pub before: u8,
+    /// The value after the change
+    pub after: u8,
+    /// The instruction that made the change, with its location in the source
+    pub instruction: LocalisedInstruction,
+}
+
+impl Display for CellChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {}:{} cell {}: {} -> {}",
+            self.clock,
+            self.instruction.line_num(),
+            self.instruction.column_num(),
+            self.cell,
+            self.before,
+            self.after
+        )
+    }
+}
+
+/// The most recent change to one cell, as summarised by [CellJournal::recent_cells]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RecentCell {
+    /// Index of the cell
+    pub cell: usize,
+    /// How many instructions ago it last changed
+    pub age: u64,
+    /// Its value after that change
+    pub value: u8,
+    /// How many of the changes in the journal were to this cell
+    pub changes: usize,
+}
+
+/// Ring buffer holding the last few [CellChange]s. Once full, each new change pushes out the
+/// oldest. Changes made by extension handlers are not recorded.
+#[derive(Debug, Clone)]
+pub struct CellJournal {
+    changes: VecDeque<CellChange>,
+    capacity: NonZeroUsize,
+}
+
+impl CellJournal {
+    /// Create an empty journal that keeps up to `capacity` changes
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            changes: VecDeque::with_capacity(capacity.get()),
+            capacity,
+        }
+    }
+
+    /// Forget every change recorded so far
+    pub(crate) fn clear(&mut self) {
+        self.changes.clear();
+    }
+
+    /// Add a change, dropping the oldest one if the journal is full
+    pub(crate) fn record(&mut self, change: CellChange) {
+        if self.changes.len() == self.capacity.get() {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(change);
+    }
+
+    /// Move every recorded change `by` cells to the right, to follow cells added at the front of
+    /// the tape
+    pub(crate) fn shift(&mut self, by: usize) {
+        for change in self.changes.iter_mut() {
+            change.cell += by;
+        }
+    }
+
+    /// The recorded changes, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &CellChange> {
+        self.changes.iter()
+    }
+
+    /// The number of changes currently recorded
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Whether no changes have been recorded yet
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Every cell that changed in the last `window` instructions before `clock`, most recently
+    /// changed first
+    pub fn recent_cells(&self, clock: u64, window: u64) -> Vec<RecentCell> {
+        let mut recent: Vec<RecentCell> = Vec::new();
+        for change in self.changes.iter().rev() {
+            let age = clock.saturating_sub(change.clock);
+            if age >= window {
+                break;
+            }
+            match recent.iter_mut().find(|recent| recent.cell == change.cell) {
+                Some(recent) => recent.changes += 1,
+                None => recent.push(RecentCell {
+                    cell: change.cell,
+                    age,
+                    value: change.after,
+                    changes: 1,
+                }),
+            }
+        }
+        recent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::Instruction;
+
+    fn change(clock: u64, cell: usize, after: u8) -> CellChange {
+        CellChange {
+            clock,
+            cell,
+            before: after.wrapping_sub(1),
+            after,
+            instruction: LocalisedInstruction::new(Instruction::Increment, 1, 1),
+        }
+    }
+
+    // Are old changes dropped once the journal is full?
+    #[test]
+    fn test_capacity() {
+        let mut journal = CellJournal::new(NonZeroUsize::new(2).unwrap());
+        for clock in 1..=3 {
+            journal.record(change(clock, 0, clock as u8));
+        }
+
+        assert_eq!(
+            journal
+                .iter()
+                .map(|change| change.clock)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+
+    // Are recent changes summarised per cell, most recent first, within the window?
+    #[test]
+    fn test_recent_cells() {
+        let mut journal = CellJournal::new(NonZeroUsize::new(8).unwrap());
+        journal.record(change(1, 5, 1));
+        journal.record(change(4, 0, 1));
+        journal.record(change(5, 1, 1));
+        journal.record(change(6, 0, 2));
+
+        assert_eq!(
+            journal.recent_cells(7, 4),
+            vec![
+                RecentCell {
+                    cell: 0,
+                    age: 1,
+                    value: 2,
+                    changes: 2
+                },
+                RecentCell {
+                    cell: 1,
+                    age: 2,
+                    value: 1,
+                    changes: 1
+                },
+            ]
+        );
+    }
+}
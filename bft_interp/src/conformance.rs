@@ -0,0 +1,314 @@
+//! The semantics every engine that runs Brainfuck programs should share with [VirtualMachine], as
+//! a battery of small programs with known results, so that other engines (compiled output, JITs,
+//! third-party backends) can show that they behave the same way.
+//!
+//! An engine implements [Engine], and [run] plays every [Case] against it. The cases cover
+//! wrapping 8-bit cells, the end of input, how brackets match and loop, and fixed and growing
+//! tapes. [Reference] is the engine the cases are checked against.
+//!
+//! ```
+//!# use bft_interp::conformance::{self, Reference};
+//!  let report = conformance::run(&mut Reference);
+//!  assert!(report.passed(), "{}", report);
+//! ```
+
+use std::fmt::Display;
+use std::io::Cursor;
+use std::num::NonZeroUsize;
+
+use bft_types::BfProgram;
+
+use crate::{EofBehavior, HaltReason, VMError, VirtualMachine};
+
+/// How a case asks for its program to be run
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Setup {
+    /// What `,` does once input has run out
+    pub eof_behavior: EofBehavior,
+    /// The number of cells the tape starts with
+    pub tape_cells: usize,
+    /// Whether the tape grows to the right when the head moves past its end
+    pub extensible: bool,
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Self {
+            eof_behavior: EofBehavior::SetZero,
+            tape_cells: 30_000,
+            extensible: false,
+        }
+    }
+}
+
+/// How a program ended
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Outcome {
+    /// It ran to the end, writing this output
+    Completed(Vec<u8>),
+    /// The head moved to the left of the first cell
+    HeadUnderrun,
+    /// The head moved to the right of the last cell of a fixed tape
+    HeadOverrun,
+    /// A `,` ran after the input had ended, with [EofBehavior::Error]
+    EndOfInput,
+    /// Anything else, described by the engine
+    Other(String),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Completed(output) => write!(f, "completed with output {:?}", output),
+            Outcome::HeadUnderrun => write!(f, "head underrun"),
+            Outcome::HeadOverrun => write!(f, "head overrun"),
+            Outcome::EndOfInput => write!(f, "read past the end of input"),
+            Outcome::Other(other) => write!(f, "{}", other),
+        }
+    }
+}
+
+/// Something that runs Brainfuck programs with 8-bit wrapping cells
+pub trait Engine {
+    /// Run `program` to the end on `input`, set up as asked
+    fn run(&mut self, program: &BfProgram, input: &[u8], setup: &Setup) -> Outcome;
+}
+
+/// A program and how it must end
+#[derive(Debug, Clone)]
+pub struct Case {
+    /// What the case checks, as a short phrase
+    pub name: &'static str,
+    /// The program's text
+    pub source: String,
+    /// The program's input
+    pub input: Vec<u8>,
+    /// How the program is run
+    pub setup: Setup,
+    /// How the program must end
+    pub expected: Outcome,
+}
+
+/// A case that ends as it should, with the default setup
+fn case(name: &'static str, source: &str, input: &[u8], output: &[u8]) -> Case {
+    Case {
+        name,
+        source: source.to_string(),
+        input: input.to_vec(),
+        setup: Setup::default(),
+        expected: Outcome::Completed(output.to_vec()),
+    }
+}
+
+/// Every case in the battery
+pub fn cases() -> Vec<Case> {
+    let with_eof = |eof_behavior| Setup {
+        eof_behavior,
+        ..Setup::default()
+    };
+    let fixed = |tape_cells| Setup {
+        tape_cells,
+        ..Setup::default()
+    };
+    vec![
+        // cells
+        case("cells start at zero", ">>>.", b"", &[0]),
+        case("increment and output", "+++.", b"", &[3]),
+        case("decrement wraps below zero", "-.", b"", &[255]),
+        case("increment wraps above 255", "-++.", b"", &[1]),
+        case("cells keep their values", "+>++<.>.", b"", &[1, 2]),
+        // input
+        case("input is read a byte at a time", ",.,.", b"hi", b"hi"),
+        Case {
+            setup: with_eof(EofBehavior::SetZero),
+            ..case("end of input reads zero", "+,.", b"", &[0])
+        },
+        Case {
+            setup: with_eof(EofBehavior::SetMax),
+            ..case("end of input reads 255", ",.", b"", &[255])
+        },
+        Case {
+            setup: with_eof(EofBehavior::LeaveUnchanged),
+            ..case("end of input leaves the cell", "+++,.", b"", &[3])
+        },
+        Case {
+            setup: with_eof(EofBehavior::Error),
+            expected: Outcome::EndOfInput,
+            ..case("end of input fails", ",", b"", &[])
+        },
+        case("echo until end of input", ",[.,]", b"abc", b"abc"),
+        // brackets
+        case("a loop on zero is skipped", "[.]+.", b"", &[1]),
+        case("a loop is checked at its end", "+++[-.]", b"", &[2, 1, 0]),
+        case("loops nest", "++[>++[>+<-]<-]>>.", b"", &[4]),
+        case(
+            "loops are skipped past nested loops",
+            "[[.]>[.].]+.",
+            b"",
+            &[1],
+        ),
+        case("comments are ignored", "+[ a >b<c- ]d+.", b"", &[1]),
+        case("multiplication", "++++++++[>++++++++<-]>+.", b"", b"A"),
+        // tape
+        Case {
+            expected: Outcome::HeadUnderrun,
+            ..case("the head can't move left of the first cell", "<", b"", &[])
+        },
+        Case {
+            setup: fixed(3),
+            expected: Outcome::HeadOverrun,
+            ..case("a fixed tape ends", ">>>", b"", &[])
+        },
+        Case {
+            setup: fixed(3),
+            ..case("a fixed tape reaches its last cell", ">>+.", b"", &[1])
+        },
+        Case {
+            setup: Setup {
+                tape_cells: 1,
+                extensible: true,
+                ..Setup::default()
+            },
+            ..case(
+                "an extensible tape grows with zeroed cells",
+                ">.+>>+<.<<.",
+                b"",
+                &[0, 0, 0],
+            )
+        },
+    ]
+}
+
+/// A case an engine got wrong
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Mismatch {
+    /// The case's name
+    pub name: &'static str,
+    /// How the program should have ended
+    pub expected: Outcome,
+    /// How it ended
+    pub actual: Outcome,
+}
+
+/// How an engine did on the battery
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Report {
+    /// The number of cases run
+    pub cases: usize,
+    /// The cases the engine got wrong
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Report {
+    /// Whether the engine got every case right
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} conformance cases passed",
+            self.cases - self.mismatches.len(),
+            self.cases
+        )?;
+        for mismatch in &self.mismatches {
+            write!(
+                f,
+                "\n{}: expected {}, but {}",
+                mismatch.name, mismatch.expected, mismatch.actual
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Play every case against `engine`
+pub fn run(engine: &mut impl Engine) -> Report {
+    let cases = cases();
+    let mut report = Report {
+        cases: cases.len(),
+        mismatches: Vec::new(),
+    };
+    for case in cases {
+        let program = BfProgram::new(case.name, &case.source)
+            .expect("every conformance case is a valid program");
+        let actual = engine.run(&program, &case.input, &case.setup);
+        if actual != case.expected {
+            report.mismatches.push(Mismatch {
+                name: case.name,
+                expected: case.expected,
+                actual,
+            });
+        }
+    }
+    report
+}
+
+/// The reference engine: a [VirtualMachine] with `u8` cells
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Reference;
+
+impl Engine for Reference {
+    fn run(&mut self, program: &BfProgram, input: &[u8], setup: &Setup) -> Outcome {
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(
+            program,
+            NonZeroUsize::new(setup.tape_cells),
+            setup.extensible,
+        )
+        .with_eof_behavior(setup.eof_behavior);
+        let mut output = Vec::new();
+        match vm.interpret(&mut Cursor::new(input), &mut output) {
+            Ok(HaltReason::Completed) => Outcome::Completed(output),
+            Ok(halt_reason) => Outcome::Other(halt_reason.to_string()),
+            Err(VMError::HeadUnderrun(_)) => Outcome::HeadUnderrun,
+            Err(VMError::HeadOverrun(_)) => Outcome::HeadOverrun,
+            Err(VMError::ReadError(..)) => Outcome::EndOfInput,
+            Err(error) => Outcome::Other(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Does the reference pass, and is an engine that gets the end of input wrong caught?
+    #[test]
+    fn test_run() {
+        let report = run(&mut Reference);
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.cases, cases().len());
+
+        /// Reads zero at the end of input, whatever it is asked to do
+        struct AlwaysZero;
+        impl Engine for AlwaysZero {
+            fn run(&mut self, program: &BfProgram, input: &[u8], setup: &Setup) -> Outcome {
+                let setup = Setup {
+                    eof_behavior: EofBehavior::SetZero,
+                    ..*setup
+                };
+                Reference.run(program, input, &setup)
+            }
+        }
+        let report = run(&mut AlwaysZero);
+        let names: Vec<_> = report
+            .mismatches
+            .iter()
+            .map(|mismatch| mismatch.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "end of input reads 255",
+                "end of input leaves the cell",
+                "end of input fails"
+            ]
+        );
+        assert!(report
+            .to_string()
+            .contains("end of input fails: expected read past the end of input, but completed"));
+    }
+}
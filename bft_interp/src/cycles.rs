@@ -0,0 +1,197 @@
+//! Charging each instruction a number of cycles, so that a program's running time can be given on
+//! a virtual clock that is the same on every host, and optionally throttling the run so that it
+//! never goes faster than a chosen machine speed.
+
+use std::fmt::Display;
+use std::num::NonZeroU64;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bft_types::{Instruction, LocalisedInstruction};
+
+use crate::{HaltReason, Observer};
+
+/// How many instructions to execute between checks of the host clock when throttling
+const THROTTLE_CHECK_INTERVAL: u64 = 256;
+
+/// The number of cycles charged for each kind of operation
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CycleCosts {
+    /// `<` and `>`
+    pub move_head: u64,
+    /// `+` and `-`
+    pub arithmetic: u64,
+    /// `,`
+    pub input: u64,
+    /// `.`
+    pub output: u64,
+    /// `[` and `]`, whether or not the jump is taken
+    pub jump: u64,
+    /// Any extension instruction, the `#` debug dump, and the instructions Extended Brainfuck
+    /// Type I adds
+    pub extension: u64,
+}
+
+impl Default for CycleCosts {
+    /// Every operation costs one cycle
+    fn default() -> Self {
+        Self {
+            move_head: 1,
+            arithmetic: 1,
+            input: 1,
+            output: 1,
+            jump: 1,
+            extension: 1,
+        }
+    }
+}
+
+impl CycleCosts {
+    /// The number of cycles charged for an instruction
+    pub fn cost_of(&self, instruction: Instruction) -> u64 {
+        match instruction {
+            Instruction::MoveLeft | Instruction::MoveRight => self.move_head,
+            Instruction::Increment | Instruction::Decrement => self.arithmetic,
+            Instruction::Input => self.input,
+            Instruction::Output => self.output,
+            Instruction::ConditionalJumpForward | Instruction::ConditionalJumpBackward => self.jump,
+            Instruction::Extension(_)
+            | Instruction::End
+            | Instruction::Store
+            | Instruction::Load
+            | Instruction::ShiftRight
+            | Instruction::ShiftLeft
+            | Instruction::Not
+            | Instruction::Xor
+            | Instruction::And
+            | Instruction::Or
+            | Instruction::DebugDump => self.extension,
+        }
+    }
+}
+
+/// An [Observer] that adds up the cycles used by a machine. If given a speed, it also holds the
+/// machine back whenever it gets ahead of where a machine running at that speed would be, so the
+/// program runs at the same speed on every host that is fast enough.
+///
+/// ```
+///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+///# use bft_types::BfProgram;
+///# use bft_interp::{CycleCosts, VirtualClock, VirtualMachine};
+///# use std::io::Cursor;
+/// let bf_program = BfProgram::new("add.bf", "++>+.")?;
+/// let mut clock = VirtualClock::new(CycleCosts { output: 10, ..CycleCosts::default() }, None);
+/// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+/// bf_interpreter.add_observer(&mut clock);
+///
+/// bf_interpreter.interpret(&mut Cursor::new([]), &mut Vec::new())?;
+/// drop(bf_interpreter);
+/// assert_eq!(clock.cycles(), 14);
+///# Ok(())
+///# }
+/// ```
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    costs: CycleCosts,
+    /// Cycles per second of the virtual machine, if the run should be throttled to it
+    hz: Option<NonZeroU64>,
+    cycles: u64,
+    instructions: u64,
+    /// Host time of the first instruction, used as the start of the virtual clock
+    started: Option<Instant>,
+}
+
+impl VirtualClock {
+    /// Create a clock charging the given costs. If `hz` is given, the run is throttled to that
+    /// many cycles per second.
+    pub fn new(costs: CycleCosts, hz: Option<NonZeroU64>) -> Self {
+        Self {
+            costs,
+            hz,
+            cycles: 0,
+            instructions: 0,
+            started: None,
+        }
+    }
+
+    /// The cycles used so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// How long the cycles used so far take at the clock's speed, if it has one
+    pub fn virtual_time(&self) -> Option<Duration> {
+        self.hz
+            .map(|hz| Duration::from_secs_f64(self.cycles as f64 / hz.get() as f64))
+    }
+
+    /// Sleep until the host has caught up with the virtual clock
+    fn throttle(&self) {
+        if let (Some(started), Some(virtual_time)) = (self.started, self.virtual_time()) {
+            if let Some(ahead) = virtual_time.checked_sub(started.elapsed()) {
+                thread::sleep(ahead);
+            }
+        }
+    }
+}
+
+impl Observer for VirtualClock {
+    fn instruction_executed(
+        &mut self,
+        _clock: u64,
+        _program_counter: usize,
+        instruction: &LocalisedInstruction,
+    ) {
+        self.started.get_or_insert_with(Instant::now);
+        self.cycles += self.costs.cost_of(instruction.instruction());
+        self.instructions += 1;
+        if self.instructions.is_multiple_of(THROTTLE_CHECK_INTERVAL) {
+            self.throttle();
+        }
+    }
+
+    fn halted(&mut self, _clock: u64, _halt_reason: HaltReason) {
+        self.throttle();
+    }
+}
+
+impl Display for VirtualClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} cycles", self.cycles)?;
+        if let (Some(hz), Some(virtual_time)) = (self.hz, self.virtual_time()) {
+            write!(
+                f,
+                " ({:.3}ms at {}Hz)",
+                virtual_time.as_secs_f64() * 1000.0,
+                hz
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VirtualMachine;
+    use bft_types::BfProgram;
+    use std::io::Cursor;
+
+    // Does a throttled run take at least as long as the virtual clock says it should?
+    #[test]
+    fn test_throttled_run() {
+        let program = BfProgram::new("count.bf", "++++++++++[-]").unwrap();
+        let mut clock = VirtualClock::new(CycleCosts::default(), NonZeroU64::new(1000));
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        vm.add_observer(&mut clock);
+
+        let started = Instant::now();
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+        let instructions = vm.clock();
+        drop(vm);
+
+        // every instruction costs one cycle by default
+        assert_eq!(clock.cycles(), instructions);
+        assert!(started.elapsed() >= clock.virtual_time().unwrap());
+    }
+}
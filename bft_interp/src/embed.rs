@@ -0,0 +1,316 @@
+//! Calling Brainfuck programs like functions from Rust: passing Rust values in as the program's
+//! input, and reading its output back as Rust values.
+//!
+//! Values are turned into input and read back from output by these conventions:
+//!
+//! - bytes (`[u8]`, `Vec<u8>`) are passed through unchanged;
+//! - text (`str`, `String`) is passed as UTF-8, and output must be valid UTF-8;
+//! - wider integers (`u16`, `u32`, `u64`, `usize`, `i16`, `i32`, `i64`) are written in decimal, one
+//!   per line, and read back as decimal numbers separated by whitespace.
+//!
+//! Reading past the end of input is an error, so the input is followed by a single zero byte to
+//! mark where it ends, which a program can stop reading at with `,[...,]`.
+//!
+//! ```
+//!# use bft_interp::embed::run_with;
+//!# use bft_types::BfProgram;
+//!# fn main() -> Result<(), Box<dyn std::error::Error>>{
+//!  // echoes its input, so the numbers come straight back
+//!  let echo = BfProgram::new("echo.bf", ",[.,]")?;
+//!  let numbers: Vec<u32> = run_with::<u8, _, _>(&echo, &[12u32, 7][..])?;
+//!  assert_eq!(numbers, vec![12, 7]);
+//!# Ok(())
+//!# }
+//! ```
+
+use std::io::Cursor;
+
+use bft_types::BfProgram;
+use thiserror::Error;
+
+use crate::{CellKind, HaltReason, VMError, VirtualMachine};
+
+/// Why a program could not be called like a function
+#[derive(Debug, Error)]
+pub enum EmbedError {
+    /// The program failed with an error
+    #[error(transparent)]
+    Program(#[from] VMError),
+    /// The program stopped before it completed, for example because of a limit
+    #[error("program stopped early: {0}")]
+    Stopped(HaltReason),
+    /// The program's output could not be read as the type asked for
+    #[error("could not read the program's output: {0}")]
+    Output(String),
+}
+
+/// A value that can be given to a program as its input
+pub trait ToInput {
+    /// The bytes of input that stand for the value
+    fn to_input(&self) -> Vec<u8>;
+}
+
+/// A value that can be read back from a program's output
+pub trait FromOutput: Sized {
+    /// Read the value from everything the program output
+    fn from_output(output: &[u8]) -> Result<Self, EmbedError>;
+}
+
+impl ToInput for [u8] {
+    fn to_input(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl ToInput for Vec<u8> {
+    fn to_input(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl ToInput for str {
+    fn to_input(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl ToInput for String {
+    fn to_input(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl FromOutput for Vec<u8> {
+    fn from_output(output: &[u8]) -> Result<Self, EmbedError> {
+        Ok(output.to_vec())
+    }
+}
+
+impl FromOutput for String {
+    fn from_output(output: &[u8]) -> Result<Self, EmbedError> {
+        String::from_utf8(output.to_vec()).map_err(|error| EmbedError::Output(error.to_string()))
+    }
+}
+
+/// Parse whitespace-separated decimal numbers
+fn decimal_numbers<N: std::str::FromStr>(output: &[u8]) -> Result<Vec<N>, EmbedError> {
+    std::str::from_utf8(output)
+        .map_err(|error| EmbedError::Output(error.to_string()))?
+        .split_whitespace()
+        .map(|number| {
+            number
+                .parse()
+                .map_err(|_| EmbedError::Output(format!("'{}' is not a valid number", number)))
+        })
+        .collect()
+}
+
+/// Implements the decimal conventions for integers wider than a byte
+macro_rules! impl_decimal {
+    ($($number:ty),*) => {
+        $(
+            impl ToInput for $number {
+                fn to_input(&self) -> Vec<u8> {
+                    format!("{}\n", self).into_bytes()
+                }
+            }
+
+            impl ToInput for [$number] {
+                fn to_input(&self) -> Vec<u8> {
+                    self.iter().flat_map(ToInput::to_input).collect()
+                }
+            }
+
+            impl ToInput for Vec<$number> {
+                fn to_input(&self) -> Vec<u8> {
+                    self.as_slice().to_input()
+                }
+            }
+
+            impl FromOutput for $number {
+                fn from_output(output: &[u8]) -> Result<Self, EmbedError> {
+                    match decimal_numbers(output)?.as_slice() {
+                        [number] => Ok(*number),
+                        numbers => Err(EmbedError::Output(format!(
+                            "expected one number, found {}",
+                            numbers.len()
+                        ))),
+                    }
+                }
+            }
+
+            impl FromOutput for Vec<$number> {
+                fn from_output(output: &[u8]) -> Result<Self, EmbedError> {
+                    decimal_numbers(output)
+                }
+            }
+        )*
+    };
+}
+
+impl_decimal!(u16, u32, u64, usize, i16, i32, i64);
+
+/// Run a machine to completion on `input` and its end marker, and read its output back as `O`.
+/// The machine is run as it was configured, so limits, tape size and so on can be set beforehand.
+pub fn call<T: CellKind, I: ToInput + ?Sized, O: FromOutput>(
+    vm: &mut VirtualMachine<'_, T>,
+    input: &I,
+) -> Result<O, EmbedError> {
+    let mut input = input.to_input();
+    input.push(0);
+    let mut output = Vec::new();
+    match vm.interpret(&mut Cursor::new(input), &mut output)? {
+        HaltReason::Completed => O::from_output(&output),
+        halt_reason => Err(EmbedError::Stopped(halt_reason)),
+    }
+}
+
+/// Run a program on a fresh machine with `T` cells and the default tape, giving it `input` and
+/// reading its output back as `O`
+pub fn run_with<T: CellKind, I: ToInput + ?Sized, O: FromOutput>(
+    program: &BfProgram,
+    input: &I,
+) -> Result<O, EmbedError> {
+    call(&mut VirtualMachine::<T>::new(program, None, false), input)
+}
+
+/// A program loaded once and called many times, each call a round with its own input and output,
+/// for using a program as a request/response service. With `preserve_tape`, each round starts
+/// with the tape and head the last one left, so the program can keep state between requests;
+/// without it, every round starts on a blank tape.
+///
+/// ```
+///# use bft_interp::embed::Rounds;
+///# use bft_interp::VirtualMachine;
+///# use bft_types::BfProgram;
+///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+///  // counts the rounds in its first cell, and answers each with the count
+///  let counter = BfProgram::new("counter.bf", "+.")?;
+///  let mut rounds = Rounds::new(VirtualMachine::<u8>::new(&counter, None, false), true);
+///  for expected in 1..=3u8 {
+///      let output: Vec<u8> = rounds.round("")?;
+///      assert_eq!(output, vec![expected]);
+///  }
+///# Ok(())
+///# }
+/// ```
+#[derive(Debug)]
+pub struct Rounds<'a, T> {
+    vm: VirtualMachine<'a, T>,
+    preserve_tape: bool,
+    rounds: usize,
+}
+
+impl<'a, T: CellKind> Rounds<'a, T> {
+    /// Run rounds on `vm`, as it was configured. The first round starts with the machine as it
+    /// is given.
+    pub fn new(vm: VirtualMachine<'a, T>, preserve_tape: bool) -> Self {
+        Self {
+            vm,
+            preserve_tape,
+            rounds: 0,
+        }
+    }
+
+    /// Run the program again, as [call] does, on `input`
+    pub fn round<I: ToInput + ?Sized, O: FromOutput>(
+        &mut self,
+        input: &I,
+    ) -> Result<O, EmbedError> {
+        if self.rounds > 0 {
+            if self.preserve_tape {
+                self.vm.rewind();
+            } else {
+                self.vm.reset();
+            }
+        }
+        self.rounds += 1;
+        call(&mut self.vm, input)
+    }
+
+    /// The number of rounds started so far
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    /// The machine, as the last round left it
+    pub fn vm(&self) -> &VirtualMachine<'a, T> {
+        &self.vm
+    }
+
+    /// Take back the machine
+    pub fn into_inner(self) -> VirtualMachine<'a, T> {
+        self.vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Limits;
+    use assert_matches::assert_matches;
+
+    // Do values survive a round trip through a program that echoes its input?
+    #[test]
+    fn test_round_trip() {
+        let echo = BfProgram::new("echo.bf", ",[.,]").unwrap();
+
+        let text: String = run_with::<u8, _, _>(&echo, "héllo").unwrap();
+        assert_eq!(text, "héllo");
+        let number: i64 = run_with::<u8, _, _>(&echo, &-42i64).unwrap();
+        assert_eq!(number, -42);
+        let bytes: Vec<u8> = run_with::<u8, _, _>(&echo, &[0xffu8, 1][..]).unwrap();
+        assert_eq!(bytes, vec![0xff, 1]);
+    }
+
+    // Are output that can't be read, and programs that don't finish, reported?
+    #[test]
+    fn test_call_errors() {
+        let program = BfProgram::new("letters.bf", "++++++++[>++++++++<-]>+.").unwrap();
+        assert_matches!(
+            run_with::<u8, _, u32>(&program, ""),
+            Err(EmbedError::Output(_))
+        );
+
+        let forever = BfProgram::new("forever.bf", "+[]").unwrap();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&forever, None, false).with_limits(Limits {
+                max_instructions: Some(100),
+                ..Limits::default()
+            });
+        assert_matches!(
+            call::<_, _, Vec<u8>>(&mut vm, ""),
+            Err(EmbedError::Stopped(HaltReason::InstructionLimit))
+        );
+    }
+
+    // Do rounds keep the tape between them only when asked to, and carry on after a failed round?
+    #[test]
+    fn test_rounds() {
+        // adds each input byte to its first cell, and outputs the running total
+        let total = BfProgram::new("total.bf", ">,[[-<+>],]<.").unwrap();
+
+        let mut rounds = Rounds::new(VirtualMachine::<u8>::new(&total, None, true), true);
+        let outputs: Vec<Vec<u8>> = (0..3).map(|_| rounds.round(&[2u8][..]).unwrap()).collect();
+        assert_eq!(outputs, vec![vec![2], vec![4], vec![6]]);
+        assert_eq!(rounds.rounds(), 3);
+
+        let mut rounds = Rounds::new(VirtualMachine::<u8>::new(&total, None, true), false);
+        for _ in 0..3 {
+            assert_eq!(rounds.round::<_, Vec<u8>>(&[2u8][..]).unwrap(), vec![2]);
+        }
+
+        let mut rounds = Rounds::new(
+            VirtualMachine::<u8>::new(&total, None, true).with_limits(Limits {
+                max_instructions: Some(20),
+                ..Limits::default()
+            }),
+            true,
+        );
+        assert_matches!(
+            rounds.round::<_, Vec<u8>>(&[200u8][..]),
+            Err(EmbedError::Stopped(HaltReason::InstructionLimit))
+        );
+        assert_eq!(rounds.round::<_, Vec<u8>>(&[1u8][..]).unwrap().len(), 1);
+    }
+}
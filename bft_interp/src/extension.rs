@@ -0,0 +1,79 @@
+//! Extra instructions defined by the embedder. A program parsed with extension characters (see
+//! [bft_types::ParseOptions::extensions]) contains [bft_types::Instruction::Extension]s, and the
+//! [crate::VirtualMachine] runs each one by calling the handler registered for its character with
+//! [crate::VirtualMachine::with_extension].
+
+use std::ops::Range;
+
+use crate::{CellKind, InputProvider, OutputSink};
+
+/// The error a handler can fail with. It is passed on in [crate::VMError::ExtensionFailed].
+pub type ExtensionError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A function run for an extension instruction
+pub type ExtensionHandler<'a, T> =
+    Box<dyn FnMut(&mut VmContext<'_, T>) -> Result<(), ExtensionError> + 'a>;
+
+/// The parts of the machine an extension handler can see and change
+pub struct VmContext<'c, T> {
+    pub(crate) cells: &'c mut Vec<T>,
+    pub(crate) head: &'c mut usize,
+    pub(crate) tape_can_grow: bool,
+    pub(crate) max_cells: Option<usize>,
+    pub(crate) protected: &'c [Range<usize>],
+    pub(crate) input: &'c mut dyn InputProvider,
+    pub(crate) output: &'c mut dyn OutputSink,
+}
+
+impl<'c, T: CellKind> VmContext<'c, T> {
+    /// The index of the cell under the head
+    pub fn head(&self) -> usize {
+        *self.head
+    }
+
+    /// Move the head to another cell. The tape is grown if needed and allowed.
+    pub fn move_head(&mut self, cell: usize) -> Result<(), ExtensionError> {
+        if cell >= self.cells.len() {
+            if !self.tape_can_grow {
+                return Err(format!("cell {} is beyond the end of the tape", cell).into());
+            }
+            if let Some(max_cells) = self.max_cells.filter(|max_cells| cell >= *max_cells) {
+                return Err(
+                    format!("cell {} is beyond the limit of {} cells", cell, max_cells).into(),
+                );
+            }
+            self.cells.resize(cell + 1, T::default());
+        }
+        *self.head = cell;
+        Ok(())
+    }
+
+    /// The whole tape
+    pub fn tape(&self) -> &[T] {
+        self.cells
+    }
+
+    /// The cell under the head
+    pub fn cell(&self) -> &T {
+        &self.cells[*self.head]
+    }
+
+    /// The cell under the head, for changing. Fails if the cell is write-protected.
+    pub fn cell_mut(&mut self) -> Result<&mut T, ExtensionError> {
+        let head = *self.head;
+        if self.protected.iter().any(|range| range.contains(&head)) {
+            return Err(format!("cell {} is write-protected", head).into());
+        }
+        Ok(&mut self.cells[head])
+    }
+
+    /// The program's input
+    pub fn input(&mut self) -> &mut dyn InputProvider {
+        self.input
+    }
+
+    /// The program's output
+    pub fn output(&mut self) -> &mut dyn OutputSink {
+        self.output
+    }
+}
@@ -0,0 +1,100 @@
+//! Reasons that a [crate::VirtualMachine] can stop running without an error, and the limits that
+//! can make it do so.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Why the [crate::VirtualMachine] stopped running. Apart from [HaltReason::Completed], the
+/// machine is left in a state where calling [crate::VirtualMachine::interpret] again carries on
+/// from the instruction that would have run next.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HaltReason {
+    /// The program counter ran off the end of the program
+    Completed,
+    /// The maximum number of instructions for this run was executed
+    InstructionLimit,
+    /// The time allowed for this run ran out
+    Timeout,
+    /// The program tried to output more bytes than allowed for this run
+    OutputLimit,
+    /// Execution was stopped at the request of the embedder
+    Interrupted,
+    /// The hook set with [crate::VirtualMachine::set_pre_step_hook] asked to pause
+    Paused,
+    /// The next instruction has a breakpoint on it, added with
+    /// [crate::VirtualMachine::add_breakpoint]
+    BreakpointHit {
+        /// Index of the instruction
+        program_counter: usize,
+        /// Line of the instruction, 1-indexed
+        line_num: usize,
+        /// Column of the instruction, 1-indexed
+        column_num: usize,
+    },
+    /// The program wants to read a byte, but the input has nothing available yet (it returned
+    /// [std::io::ErrorKind::WouldBlock])
+    NeedsInput,
+    /// A loop was about to start more iterations than allowed by [Limits::max_loop_iterations]
+    LoopIterationLimit {
+        /// Line of the loop's `[`, 1-indexed
+        line_num: usize,
+        /// Column of the loop's `[`, 1-indexed
+        column_num: usize,
+        /// How many iterations of the loop had started
+        iterations: u64,
+    },
+}
+
+impl Display for HaltReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            HaltReason::Completed => "program completed",
+            HaltReason::InstructionLimit => "instruction limit reached",
+            HaltReason::Timeout => "time limit reached",
+            HaltReason::OutputLimit => "output limit reached",
+            HaltReason::Interrupted => "interrupted",
+            HaltReason::Paused => "paused by a hook",
+            HaltReason::NeedsInput => "waiting for input",
+            HaltReason::BreakpointHit {
+                line_num,
+                column_num,
+                ..
+            } => {
+                return write!(
+                    f,
+                    "breakpoint hit at line {} column {}",
+                    line_num, column_num
+                )
+            }
+            HaltReason::LoopIterationLimit {
+                line_num,
+                column_num,
+                iterations,
+            } => {
+                return write!(
+                    f,
+                    "loop at line {} column {} reached the limit of {} iterations",
+                    line_num, column_num, iterations
+                )
+            }
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+/// Limits on a single call to [crate::VirtualMachine::interpret]. Anything left as `None` is
+/// unlimited.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Limits {
+    /// Maximum number of instructions to execute
+    pub max_instructions: Option<u64>,
+    /// Maximum wall-clock time to run for. This is checked every few instructions rather than
+    /// before each one, so it may be overshot very slightly.
+    pub timeout: Option<Duration>,
+    /// Maximum number of bytes to output
+    pub max_output: Option<u64>,
+    /// Maximum number of iterations any one loop may run each time it is entered. Nested loops
+    /// start counting again each time their enclosing loop comes round.
+    pub max_loop_iterations: Option<u64>,
+}
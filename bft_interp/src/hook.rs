@@ -0,0 +1,64 @@
+//! A callback run before each instruction, set with [crate::VirtualMachine::set_pre_step_hook].
+//! Unlike an [crate::Observer], which is only told what happened, the hook decides whether the
+//! instruction runs at all, so tracers, profilers and debuggers can be built on it outside this
+//! crate.
+
+use bft_types::LocalisedInstruction;
+
+/// What the machine should do after the hook has looked at the next instruction
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HookAction {
+    /// Execute the instruction as normal
+    Continue,
+    /// Stop before the instruction with [crate::HaltReason::Paused]. Interpreting again carries
+    /// on from it, without calling the hook for it a second time.
+    Pause,
+    /// Stop before the instruction with [crate::VMError::Aborted]
+    Abort,
+}
+
+/// A function run before each instruction
+pub type PreStepHook<'a, T> =
+    Box<dyn FnMut(&VmView<'_, T>, &LocalisedInstruction) -> HookAction + 'a>;
+
+/// What a hook can see of the machine: everything about where it is, but nothing it can change
+#[derive(Debug)]
+pub struct VmView<'v, T> {
+    pub(crate) cells: &'v [T],
+    pub(crate) head: usize,
+    pub(crate) origin: usize,
+    pub(crate) program_counter: usize,
+    pub(crate) clock: u64,
+}
+
+impl<'v, T> VmView<'v, T> {
+    /// The whole tape, as in [crate::VirtualMachine::tape]
+    pub fn tape(&self) -> &'v [T] {
+        self.cells
+    }
+
+    /// Index in [VmView::tape] of the cell under the head
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    /// Index in [VmView::tape] of cell 0, as in [crate::VirtualMachine::origin]
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    /// The cell under the head
+    pub fn cell(&self) -> &'v T {
+        &self.cells[self.head]
+    }
+
+    /// Index of the instruction about to be executed
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    /// The number of instructions executed so far, as in [crate::VirtualMachine::clock]
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+}
@@ -0,0 +1,67 @@
+//! Running a [crate::VirtualMachine] a step of I/O at a time, with
+//! [crate::VirtualMachine::run_until_io], for embedders such as GUIs and games that can't hand the
+//! machine a blocking stream and need to deal with each byte themselves.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::HaltReason;
+
+/// Why [crate::VirtualMachine::run_until_io] handed control back
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IoRequest {
+    /// The program wants to read a byte. Give it one with
+    /// [crate::VirtualMachine::provide_input], or end its input with
+    /// [crate::VirtualMachine::provide_eof], then call [crate::VirtualMachine::run_until_io] again.
+    Input,
+    /// The program wrote this byte
+    Output(u8),
+    /// The program stopped, as [crate::VirtualMachine::interpret] would have
+    Halted(HaltReason),
+}
+
+/// The input given to a machine for its next `,`
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum PendingInput {
+    /// Nothing yet: the next `,` has to wait for the caller
+    #[default]
+    Nothing,
+    /// A byte for the next `,`
+    Byte(u8),
+    /// The input has ended, for this and every later `,`
+    Eof,
+}
+
+impl Read for PendingInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            _ if buf.is_empty() => Ok(0),
+            PendingInput::Nothing => Err(ErrorKind::WouldBlock.into()),
+            PendingInput::Byte(byte) => {
+                buf[0] = byte;
+                *self = PendingInput::Nothing;
+                Ok(1)
+            }
+            PendingInput::Eof => Ok(0),
+        }
+    }
+}
+
+/// Holds the byte written by a `.`, until it is handed to the caller
+#[derive(Debug, Default)]
+pub(crate) struct OutputSlot(pub(crate) Option<u8>);
+
+impl Write for OutputSlot {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match buf.first() {
+            Some(&byte) => {
+                self.0 = Some(byte);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
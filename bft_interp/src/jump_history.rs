@@ -0,0 +1,108 @@
+//! A record of the most recent jumps taken by a [crate::VirtualMachine], for giving context when
+//! something goes wrong.
+
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::num::NonZeroUsize;
+
+use bft_types::LocalisedInstruction;
+
+/// A single jump taken by the machine
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TakenJump {
+    /// The machine's clock when the jump was taken (see [crate::VirtualMachine::clock])
+    pub clock: u64,
+    /// Index of the jump instruction
+    pub from: usize,
+    /// Index of the instruction jumped to
+    pub to: usize,
+    /// The jump instruction itself, with its location in the source
+    pub instruction: LocalisedInstruction,
+}
+
+impl Display for TakenJump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] {}:{} jumped from instruction {} to {}",
+            self.clock,
+            self.instruction.line_num(),
+            self.instruction.column_num(),
+            self.from,
+            self.to
+        )
+    }
+}
+
+/// Ring buffer holding the last few [TakenJump]s. Once full, each new jump pushes out the oldest.
+#[derive(Debug, Clone)]
+pub struct JumpHistory {
+    jumps: VecDeque<TakenJump>,
+    capacity: NonZeroUsize,
+}
+
+impl JumpHistory {
+    /// Create an empty history that keeps up to `capacity` jumps
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            jumps: VecDeque::with_capacity(capacity.get()),
+            capacity,
+        }
+    }
+
+    /// Forget every jump recorded so far
+    pub(crate) fn clear(&mut self) {
+        self.jumps.clear();
+    }
+
+    /// Add a jump, dropping the oldest one if the history is full
+    pub(crate) fn record(&mut self, jump: TakenJump) {
+        if self.jumps.len() == self.capacity.get() {
+            self.jumps.pop_front();
+        }
+        self.jumps.push_back(jump);
+    }
+
+    /// The recorded jumps, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &TakenJump> {
+        self.jumps.iter()
+    }
+
+    /// The number of jumps currently recorded
+    pub fn len(&self) -> usize {
+        self.jumps.len()
+    }
+
+    /// Whether no jumps have been recorded yet
+    pub fn is_empty(&self) -> bool {
+        self.jumps.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::Instruction;
+
+    fn jump(clock: u64) -> TakenJump {
+        TakenJump {
+            clock,
+            from: 3,
+            to: 1,
+            instruction: LocalisedInstruction::new(Instruction::ConditionalJumpBackward, 1, 4),
+        }
+    }
+
+    // Does the history keep only the newest jumps once it is full?
+    #[test]
+    fn test_ring_buffer_drops_oldest() {
+        let mut history = JumpHistory::new(NonZeroUsize::new(2).unwrap());
+
+        history.record(jump(1));
+        history.record(jump(2));
+        history.record(jump(3));
+
+        let clocks: Vec<u64> = history.iter().map(|jump| jump.clock).collect();
+        assert_eq!(clocks, vec![2, 3]);
+    }
+}
@@ -0,0 +1,295 @@
+//! Symbolic names for regions of the tape, loaded from a layout file, and tape dumps that use them.
+//!
+//! A layout file has one region per line, made of a name and either a single cell index or a
+//! range of cells. Blank lines and anything after a `#` are ignored:
+//!
+//! ```text
+//! # registers
+//! counter 0
+//! flag    1
+//! buffer  2..34    # 32 cells, shown as buffer[0] to buffer[31]
+//! ```
+
+use std::fmt::{Display, Write};
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use thiserror::Error;
+
+use bft_types::messages::{self, Localise, Message};
+
+use crate::{CellKind, RecentCell};
+
+/// Problems loading a layout file
+#[derive(Debug, Error)]
+pub enum LayoutError {
+    /// Something went wrong reading the file
+    IoError(std::io::Error),
+    /// A line of the file could not be understood
+    InvalidLine { line_num: usize, reason: String },
+    /// Two regions claim the same cell
+    Overlap { first: String, second: String },
+}
+
+impl Localise for LayoutError {
+    fn message(&self) -> Message {
+        match self {
+            LayoutError::IoError(error) => Message::new(
+                messages::LAYOUT_FILE_ERROR,
+                vec![("error", error.to_string())],
+            ),
+            LayoutError::InvalidLine { line_num, reason } => Message::new(
+                messages::LAYOUT_INVALID_LINE,
+                vec![("line", line_num.to_string()), ("reason", reason.clone())],
+            ),
+            LayoutError::Overlap { first, second } => Message::new(
+                messages::LAYOUT_OVERLAP,
+                vec![("first", first.clone()), ("second", second.clone())],
+            ),
+        }
+    }
+}
+
+impl Display for LayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+/// A named range of cells
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Region {
+    /// Name shown in place of the cell indexes
+    pub name: String,
+    /// The cells covered by the region
+    pub cells: Range<usize>,
+}
+
+/// A set of non-overlapping named [Region]s of the tape
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct TapeLayout {
+    /// Regions, sorted by their first cell
+    regions: Vec<Region>,
+}
+
+impl TapeLayout {
+    /// Load a layout from a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<TapeLayout, LayoutError> {
+        let text = fs::read_to_string(path).map_err(LayoutError::IoError)?;
+        Self::parse(&text)
+    }
+
+    /// Parse a layout from the text of a layout file
+    ///
+    /// ```
+    ///# use bft_interp::layout::TapeLayout;
+    ///# fn main() -> Result<(), bft_interp::layout::LayoutError>{
+    /// let layout = TapeLayout::parse("counter 0\nbuffer 1..9")?;
+    ///
+    /// assert_eq!(layout.name_of(0).as_deref(), Some("counter"));
+    /// assert_eq!(layout.name_of(3).as_deref(), Some("buffer[2]"));
+    /// assert_eq!(layout.name_of(9), None);
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn parse(text: &str) -> Result<TapeLayout, LayoutError> {
+        let mut regions = Vec::new();
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = |reason: &str| LayoutError::InvalidLine {
+                line_num: line_index + 1,
+                reason: reason.to_string(),
+            };
+            let mut parts = line.split_whitespace();
+            let (Some(name), Some(cells), None) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(invalid(
+                    "expected a name followed by a cell or range of cells",
+                ));
+            };
+            let cells = parse_cells(cells).ok_or_else(|| invalid("bad cell or range of cells"))?;
+
+            regions.push(Region {
+                name: name.to_string(),
+                cells,
+            });
+        }
+
+        regions.sort_by_key(|region| region.cells.start);
+        for pair in regions.windows(2) {
+            if pair[1].cells.start < pair[0].cells.end {
+                return Err(LayoutError::Overlap {
+                    first: pair[0].name.clone(),
+                    second: pair[1].name.clone(),
+                });
+            }
+        }
+
+        Ok(Self { regions })
+    }
+
+    /// The regions in this layout, in tape order
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// The region containing the given cell, if any
+    pub fn region_of(&self, cell: usize) -> Option<&Region> {
+        let index = self
+            .regions
+            .partition_point(|region| region.cells.end <= cell);
+        self.regions
+            .get(index)
+            .filter(|region| region.cells.contains(&cell))
+    }
+
+    /// The symbolic name of a cell: the region name for single-cell regions, or the region name
+    /// with an index into it for larger ones
+    pub fn name_of(&self, cell: usize) -> Option<String> {
+        self.region_of(cell).map(|region| {
+            if region.cells.len() == 1 {
+                region.name.clone()
+            } else {
+                format!("{}[{}]", region.name, cell - region.cells.start)
+            }
+        })
+    }
+}
+
+/// Parse `N`, `N..M` or `N..=M` into a range of cells
+fn parse_cells(cells: &str) -> Option<Range<usize>> {
+    let range = match cells.split_once("..") {
+        None => {
+            let cell: usize = cells.parse().ok()?;
+            cell..cell + 1
+        }
+        Some((start, end)) => match end.strip_prefix('=') {
+            Some(end) => start.parse().ok()?..end.parse::<usize>().ok()? + 1,
+            None => start.parse().ok()?..end.parse().ok()?,
+        },
+    };
+    (!range.is_empty()).then_some(range)
+}
+
+/// Render the values of a range of cells, one per line, using the names from the layout if one is
+/// given and marking the cell under the head. Cells beyond the end of the tape are left out.
+///
+/// ```
+///# use bft_interp::layout::{dump_tape, TapeLayout};
+///# fn main() -> Result<(), bft_interp::layout::LayoutError>{
+/// let layout = TapeLayout::parse("counter 0")?;
+/// let cells: Vec<u8> = vec![3, 72, 0];
+///
+/// let dump = dump_tape(&cells, 1, 0..2, Some(&layout));
+/// assert_eq!(dump, "     0 counter        3\n     1               72  <- head\n");
+///# Ok(())
+///# }
+/// ```
+pub fn dump_tape<T: CellKind>(
+    cells: &[T],
+    head: usize,
+    range: Range<usize>,
+    layout: Option<&TapeLayout>,
+) -> String {
+    let mut dump = String::new();
+    let end = range.end.min(cells.len());
+
+    for (index, cell) in cells.iter().enumerate().take(end).skip(range.start) {
+        let name = layout.and_then(|layout| layout.name_of(index));
+        let _ = write!(
+            dump,
+            "{:>6} {:<12} {:>3}",
+            index,
+            name.unwrap_or_default(),
+            cell.get_value()
+        );
+        if index == head {
+            dump.push_str("  <- head");
+        }
+        dump.push('\n');
+    }
+
+    dump
+}
+
+/// Shades used by [recent_changes], from the most to the least recently changed
+const FADE: [char; 4] = ['█', '▓', '▒', '░'];
+
+/// Render the cells that changed in the last `window` instructions, most recent first. Each is
+/// shaded by how long ago it changed, fading from solid to light across the window, and labelled
+/// with its name from the layout if it has one.
+pub fn recent_changes(recent: &[RecentCell], window: u64, layout: Option<&TapeLayout>) -> String {
+    let mut view = String::new();
+    for cell in recent {
+        let fade = cell.age.saturating_mul(FADE.len() as u64) / window.max(1);
+        let shade = FADE[(fade as usize).min(FADE.len() - 1)];
+        let name = layout.and_then(|layout| layout.name_of(cell.cell));
+        let _ = writeln!(
+            view,
+            "{} {:>6} {:<12} {:>3}  changed {} instructions ago ({} times)",
+            shade,
+            cell.cell,
+            name.unwrap_or_default(),
+            cell.value,
+            cell.age,
+            cell.changes
+        );
+    }
+    view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    // Are comments and blank lines skipped, and the regions found in tape order?
+    #[test]
+    fn test_parse_layout() {
+        let layout =
+            TapeLayout::parse("# header\n\nbuffer 4..=7 # four cells\ncounter 0\n").unwrap();
+
+        assert_eq!(
+            layout.regions(),
+            &[
+                Region {
+                    name: "counter".to_string(),
+                    cells: 0..1
+                },
+                Region {
+                    name: "buffer".to_string(),
+                    cells: 4..8
+                }
+            ]
+        );
+        assert_eq!(layout.name_of(7).as_deref(), Some("buffer[3]"));
+        assert_eq!(layout.name_of(2), None);
+    }
+
+    // Are bad lines reported with their line number?
+    #[test]
+    fn test_parse_layout_bad_line() {
+        assert_matches!(
+            TapeLayout::parse("counter 0\nbuffer\n"),
+            Err(LayoutError::InvalidLine { line_num: 2, .. })
+        );
+        assert_matches!(
+            TapeLayout::parse("empty 3..3"),
+            Err(LayoutError::InvalidLine { line_num: 1, .. })
+        );
+    }
+
+    // Are overlapping regions refused?
+    #[test]
+    fn test_parse_layout_overlap() {
+        assert_matches!(
+            TapeLayout::parse("a 0..4\nb 3"),
+            Err(LayoutError::Overlap { .. })
+        );
+    }
+}
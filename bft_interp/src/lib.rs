@@ -4,51 +4,435 @@
 //! [BfProgram] it was given.
 
 use std::{
-    io::{Read, Write},
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::{self, ErrorKind, Write},
     num::NonZeroUsize,
+    ops::Range,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
+use bft_types::assertion::AssertionCheck;
+use bft_types::messages::{self, Localise, Message};
+use bft_types::optimize::{Op, OptLevel, OptimizedProgram};
 use bft_types::{BfProgram, Instruction, LocalisedInstruction};
 
+#[cfg(feature = "bignum")]
+pub mod bignum;
+pub mod conformance;
+pub mod embed;
+pub mod layout;
+pub mod lockstep;
+pub mod raw_mode;
+pub mod scheduler;
+pub mod terminal;
+
+#[cfg(feature = "async")]
+mod async_io;
+mod breakpoint;
+mod byte_io;
+mod cancel;
+mod cell_journal;
+mod cycles;
+mod extension;
+mod halt;
+mod hook;
+mod io_request;
+mod jump_history;
+mod memory;
+mod observer;
+mod snapshot;
+mod stats;
+
+pub use breakpoint::{Breakpoint, BreakpointError};
+pub use byte_io::{FnInput, FnOutput, InputProvider, OutputSink};
+pub use cancel::CancelToken;
+pub use cell_journal::{CellChange, CellJournal, RecentCell};
+pub use cycles::{CycleCosts, VirtualClock};
+pub use extension::{ExtensionError, ExtensionHandler, VmContext};
+pub use halt::{HaltReason, Limits};
+pub use hook::{HookAction, PreStepHook, VmView};
+pub use io_request::IoRequest;
+pub use jump_history::{JumpHistory, TakenJump};
+pub use memory::{MemoryBudget, MemoryError, Subsystem};
+pub use observer::Observer;
+pub use snapshot::{SnapshotError, TapeState, VmState};
+pub use stats::RunStats;
+
+use io_request::{OutputSlot, PendingInput};
+
+/// How many instructions to execute between checks of the clock when a timeout is set
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// How many cells either side of the head `#` shows
+const DEBUG_DUMP_RADIUS: usize = 8;
+
 /// Error types that the [VirtualMachine] can emit. In all cases, the [VMError] includes details of
 /// the [LocalisedInstruction] that caused it.
 #[derive(Debug, Error)]
 pub enum VMError {
-    /// The head ran off the start of the tape. Note that the tape may never be extended at the start.
-    #[error("Head underrun error occured at line {} column {}", .0.line_num(), .0.column_num())]
+    /// The head ran off the start of the tape. The tape is only extended at the start if it was
+    /// made bidirectional with [VirtualMachine::with_bidirectional_tape].
     HeadUnderrun(LocalisedInstruction),
     /// The head ran off the end of the (non-auto-extending) tape.
-    #[error("Head overrun error occured at line {} column {}", .0.line_num(), .0.column_num())]
     HeadOverrun(LocalisedInstruction),
     /// Reading a byte from stdio failed. The text of the underlying IO error is included.
-    #[error("Read error occured at line {} column {}: {}", .0.line_num(), .0.column_num(), .1)]
     ReadError(LocalisedInstruction, std::io::Error),
     /// Writing a byte from stdio failed. The text of the underlying IO error is included.
-    #[error("Write error occured at line {} column {}: {}", .0.line_num(), .0.column_num(), .1)]
     WriteError(LocalisedInstruction, std::io::Error),
+    /// The program tried to change a cell in a write-protected region of the tape. The index of
+    /// the cell is included.
+    WriteProtected(LocalisedInstruction, usize),
+    /// An extension instruction failed, or has no handler registered. The handler's error is
+    /// included.
+    ExtensionFailed(LocalisedInstruction, ExtensionError),
+    /// An `@assert` directive did not hold. Gives the location of the directive, the condition
+    /// that failed and the value actually found.
+    AssertionFailed {
+        line_num: usize,
+        column_num: usize,
+        expected: AssertionCheck,
+        actual: usize,
+    },
+    /// A `+` would have taken a cell past its largest value, with [Arithmetic::Checked]
+    CellOverflow(LocalisedInstruction),
+    /// A `-` would have taken a cell below its smallest value, with [Arithmetic::Checked]
+    CellUnderflow(LocalisedInstruction),
+    /// The tape would have grown beyond the limit set with [VirtualMachine::with_max_cells]. The
+    /// limit is included.
+    TapeLimitExceeded(LocalisedInstruction, usize),
+    /// The hook set with [VirtualMachine::set_pre_step_hook] returned [HookAction::Abort] before
+    /// the instruction was executed
+    Aborted(LocalisedInstruction),
+    /// The tape, a snapshot or the traces would have taken the machine over the budget set with
+    /// [VirtualMachine::with_memory_budget]. Says which of them it was.
+    MemoryLimitExceeded(MemoryError),
+}
+
+impl VMError {
+    /// The instruction that caused the error, if it was caused by one
+    pub fn instruction(&self) -> Option<&LocalisedInstruction> {
+        match self {
+            VMError::HeadUnderrun(instruction)
+            | VMError::HeadOverrun(instruction)
+            | VMError::ReadError(instruction, _)
+            | VMError::WriteError(instruction, _)
+            | VMError::WriteProtected(instruction, _)
+            | VMError::ExtensionFailed(instruction, _)
+            | VMError::CellOverflow(instruction)
+            | VMError::CellUnderflow(instruction)
+            | VMError::TapeLimitExceeded(instruction, _)
+            | VMError::Aborted(instruction) => Some(instruction),
+            VMError::AssertionFailed { .. } | VMError::MemoryLimitExceeded(_) => None,
+        }
+    }
+}
+
+impl Localise for VMError {
+    fn message(&self) -> Message {
+        let at = |instruction: &LocalisedInstruction| {
+            vec![
+                ("line", instruction.line_num().to_string()),
+                ("column", instruction.column_num().to_string()),
+            ]
+        };
+        let with = |mut args: Vec<(&'static str, String)>, name, value: String| {
+            args.push((name, value));
+            args
+        };
+        match self {
+            VMError::HeadUnderrun(instruction) => {
+                Message::new(messages::HEAD_UNDERRUN, at(instruction))
+            }
+            VMError::HeadOverrun(instruction) => {
+                Message::new(messages::HEAD_OVERRUN, at(instruction))
+            }
+            VMError::ReadError(instruction, error) => Message::new(
+                messages::READ_ERROR,
+                with(at(instruction), "error", error.to_string()),
+            ),
+            VMError::WriteError(instruction, error) => Message::new(
+                messages::WRITE_ERROR,
+                with(at(instruction), "error", error.to_string()),
+            ),
+            VMError::WriteProtected(instruction, cell) => Message::new(
+                messages::WRITE_PROTECTED,
+                with(at(instruction), "cell", cell.to_string()),
+            ),
+            VMError::ExtensionFailed(instruction, error) => Message::new(
+                messages::EXTENSION_FAILED,
+                with(at(instruction), "error", error.to_string()),
+            ),
+            VMError::CellOverflow(instruction) => {
+                Message::new(messages::CELL_OVERFLOW, at(instruction))
+            }
+            VMError::CellUnderflow(instruction) => {
+                Message::new(messages::CELL_UNDERFLOW, at(instruction))
+            }
+            VMError::TapeLimitExceeded(instruction, max_cells) => Message::new(
+                messages::TAPE_LIMIT_EXCEEDED,
+                with(at(instruction), "cells", max_cells.to_string()),
+            ),
+            VMError::Aborted(instruction) => Message::new(messages::ABORTED, at(instruction)),
+            VMError::MemoryLimitExceeded(error) => error.message(),
+            VMError::AssertionFailed {
+                line_num,
+                column_num,
+                expected,
+                actual,
+            } => Message::new(
+                messages::ASSERTION_FAILED,
+                vec![
+                    ("line", line_num.to_string()),
+                    ("column", column_num.to_string()),
+                    ("expected", expected.to_string()),
+                    ("actual", actual.to_string()),
+                ],
+            ),
+        }
+    }
+}
+
+impl Display for VMError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+/// When the [VirtualMachine] flushes its output
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum FlushPolicy {
+    /// After every byte
+    #[default]
+    EveryByte,
+    /// After every newline, before waiting for input, and when the machine stops. Prompts and
+    /// lines appear as soon as they are complete, without paying for a flush on every byte.
+    Line,
+    /// Never. Whoever owns the output flushes it.
+    Manual,
+}
+
+/// What `+` and `-` do when a cell is already at the end of its range
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Arithmetic {
+    /// Wrap round to the other end of the range, so 255 + 1 is 0 for a `u8` cell
+    #[default]
+    Wrapping,
+    /// Stay at the end of the range, so 255 + 1 is 255 for a `u8` cell
+    Saturating,
+    /// Stop with [VMError::CellOverflow] or [VMError::CellUnderflow]
+    Checked,
+}
+
+/// What `,` does once input has run out
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum EofBehavior {
+    /// Stop with [VMError::ReadError]
+    #[default]
+    Error,
+    /// Set the cell to 0
+    SetZero,
+    /// Set the cell to its largest value, such as 255 for a `u8` cell, or to -1 for a signed
+    /// cell, as programs written for EOF=-1 expect
+    SetMax,
+    /// Leave the cell as it was
+    LeaveUnchanged,
+}
+
+/// What happened in one call to [VirtualMachine::step]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Step {
+    /// The instruction was executed. `finished` is set if it was the program's last, so that the
+    /// program has now completed.
+    Executed {
+        instruction: LocalisedInstruction,
+        finished: bool,
+    },
+    /// Nothing was executed: either the program had already completed, or the next instruction is
+    /// a `,` with no input available yet ([HaltReason::NeedsInput])
+    Halted(HaltReason),
+}
+
+/// How a call to [VirtualMachine::interpret_resumable] or [VirtualMachine::resume] ended
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RunOutcome<T> {
+    /// The program ran to the end
+    Completed,
+    /// The program stopped early, for a [Limits] budget, a timeout, a
+    /// [CancelToken::cancel] or a lack of input. `state` carries on from there with
+    /// [VirtualMachine::resume], on this machine or a new one for the same program.
+    Paused {
+        reason: HaltReason,
+        state: VmState<T>,
+    },
+}
+
+/// Problems carrying on from a [VmState] with [VirtualMachine::resume]
+#[derive(Debug, Error)]
+pub enum ResumeError {
+    /// The state does not fit the machine
+    #[error(transparent)]
+    State(#[from] SnapshotError),
+    /// The program failed after resuming
+    #[error(transparent)]
+    Run(#[from] VMError),
 }
 
 /// Represents a virtual machine with a memory tape of cells. Accepts a type T for the tape,
 /// provided [CellKind] is implemented for T
-#[derive(Debug)]
 pub struct VirtualMachine<'a, T> {
     cells: Vec<T>,
+    /// The number of cells the tape started with, which [VirtualMachine::reset] returns it to
+    initial_tape_len: usize,
     head: usize,
     tape_can_grow: bool,
+    tape_can_grow_left: bool,
+    circular: bool,
+    /// The most cells a growing tape may have, if limited
+    max_cells: Option<usize>,
+    /// Shared with everything else the budget covers, if there is one
+    memory_budget: Option<MemoryBudget>,
+    /// Index in `cells` of cell 0. Only ever above zero once a bidirectional tape has grown to
+    /// the left.
+    origin: usize,
     program_counter: usize,
     program: &'a BfProgram,
+    limits: Limits,
+    cancel_token: CancelToken,
+    clock: u64,
+    observers: Vec<Box<dyn Observer + 'a>>,
+    jump_history: Option<JumpHistory>,
+    cell_journal: Option<CellJournal>,
+    stats: RunStats,
+    protected: Vec<Range<usize>>,
+    extensions: HashMap<char, ExtensionHandler<'a, T>>,
+    pre_step_hook: Option<PreStepHook<'a, T>>,
+    /// The program counter and clock when the hook last paused, so that it is not asked about the
+    /// same instruction again when the machine carries on
+    hook_paused_at: Option<(usize, u64)>,
+    /// Indexes of the instructions to stop before
+    breakpoints: HashSet<usize>,
+    /// As `hook_paused_at`, for the last breakpoint hit
+    breakpoint_paused_at: Option<(usize, u64)>,
+    /// Whether the observers have been told the program completed, so that stepping again at the
+    /// end does not tell them twice
+    completion_reported: bool,
+    /// Input given with [VirtualMachine::provide_input] for [VirtualMachine::run_until_io]
+    pending_input: PendingInput,
+    /// The storage byte of Extended Brainfuck Type I, set by `$` and used by `!`, `^`, `&` and `|`
+    storage: u8,
+    /// Where `#` shows the head and the cells around it
+    debug_output: Box<dyn Write + 'a>,
+    flush_policy: FlushPolicy,
+    arithmetic: Arithmetic,
+    eof_behavior: EofBehavior,
+    /// Iterations started by each loop since it was last entered, indexed by loop number. Only
+    /// kept while [Limits::max_loop_iterations] is set.
+    loop_iterations: Vec<u64>,
+    /// The program lowered to ops, whose runs are executed in one step, if asked for with
+    /// [VirtualMachine::with_optimizations]
+    optimized: Option<OptimizedProgram>,
+}
+
+impl<'a, T: std::fmt::Debug> std::fmt::Debug for VirtualMachine<'a, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VirtualMachine")
+            .field("cells", &self.cells)
+            .field("head", &self.head)
+            .field("tape_can_grow", &self.tape_can_grow)
+            .field("tape_can_grow_left", &self.tape_can_grow_left)
+            .field("circular", &self.circular)
+            .field("max_cells", &self.max_cells)
+            .field("memory_budget", &self.memory_budget)
+            .field("origin", &self.origin)
+            .field("program_counter", &self.program_counter)
+            .field("program", &self.program)
+            .field("limits", &self.limits)
+            .field("clock", &self.clock)
+            .field("observers", &self.observers.len())
+            .field("jump_history", &self.jump_history)
+            .field("cell_journal", &self.cell_journal)
+            .field("stats", &self.stats)
+            .field("protected", &self.protected)
+            .field("extensions", &self.extensions.keys())
+            .field("pre_step_hook", &self.pre_step_hook.is_some())
+            .field("breakpoints", &self.breakpoints)
+            .field("flush_policy", &self.flush_policy)
+            .field("arithmetic", &self.arithmetic)
+            .field("eof_behavior", &self.eof_behavior)
+            .field(
+                "optimized",
+                &self.optimized.as_ref().map(OptimizedProgram::fused),
+            )
+            .finish()
+    }
 }
 
-/// Trait requirements for the [VirtualMachine] tape cells
+/// Trait requirements for the [VirtualMachine] tape cells.
+///
+/// Input and output are always a byte at a time, whatever the width of the cells. For the
+/// unsigned cells implemented here (`u8`, `u16` and `u32`), a byte that is read in is stored as
+/// its value, and a cell that is written out gives its lowest 8 bits, so a `u16` cell holding 321
+/// outputs 65 (`'A'`). Arithmetic wraps at the width of the cell, not at 256.
+///
+/// Signed cells (`i8` and `i32`) let a program work with negative values. They output the lowest
+/// 8 bits of their two's complement value, so -1 outputs 255 and -191 outputs 65. An `i32` cell
+/// stores a byte that is read in as its value, from 0 to 255; an `i8` cell cannot hold values
+/// above 127, so it stores the byte's two's complement reading instead, and 255 is read in as -1.
+/// Either way, writing out a cell that was just read in gives back the same byte.
+///
+/// With the `bignum` feature, `bignum::BigCell` holds integers of any size, and never wraps.
 pub trait CellKind: Clone + Default {
     /// Increment the given value, wrapping on overflow
     fn wrapping_increment(&mut self);
-    /// Increment the given value, wrapping on underflow
+    /// Decrement the given value, wrapping on underflow
     fn wrapping_decrement(&mut self);
-    /// Sets the value of the cell
+    /// Increment the given value, unless it is already the largest the cell can hold. Returns
+    /// whether it was incremented.
+    fn checked_increment(&mut self) -> bool;
+    /// Decrement the given value, unless it is already the smallest the cell can hold. Returns
+    /// whether it was decremented.
+    fn checked_decrement(&mut self) -> bool;
+    /// Add `amount` to the value, which may be negative, wrapping as
+    /// [CellKind::wrapping_increment] and [CellKind::wrapping_decrement] would. This is how the
+    /// machine runs a whole [Op::Add] at once; the default takes one step at a time.
+    fn wrapping_add_by(&mut self, amount: isize) {
+        for _ in 0..amount.unsigned_abs() {
+            if amount > 0 {
+                self.wrapping_increment();
+            } else {
+                self.wrapping_decrement();
+            }
+        }
+    }
+    /// How many times [CellKind::wrapping_decrement] (or [CellKind::wrapping_increment], if `up`
+    /// is set) takes the value to zero, which is how the machine runs a whole [Op::SetZero] at
+    /// once. `None` if it never does, or can't be worked out without taking the steps, as by
+    /// default.
+    fn steps_to_zero(&self, up: bool) -> Option<u64> {
+        let _ = up;
+        None
+    }
+    /// The index of the first zero cell in `cells`, or with `right` unset the last, which is how
+    /// the machine runs a whole [Op::Scan] at once
+    fn find_zero(cells: &[Self], right: bool) -> Option<usize> {
+        if right {
+            cells.iter().position(Self::is_zero)
+        } else {
+            cells.iter().rposition(Self::is_zero)
+        }
+    }
+    /// Set the value to `change(value, storage)`, for the Extended Type I instructions that
+    /// change a cell, with the value's bits and the storage byte both widened to a `u64`. Cells of
+    /// a fixed width change all their bits; by default only the byte [CellKind::get_value] reads
+    /// is changed.
+    fn change_bits(&mut self, change: impl FnOnce(u64, u64) -> u64, storage: u8) {
+        self.set_value(change(self.get_value().into(), storage.into()) as u8);
+    }
+    /// Sets the value of the cell from a byte of input
     fn set_value(&mut self, value: u8);
-    /// Gets the value of the cell
+    /// Gets the value of the cell as a byte of output
     fn get_value(&self) -> u8;
     /// Determine if the value of the cell is zero
     fn is_zero(&self) -> bool;
@@ -83,62 +467,1661 @@ where
 
         Self {
             cells: vec![T::default(); tape_size],
+            initial_tape_len: tape_size,
             head: 0,
             tape_can_grow,
+            tape_can_grow_left: false,
+            circular: false,
+            max_cells: None,
+            memory_budget: None,
+            origin: 0,
             program,
             program_counter: 0,
+            limits: Limits::default(),
+            cancel_token: CancelToken::new(),
+            clock: 0,
+            observers: Vec::new(),
+            jump_history: None,
+            cell_journal: None,
+            stats: RunStats::default(),
+            protected: Vec::new(),
+            extensions: HashMap::new(),
+            pre_step_hook: None,
+            hook_paused_at: None,
+            completion_reported: false,
+            breakpoints: HashSet::new(),
+            breakpoint_paused_at: None,
+            pending_input: PendingInput::default(),
+            storage: 0,
+            debug_output: Box::new(io::stderr()),
+            flush_policy: FlushPolicy::default(),
+            arithmetic: Arithmetic::default(),
+            eof_behavior: EofBehavior::default(),
+            loop_iterations: Vec::new(),
+            optimized: None,
         }
     }
 
-    /// Interprets the [BfProgram] the machine was instantiated with.
+    /// Choose when output is flushed. By default it is flushed after every byte.
+    pub fn with_flush_policy(mut self, flush_policy: FlushPolicy) -> Self {
+        self.flush_policy = flush_policy;
+        self
+    }
+
+    /// Let the tape grow to the left as well as the right, so that moving left of cell 0 reaches
+    /// cells -1, -2 and so on rather than failing with [VMError::HeadUnderrun]. New cells are
+    /// added at the front of [VirtualMachine::tape], and [VirtualMachine::origin] tracks where
+    /// cell 0 has moved to. Write protection and `@assert` directives still number cells from
+    /// cell 0, so cells to its left can never be protected or checked.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("left.bf", "+<<++")?;
+    ///
+    /// let mut bf_interpreter: VirtualMachine<u8> =
+    ///     VirtualMachine::new(&bf_program, None, false).with_bidirectional_tape();
+    /// bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    ///
+    /// assert_eq!(bf_interpreter.head_position(), -2);
+    /// let origin = bf_interpreter.origin();
+    /// assert_eq!(bf_interpreter.tape()[origin - 2..=origin], [2, 0, 1]);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_bidirectional_tape(mut self) -> Self {
+        self.tape_can_grow_left = true;
+        self
+    }
+
+    /// Stop the tape growing beyond `max_cells` cells, so that a program moving ever further along
+    /// an extensible or bidirectional tape fails with [VMError::TapeLimitExceeded] rather than
+    /// using up all the memory there is. This matters most when running programs that can't be
+    /// trusted. A tape that starts out larger than the limit is not shrunk, but cannot grow any
+    /// further.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{VMError, VirtualMachine};
+    ///# use std::io::{empty, sink};
+    ///# use std::num::NonZeroUsize;
+    ///#
+    /// let bf_program = BfProgram::new("runaway.bf", "+[>+]")?;
+    ///
+    /// let mut bf_interpreter: VirtualMachine<u8> =
+    ///     VirtualMachine::new(&bf_program, NonZeroUsize::new(10), true)
+    ///         .with_max_cells(NonZeroUsize::new(1000).unwrap());
+    /// let result = bf_interpreter.interpret(&mut empty(), &mut sink());
+    ///
+    /// assert!(matches!(result, Err(VMError::TapeLimitExceeded(_, 1000))));
+    /// assert_eq!(bf_interpreter.tape().len(), 1000);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_max_cells(mut self, max_cells: NonZeroUsize) -> Self {
+        self.max_cells = Some(max_cells.get());
+        self
+    }
+
+    /// Count the memory the machine holds against `budget`: the tape as it grows, the snapshot
+    /// taken by [VirtualMachine::interpret_resumable], and the jump history and cell journal as
+    /// they fill. Whichever would take the total over the limit stops the machine with
+    /// [VMError::MemoryLimitExceeded]. The budget may be shared with other machines, each given a
+    /// clone of it, or with anything else that reports to it. Cells are counted at their size in the tape, so a
+    /// `bignum::BigCell` holding a large number takes more than it is counted as.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{MemoryBudget, Subsystem, VMError, VirtualMachine};
+    ///# use std::io::{empty, sink};
+    ///# use std::num::NonZeroUsize;
+    ///#
+    /// let bf_program = BfProgram::new("runaway.bf", "+[>+]")?;
+    ///
+    /// let budget = MemoryBudget::new(4096);
+    /// let mut bf_interpreter: VirtualMachine<u16> =
+    ///     VirtualMachine::new(&bf_program, NonZeroUsize::new(10), true)
+    ///         .with_memory_budget(budget.clone());
+    /// let result = bf_interpreter.interpret(&mut empty(), &mut sink());
+    ///
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(VMError::MemoryLimitExceeded(error)) if error.subsystem == Subsystem::Tape
+    /// ));
+    /// assert_eq!(budget.held(Subsystem::Tape), 4096);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.memory_budget = Some(budget);
+        self
+    }
+
+    /// Join the ends of the tape, so that moving right from the last cell reaches cell 0 and moving
+    /// left from cell 0 reaches the last cell, rather than either failing. A circular tape never
+    /// grows, whether or not it was made extensible or bidirectional.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::io::{empty, sink};
+    ///# use std::num::NonZeroUsize;
+    ///#
+    /// let bf_program = BfProgram::new("around.bf", "<+>>+")?;
+    ///
+    /// let mut bf_interpreter: VirtualMachine<u8> =
+    ///     VirtualMachine::new(&bf_program, NonZeroUsize::new(3), false).with_circular_tape();
+    /// bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    ///
+    /// assert_eq!(bf_interpreter.tape(), [0, 1, 1]);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_circular_tape(mut self) -> Self {
+        self.circular = true;
+        self
+    }
+
+    /// Choose what `+` and `-` do at the ends of a cell's range. By default they wrap.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{Arithmetic, VMError, VirtualMachine};
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("below_zero.bf", "+--")?;
+    ///
+    /// let mut bf_interpreter: VirtualMachine<u8> =
+    ///     VirtualMachine::new(&bf_program, None, false).with_arithmetic(Arithmetic::Checked);
+    /// let result = bf_interpreter.interpret(&mut empty(), &mut sink());
+    ///
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(VMError::CellUnderflow(instruction)) if instruction.column_num() == 3
+    /// ));
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_arithmetic(mut self, arithmetic: Arithmetic) -> Self {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    /// Choose what `,` does once input has run out. By default it fails with
+    /// [VMError::ReadError].
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{EofBehavior, VirtualMachine};
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("read.bf", "+,")?;
+    ///
+    /// let mut bf_interpreter: VirtualMachine<u8> =
+    ///     VirtualMachine::new(&bf_program, None, false).with_eof_behavior(EofBehavior::SetMax);
+    /// bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    ///
+    /// assert_eq!(bf_interpreter.tape()[0], 255);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_eof_behavior(mut self, eof_behavior: EofBehavior) -> Self {
+        self.eof_behavior = eof_behavior;
+        self
+    }
+
+    /// Execute the ops that the program is lowered to at the given [OptLevel] (see
+    /// [BfProgram::optimize]) in one step rather than one instruction at a time: each run of
+    /// `+`, `-`, `>` or `<`, and at [OptLevel::Full] each `[-]` or `[+]` clear loop and each
+    /// `[>]` or `[<]` scan loop.
+    /// This only speeds [VirtualMachine::interpret] up: the tape, clock, [RunStats] and any error
+    /// are the same as without it. Runs are still executed an instruction at a time while something
+    /// watches each instruction (an observer, the pre-step hook, a breakpoint, the cell journal or
+    /// an `@assert` directive), and wherever one step would not stop in the same place, such as at
+    /// the edge of the tape or with [Arithmetic] other than wrapping.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_types::optimize::OptLevel;
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("runs.bf", "++++++++[>>++++<<-]>>+<<+++[+]>>[>]")?;
+    ///
+    /// let mut bf_interpreter: VirtualMachine<u8> =
+    ///     VirtualMachine::new(&bf_program, None, false).with_optimizations(OptLevel::Full);
+    /// bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    ///
+    /// assert_eq!(bf_interpreter.tape()[..4], [0, 0, 33, 0]);
+    /// assert_eq!(bf_interpreter.head(), 3);
+    /// assert_eq!(bf_interpreter.clock(), 92 + 5 + 1 + 2 * 253 + 2 + 1 + 2 * 1);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_optimizations(mut self, level: OptLevel) -> Self {
+        self.optimized = Some(self.program.optimize(level));
+        self
+    }
+
+    /// Register the handler for an extension instruction. Whenever the program reaches an
+    /// [Instruction::Extension] for `c`, the handler is called with a [VmContext] for the machine.
+    /// Registering a second handler for the same character replaces the first.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::{BfProgram, ParseOptions};
+    ///# use bft_interp::{VirtualMachine, VmContext};
+    ///# use std::io::Cursor;
+    /// let options = ParseOptions {
+    ///     extensions: vec!['*'],
+    ///     ..ParseOptions::default()
+    /// };
+    /// let bf_program = BfProgram::new_with_options("double.bf", "+++*.", &options)?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false)
+    ///     .with_extension('*', |context: &mut VmContext<u8>| {
+    ///         let cell = context.cell_mut()?;
+    ///         *cell = cell.wrapping_mul(2);
+    ///         Ok(())
+    ///     });
+    ///
+    /// let mut output = Vec::new();
+    /// bf_interpreter.interpret(&mut Cursor::new([]), &mut output)?;
+    /// assert_eq!(output, vec![6]);
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_extension(
+        mut self,
+        c: char,
+        handler: impl FnMut(&mut VmContext<'_, T>) -> Result<(), ExtensionError> + 'a,
+    ) -> Self {
+        self.extensions.insert(c, Box::new(handler));
+        self
+    }
+
+    /// Give the program a second output stream, for diagnostics that should not be mixed in with
+    /// its data. Each time the program reaches the extension instruction `c`, the cell under the
+    /// head is written to `channel` and flushed, just as `.` writes it to the output. The program
+    /// must have been parsed with `c` as an extension (see [bft_types::ParseOptions::extensions]);
+    /// `;` is the usual choice.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::{BfProgram, ParseOptions};
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::io::Cursor;
+    /// let options = ParseOptions {
+    ///     extensions: vec![';'],
+    ///     ..ParseOptions::default()
+    /// };
+    /// let bf_program = BfProgram::new_with_options("chatty.bf", "+;+.", &options)?;
+    /// let mut diagnostics = Vec::new();
+    /// let mut output = Vec::new();
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false)
+    ///     .with_diagnostic_channel(';', &mut diagnostics);
+    /// bf_interpreter.interpret(&mut Cursor::new([]), &mut output)?;
+    /// drop(bf_interpreter);
+    ///
+    /// assert_eq!((output, diagnostics), (vec![2], vec![1]));
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_diagnostic_channel(self, c: char, mut channel: impl Write + 'a) -> Self {
+        self.with_extension(c, move |context: &mut VmContext<'_, T>| {
+            channel.write_all(&[context.cell().get_value()])?;
+            channel.flush()?;
+            Ok(())
+        })
+    }
+
+    /// Show the output of `#` ([Instruction::DebugDump]) on `debug_output` rather than stderr. Each
+    /// `#` writes a line giving where it is in the program, the cell the head is on, and the
+    /// values of the cells around it, with the one under the head in brackets.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::{BfProgram, ParseOptions};
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::io::Cursor;
+    /// let options = ParseOptions {
+    ///     debug_dump: true,
+    ///     ..ParseOptions::default()
+    /// };
+    /// let bf_program = BfProgram::new_with_options("dump.bf", "+>++#", &options)?;
+    /// let mut dump = Vec::new();
+    /// let mut bf_interpreter: VirtualMachine<u8> =
+    ///     VirtualMachine::new(&bf_program, std::num::NonZeroUsize::new(4), false)
+    ///         .with_debug_output(&mut dump);
+    /// bf_interpreter.interpret(&mut Cursor::new([]), &mut Vec::new())?;
+    /// drop(bf_interpreter);
+    ///
+    /// assert_eq!(
+    ///     String::from_utf8(dump)?,
+    ///     "# at line 1, column 5: head on cell 1; cells 0 to 3: 1 [2] 0 0\n"
+    /// );
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_debug_output(mut self, debug_output: impl Write + 'a) -> Self {
+        self.debug_output = Box::new(debug_output);
+        self
+    }
+
+    /// Mark a range of cells as read-only. Any instruction that would change one of them fails
+    /// with [VMError::WriteProtected]. May be called more than once to protect several regions.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{VMError, VirtualMachine};
+    ///# use std::io::{stdin, stdout};
+    ///#
+    /// let bf_program = BfProgram::new("scribble.bf", ">>+")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> =
+    ///     VirtualMachine::new(&bf_program, None, false).with_write_protection(0..4);
+    ///
+    /// let result = bf_interpreter.interpret(&mut stdin(), &mut stdout());
+    /// assert!(matches!(result, Err(VMError::WriteProtected(_, 2))));
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_write_protection(mut self, cells: Range<usize>) -> Self {
+        self.protected.push(cells);
+        self
+    }
+
+    /// Keep a record of the last `capacity` jumps taken, which can be read back with
+    /// [VirtualMachine::jump_history] to see how the machine got to where it is (for example,
+    /// after an error). No history is kept unless this is called.
     ///
     /// ```
     ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
     ///# use bft_types::BfProgram;
     ///# use bft_interp::VirtualMachine;
+    ///# use std::io::{stdin, stdout};
     ///# use std::num::NonZeroUsize;
+    ///#
+    /// let bf_program = BfProgram::new("underrun.bf", "+++[-<]")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false)
+    ///     .with_jump_history(NonZeroUsize::new(8).unwrap());
+    ///
+    /// assert!(bf_interpreter.interpret(&mut stdin(), &mut stdout()).is_err());
+    /// for jump in bf_interpreter.jump_history().unwrap().iter() {
+    ///     println!("{}", jump);
+    /// }
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_jump_history(mut self, capacity: NonZeroUsize) -> Self {
+        self.jump_history = Some(JumpHistory::new(capacity));
+        self
+    }
+
+    /// The recent jumps taken by the machine, if it was set up to record them with
+    /// [VirtualMachine::with_jump_history]
+    pub fn jump_history(&self) -> Option<&JumpHistory> {
+        self.jump_history.as_ref()
+    }
+
+    /// Keep a journal of the last `capacity` changes made to cells, which can be read back with
+    /// [VirtualMachine::cell_journal] to see which cells a program has been working on. No
+    /// journal is kept unless this is called.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::VirtualMachine;
     ///# use std::io::{stdin, stdout};
+    ///# use std::num::NonZeroUsize;
     ///#
-    /// let mut bf_program = BfProgram::new("my_file.bf",".>.>+++")?;
+    /// let bf_program = BfProgram::new("move.bf", "++[->+<]")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false)
+    ///     .with_cell_journal(NonZeroUsize::new(64).unwrap());
     ///
-    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, true);
     /// bf_interpreter.interpret(&mut stdin(), &mut stdout())?;
+    /// let journal = bf_interpreter.cell_journal().unwrap();
+    /// assert_eq!(journal.len(), 6);
+    /// // the last change was the final `+` to cell 1
+    /// assert_eq!(journal.recent_cells(bf_interpreter.clock(), 4)[0].cell, 1);
     ///#
     ///# Ok(())
     ///# }
-    /// ```   
-    pub fn interpret(
+    /// ```
+    pub fn with_cell_journal(mut self, capacity: NonZeroUsize) -> Self {
+        self.cell_journal = Some(CellJournal::new(capacity));
+        self
+    }
+
+    /// The recent changes to cells, if the machine was set up to record them with
+    /// [VirtualMachine::with_cell_journal]
+    pub fn cell_journal(&self) -> Option<&CellJournal> {
+        self.cell_journal.as_ref()
+    }
+
+    /// Register an [Observer] to be told about each instruction executed and each halt
+    pub fn add_observer(&mut self, observer: impl Observer + 'a) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Set a hook to be called before each instruction, given a view of the machine and the
+    /// instruction about to run, replacing any hook set before. Its [HookAction] decides whether
+    /// the instruction is executed, or the machine pauses or aborts. The hook is called by
+    /// [VirtualMachine::interpret] and [VirtualMachine::step], after [Limits] are checked.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::{BfProgram, Instruction, LocalisedInstruction};
+    ///# use bft_interp::{HaltReason, HookAction, VirtualMachine, VmView};
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("print.bf", "+++.")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    /// bf_interpreter.set_pre_step_hook(|view: &VmView<u8>, instruction: &LocalisedInstruction| {
+    ///     if instruction.instruction() == Instruction::Output {
+    ///         assert_eq!(*view.cell(), 3);
+    ///         HookAction::Pause
+    ///     } else {
+    ///         HookAction::Continue
+    ///     }
+    /// });
+    ///
+    /// let halt_reason = bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    /// assert_eq!(halt_reason, HaltReason::Paused);
+    /// assert_eq!(bf_interpreter.clock(), 3);
+    ///
+    /// let halt_reason = bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    /// assert_eq!(halt_reason, HaltReason::Completed);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn set_pre_step_hook(
         &mut self,
-        input: &mut impl Read,
-        output: &mut impl Write,
-    ) -> Result<(), VMError> {
-        while self.program_counter < self.program.localised_instructions().len() {
-            self.program_counter =
-                match self.program.localised_instructions()[self.program_counter].instruction() {
-                    Instruction::MoveLeft => self.move_head_left()?,
-                    Instruction::MoveRight => self.move_head_right()?,
-                    Instruction::Increment => self.increment_cell()?,
-                    Instruction::Decrement => self.decrement_cell()?,
-                    Instruction::Input => self.read_value(input)?,
-                    Instruction::Output => self.print_value(output)?,
-                    Instruction::ConditionalJumpForward => self.conditional_jump_forward()?,
-                    Instruction::ConditionalJumpBackward => self.conditional_jump_backward()?,
-                };
-        }
-        Ok(())
+        hook: impl FnMut(&VmView<'_, T>, &LocalisedInstruction) -> HookAction + 'a,
+    ) {
+        self.pre_step_hook = Some(Box::new(hook));
     }
 
-    /// Move the head one cell towards the left (start) of the tape
-    fn move_head_left(&mut self) -> Result<usize, VMError> {
-        if self.head > 0 {
-            // note: went with this over checked_sub
-            self.head -= 1;
+    /// Stop before the instruction a [Breakpoint] refers to, each time it is reached, with
+    /// [HaltReason::BreakpointHit]. Interpreting again carries on from that instruction. Returns
+    /// the index of the instruction, for [VirtualMachine::remove_breakpoint].
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{Breakpoint, HaltReason, VirtualMachine};
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("count.bf", "++\n[->+<]")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    /// let index = bf_interpreter.add_breakpoint(Breakpoint::Location {
+    ///     line_num: 2,
+    ///     column_num: 2,
+    /// })?;
+    /// assert_eq!(index, 3);
+    ///
+    /// let halt_reason = bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    /// assert_eq!(
+    ///     halt_reason,
+    ///     HaltReason::BreakpointHit { program_counter: 3, line_num: 2, column_num: 2 }
+    /// );
+    /// assert_eq!(bf_interpreter.tape()[..2], [2, 0]);
+    ///
+    /// bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    /// assert_eq!(bf_interpreter.tape()[..2], [1, 1]);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> Result<usize, BreakpointError> {
+        let index = breakpoint.resolve(self.program.localised_instructions())?;
+        self.breakpoints.insert(index);
+        Ok(index)
+    }
 
-            Ok(self.program_counter + 1)
-        } else {
-            let bad_instruction = self.program.localised_instructions()[self.program_counter];
-            Err(VMError::HeadUnderrun(bad_instruction))
-        }
+    /// Remove the breakpoint before the instruction with this index. Returns whether there was one.
+    pub fn remove_breakpoint(&mut self, index: usize) -> bool {
+        self.breakpoints.remove(&index)
+    }
+
+    /// Remove every breakpoint
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// The number of instructions this machine has executed since it was created. This is the
+    /// time base shared by everything that observes or records the machine.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::io::{stdin, stdout};
+    ///#
+    /// let bf_program = BfProgram::new("loop.bf", "++[-]")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    /// bf_interpreter.interpret(&mut stdin(), &mut stdout())?;
+    ///
+    /// assert_eq!(bf_interpreter.clock(), 7);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    /// The position of the head on the tape, as an index into [VirtualMachine::tape]
+    pub fn head(&self) -> usize {
+        self.head
+    }
+
+    /// The cells on the tape
+    pub fn tape(&self) -> &[T] {
+        &self.cells
+    }
+
+    /// The index in [VirtualMachine::tape] of cell 0. This is always 0 unless a bidirectional tape
+    /// has grown to the left.
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    /// The number of the cell under the head, counting from cell 0, which is negative if a
+    /// bidirectional tape has been moved to the left of cell 0
+    pub fn head_position(&self) -> isize {
+        self.head as isize - self.origin as isize
+    }
+
+    /// Running totals of the work this machine has done
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::io::{stdin, stdout};
+    ///#
+    /// let bf_program = BfProgram::new("stats.bf", ">>+<")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    /// bf_interpreter.interpret(&mut stdin(), &mut stdout())?;
+    ///
+    /// let stats = bf_interpreter.run_stats();
+    /// assert_eq!(stats.instructions_executed, 4);
+    /// assert_eq!(stats.peak_head, 2);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn run_stats(&self) -> RunStats {
+        RunStats {
+            instructions_executed: self.clock,
+            tape_len: self.cells.len(),
+            ..self.stats
+        }
+    }
+
+    /// Set the [Limits] applied to each call to [VirtualMachine::interpret]. When a limit is hit,
+    /// the machine stops with the corresponding [HaltReason] instead of running to completion.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{HaltReason, Limits, VirtualMachine};
+    ///# use std::io::{stdin, stdout};
+    ///#
+    /// let bf_program = BfProgram::new("forever.bf", "+[]")?;
+    /// let limits = Limits {
+    ///     max_instructions: Some(1000),
+    ///     ..Limits::default()
+    /// };
+    ///
+    /// let mut bf_interpreter: VirtualMachine<u8> =
+    ///     VirtualMachine::new(&bf_program, None, false).with_limits(limits);
+    /// let halt_reason = bf_interpreter.interpret(&mut stdin(), &mut stdout())?;
+    ///
+    /// assert_eq!(halt_reason, HaltReason::InstructionLimit);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Put the machine back as it was before it first ran, ready to run the program again from
+    /// the start, while keeping its configuration and the memory it has already allocated. The
+    /// tape is cleared and shrunk back to its original length, and the clock, [RunStats], jump
+    /// history and cell journal are reset. This is much cheaper than building a new machine when
+    /// the same program is run many times.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("twice.bf", "+++>+")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    /// bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    ///
+    /// bf_interpreter.reset();
+    /// assert_eq!((bf_interpreter.head(), bf_interpreter.clock()), (0, 0));
+    /// assert!(bf_interpreter.tape().iter().all(|cell| *cell == 0));
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn reset(&mut self) {
+        self.cells.truncate(self.initial_tape_len);
+        self.cells.fill(T::default());
+        self.head = 0;
+        self.origin = 0;
+        self.storage = 0;
+        self.rewind();
+    }
+
+    /// Start the program again from its first instruction, leaving the tape and head as the last
+    /// run left them. The clock, [RunStats], jump history and cell journal are reset as with
+    /// [VirtualMachine::reset], so limits apply to each run on its own. This lets a program keep
+    /// state in its cells from one run to the next.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("tally.bf", "+")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    /// bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    ///
+    /// bf_interpreter.rewind();
+    /// bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    /// assert_eq!((bf_interpreter.tape()[0], bf_interpreter.clock()), (2, 1));
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn rewind(&mut self) {
+        self.program_counter = 0;
+        self.clock = 0;
+        self.stats = RunStats::default();
+        self.loop_iterations.clear();
+        self.pending_input = PendingInput::default();
+        self.hook_paused_at = None;
+        self.breakpoint_paused_at = None;
+        self.completion_reported = false;
+        if let Some(jump_history) = &mut self.jump_history {
+            jump_history.clear();
+        }
+        if let Some(cell_journal) = &mut self.cell_journal {
+            cell_journal.clear();
+        }
+    }
+
+    /// Take a copy of the tape, the head and the next instruction, so that the program can be
+    /// carried on from here later with [VirtualMachine::restore]. The [TapeState] can be saved as
+    /// text, so it may be restored in another process.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{HaltReason, Limits, TapeState, VirtualMachine};
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("count.bf", "+++++[>++<-]")?;
+    ///
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false)
+    ///     .with_limits(Limits {
+    ///         max_instructions: Some(10),
+    ///         ..Limits::default()
+    ///     });
+    /// assert_eq!(
+    ///     bf_interpreter.interpret(&mut empty(), &mut sink())?,
+    ///     HaltReason::InstructionLimit
+    /// );
+    /// let saved = bf_interpreter.snapshot().to_string();
+    ///
+    /// let mut resumed: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    /// resumed.restore(saved.parse::<TapeState<u8>>()?)?;
+    /// resumed.interpret(&mut empty(), &mut sink())?;
+    /// assert_eq!(resumed.tape()[1], 10);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn snapshot(&self) -> TapeState<T> {
+        TapeState {
+            cells: self.cells.clone(),
+            head: self.head,
+            origin: self.origin,
+            program_counter: self.program_counter,
+        }
+    }
+
+    /// Put back a [TapeState] taken with [VirtualMachine::snapshot], so that the next call to
+    /// [VirtualMachine::interpret] carries on from there. The machine keeps its own settings,
+    /// clock and statistics. Loop iteration counts, jump history and the cell journal start
+    /// afresh, as they can't be known for the restored tape.
+    ///
+    /// Fails, leaving the machine as it was, if the state does not fit this machine: if its head,
+    /// origin or next instruction lie outside its tape or program, or it has more cells than
+    /// [VirtualMachine::with_max_cells] allows.
+    pub fn restore(&mut self, state: TapeState<T>) -> Result<(), SnapshotError> {
+        let mismatch = |reason: String| Err(SnapshotError::Mismatch { reason });
+        let cells = state.cells.len();
+        if state.head >= cells || state.origin >= cells {
+            return mismatch(format!(
+                "head {} and origin {} must be within its {} cells",
+                state.head, state.origin, cells
+            ));
+        }
+        let instructions = self.program.localised_instructions().len();
+        if state.program_counter > instructions {
+            return mismatch(format!(
+                "next instruction {} is beyond the end of the program, which has {}",
+                state.program_counter, instructions
+            ));
+        }
+        if let Some(max_cells) = self.max_cells.filter(|max_cells| cells > *max_cells) {
+            return mismatch(format!(
+                "it has {} cells, more than the limit of {}",
+                cells, max_cells
+            ));
+        }
+
+        self.cells = state.cells;
+        self.head = state.head;
+        self.origin = state.origin;
+        self.program_counter = state.program_counter;
+        self.completion_reported = false;
+        self.loop_iterations.clear();
+        self.hook_paused_at = None;
+        self.breakpoint_paused_at = None;
+        if let Some(jump_history) = &mut self.jump_history {
+            jump_history.clear();
+        }
+        if let Some(cell_journal) = &mut self.cell_journal {
+            cell_journal.clear();
+        }
+        Ok(())
+    }
+
+    /// Get the tape ready ahead of time, so that the cost of allocating it is not paid while the
+    /// program runs. If the tape can grow and `expected_cells` is more than its current size, it is
+    /// first grown to that size. Every cell is then written to, so the memory behind the tape is
+    /// actually faulted in rather than merely reserved. Returns how long this took, which is also
+    /// recorded in [RunStats::warm_up].
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::VirtualMachine;
+    ///#
+    /// let bf_program = BfProgram::new("big.bf", "+[>+]")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, true);
+    ///
+    /// bf_interpreter.warm_up(Some(1_000_000));
+    /// assert_eq!(bf_interpreter.tape().len(), 1_000_000);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn warm_up(&mut self, expected_cells: Option<usize>) -> Duration {
+        let started = Instant::now();
+
+        if let Some(expected_cells) = expected_cells {
+            let expected_cells = self
+                .max_cells
+                .map_or(expected_cells, |max_cells| expected_cells.min(max_cells));
+            if self.tape_can_grow && expected_cells > self.cells.len() {
+                self.cells.resize(expected_cells, T::default());
+            }
+        }
+        for cell in self.cells.iter_mut() {
+            *cell = std::hint::black_box(T::default());
+        }
+
+        let warm_up = started.elapsed();
+        self.stats.warm_up += warm_up;
+        warm_up
+    }
+
+    /// Get a [CancelToken] that can be used from another thread to stop the machine cleanly at the
+    /// next instruction boundary, with [HaltReason::Interrupted].
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{HaltReason, VirtualMachine};
+    ///# use std::io::{stdin, stdout};
+    ///#
+    /// let bf_program = BfProgram::new("forever.bf", "+[]")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    ///
+    /// let cancel_token = bf_interpreter.cancel_token();
+    /// std::thread::spawn(move || cancel_token.cancel());
+    ///
+    /// let halt_reason = bf_interpreter.interpret(&mut stdin(), &mut stdout())?;
+    /// assert_eq!(halt_reason, HaltReason::Interrupted);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel_token.clone()
+    }
+
+    /// Stop when `cancel_token` is cancelled, instead of with a token of the machine's own. This
+    /// lets one token outlive the machine, for a caller that builds a new machine in its place.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{CancelToken, HaltReason, VirtualMachine};
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("forever.bf", "+[]")?;
+    /// let cancel_token = CancelToken::new();
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false)
+    ///     .with_cancel_token(cancel_token.clone());
+    ///
+    /// cancel_token.cancel();
+    /// let halt_reason = bf_interpreter.interpret(&mut empty(), &mut sink())?;
+    /// assert_eq!(halt_reason, HaltReason::Interrupted);
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn with_cancel_token(mut self, cancel_token: CancelToken) -> Self {
+        self.cancel_token = cancel_token;
+        self
+    }
+
+    /// Interprets the [BfProgram] the machine was instantiated with, returning the [HaltReason]
+    /// once it stops. If it stops for any reason other than [HaltReason::Completed], calling this
+    /// again resumes from where it left off.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::VirtualMachine;
+    ///# use std::num::NonZeroUsize;
+    ///# use std::io::{stdin, stdout};
+    ///#
+    /// let mut bf_program = BfProgram::new("my_file.bf",".>.>+++")?;
+    ///
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, true);
+    /// bf_interpreter.interpret(&mut stdin(), &mut stdout())?;
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```   
+    pub fn interpret(
+        &mut self,
+        input: &mut impl InputProvider,
+        output: &mut impl OutputSink,
+    ) -> Result<HaltReason, VMError> {
+        let started = Instant::now();
+        let result = self.run(input, output, started);
+        self.stats.elapsed += started.elapsed();
+
+        if result.is_ok() && self.flush_policy == FlushPolicy::Line {
+            if let Some(last) = self.program.localised_instructions().last() {
+                output
+                    .flush_output()
+                    .map_err(|error| VMError::WriteError(*last, error))?;
+            }
+        }
+        result
+    }
+
+    /// As [VirtualMachine::interpret], but if the program stops early, also returns the
+    /// [VmState] needed to carry on from there. The state can be saved as text and given to
+    /// [VirtualMachine::resume], so a long run can be suspended across restarts of the process.
+    /// To pause on request, call [CancelToken::cancel] on the machine's
+    /// [VirtualMachine::cancel_token].
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{Limits, RunOutcome, VirtualMachine, VmState};
+    ///# use std::io::empty;
+    ///#
+    /// let bf_program = BfProgram::new("count.bf", "++++++++[>+++++<-]>+.")?;
+    /// let mut first: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false)
+    ///     .with_limits(Limits { max_instructions: Some(20), ..Limits::default() });
+    ///
+    /// let RunOutcome::Paused { state, .. } =
+    ///     first.interpret_resumable(&mut empty(), &mut Vec::new())?
+    /// else {
+    ///     panic!("expected the program to be paused");
+    /// };
+    /// let saved = state.to_string();
+    ///
+    /// let mut second: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    /// let mut output = Vec::new();
+    /// let outcome = second.resume(saved.parse::<VmState<u8>>()?, &mut empty(), &mut output)?;
+    /// assert_eq!(outcome, RunOutcome::Completed);
+    /// assert_eq!(output, b")");
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn interpret_resumable(
+        &mut self,
+        input: &mut impl InputProvider,
+        output: &mut impl OutputSink,
+    ) -> Result<RunOutcome<T>, VMError> {
+        match self.interpret(input, output)? {
+            HaltReason::Completed => Ok(RunOutcome::Completed),
+            reason => {
+                self.account(Subsystem::Snapshots, Self::tape_bytes(self.cells.len()))?;
+                Ok(RunOutcome::Paused {
+                    reason,
+                    state: VmState {
+                        tape: self.snapshot(),
+                        clock: self.clock,
+                    },
+                })
+            }
+        }
+    }
+
+    /// Carry on from a [VmState] returned by [VirtualMachine::interpret_resumable], as with
+    /// [VirtualMachine::restore] but also taking up the clock where it was. Output already
+    /// written before the pause is not written again; input already read is not read again.
+    pub fn resume(
+        &mut self,
+        state: VmState<T>,
+        input: &mut impl InputProvider,
+        output: &mut impl OutputSink,
+    ) -> Result<RunOutcome<T>, ResumeError> {
+        self.restore(state.tape)?;
+        self.clock = state.clock;
+        Ok(self.interpret_resumable(input, output)?)
+    }
+
+    /// The main loop of [VirtualMachine::interpret]
+    fn run(
+        &mut self,
+        input: &mut impl InputProvider,
+        output: &mut impl OutputSink,
+        started: Instant,
+    ) -> Result<HaltReason, VMError> {
+        self.account_memory()?;
+        let start_clock = self.clock;
+        let start_bytes_output = self.stats.bytes_output;
+        let fuse = self.can_fuse();
+
+        for steps in 0.. {
+            let Some(instruction) = self.next_instruction() else {
+                break;
+            };
+            if let Some(halt_reason) = self.check_limits(
+                &instruction,
+                self.clock - start_clock,
+                steps,
+                self.stats.bytes_output - start_bytes_output,
+                started,
+            ) {
+                return Ok(self.halt(halt_reason));
+            }
+            if let Some(halt_reason) = self.check_pauses(&instruction)? {
+                return Ok(self.halt(halt_reason));
+            }
+
+            if fuse && self.execute_fused(self.clock - start_clock) {
+                continue;
+            }
+            if let Some(halt_reason) = self.execute_next(input, output)? {
+                return Ok(self.halt(halt_reason));
+            }
+        }
+        self.check_assertions()?;
+        Ok(self.halt(HaltReason::Completed))
+    }
+
+    /// Execute exactly one instruction, for debuggers and visualisers that need to look at the
+    /// machine between instructions. Unlike [VirtualMachine::interpret], no [Limits] are checked
+    /// and no time is added to [RunStats::elapsed]: the caller decides when to stop. `@assert`
+    /// directives, observers, the jump history and the cell journal work as they do when
+    /// interpreting.
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::{BfProgram, Instruction};
+    ///# use bft_interp::{HaltReason, Step, VirtualMachine};
+    ///# use std::io::{empty, sink};
+    ///#
+    /// let bf_program = BfProgram::new("two.bf", "+>")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    ///
+    /// let step = bf_interpreter.step(&mut empty(), &mut sink())?;
+    /// assert!(matches!(step, Step::Executed { instruction, finished: false }
+    ///     if instruction.instruction() == Instruction::Increment));
+    /// assert_eq!(bf_interpreter.tape()[0], 1);
+    ///
+    /// let step = bf_interpreter.step(&mut empty(), &mut sink())?;
+    /// assert!(matches!(step, Step::Executed { finished: true, .. }));
+    /// assert_eq!(bf_interpreter.head(), 1);
+    ///
+    /// let step = bf_interpreter.step(&mut empty(), &mut sink())?;
+    /// assert_eq!(step, Step::Halted(HaltReason::Completed));
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn step(
+        &mut self,
+        input: &mut impl InputProvider,
+        output: &mut impl OutputSink,
+    ) -> Result<Step, VMError> {
+        self.account_memory()?;
+        let Some(instruction) = self.next_instruction() else {
+            return Ok(Step::Halted(self.halt(HaltReason::Completed)));
+        };
+        if let Some(halt_reason) = self.check_pauses(&instruction)? {
+            return Ok(Step::Halted(self.halt(halt_reason)));
+        }
+        if let Some(halt_reason) = self.execute_next(input, output)? {
+            return Ok(Step::Halted(self.halt(halt_reason)));
+        }
+
+        let finished = self.next_instruction().is_none();
+        if finished {
+            self.check_assertions()?;
+            if self.flush_policy == FlushPolicy::Line {
+                output
+                    .flush_output()
+                    .map_err(|error| VMError::WriteError(instruction, error))?;
+            }
+            self.halt(HaltReason::Completed);
+        }
+        Ok(Step::Executed {
+            instruction,
+            finished,
+        })
+    }
+
+    /// Run until the program needs input, writes a byte or stops, and hand control back with an
+    /// [IoRequest] saying which, for embedders that drive I/O themselves rather than lending the
+    /// machine a stream. After [IoRequest::Input], give the machine a byte with
+    /// [VirtualMachine::provide_input] or end its input with [VirtualMachine::provide_eof] before
+    /// calling this again. [Limits] apply to each call, as they do to
+    /// [VirtualMachine::interpret].
+    ///
+    /// ```
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///# use bft_types::BfProgram;
+    ///# use bft_interp::{HaltReason, IoRequest, VirtualMachine};
+    ///#
+    /// // echoes one byte, one higher
+    /// let bf_program = BfProgram::new("next.bf", ",+.")?;
+    /// let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, false);
+    ///
+    /// assert_eq!(bf_interpreter.run_until_io()?, IoRequest::Input);
+    /// bf_interpreter.provide_input(b'a');
+    /// assert_eq!(bf_interpreter.run_until_io()?, IoRequest::Output(b'b'));
+    /// assert_eq!(bf_interpreter.run_until_io()?, IoRequest::Halted(HaltReason::Completed));
+    ///#
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn run_until_io(&mut self) -> Result<IoRequest, VMError> {
+        let started = Instant::now();
+        let mut input = std::mem::take(&mut self.pending_input);
+        let mut output = OutputSlot::default();
+        let result = self.run_io(&mut input, &mut output, started);
+        self.pending_input = input;
+        self.stats.elapsed += started.elapsed();
+        result
+    }
+
+    /// Give the machine the byte its next `,` reads, after [IoRequest::Input]. Replaces any byte
+    /// given before that has not been read yet.
+    pub fn provide_input(&mut self, byte: u8) {
+        self.pending_input = PendingInput::Byte(byte);
+    }
+
+    /// End the machine's input, so that its next `,` and every one after meets the end of input
+    /// and does what the machine's [EofBehavior] says
+    pub fn provide_eof(&mut self) {
+        self.pending_input = PendingInput::Eof;
+    }
+
+    /// The main loop of [VirtualMachine::run_until_io]
+    fn run_io(
+        &mut self,
+        input: &mut PendingInput,
+        output: &mut OutputSlot,
+        started: Instant,
+    ) -> Result<IoRequest, VMError> {
+        self.account_memory()?;
+        let start_clock = self.clock;
+        let start_bytes_output = self.stats.bytes_output;
+
+        while let Some(instruction) = self.next_instruction() {
+            if let Some(halt_reason) = self.check_limits(
+                &instruction,
+                self.clock - start_clock,
+                self.clock - start_clock,
+                self.stats.bytes_output - start_bytes_output,
+                started,
+            ) {
+                return Ok(IoRequest::Halted(self.halt(halt_reason)));
+            }
+            if let Some(halt_reason) = self.check_pauses(&instruction)? {
+                return Ok(IoRequest::Halted(self.halt(halt_reason)));
+            }
+
+            match self.execute_next(input, output)? {
+                Some(HaltReason::NeedsInput) => return Ok(IoRequest::Input),
+                Some(halt_reason) => return Ok(IoRequest::Halted(self.halt(halt_reason))),
+                None => {}
+            }
+            if let Some(byte) = output.0.take() {
+                return Ok(IoRequest::Output(byte));
+            }
+        }
+        self.check_assertions()?;
+        Ok(IoRequest::Halted(self.halt(HaltReason::Completed)))
+    }
+
+    /// The instruction at the program counter, or `None` if the program has finished
+    pub(crate) fn next_instruction(&self) -> Option<LocalisedInstruction> {
+        self.program
+            .localised_instructions()
+            .get(self.program_counter)
+            .copied()
+    }
+
+    /// Execute the instruction at the program counter, which must be within the program. Returns
+    /// a [HaltReason] if the instruction could not be executed yet, in which case the machine is
+    /// left as it was.
+    pub(crate) fn execute_next(
+        &mut self,
+        input: &mut impl InputProvider,
+        output: &mut impl OutputSink,
+    ) -> Result<Option<HaltReason>, VMError> {
+        self.check_assertions()?;
+        let instruction = self.program.localised_instructions()[self.program_counter];
+        let executed_counter = self.program_counter;
+        let cell_before = (self.cell_journal.is_some()
+            && matches!(
+                instruction.instruction(),
+                Instruction::Increment
+                    | Instruction::Decrement
+                    | Instruction::Input
+                    | Instruction::Load
+                    | Instruction::ShiftRight
+                    | Instruction::ShiftLeft
+                    | Instruction::Not
+                    | Instruction::Xor
+                    | Instruction::And
+                    | Instruction::Or
+            ))
+        .then(|| (self.head, self.cells[self.head].get_value()));
+
+        self.program_counter = match instruction.instruction() {
+            Instruction::MoveLeft => self.move_head_left()?,
+            Instruction::MoveRight => self.move_head_right()?,
+            Instruction::Increment => self.increment_cell()?,
+            Instruction::Decrement => self.decrement_cell()?,
+            Instruction::Input => match self.flush_before_input(output)?.read_value(input) {
+                Err(VMError::ReadError(_, error)) if error.kind() == ErrorKind::WouldBlock => {
+                    return Ok(Some(HaltReason::NeedsInput));
+                }
+                result => result?,
+            },
+            Instruction::Output => {
+                let next_counter = self.print_value(output)?;
+                self.stats.bytes_output += 1;
+                next_counter
+            }
+            Instruction::ConditionalJumpForward => self.conditional_jump_forward()?,
+            Instruction::ConditionalJumpBackward => self.conditional_jump_backward()?,
+            Instruction::Extension(c) => self.run_extension(c, input, output)?,
+            Instruction::End => self.program.localised_instructions().len(),
+            Instruction::Store => {
+                self.storage = self.cells[self.head].get_value();
+                self.program_counter + 1
+            }
+            Instruction::Load => self.change_cell(|_, storage| storage)?,
+            Instruction::ShiftRight => self.change_cell(|value, _| value >> 1)?,
+            Instruction::ShiftLeft => self.change_cell(|value, _| value << 1)?,
+            Instruction::Not => self.change_cell(|value, _| !value)?,
+            Instruction::Xor => self.change_cell(|value, storage| value ^ storage)?,
+            Instruction::And => self.change_cell(|value, storage| value & storage)?,
+            Instruction::Or => self.change_cell(|value, storage| value | storage)?,
+            Instruction::DebugDump => self.debug_dump()?,
+        };
+        self.clock += 1;
+
+        if self.limits.max_loop_iterations.is_some() && instruction.instruction().is_jump() {
+            self.count_loop_iteration(instruction.instruction(), executed_counter);
+        }
+
+        if let Some(jump_history) = &mut self.jump_history {
+            if instruction.instruction().is_jump() && self.program_counter != executed_counter + 1 {
+                jump_history.record(TakenJump {
+                    clock: self.clock,
+                    from: executed_counter,
+                    to: self.program_counter,
+                    instruction,
+                });
+            }
+        }
+
+        if let (Some(cell_journal), Some((cell, before))) = (&mut self.cell_journal, cell_before) {
+            let after = self.cells[cell].get_value();
+            if after != before {
+                cell_journal.record(CellChange {
+                    clock: self.clock,
+                    cell,
+                    before,
+                    after,
+                    instruction,
+                });
+            }
+        }
+
+        if self.memory_budget.is_some()
+            && (self.jump_history.is_some() || self.cell_journal.is_some())
+        {
+            self.account(Subsystem::Traces, self.trace_bytes())?;
+        }
+
+        for observer in self.observers.iter_mut() {
+            observer.instruction_executed(self.clock, executed_counter, &instruction);
+        }
+
+        Ok(None)
+    }
+
+    /// Check any `@assert` directives that come just before the instruction at the program counter
+    fn check_assertions(&self) -> Result<(), VMError> {
+        if !self.program.has_assertions() {
+            return Ok(());
+        }
+
+        for assertion in self.program.assertions_at(self.program_counter) {
+            for check in &assertion.checks {
+                let cell_value = |cell: usize| {
+                    self.cells
+                        .get(cell)
+                        .map_or(0, |cell| cell.get_value() as usize)
+                };
+                // to the left of cell 0, the head can't be where any check expects it
+                let head = self.head.checked_sub(self.origin).unwrap_or(usize::MAX);
+                let (expected, actual) = match *check {
+                    AssertionCheck::Head(expected) => (expected, head),
+                    AssertionCheck::CurrentCell(value) => (value as usize, cell_value(self.head)),
+                    AssertionCheck::Cell(cell, value) => {
+                        (value as usize, cell_value(self.origin + cell))
+                    }
+                };
+                if expected != actual {
+                    return Err(VMError::AssertionFailed {
+                        line_num: assertion.line_num,
+                        column_num: assertion.column_num,
+                        expected: *check,
+                        actual,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check for a breakpoint on the instruction at the program counter, then ask the pre-step
+    /// hook, if there is one, whether it may run. Neither stops the machine twice before the same
+    /// instruction, so carrying on after a pause runs it.
+    fn check_pauses(
+        &mut self,
+        instruction: &LocalisedInstruction,
+    ) -> Result<Option<HaltReason>, VMError> {
+        let here = Some((self.program_counter, self.clock));
+        if self.breakpoints.contains(&self.program_counter) && self.breakpoint_paused_at != here {
+            self.breakpoint_paused_at = here;
+            return Ok(Some(HaltReason::BreakpointHit {
+                program_counter: self.program_counter,
+                line_num: instruction.line_num(),
+                column_num: instruction.column_num(),
+            }));
+        }
+
+        let Some(hook) = &mut self.pre_step_hook else {
+            return Ok(None);
+        };
+        if self.hook_paused_at == here {
+            return Ok(None);
+        }
+        let view = VmView {
+            cells: &self.cells,
+            head: self.head,
+            origin: self.origin,
+            program_counter: self.program_counter,
+            clock: self.clock,
+        };
+        match hook(&view, instruction) {
+            HookAction::Continue => Ok(None),
+            HookAction::Pause => {
+                self.hook_paused_at = here;
+                Ok(Some(HaltReason::Paused))
+            }
+            HookAction::Abort => Err(VMError::Aborted(*instruction)),
+        }
+    }
+
+    /// Whether [VirtualMachine::interpret] may use the machine's [OptimizedProgram]: nothing may
+    /// be watching the instructions inside a run go by one at a time
+    fn can_fuse(&self) -> bool {
+        self.optimized.is_some()
+            && self.observers.is_empty()
+            && self.pre_step_hook.is_none()
+            && self.breakpoints.is_empty()
+            && self.cell_journal.is_none()
+            && !self.program.has_assertions()
+    }
+
+    /// Execute the instructions that the op starting at the program counter stands in for in one
+    /// step, if the machine's [OptimizedProgram] has one that does the work of several and what
+    /// is left of
+    /// [Limits::max_instructions] after `instructions_executed` covers all of them. Returns
+    /// whether it did. Anything that could stop partway, at the edge of the tape or on a cell that
+    /// can't be changed, or that doesn't simply wrap, is left to be executed an instruction at a
+    /// time, so that it stops in the same place. So is a clear or scan loop while its jumps are
+    /// being recorded or counted.
+    fn execute_fused(&mut self, instructions_executed: u64) -> bool {
+        let Some((op, instructions)) = self.optimized.as_ref().and_then(|optimized| {
+            let index = optimized.op_starting_at(self.program_counter)?;
+            Some((
+                optimized.ops()[index],
+                optimized.instructions_of(index).len(),
+            ))
+        }) else {
+            return false;
+        };
+        // an op for a single instruction saves nothing
+        if instructions < 2 {
+            return false;
+        }
+        let changes_cell = matches!(op, Op::Add(_) | Op::SetZero { .. });
+        if changes_cell
+            && (self.arithmetic != Arithmetic::Wrapping || self.check_writable().is_err())
+        {
+            return false;
+        }
+        // whether recording or counting jumps needs the loop's jumps taken one at a time
+        let tracks_jumps = self.jump_history.is_some() || self.limits.max_loop_iterations.is_some();
+        let (executed, head) = match op {
+            Op::Add(_) => (instructions as u64, self.head),
+            Op::Move(distance) => {
+                let Some(head) = self
+                    .head
+                    .checked_add_signed(distance)
+                    .filter(|head| *head < self.cells.len())
+                else {
+                    return false;
+                };
+                (instructions as u64, head)
+            }
+            Op::SetZero { up } => {
+                if tracks_jumps {
+                    return false;
+                }
+                // the `[`, then the body and the `]` each time round
+                match self.cells[self.head].steps_to_zero(up) {
+                    Some(steps) => (1 + 2 * steps, self.head),
+                    None => return false,
+                }
+            }
+            Op::Scan { right } => {
+                if tracks_jumps {
+                    return false;
+                }
+                let found = if right {
+                    T::find_zero(&self.cells[self.head..], true).map(|offset| self.head + offset)
+                } else {
+                    T::find_zero(&self.cells[..=self.head], false)
+                };
+                // with no zero cell before the end of the tape, the loop grows, wraps or fails
+                let Some(head) = found else {
+                    return false;
+                };
+                (1 + 2 * self.head.abs_diff(head) as u64, head)
+            }
+            // jumps, I/O and other instructions are executed as they are
+            _ => return false,
+        };
+        if self
+            .limits
+            .max_instructions
+            .is_some_and(|max| max - instructions_executed < executed)
+        {
+            return false;
+        }
+
+        match op {
+            Op::Add(amount) => self.cells[self.head].wrapping_add_by(amount),
+            Op::SetZero { .. } => self.cells[self.head] = T::default(),
+            _ => {
+                self.head = head;
+                self.stats.peak_head = self.stats.peak_head.max(head);
+            }
+        }
+        self.program_counter += instructions;
+        self.clock += executed;
+        true
+    }
+
+    /// Tell the observers that the machine has halted, and hand back the reason. Completion is
+    /// only told once, however often the machine is asked to carry on past the end.
+    fn halt(&mut self, halt_reason: HaltReason) -> HaltReason {
+        if halt_reason == HaltReason::Completed {
+            if self.completion_reported {
+                return halt_reason;
+            }
+            self.completion_reported = true;
+        }
+        for observer in self.observers.iter_mut() {
+            observer.halted(self.clock, halt_reason);
+        }
+        halt_reason
+    }
+
+    /// Check whether a cancellation request or any of the [Limits] stop the given instruction from
+    /// being executed. `steps` counts the times round the main loop, which decides when the clock
+    /// is checked, since one step may execute several instructions.
+    fn check_limits(
+        &self,
+        instruction: &LocalisedInstruction,
+        instructions_executed: u64,
+        steps: u64,
+        bytes_output: u64,
+        started: Instant,
+    ) -> Option<HaltReason> {
+        if self.cancel_token.take_request() {
+            return Some(HaltReason::Interrupted);
+        }
+
+        if self
+            .limits
+            .max_instructions
+            .is_some_and(|max| instructions_executed >= max)
+        {
+            return Some(HaltReason::InstructionLimit);
+        }
+
+        if instruction.instruction() == Instruction::Output
+            && self
+                .limits
+                .max_output
+                .is_some_and(|max| bytes_output >= max)
+        {
+            return Some(HaltReason::OutputLimit);
+        }
+
+        if let Some(max) = self.limits.max_loop_iterations {
+            if instruction.instruction() == Instruction::ConditionalJumpBackward
+                && !self.cells[self.head].is_zero()
+            {
+                if let Some(halt_reason) = self.loop_limit_reached(max) {
+                    return Some(halt_reason);
+                }
+            }
+        }
+
+        if let Some(timeout) = self.limits.timeout {
+            if steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) && started.elapsed() >= timeout {
+                return Some(HaltReason::Timeout);
+            }
+        }
+
+        None
+    }
+
+    /// Update [VirtualMachine::loop_iterations] after the jump instruction at `executed_counter`
+    /// has run: entering a loop starts its first iteration, and jumping back starts another
+    fn count_loop_iteration(&mut self, jump: Instruction, executed_counter: usize) {
+        let loops = self.program.loops();
+        if self.loop_iterations.len() != loops.len() {
+            self.loop_iterations = vec![0; loops.len()];
+        }
+        let entered = self.program_counter == executed_counter + 1;
+        let Some(number) = loops.innermost_at(executed_counter) else {
+            return;
+        };
+        match jump {
+            Instruction::ConditionalJumpForward if entered => self.loop_iterations[number] = 1,
+            Instruction::ConditionalJumpBackward if !entered => self.loop_iterations[number] += 1,
+            _ => {}
+        }
+    }
+
+    /// The [HaltReason] if the loop whose `]` is at the program counter has already started `max`
+    /// iterations, and so may not jump back for another
+    fn loop_limit_reached(&self, max: u64) -> Option<HaltReason> {
+        let number = self.program.loops().innermost_at(self.program_counter)?;
+        let iterations = self.loop_iterations.get(number).copied().unwrap_or(0);
+        (iterations >= max).then(|| {
+            let open = self.program.localised_instructions()[self.program.loops().open_of(number)];
+            HaltReason::LoopIterationLimit {
+                line_num: open.line_num(),
+                column_num: open.column_num(),
+                iterations,
+            }
+        })
+    }
+
+    /// Move the head one cell towards the left (start) of the tape
+    fn move_head_left(&mut self) -> Result<usize, VMError> {
+        if self.head > 0 {
+            // note: went with this over checked_sub
+            self.head -= 1;
+
+            Ok(self.program_counter + 1)
+        } else if self.circular {
+            self.head = self.cells.len() - 1;
+            self.stats.peak_head = self.stats.peak_head.max(self.head);
+
+            Ok(self.program_counter + 1)
+        } else if self.tape_can_grow_left {
+            self.grow_left()?;
+            self.head -= 1;
+
+            Ok(self.program_counter + 1)
+        } else {
+            let bad_instruction = self.program.localised_instructions()[self.program_counter];
+            Err(VMError::HeadUnderrun(bad_instruction))
+        }
+    }
+
+    /// Add cells to the front of the tape, as many as it already has so that a program walking
+    /// steadily left only pays for copying the tape a few times. Everything that refers to a
+    /// position on the tape is moved along to match.
+    fn grow_left(&mut self) -> Result<(), VMError> {
+        let added = match self.max_cells {
+            Some(max_cells) => self
+                .cells
+                .len()
+                .min(max_cells.saturating_sub(self.cells.len())),
+            None => self.cells.len(),
+        };
+        if added == 0 {
+            return Err(self.tape_limit_exceeded());
+        }
+        self.account(Subsystem::Tape, Self::tape_bytes(self.cells.len() + added))?;
+        self.cells
+            .splice(0..0, std::iter::repeat_n(T::default(), added));
+        self.head += added;
+        self.origin += added;
+        self.stats.peak_head += added;
+        if let Some(cell_journal) = &mut self.cell_journal {
+            cell_journal.shift(added);
+        }
+        Ok(())
+    }
+
+    /// Report to the memory budget, if there is one, that `subsystem` now holds `bytes`
+    fn account(&self, subsystem: Subsystem, bytes: usize) -> Result<(), VMError> {
+        match &self.memory_budget {
+            Some(budget) => budget
+                .set(subsystem, bytes)
+                .map_err(VMError::MemoryLimitExceeded),
+            None => Ok(()),
+        }
+    }
+
+    /// The bytes a tape of `cells` cells holds
+    fn tape_bytes(cells: usize) -> usize {
+        cells.saturating_mul(std::mem::size_of::<T>())
+    }
+
+    /// The bytes held by the jump history and cell journal
+    fn trace_bytes(&self) -> usize {
+        self.jump_history.as_ref().map_or(0, |jump_history| {
+            jump_history.len() * std::mem::size_of::<TakenJump>()
+        }) + self.cell_journal.as_ref().map_or(0, |cell_journal| {
+            cell_journal.len() * std::mem::size_of::<CellChange>()
+        })
+    }
+
+    /// Report everything the machine holds to the memory budget, as a run starts, since the tape
+    /// or traces may have changed since the last one
+    fn account_memory(&self) -> Result<(), VMError> {
+        if self.memory_budget.is_some() {
+            self.account(Subsystem::Tape, Self::tape_bytes(self.cells.len()))?;
+            self.account(Subsystem::Traces, self.trace_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// The error for growing the tape beyond [VirtualMachine::with_max_cells] at the program
+    /// counter
+    fn tape_limit_exceeded(&self) -> VMError {
+        let bad_instruction = self.program.localised_instructions()[self.program_counter];
+        VMError::TapeLimitExceeded(bad_instruction, self.max_cells.unwrap_or(self.cells.len()))
     }
 
     /// Move the head one cell towards the right (end) of the tape.
@@ -146,15 +2129,31 @@ where
     /// with an auto-extending tape, more cells will be added. If not, the VM
     /// will be sad and will throw an error out.
     fn move_head_right(&mut self) -> Result<usize, VMError> {
+        if self.circular && self.head + 1 == self.cells.len() {
+            self.head = 0;
+            return Ok(self.program_counter + 1);
+        }
+
         self.head += 1;
+        self.stats.peak_head = self.stats.peak_head.max(self.head);
 
         if self.head == self.cells.len() {
-            if self.tape_can_grow {
-                self.cells.push(T::default());
-            } else {
+            if !self.tape_can_grow {
                 let bad_instruction = self.program.localised_instructions()[self.program_counter];
                 return Err(VMError::HeadOverrun(bad_instruction));
             }
+            if self
+                .max_cells
+                .is_some_and(|max_cells| self.cells.len() >= max_cells)
+            {
+                self.head -= 1;
+                return Err(self.tape_limit_exceeded());
+            }
+            if let Err(error) = self.account(Subsystem::Tape, Self::tape_bytes(self.head + 1)) {
+                self.head -= 1;
+                return Err(error);
+            }
+            self.cells.push(T::default());
         }
 
         Ok(self.program_counter + 1)
@@ -162,24 +2161,155 @@ where
 
     /// Perform a wrapping increment on the cell pointed at by the head
     fn increment_cell(&mut self) -> Result<usize, VMError> {
-        self.cells[self.head].wrapping_increment();
+        self.check_writable()?;
+        let cell = &mut self.cells[self.head];
+        match self.arithmetic {
+            Arithmetic::Wrapping => cell.wrapping_increment(),
+            Arithmetic::Saturating => {
+                cell.checked_increment();
+            }
+            Arithmetic::Checked => {
+                if !cell.checked_increment() {
+                    let bad_instruction =
+                        self.program.localised_instructions()[self.program_counter];
+                    return Err(VMError::CellOverflow(bad_instruction));
+                }
+            }
+        }
         Ok(self.program_counter + 1)
     }
 
-    /// Perform a wrapping decrement on the cell pointed at by the head
+    /// Decrement the cell pointed at by the head, according to the machine's [Arithmetic]
     fn decrement_cell(&mut self) -> Result<usize, VMError> {
-        self.cells[self.head].wrapping_decrement();
+        self.check_writable()?;
+        let cell = &mut self.cells[self.head];
+        match self.arithmetic {
+            Arithmetic::Wrapping => cell.wrapping_decrement(),
+            Arithmetic::Saturating => {
+                cell.checked_decrement();
+            }
+            Arithmetic::Checked => {
+                if !cell.checked_decrement() {
+                    let bad_instruction =
+                        self.program.localised_instructions()[self.program_counter];
+                    return Err(VMError::CellUnderflow(bad_instruction));
+                }
+            }
+        }
+        Ok(self.program_counter + 1)
+    }
+
+    /// Make sure the cell under the head is not write-protected
+    fn check_writable(&self) -> Result<(), VMError> {
+        if self.protected.iter().any(|range| {
+            self.head
+                .checked_sub(self.origin)
+                .is_some_and(|cell| range.contains(&cell))
+        }) {
+            let bad_instruction = self.program.localised_instructions()[self.program_counter];
+            return Err(VMError::WriteProtected(
+                bad_instruction,
+                self.head - self.origin,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Set the cell at head to `change(value, storage)`, for the Extended Type I instructions
+    /// that change the cell
+    fn change_cell(&mut self, change: impl FnOnce(u64, u64) -> u64) -> Result<usize, VMError> {
+        self.check_writable()?;
+        self.cells[self.head].change_bits(change, self.storage);
+        Ok(self.program_counter + 1)
+    }
+
+    /// Show where the head is and the cells within [DEBUG_DUMP_RADIUS] of it on the debug output,
+    /// as `#` does
+    fn debug_dump(&mut self) -> Result<usize, VMError> {
+        let instruction = self.program.localised_instructions()[self.program_counter];
+        let start = self.head.saturating_sub(DEBUG_DUMP_RADIUS);
+        let end = (self.head + DEBUG_DUMP_RADIUS).min(self.cells.len() - 1);
+        let cells: Vec<_> = (start..=end)
+            .map(|index| match self.cells[index].get_value() {
+                value if index == self.head => format!("[{}]", value),
+                value => value.to_string(),
+            })
+            .collect();
+        // cells are numbered from cell 0, which is only ever moved from the start of the tape by
+        // a bidirectional tape growing to the left
+        let number = |index: usize| index as i64 - self.origin as i64;
+        writeln!(
+            self.debug_output,
+            "# at line {}, column {}: head on cell {}; cells {} to {}: {}",
+            instruction.line_num(),
+            instruction.column_num(),
+            number(self.head),
+            number(start),
+            number(end),
+            cells.join(" ")
+        )
+        .and_then(|_| self.debug_output.flush())
+        .map_err(|error| VMError::WriteError(instruction, error))?;
+        Ok(self.program_counter + 1)
+    }
+
+    /// Call the handler registered for an extension instruction
+    fn run_extension(
+        &mut self,
+        c: char,
+        input: &mut impl InputProvider,
+        output: &mut impl OutputSink,
+    ) -> Result<usize, VMError> {
+        let bad_instruction = self.program.localised_instructions()[self.program_counter];
+        let Some(handler) = self.extensions.get_mut(&c) else {
+            return Err(VMError::ExtensionFailed(
+                bad_instruction,
+                format!("no handler is registered for '{}'", c).into(),
+            ));
+        };
+
+        let mut context = VmContext {
+            cells: &mut self.cells,
+            head: &mut self.head,
+            tape_can_grow: self.tape_can_grow,
+            max_cells: self.max_cells,
+            protected: &self.protected,
+            input,
+            output,
+        };
+        handler(&mut context).map_err(|error| VMError::ExtensionFailed(bad_instruction, error))?;
+        self.stats.peak_head = self.stats.peak_head.max(self.head);
+
         Ok(self.program_counter + 1)
     }
 
     /// Read a single byte from [source] and write it to the cell at head
-    fn read_value(&mut self, source: &mut impl Read) -> Result<usize, VMError> {
-        let mut buffer = [0];
-        match source.read_exact(&mut buffer) {
-            Ok(_) => {
-                self.cells[self.head].set_value(buffer[0]);
+    fn read_value(&mut self, source: &mut impl InputProvider) -> Result<usize, VMError> {
+        // check before reading, so that no input is used up by a failed write
+        self.check_writable()?;
+        match source.read_byte() {
+            Ok(Some(byte)) => {
+                self.cells[self.head].set_value(byte);
+                self.stats.bytes_input += 1;
+                Ok(self.program_counter + 1)
+            }
+            Ok(None) if self.eof_behavior != EofBehavior::Error => {
+                let cell = &mut self.cells[self.head];
+                match self.eof_behavior {
+                    EofBehavior::SetZero => *cell = T::default(),
+                    EofBehavior::SetMax => {
+                        *cell = T::default();
+                        cell.wrapping_decrement();
+                    }
+                    EofBehavior::Error | EofBehavior::LeaveUnchanged => {}
+                }
                 Ok(self.program_counter + 1)
             }
+            Ok(None) => {
+                let bad_instruction = self.program.localised_instructions()[self.program_counter];
+                let error = io::Error::new(ErrorKind::UnexpectedEof, "the input has ended");
+                Err(VMError::from((bad_instruction, error)))
+            }
             Err(error) => {
                 let bad_instruction = self.program.localised_instructions()[self.program_counter];
                 Err(VMError::from((bad_instruction, error)))
@@ -187,12 +2317,29 @@ where
         }
     }
 
+    /// With [FlushPolicy::Line], flush the output so that any prompt is seen before the machine
+    /// waits for input
+    fn flush_before_input(&mut self, output: &mut impl OutputSink) -> Result<&mut Self, VMError> {
+        if self.flush_policy == FlushPolicy::Line {
+            output.flush_output().map_err(|error| {
+                let bad_instruction = self.program.localised_instructions()[self.program_counter];
+                VMError::WriteError(bad_instruction, error)
+            })?;
+        }
+        Ok(self)
+    }
+
     /// Print the value at head to the target output
-    fn print_value(&self, output: &mut impl Write) -> Result<usize, VMError> {
+    fn print_value(&self, output: &mut impl OutputSink) -> Result<usize, VMError> {
         let output_buf = [self.cells[self.head].get_value()];
+        let flush = match self.flush_policy {
+            FlushPolicy::EveryByte => true,
+            FlushPolicy::Line => output_buf[0] == b'\n',
+            FlushPolicy::Manual => false,
+        };
         output
-            .write_all(&output_buf)
-            .and_then(|_| output.flush())
+            .write_byte(output_buf[0])
+            .and_then(|_| if flush { output.flush_output() } else { Ok(()) })
             .map(|_| &self.program_counter + 1)
             .map_err(|error| {
                 let bad_instruction = self.program.localised_instructions()[self.program_counter];
@@ -221,7 +2368,121 @@ where
     }
 }
 
-impl CellKind for u8 {
+/// Implements [CellKind] for unsigned integer types, masking to the lowest byte on output. Any
+/// methods given in braces after a type are added to its implementation.
+macro_rules! impl_unsigned_cell_kind {
+    ($($cell:ty $({ $($extra:item)* })?),*) => {
+        $(
+            impl CellKind for $cell {
+                $($($extra)*)?
+
+                fn wrapping_increment(&mut self) {
+                    *self = self.wrapping_add(1);
+                }
+
+                fn wrapping_decrement(&mut self) {
+                    *self = self.wrapping_sub(1);
+                }
+
+                fn wrapping_add_by(&mut self, amount: isize) {
+                    // truncating keeps the amount right modulo the width of the cell
+                    *self = self.wrapping_add(amount as $cell);
+                }
+
+                fn steps_to_zero(&self, up: bool) -> Option<u64> {
+                    Some(if up { self.wrapping_neg() } else { *self }.into())
+                }
+
+                fn checked_increment(&mut self) -> bool {
+                    self.checked_add(1).map(|value| *self = value).is_some()
+                }
+
+                fn checked_decrement(&mut self) -> bool {
+                    self.checked_sub(1).map(|value| *self = value).is_some()
+                }
+
+                fn change_bits(&mut self, change: impl FnOnce(u64, u64) -> u64, storage: u8) {
+                    // truncating drops any bits shifted or inverted past the width of the cell
+                    *self = change((*self).into(), storage.into()) as $cell;
+                }
+
+                fn set_value(&mut self, value: u8) {
+                    *self = value.into();
+                }
+
+                fn get_value(&self) -> u8 {
+                    (*self & 0xff) as u8
+                }
+
+                fn is_zero(&self) -> bool {
+                    *self == 0
+                }
+            }
+        )*
+    };
+}
+
+impl_unsigned_cell_kind!(
+    u8 {
+        fn find_zero(cells: &[Self], right: bool) -> Option<usize> {
+            if right {
+                memchr::memchr(0, cells)
+            } else {
+                memchr::memrchr(0, cells)
+            }
+        }
+    },
+    u16,
+    u32
+);
+
+impl CellKind for i8 {
+    fn wrapping_increment(&mut self) {
+        *self = self.wrapping_add(1);
+    }
+
+    fn wrapping_decrement(&mut self) {
+        *self = self.wrapping_sub(1);
+    }
+
+    fn wrapping_add_by(&mut self, amount: isize) {
+        *self = self.wrapping_add(amount as i8);
+    }
+
+    fn steps_to_zero(&self, up: bool) -> Option<u64> {
+        // counting wraps round the same as it would for the unsigned cell of the same width
+        (*self as u8).steps_to_zero(up)
+    }
+
+    fn checked_increment(&mut self) -> bool {
+        self.checked_add(1).map(|value| *self = value).is_some()
+    }
+
+    fn checked_decrement(&mut self) -> bool {
+        self.checked_sub(1).map(|value| *self = value).is_some()
+    }
+
+    fn change_bits(&mut self, change: impl FnOnce(u64, u64) -> u64, storage: u8) {
+        // the bits are those of the unsigned cell of the same width
+        let mut bits = *self as u8;
+        bits.change_bits(change, storage);
+        *self = bits as i8;
+    }
+
+    fn set_value(&mut self, value: u8) {
+        *self = value as i8;
+    }
+
+    fn get_value(&self) -> u8 {
+        *self as u8
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+}
+
+impl CellKind for i32 {
     fn wrapping_increment(&mut self) {
         *self = self.wrapping_add(1);
     }
@@ -230,12 +2491,35 @@ impl CellKind for u8 {
         *self = self.wrapping_sub(1);
     }
 
+    fn wrapping_add_by(&mut self, amount: isize) {
+        *self = self.wrapping_add(amount as i32);
+    }
+
+    fn steps_to_zero(&self, up: bool) -> Option<u64> {
+        // counting wraps round the same as it would for the unsigned cell of the same width
+        (*self as u32).steps_to_zero(up)
+    }
+
+    fn checked_increment(&mut self) -> bool {
+        self.checked_add(1).map(|value| *self = value).is_some()
+    }
+
+    fn checked_decrement(&mut self) -> bool {
+        self.checked_sub(1).map(|value| *self = value).is_some()
+    }
+
+    fn change_bits(&mut self, change: impl FnOnce(u64, u64) -> u64, storage: u8) {
+        let mut bits = *self as u32;
+        bits.change_bits(change, storage);
+        *self = bits as i32;
+    }
+
     fn set_value(&mut self, value: u8) {
-        *self = value;
+        *self = value.into();
     }
 
     fn get_value(&self) -> u8 {
-        *self
+        (*self & 0xff) as u8
     }
 
     fn is_zero(&self) -> bool {
@@ -259,7 +2543,8 @@ impl From<(LocalisedInstruction, std::io::Error)> for VMError {
 mod tests {
     use super::*;
     use assert_matches::assert_matches;
-    use std::io::Cursor;
+    use bft_types::ParseOptions;
+    use std::io::{Cursor, Read};
 
     fn make_placeholder_program() -> BfProgram {
         BfProgram::new("test_file.bf", ",.[test]+++.").unwrap()
@@ -557,4 +2842,1001 @@ mod tests {
         let actual = output_cursor.into_inner();
         assert_eq!(expected, actual);
     }
+
+    // Are writes to protected cells refused, while other cells can still be written?
+    #[test]
+    fn test_write_protection() {
+        let program = BfProgram::new("test.bf", ",>,>,").unwrap();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_write_protection(2..3);
+        let mut input = Cursor::new([1, 2, 3]);
+
+        let result = vm.interpret(&mut input, &mut Vec::new());
+
+        assert_matches!(result, Err(VMError::WriteProtected(_, 2)));
+        assert_eq!(&vm.cells[..3], &[1, 2, 0]);
+        assert_eq!(input.position(), 2);
+    }
+
+    // Does a program that runs to the end report that it completed?
+    #[test]
+    fn test_halt_completed() {
+        let program = BfProgram::new("test.bf", "+++").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+
+        let halt_reason = vm.interpret(&mut Cursor::new([]), &mut Vec::new());
+
+        assert_matches!(halt_reason, Ok(HaltReason::Completed));
+    }
+
+    // Does the instruction limit stop the machine, and can it then be resumed?
+    #[test]
+    fn test_halt_instruction_limit() {
+        let program = BfProgram::new("test.bf", "+++++").unwrap();
+        let limits = Limits {
+            max_instructions: Some(3),
+            ..Limits::default()
+        };
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_limits(limits);
+
+        let halt_reason = vm.interpret(&mut Cursor::new([]), &mut Vec::new());
+
+        assert_matches!(halt_reason, Ok(HaltReason::InstructionLimit));
+        assert_eq!(vm.cells[0], 3);
+
+        let halt_reason = vm.interpret(&mut Cursor::new([]), &mut Vec::new());
+
+        assert_matches!(halt_reason, Ok(HaltReason::Completed));
+        assert_eq!(vm.cells[0], 5);
+    }
+
+    // Does the output limit stop the machine before the extra byte is written?
+    #[test]
+    fn test_halt_output_limit() {
+        let program = BfProgram::new("test.bf", "+.+.+.").unwrap();
+        let limits = Limits {
+            max_output: Some(2),
+            ..Limits::default()
+        };
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_limits(limits);
+        let mut output = Vec::new();
+
+        let halt_reason = vm.interpret(&mut Cursor::new([]), &mut output);
+
+        assert_matches!(halt_reason, Ok(HaltReason::OutputLimit));
+        assert_eq!(output, vec![1, 2]);
+        assert_eq!(vm.program_counter, 5);
+    }
+
+    // Does a zero timeout stop the machine straight away?
+    #[test]
+    fn test_halt_timeout() {
+        let program = BfProgram::new("test.bf", "+[]").unwrap();
+        let limits = Limits {
+            timeout: Some(std::time::Duration::ZERO),
+            ..Limits::default()
+        };
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_limits(limits);
+
+        let halt_reason = vm.interpret(&mut Cursor::new([]), &mut Vec::new());
+
+        assert_matches!(halt_reason, Ok(HaltReason::Timeout));
+    }
+
+    // Does cancelling from another thread interrupt the machine, leaving it resumable?
+    #[test]
+    fn test_halt_interrupted() {
+        let program = BfProgram::new("test.bf", "+[]").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        let cancel_token = vm.cancel_token();
+
+        let canceller = std::thread::spawn(move || cancel_token.cancel());
+        let halt_reason = vm.interpret(&mut Cursor::new([]), &mut Vec::new());
+        canceller.join().unwrap();
+
+        assert_matches!(halt_reason, Ok(HaltReason::Interrupted));
+        assert!(!vm.cancel_token().is_cancelled());
+
+        // drop out of the loop and check the machine carries on to completion
+        vm.cells[0] = 0;
+        vm.program_counter = 1;
+        let halt_reason = vm.interpret(&mut Cursor::new([]), &mut Vec::new());
+        assert_matches!(halt_reason, Ok(HaltReason::Completed));
+    }
+
+    // Does the clock count every executed instruction across runs, and do observers see it?
+    #[test]
+    fn test_clock_and_observers() {
+        #[derive(Default)]
+        struct Recorder {
+            executed: Vec<(u64, usize)>,
+            halts: Vec<(u64, HaltReason)>,
+        }
+        impl Observer for &mut Recorder {
+            fn instruction_executed(
+                &mut self,
+                clock: u64,
+                program_counter: usize,
+                _instruction: &LocalisedInstruction,
+            ) {
+                self.executed.push((clock, program_counter));
+            }
+
+            fn halted(&mut self, clock: u64, halt_reason: HaltReason) {
+                self.halts.push((clock, halt_reason));
+            }
+        }
+
+        let program = BfProgram::new("test.bf", "+[-]").unwrap();
+        let limits = Limits {
+            max_instructions: Some(2),
+            ..Limits::default()
+        };
+        let mut recorder = Recorder::default();
+        {
+            let mut vm: VirtualMachine<u8> =
+                VirtualMachine::new(&program, None, false).with_limits(limits);
+            vm.add_observer(&mut recorder);
+
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+
+            assert_eq!(vm.clock(), 4);
+        }
+
+        assert_eq!(recorder.executed, vec![(1, 0), (2, 1), (3, 2), (4, 3)]);
+        assert_eq!(
+            recorder.halts,
+            vec![
+                (2, HaltReason::InstructionLimit),
+                (4, HaltReason::Completed)
+            ]
+        );
+    }
+
+    // Are only the jumps actually taken recorded in the history?
+    #[test]
+    fn test_jump_history() {
+        let program = BfProgram::new("test.bf", "[]++[-]").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false)
+            .with_jump_history(NonZeroUsize::new(10).unwrap());
+
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+
+        let jumps: Vec<(usize, usize)> = vm
+            .jump_history()
+            .unwrap()
+            .iter()
+            .map(|jump| (jump.from, jump.to))
+            .collect();
+        // skip the first loop, then go round the second loop once
+        assert_eq!(jumps, vec![(0, 2), (6, 5)]);
+    }
+
+    // Input that would block should pause the machine on the ',' rather than erroring
+    #[test]
+    fn test_halt_needs_input() {
+        struct WouldBlock;
+        impl Read for WouldBlock {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::from(ErrorKind::WouldBlock))
+            }
+        }
+
+        let program = BfProgram::new("test.bf", "+,.").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+
+        let halt_reason = vm.interpret(&mut WouldBlock, &mut Vec::new());
+
+        assert_matches!(halt_reason, Ok(HaltReason::NeedsInput));
+        assert_eq!(vm.program_counter, 1);
+
+        let mut output = Vec::new();
+        let halt_reason = vm.interpret(&mut Cursor::new([7]), &mut output);
+
+        assert_matches!(halt_reason, Ok(HaltReason::Completed));
+        assert_eq!(output, vec![7]);
+    }
+
+    // Does warm-up grow only extensible tapes, and keep its time apart from the run time?
+    #[test]
+    fn test_warm_up() {
+        let program = BfProgram::new("test.bf", "+").unwrap();
+        let mut fixed: VirtualMachine<u8> =
+            VirtualMachine::new(&program, NonZeroUsize::new(4), false);
+        let mut growing: VirtualMachine<u8> =
+            VirtualMachine::new(&program, NonZeroUsize::new(4), true);
+
+        fixed.warm_up(Some(100));
+        let warm_up = growing.warm_up(Some(100));
+
+        assert_eq!(fixed.tape().len(), 4);
+        assert_eq!(growing.tape().len(), 100);
+        assert_eq!(growing.run_stats().warm_up, warm_up);
+        assert!(growing.tape().iter().all(|cell| *cell == 0));
+    }
+
+    // Are assertions checked each time they are reached, and when the program completes?
+    #[test]
+    fn test_assertions() {
+        let options = ParseOptions {
+            assertions: true,
+            ..ParseOptions::default()
+        };
+        let passing = BfProgram::new_with_options(
+            "test.bf",
+            "+++[>++<-] @assert head=0 cell=0 cell1=6\n> @assert cell=6",
+            &options,
+        )
+        .unwrap();
+        let failing =
+            BfProgram::new_with_options("test.bf", "+++[ @assert cell=3\n-]", &options).unwrap();
+
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&passing, None, false);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Ok(HaltReason::Completed)
+        );
+
+        // passes on the first time round the loop, but not the second
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&failing, None, false);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Err(VMError::AssertionFailed {
+                line_num: 1,
+                column_num: 6,
+                expected: AssertionCheck::CurrentCell(3),
+                actual: 2
+            })
+        );
+    }
+
+    // Do extension handlers see the machine, and are missing handlers and failures reported?
+    #[test]
+    fn test_extensions() {
+        let options = ParseOptions {
+            extensions: vec!['^', '?'],
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("test.bf", "+^.^^?", &options).unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, NonZeroUsize::new(2), false)
+            .with_extension('^', |context: &mut VmContext<u8>| {
+                let next = context.head() + 1;
+                let value = context.cell().get_value();
+                context.move_head(next)?;
+                context.cell_mut()?.set_value(value * 10);
+                Ok(())
+            });
+
+        let mut output = Vec::new();
+        let result = vm.interpret(&mut Cursor::new([]), &mut output);
+
+        // the second '^' walks off the end of the tape
+        assert_eq!(output, vec![10]);
+        assert_matches!(result, Err(VMError::ExtensionFailed(instruction, _)) if instruction.column_num() == 4);
+    }
+
+    // Is output flushed only at newlines, before input, and at the end with the line policy?
+    #[test]
+    fn test_line_flush_policy() {
+        /// Records what had been written at each flush
+        #[derive(Default)]
+        struct FlushRecorder {
+            written: Vec<u8>,
+            flushes: Vec<Vec<u8>>,
+        }
+        impl Write for FlushRecorder {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.written.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.flushes.push(self.written.clone());
+                Ok(())
+            }
+        }
+
+        // print 0 and a newline, then 0 before reading, then what was read
+        let program = BfProgram::new("test.bf", ">++++++++++<.>.<.,.").unwrap();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_flush_policy(FlushPolicy::Line);
+
+        let mut output = FlushRecorder::default();
+        vm.interpret(&mut Cursor::new([b'c']), &mut output).unwrap();
+
+        assert_eq!(
+            output.flushes,
+            vec![vec![0, 10], vec![0, 10, 0], vec![0, 10, 0, b'c']]
+        );
+    }
+
+    // Do wide cells count past 255 before wrapping, and output only their lowest byte?
+    #[test]
+    fn test_wide_cells() {
+        // 0 - 1 is 0xffff, which outputs 0xff; 16 * 20 + 1 is 321, which outputs 65 ('A')
+        let program = BfProgram::new(
+            "test.bf",
+            "-.>>++++++++++++++++[<++++++++++++++++++++>-]<+.",
+        )
+        .unwrap();
+        let mut vm: VirtualMachine<u16> = VirtualMachine::new(&program, None, false);
+        let mut output = Vec::new();
+        vm.interpret(&mut Cursor::new([]), &mut output).unwrap();
+        assert_eq!(output, vec![0xff, b'A']);
+        assert_eq!(vm.cells[..2], [0xffff, 321]);
+
+        let program = BfProgram::new("test.bf", "-,+.").unwrap();
+        let mut vm: VirtualMachine<u32> = VirtualMachine::new(&program, None, false);
+        let mut output = Vec::new();
+        vm.interpret(&mut Cursor::new([255]), &mut output).unwrap();
+        assert_eq!(output, vec![0]);
+        assert_eq!(vm.cells[0], 256);
+    }
+
+    // Do signed cells go negative, and convert to and from bytes as documented?
+    #[test]
+    fn test_signed_cells() {
+        // 0 - 1 is -1, which outputs 255; 127 + 1 wraps to -128, which outputs 128
+        let program =
+            BfProgram::new("test.bf", "-.>-[-]+++++++[>++++++++++++++++++<-]>+.+.").unwrap();
+        let mut vm: VirtualMachine<i8> = VirtualMachine::new(&program, None, false);
+        let mut output = Vec::new();
+        vm.interpret(&mut Cursor::new([]), &mut output).unwrap();
+        assert_eq!(output, vec![255, 127, 128]);
+        assert_eq!(vm.cells[..3], [-1, 0, -128]);
+
+        let program = BfProgram::new("test.bf", ",.>,.>-.").unwrap();
+        let mut vm: VirtualMachine<i8> = VirtualMachine::new(&program, None, false);
+        let mut output = Vec::new();
+        vm.interpret(&mut Cursor::new([255, 65]), &mut output)
+            .unwrap();
+        assert_eq!(output, vec![255, 65, 255]);
+        assert_eq!(vm.cells[..3], [-1, 65, -1]);
+
+        let mut vm: VirtualMachine<i32> = VirtualMachine::new(&program, None, false);
+        let mut output = Vec::new();
+        vm.interpret(&mut Cursor::new([255, 65]), &mut output)
+            .unwrap();
+        assert_eq!(output, vec![255, 65, 255]);
+        assert_eq!(vm.cells[..3], [255, 65, -1]);
+
+        let mut cell: i32 = -191;
+        assert_eq!(cell.get_value(), 65);
+        cell.set_value(200);
+        assert_eq!(cell, 200);
+    }
+
+    // Is a runaway loop stopped at its own location, while loops that are re-entered start
+    // counting again?
+    #[test]
+    fn test_loop_iteration_limit() {
+        let limits = Limits {
+            max_loop_iterations: Some(5),
+            ..Limits::default()
+        };
+
+        // the inner loop runs 4 times each time round the outer loop, which runs 3 times
+        let program = BfProgram::new("test.bf", "+++[>++++[-]<-]").unwrap();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_limits(limits);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Ok(HaltReason::Completed)
+        );
+
+        let program = BfProgram::new("test.bf", "+++\n+[>+<]").unwrap();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_limits(limits);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Ok(HaltReason::LoopIterationLimit {
+                line_num: 2,
+                column_num: 2,
+                iterations: 5
+            })
+        );
+        assert_eq!(vm.cells[1], 5);
+    }
+
+    // Do saturating and checked arithmetic stop at the ends of a cell's range?
+    #[test]
+    fn test_arithmetic() {
+        let program = BfProgram::new("test.bf", "->-+++").unwrap();
+
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_arithmetic(Arithmetic::Saturating);
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+        assert_eq!(vm.cells[..2], [0, 3]);
+
+        let mut vm: VirtualMachine<i8> =
+            VirtualMachine::new(&program, None, false).with_arithmetic(Arithmetic::Checked);
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+        assert_eq!(vm.cells[..2], [-1, 2]);
+
+        let program = BfProgram::new("test.bf", "-\n+").unwrap();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_arithmetic(Arithmetic::Checked);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Err(VMError::CellUnderflow(instruction)) if instruction.line_num() == 1
+        );
+
+        vm.cells[0] = 255;
+        vm.program_counter = 1;
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Err(VMError::CellOverflow(instruction)) if instruction.line_num() == 2
+        );
+        assert_eq!(vm.cells[0], 255);
+    }
+
+    // Does each EOF behaviour give the cell programs expect once input runs out?
+    #[test]
+    fn test_eof_behavior() {
+        // reads one byte, then a second after input has run out
+        let program = BfProgram::new("test.bf", ",+>+++,").unwrap();
+        for (eof_behavior, expected) in [
+            (EofBehavior::SetZero, [66, 0]),
+            (EofBehavior::SetMax, [66, 255]),
+            (EofBehavior::LeaveUnchanged, [66, 3]),
+        ] {
+            let mut vm: VirtualMachine<u8> =
+                VirtualMachine::new(&program, None, false).with_eof_behavior(eof_behavior);
+            vm.interpret(&mut Cursor::new(b"A"), &mut Vec::new())
+                .unwrap();
+            assert_eq!(vm.cells[..2], expected);
+        }
+
+        let mut vm: VirtualMachine<i8> =
+            VirtualMachine::new(&program, None, false).with_eof_behavior(EofBehavior::SetMax);
+        vm.interpret(&mut Cursor::new(b"A"), &mut Vec::new())
+            .unwrap();
+        assert_eq!(vm.cells[1], -1);
+
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new(b"A"), &mut Vec::new()),
+            Err(VMError::ReadError(_, error)) if error.kind() == ErrorKind::UnexpectedEof
+        );
+    }
+
+    // Is a state that doesn't fit the machine refused, leaving the machine as it was?
+    #[test]
+    fn test_restore_mismatch() {
+        let program = BfProgram::new("test.bf", "+>+").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, NonZeroUsize::new(4), true)
+            .with_max_cells(NonZeroUsize::new(8).unwrap());
+        let state = TapeState {
+            cells: vec![1, 2],
+            head: 1,
+            origin: 0,
+            program_counter: 3,
+        };
+
+        for bad_state in [
+            TapeState {
+                head: 2,
+                ..state.clone()
+            },
+            TapeState {
+                program_counter: 4,
+                ..state.clone()
+            },
+            TapeState {
+                cells: vec![0; 9],
+                ..state.clone()
+            },
+        ] {
+            assert_matches!(vm.restore(bad_state), Err(SnapshotError::Mismatch { .. }));
+            assert_eq!(vm.snapshot().cells, [0; 4]);
+        }
+        vm.restore(state.clone()).unwrap();
+        assert_eq!(vm.snapshot(), state);
+    }
+
+    // Does stepping one instruction at a time end where interpreting does, and wait for input?
+    #[test]
+    fn test_step() {
+        let program = BfProgram::new("test.bf", "++[>+++<-]>.").unwrap();
+        let mut interpreted: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        interpreted
+            .interpret(&mut Cursor::new([]), &mut Vec::new())
+            .unwrap();
+
+        #[derive(Default)]
+        struct Halts(Vec<HaltReason>);
+        impl Observer for Halts {
+            fn halted(&mut self, _clock: u64, halt_reason: HaltReason) {
+                self.0.push(halt_reason);
+            }
+        }
+
+        let mut halts = Halts::default();
+        let mut stepped: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        stepped.add_observer(&mut halts);
+        let mut output = Vec::new();
+        let mut steps = 0;
+        while let Step::Executed { finished, .. } =
+            stepped.step(&mut Cursor::new([]), &mut output).unwrap()
+        {
+            steps += 1;
+            assert_eq!(finished, stepped.clock() == interpreted.clock());
+        }
+        assert_eq!(
+            stepped.step(&mut Cursor::new([]), &mut output).unwrap(),
+            Step::Halted(HaltReason::Completed)
+        );
+        assert_eq!(steps, interpreted.clock());
+        assert_eq!(stepped.tape(), interpreted.tape());
+        drop(stepped);
+        assert_eq!(output, [6]);
+        // told once, though the machine was stepped twice more after the last instruction
+        assert_eq!(halts.0, [HaltReason::Completed]);
+
+        struct WouldBlock;
+        impl Read for WouldBlock {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::from(ErrorKind::WouldBlock))
+            }
+        }
+        let program = BfProgram::new("test.bf", ",").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        assert_eq!(
+            vm.step(&mut WouldBlock, &mut Vec::new()).unwrap(),
+            Step::Halted(HaltReason::NeedsInput)
+        );
+        assert_eq!(vm.clock(), 0);
+    }
+
+    // Is the hook asked about every instruction, and can it pause and abort the run?
+    #[test]
+    fn test_pre_step_hook() {
+        let program = BfProgram::new("test.bf", "++[-]>+").unwrap();
+        let mut seen = Vec::new();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        vm.set_pre_step_hook(|view: &VmView<u8>, _: &LocalisedInstruction| {
+            seen.push((view.program_counter(), view.clock()));
+            HookAction::Continue
+        });
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+        let executed = vm.clock();
+        drop(vm);
+        assert_eq!(seen.len() as u64, executed);
+        assert_eq!(seen[..3], [(0, 0), (1, 1), (2, 2)]);
+
+        // pauses before the second loop iteration's `-`
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        vm.set_pre_step_hook(|view: &VmView<u8>, instruction: &LocalisedInstruction| {
+            match (instruction.instruction(), *view.cell()) {
+                (Instruction::Decrement, 1) => HookAction::Pause,
+                (Instruction::MoveRight, _) => HookAction::Abort,
+                _ => HookAction::Continue,
+            }
+        });
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Ok(HaltReason::Paused)
+        );
+        assert_eq!((vm.program_counter, vm.cells[0]), (3, 1));
+        assert_matches!(
+            vm.step(&mut Cursor::new([]), &mut Vec::new()),
+            Ok(Step::Executed { .. })
+        );
+        assert_eq!(vm.cells[0], 0);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Err(VMError::Aborted(instruction)) if instruction.column_num() == 6
+        );
+        assert_eq!(vm.head, 0);
+    }
+
+    // Does a breakpoint in a loop stop it every time round, and can a hook pause on the same
+    // instruction without either stopping twice?
+    #[test]
+    fn test_breakpoints() {
+        let program = BfProgram::new("test.bf", "+++[-]").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        let index = vm.add_breakpoint(Breakpoint::Instruction(4)).unwrap();
+        let mut hits = Vec::new();
+        loop {
+            match vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap() {
+                HaltReason::BreakpointHit {
+                    program_counter, ..
+                } => hits.push((program_counter, vm.cells[0])),
+                halt_reason => {
+                    assert_eq!(halt_reason, HaltReason::Completed);
+                    break;
+                }
+            }
+        }
+        assert_eq!(hits, [(4, 3), (4, 2), (4, 1)]);
+        assert!(vm.remove_breakpoint(index));
+        assert!(!vm.remove_breakpoint(index));
+
+        vm.reset();
+        vm.add_breakpoint(Breakpoint::Instruction(1)).unwrap();
+        vm.set_pre_step_hook(|view: &VmView<u8>, _: &LocalisedInstruction| {
+            if view.program_counter() == 1 {
+                HookAction::Pause
+            } else {
+                HookAction::Continue
+            }
+        });
+        let mut halts = Vec::new();
+        for _ in 0..3 {
+            halts.push(vm.step(&mut Cursor::new([]), &mut Vec::new()).unwrap());
+        }
+        assert_matches!(
+            halts[..],
+            [
+                Step::Executed { .. },
+                Step::Halted(HaltReason::BreakpointHit { .. }),
+                Step::Halted(HaltReason::Paused)
+            ]
+        );
+        assert_matches!(
+            vm.step(&mut Cursor::new([]), &mut Vec::new()),
+            Ok(Step::Executed { .. })
+        );
+        assert_eq!(vm.cells[0], 2);
+
+        vm.clear_breakpoints();
+        assert_matches!(
+            vm.add_breakpoint(Breakpoint::Location {
+                line_num: 2,
+                column_num: 1
+            }),
+            Err(BreakpointError::NoInstruction { .. })
+        );
+    }
+
+    // Can a caller drive all of a program's I/O itself, byte by byte, up to the end of input?
+    #[test]
+    fn test_run_until_io() {
+        // upper-cases its input until EOF, which reads as 0
+        let program = BfProgram::new("test.bf", ",[>++++[<-------->-]<.,]").unwrap();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_eof_behavior(EofBehavior::SetZero);
+        let mut input = b"bf".iter();
+        let mut output = Vec::new();
+        let mut requests = 0;
+        loop {
+            requests += 1;
+            match vm.run_until_io().unwrap() {
+                IoRequest::Input => match input.next() {
+                    Some(&byte) => vm.provide_input(byte),
+                    None => vm.provide_eof(),
+                },
+                IoRequest::Output(byte) => output.push(byte),
+                IoRequest::Halted(halt_reason) => {
+                    assert_eq!(halt_reason, HaltReason::Completed);
+                    break;
+                }
+            }
+        }
+        assert_eq!(output, b"BF");
+        assert_eq!(requests, 3 + 2 + 1);
+        assert_eq!(vm.run_stats().bytes_output, 2);
+        assert_eq!(
+            vm.run_until_io().unwrap(),
+            IoRequest::Halted(HaltReason::Completed)
+        );
+    }
+
+    // Does a program paused again and again, with its state saved and loaded into a new machine
+    // each time, give the same output and clock as one run straight through?
+    #[test]
+    fn test_resume() {
+        let program = BfProgram::new("test.bf", "++++++++[>++++++++<-]>+.+.+.").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        let mut expected = Vec::new();
+        vm.interpret(&mut Cursor::new([]), &mut expected).unwrap();
+        let expected_clock = vm.clock();
+
+        let limits = Limits {
+            max_instructions: Some(7),
+            ..Limits::default()
+        };
+        let mut output = Vec::new();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_limits(limits);
+        let mut outcome = vm
+            .interpret_resumable(&mut Cursor::new([]), &mut output)
+            .unwrap();
+        let mut pauses = 0;
+        while let RunOutcome::Paused { reason, state } = outcome {
+            assert_eq!(reason, HaltReason::InstructionLimit);
+            pauses += 1;
+            vm = VirtualMachine::new(&program, None, false).with_limits(limits);
+            outcome = vm
+                .resume(
+                    state.to_string().parse().unwrap(),
+                    &mut Cursor::new([]),
+                    &mut output,
+                )
+                .unwrap();
+        }
+        assert!(pauses > 10);
+        assert_eq!(output, expected);
+        assert_eq!(output, b"ABC");
+        assert_eq!(vm.clock(), expected_clock);
+
+        let mut state = VmState {
+            tape: vm.snapshot(),
+            clock: 0,
+        };
+        state.tape.program_counter = 100;
+        assert_matches!(
+            vm.resume(state, &mut Cursor::new([]), &mut output),
+            Err(ResumeError::State(SnapshotError::Mismatch { .. }))
+        );
+    }
+
+    // Does a bidirectional tape grow to the left, keeping cell numbers counted from cell 0?
+    #[test]
+    fn test_bidirectional_tape() {
+        let options = ParseOptions {
+            assertions: true,
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options(
+            "test.bf",
+            "+>++<<<<<+++>>>>>+ @assert head=1 cell1=3",
+            &options,
+        )
+        .unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, NonZeroUsize::new(2), false)
+            .with_bidirectional_tape()
+            .with_cell_journal(NonZeroUsize::new(8).unwrap());
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+
+        assert_eq!(vm.origin(), 6);
+        assert_eq!(vm.head_position(), 1);
+        assert_eq!(
+            vm.tape()[vm.origin() - 4..vm.origin() + 2],
+            [3, 0, 0, 0, 1, 3]
+        );
+        let last_change = vm.cell_journal().unwrap().iter().last().unwrap();
+        assert_eq!(last_change.cell, vm.origin() + 1);
+
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Err(VMError::HeadUnderrun(_))
+        );
+    }
+
+    // Does the head wrap round both ends of a circular tape, without it growing?
+    #[test]
+    fn test_circular_tape() {
+        let program = BfProgram::new("test.bf", "<<+>>>>++>-").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, NonZeroUsize::new(3), true)
+            .with_bidirectional_tape()
+            .with_circular_tape();
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+
+        assert_eq!(vm.tape(), [255, 1, 2]);
+        assert_eq!(vm.head(), 0);
+    }
+
+    // Is growth stopped at the limit in both directions, with a fixed tape still overrunning?
+    #[test]
+    fn test_max_cells() {
+        let max_cells = NonZeroUsize::new(5).unwrap();
+        let program = BfProgram::new("test.bf", "+[<+]").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, NonZeroUsize::new(2), false)
+            .with_bidirectional_tape()
+            .with_max_cells(max_cells);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Err(VMError::TapeLimitExceeded(instruction, 5)) if instruction.column_num() == 3
+        );
+        assert_eq!(vm.tape().len(), 5);
+        assert_eq!(vm.head_position(), -3);
+
+        let program = BfProgram::new("test.bf", ">>").unwrap();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, NonZeroUsize::new(2), false).with_max_cells(max_cells);
+        assert_matches!(
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Err(VMError::HeadOverrun(_))
+        );
+    }
+
+    // Does a program nested a million loops deep run, both into and straight past its loops?
+    #[test]
+    fn test_deep_nesting() {
+        let depth = 1_000_000;
+        let text = format!("+{}-{}.", "[".repeat(depth), "]".repeat(depth));
+        let program = BfProgram::new("deep.bf", &text).unwrap();
+
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        let mut output = Vec::new();
+        vm.interpret(&mut Cursor::new([]), &mut output).unwrap();
+        assert_eq!(output, [0]);
+
+        let program = BfProgram::new("deep.bf", &text[1..]).unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+        assert_eq!(vm.clock(), 2);
+    }
+
+    // Are traces and snapshots counted against the budget along with the tape?
+    #[test]
+    fn test_memory_budget() {
+        let tape = NonZeroUsize::new(10);
+        let jump = std::mem::size_of::<TakenJump>();
+        let budget = || MemoryBudget::new(10 + 2 * jump);
+        let traced = |source: &str, budget: MemoryBudget| {
+            let program = BfProgram::new("test.bf", source).unwrap();
+            let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, tape, false)
+                .with_jump_history(NonZeroUsize::new(100).unwrap())
+                .with_memory_budget(budget);
+            vm.interpret(&mut Cursor::new([]), &mut Vec::new())
+                .map(|_| ())
+        };
+
+        let two_jumps = budget();
+        let program = BfProgram::new("test.bf", "+++[-]").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, tape, false)
+            .with_jump_history(NonZeroUsize::new(100).unwrap())
+            .with_memory_budget(two_jumps.clone());
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+        assert_eq!(two_jumps.held(Subsystem::Traces), 2 * jump);
+        // what a machine held is given back when it goes
+        drop(vm);
+        assert_eq!(two_jumps.total(), 0);
+        assert_matches!(
+            traced("++++[-]", budget()),
+            Err(VMError::MemoryLimitExceeded(MemoryError {
+                subsystem: Subsystem::Traces,
+                ..
+            }))
+        );
+
+        let program = BfProgram::new("test.bf", "+[]").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, tape, false)
+            .with_limits(Limits {
+                max_instructions: Some(10),
+                ..Limits::default()
+            })
+            .with_memory_budget(MemoryBudget::new(15));
+        assert_matches!(
+            vm.interpret_resumable(&mut Cursor::new([]), &mut Vec::new()),
+            Err(VMError::MemoryLimitExceeded(MemoryError {
+                subsystem: Subsystem::Snapshots,
+                requested: 10,
+                others: 10,
+                limit: 15
+            }))
+        );
+    }
+
+    // Do two machines given clones of one budget count their tapes together against its limit?
+    #[test]
+    fn test_shared_memory_budget() {
+        let program = BfProgram::new("test.bf", "+").unwrap();
+        let budget = MemoryBudget::new(100);
+        let machine = || -> VirtualMachine<u8> {
+            VirtualMachine::new(&program, NonZeroUsize::new(60), false)
+                .with_memory_budget(budget.clone())
+        };
+
+        let mut first = machine();
+        first
+            .interpret(&mut Cursor::new([]), &mut Vec::new())
+            .unwrap();
+        let mut second = machine();
+        assert_matches!(
+            second.interpret(&mut Cursor::new([]), &mut Vec::new()),
+            Err(VMError::MemoryLimitExceeded(MemoryError {
+                subsystem: Subsystem::Tape,
+                requested: 60,
+                others: 60,
+                limit: 100
+            }))
+        );
+        assert_eq!(budget.held(Subsystem::Tape), 60);
+
+        drop(first);
+        second
+            .interpret(&mut Cursor::new([]), &mut Vec::new())
+            .unwrap();
+        assert_eq!(budget.held(Subsystem::Tape), 60);
+    }
+
+    // Do the Extended Type I instructions work on the stored byte, and does `@` end the program?
+    #[test]
+    fn test_extended() {
+        let options = ParseOptions {
+            extended: true,
+            ..ParseOptions::default()
+        };
+        let program =
+            BfProgram::new_with_options("test.bf", "++++++$>!}>!{>~>+++^>+++&>+++|@+", &options)
+                .unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, NonZeroUsize::new(7), false);
+        let halt_reason = vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+
+        assert_eq!(halt_reason, HaltReason::Completed);
+        assert_eq!(vm.tape(), [6, 3, 12, 255, 5, 2, 7]);
+
+        // wider cells change all their bits, with the storage byte widened to match
+        let program =
+            BfProgram::new_with_options("test.bf", "-}>-{>~>-$-&>-}$-|>-$!", &options).unwrap();
+        let mut vm: VirtualMachine<u16> =
+            VirtualMachine::new(&program, NonZeroUsize::new(6), false);
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+        assert_eq!(vm.tape(), [0x7fff, 0xfffe, 0xffff, 0xfe, 0x7fff, 0xff]);
+        let mut vm: VirtualMachine<i32> =
+            VirtualMachine::new(&program, NonZeroUsize::new(6), false);
+        vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+        assert_eq!(vm.tape(), [i32::MAX, -2, -1, 0xfe, i32::MAX, 0xff]);
+    }
+
+    // Does executing runs in one step leave the machine as executing them one at a time does,
+    // including where a run stops partway?
+    #[test]
+    fn test_optimizations() {
+        fn run(text: &str, configure: fn(VirtualMachine<u8>) -> VirtualMachine<u8>) {
+            let program = BfProgram::new("test.bf", text).unwrap();
+            let outcome = |optimize: bool| {
+                let mut vm = VirtualMachine::new(&program, NonZeroUsize::new(8), false);
+                if optimize {
+                    vm = vm.with_optimizations(OptLevel::Full);
+                }
+                let mut vm = configure(vm);
+                let mut output = Vec::new();
+                let result = vm.interpret(&mut Cursor::new([]), &mut output);
+                (
+                    format!("{:?}", result),
+                    vm.tape().to_vec(),
+                    vm.head(),
+                    vm.clock(),
+                    output,
+                )
+            };
+            assert_eq!(outcome(true), outcome(false), "running {}", text);
+        }
+
+        run("++++++++[>++++[>++>+++<<-]<-]>>.>---.", |vm| vm);
+        run(">>>>>>>>>>", |vm| vm);
+        run("+++>>>>>>>>>", |vm| vm);
+        run("<<", |vm| vm);
+        run("----->>+++", |vm| {
+            vm.with_arithmetic(Arithmetic::Saturating)
+        });
+        run("+>>+++", |vm| vm.with_write_protection(2..3));
+        run("+[>>>+]", |vm| {
+            vm.with_limits(Limits {
+                max_instructions: Some(10),
+                ..Limits::default()
+            })
+        });
+        run(">>>><<<<<<", |vm| vm.with_circular_tape());
+        run("+++[-]>-[-]>[+]>+++[+]", |vm| vm);
+        run("++[-]>+[+]", |vm| vm.with_arithmetic(Arithmetic::Checked));
+        run("++[-]", |vm| vm.with_write_protection(0..1));
+        run("-[-]", |vm| {
+            vm.with_limits(Limits {
+                max_instructions: Some(100),
+                ..Limits::default()
+            })
+        });
+        run("+++[-]", |vm| {
+            vm.with_jump_history(NonZeroUsize::new(4).unwrap())
+        });
+        run("+>+>+>>+<<<<[>]>[>]<[<]+[<]>>>>[>]", |vm| vm);
+        run("+>+>+[>]", |vm| vm.with_circular_tape());
+        run("+>+>+[>]", |vm| {
+            vm.with_max_cells(NonZeroUsize::new(5).unwrap())
+        });
+        run(">+[<]", |vm| vm.with_bidirectional_tape());
+        run("+>+>+<<[>]", |vm| {
+            vm.with_jump_history(NonZeroUsize::new(4).unwrap())
+        });
+    }
 }
@@ -0,0 +1,336 @@
+//! Running two differently configured [VirtualMachine]s side by side on the same program and
+//! input, to find the first point at which they behave differently.
+//!
+//! This is useful when moving a program between Brainfuck variants: give one machine the
+//! configuration the program was written for and the other the configuration it is being moved
+//! to, and the report says exactly which instruction first behaved differently.
+
+use std::fmt::Display;
+use std::io::Cursor;
+
+use bft_types::LocalisedInstruction;
+
+use crate::{CellKind, VirtualMachine};
+
+/// The state of one machine after a step, as far as the comparison is concerned
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum StepState {
+    /// The machine executed the instruction
+    Running,
+    /// The instruction failed. The error's text is kept.
+    Failed(String),
+}
+
+/// The first difference found between the two machines
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Divergence {
+    /// One machine stopped or failed when the other did not, or they failed differently
+    State { left: StepState, right: StepState },
+    /// The machines are at different instructions
+    ProgramCounter { left: usize, right: usize },
+    /// The heads are over different cells
+    Head { left: usize, right: usize },
+    /// The cell under the head holds different values. Values are compared as output bytes and by
+    /// whether they are zero, so cells of different widths can be compared.
+    Cell {
+        cell: usize,
+        left: (u8, bool),
+        right: (u8, bool),
+    },
+    /// The machines have produced different output
+    Output { left: Vec<u8>, right: Vec<u8> },
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Divergence::State { left, right } => {
+                write!(f, "left is {:?} but right is {:?}", left, right)
+            }
+            Divergence::ProgramCounter { left, right } => write!(
+                f,
+                "left is at instruction {} but right is at instruction {}",
+                left, right
+            ),
+            Divergence::Head { left, right } => {
+                write!(f, "left head is at {} but right head is at {}", left, right)
+            }
+            Divergence::Cell { cell, left, right } => write!(
+                f,
+                "cell {} is {} (zero: {}) on the left but {} (zero: {}) on the right",
+                cell, left.0, left.1, right.0, right.1
+            ),
+            Divergence::Output { left, right } => {
+                write!(f, "left output {:?} but right output {:?}", left, right)
+            }
+        }
+    }
+}
+
+/// Where and how the machines first diverged
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DivergenceReport {
+    /// How many steps both machines had taken when the divergence was seen, including the one
+    /// that caused it
+    pub step: u64,
+    /// Index of the instruction that caused the divergence
+    pub program_counter: usize,
+    /// The instruction that caused the divergence
+    pub instruction: LocalisedInstruction,
+    /// What was different
+    pub divergence: Divergence,
+    /// Summary of the left machine's configuration
+    pub left_config: String,
+    /// Summary of the right machine's configuration
+    pub right_config: String,
+}
+
+impl Display for DivergenceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Diverged at step {} on instruction {} ({}:{}): {}\n  left:  {}\n  right: {}",
+            self.step,
+            self.program_counter,
+            self.instruction.line_num(),
+            self.instruction.column_num(),
+            self.divergence,
+            self.left_config,
+            self.right_config
+        )
+    }
+}
+
+/// The result of a lockstep comparison
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LockstepOutcome {
+    /// No divergence was found in the given number of steps. `finished` says whether both
+    /// machines reached the end of the program (or failed in the same way), or whether the step
+    /// limit was reached first.
+    Agreed { steps: u64, finished: bool },
+    /// The machines diverged
+    Diverged(DivergenceReport),
+}
+
+/// Step two machines, which should have been created for the same program, one instruction at a
+/// time, each reading its own copy of `input`, until they diverge, both finish, or `max_steps`
+/// steps have been taken. After every step the program counters, heads, the cell under the head
+/// and the output so far are compared.
+///
+/// ```
+///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+///# use bft_types::BfProgram;
+///# use bft_interp::VirtualMachine;
+///# use bft_interp::lockstep::{run_lockstep, LockstepOutcome};
+///# use std::num::NonZeroUsize;
+/// let bf_program = BfProgram::new("walk.bf", ">>>>+.")?;
+/// let mut fixed: VirtualMachine<u8> =
+///     VirtualMachine::new(&bf_program, NonZeroUsize::new(2), false);
+/// let mut growing: VirtualMachine<u8> =
+///     VirtualMachine::new(&bf_program, NonZeroUsize::new(2), true);
+///
+/// let outcome = run_lockstep(&mut fixed, &mut growing, b"", 1000);
+/// if let LockstepOutcome::Diverged(report) = outcome {
+///     assert_eq!(report.program_counter, 1);
+///     println!("{}", report);
+/// }
+///# Ok(())
+///# }
+/// ```
+pub fn run_lockstep<T: CellKind, U: CellKind>(
+    left: &mut VirtualMachine<T>,
+    right: &mut VirtualMachine<U>,
+    input: &[u8],
+    max_steps: u64,
+) -> LockstepOutcome {
+    let mut left_input = Cursor::new(input);
+    let mut right_input = Cursor::new(input);
+    let mut left_output = Vec::new();
+    let mut right_output = Vec::new();
+
+    for step in 1..=max_steps {
+        let program_counter = left.program_counter;
+        // the program counters are compared after every step, so the machines finish together
+        let instruction = match (left.next_instruction(), right.next_instruction()) {
+            (Some(instruction), Some(_)) => instruction,
+            _ => {
+                return LockstepOutcome::Agreed {
+                    steps: step - 1,
+                    finished: true,
+                }
+            }
+        };
+
+        let left_state = step_state(left, &mut left_input, &mut left_output);
+        let right_state = step_state(right, &mut right_input, &mut right_output);
+        let report = |divergence| {
+            LockstepOutcome::Diverged(DivergenceReport {
+                step,
+                program_counter,
+                instruction,
+                divergence,
+                left_config: config_summary(left),
+                right_config: config_summary(right),
+            })
+        };
+
+        if left_state != right_state {
+            return report(Divergence::State {
+                left: left_state,
+                right: right_state,
+            });
+        }
+        if let StepState::Failed(_) = left_state {
+            return LockstepOutcome::Agreed {
+                steps: step,
+                finished: true,
+            };
+        }
+        if left.program_counter != right.program_counter {
+            return report(Divergence::ProgramCounter {
+                left: left.program_counter,
+                right: right.program_counter,
+            });
+        }
+        if left.head != right.head {
+            return report(Divergence::Head {
+                left: left.head,
+                right: right.head,
+            });
+        }
+        let left_cell = &left.cells[left.head];
+        let right_cell = &right.cells[right.head];
+        if (left_cell.get_value(), left_cell.is_zero())
+            != (right_cell.get_value(), right_cell.is_zero())
+        {
+            return report(Divergence::Cell {
+                cell: left.head,
+                left: (left_cell.get_value(), left_cell.is_zero()),
+                right: (right_cell.get_value(), right_cell.is_zero()),
+            });
+        }
+        if left_output != right_output {
+            return report(Divergence::Output {
+                left: left_output,
+                right: right_output,
+            });
+        }
+    }
+
+    LockstepOutcome::Agreed {
+        steps: max_steps,
+        finished: false,
+    }
+}
+
+/// Execute one instruction on a machine that has not finished
+fn step_state<T: CellKind>(
+    vm: &mut VirtualMachine<T>,
+    input: &mut Cursor<&[u8]>,
+    output: &mut Vec<u8>,
+) -> StepState {
+    match vm.execute_next(input, output) {
+        Ok(None) => StepState::Running,
+        Ok(Some(halt_reason)) => StepState::Failed(halt_reason.to_string()),
+        Err(error) => StepState::Failed(error.to_string()),
+    }
+}
+
+/// Describe the parts of a machine's configuration that can change how a program behaves
+fn config_summary<T: CellKind>(vm: &VirtualMachine<T>) -> String {
+    format!(
+        "{} cells, {} cells on tape, {}{}",
+        std::any::type_name::<T>(),
+        vm.cells.len(),
+        if vm.tape_can_grow {
+            "extensible"
+        } else {
+            "fixed size"
+        },
+        if vm.protected.is_empty() {
+            String::new()
+        } else {
+            format!(", write-protected {:?}", vm.protected)
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use bft_types::BfProgram;
+    use std::num::NonZeroUsize;
+
+    // Do identically configured machines agree all the way to the end?
+    #[test]
+    fn test_lockstep_agrees() {
+        let program = BfProgram::new("echo.bf", ",[.,]").unwrap();
+        let mut left: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        let mut right: VirtualMachine<u8> = VirtualMachine::new(&program, None, true);
+
+        let outcome = run_lockstep(&mut left, &mut right, b"ab", 100);
+
+        // both machines fail identically when they run out of input
+        assert_matches!(outcome, LockstepOutcome::Agreed { finished: true, .. });
+    }
+
+    // Is the step limit respected?
+    #[test]
+    fn test_lockstep_step_limit() {
+        let program = BfProgram::new("forever.bf", "+[]").unwrap();
+        let mut left: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        let mut right: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+
+        let outcome = run_lockstep(&mut left, &mut right, b"", 50);
+
+        assert_eq!(
+            outcome,
+            LockstepOutcome::Agreed {
+                steps: 50,
+                finished: false
+            }
+        );
+    }
+
+    // Is a difference in tape policy pinned to the instruction that exposed it?
+    #[test]
+    fn test_lockstep_tape_policy() {
+        let program = BfProgram::new("walk.bf", "+>>+").unwrap();
+        let tape_size = NonZeroUsize::new(2);
+        let mut left: VirtualMachine<u8> = VirtualMachine::new(&program, tape_size, true);
+        let mut right: VirtualMachine<u8> = VirtualMachine::new(&program, tape_size, false);
+
+        let outcome = run_lockstep(&mut left, &mut right, b"", 100);
+
+        let LockstepOutcome::Diverged(report) = outcome else {
+            panic!("expected a divergence, got {:?}", outcome);
+        };
+        assert_eq!(report.step, 3);
+        assert_eq!(report.program_counter, 2);
+        assert_matches!(
+            report.divergence,
+            Divergence::State {
+                left: StepState::Running,
+                right: StepState::Failed(_)
+            }
+        );
+    }
+
+    // Is a difference in cell contents found as soon as it happens?
+    #[test]
+    fn test_lockstep_protected_cell() {
+        let program = BfProgram::new("write.bf", ">+<").unwrap();
+        let mut left: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        let mut right: VirtualMachine<u8> =
+            VirtualMachine::new(&program, None, false).with_write_protection(1..2);
+
+        let outcome = run_lockstep(&mut left, &mut right, b"", 100);
+
+        let LockstepOutcome::Diverged(report) = outcome else {
+            panic!("expected a divergence, got {:?}", outcome);
+        };
+        assert_eq!(report.program_counter, 1);
+        assert!(report.right_config.contains("write-protected"));
+    }
+}
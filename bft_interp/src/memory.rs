@@ -0,0 +1,216 @@
+//! One budget for the memory a [crate::VirtualMachine] holds, shared by everything that allocates
+//! on its behalf: the tape as it grows, snapshots of it, and the traces kept of jumps and cell
+//! changes. Each part reports what it holds, and whichever would take the total over the limit
+//! fails with [MemoryError], saying which part it was.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+use bft_types::messages::{self, Localise, Message};
+use thiserror::Error;
+
+/// The parts of a machine whose memory is counted
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Subsystem {
+    /// The cells of the tape
+    Tape,
+    /// The copy of the tape taken by the last snapshot
+    Snapshots,
+    /// The jump history and cell journal
+    Traces,
+}
+
+impl Subsystem {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Display for Subsystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Subsystem::Tape => write!(f, "tape"),
+            Subsystem::Snapshots => write!(f, "snapshots"),
+            Subsystem::Traces => write!(f, "traces"),
+        }
+    }
+}
+
+/// A subsystem needed more memory than was left in the budget
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+pub struct MemoryError {
+    /// The subsystem that asked
+    pub subsystem: Subsystem,
+    /// The bytes it would have held
+    pub requested: usize,
+    /// The bytes held by everything else at the time
+    pub others: usize,
+    /// The budget's limit, in bytes
+    pub limit: usize,
+}
+
+impl Localise for MemoryError {
+    fn message(&self) -> Message {
+        Message::new(
+            messages::MEMORY_LIMIT_EXCEEDED,
+            vec![
+                ("subsystem", self.subsystem.to_string()),
+                ("requested", self.requested.to_string()),
+                ("others", self.others.to_string()),
+                ("limit", self.limit.to_string()),
+            ],
+        )
+    }
+}
+
+impl Display for MemoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+/// A limit on the memory held by one or more machines, given to each with
+/// [crate::VirtualMachine::with_memory_budget]. Clones share the same budget, each counting what
+/// it holds separately, so that machines given clones of one budget add up against its limit
+/// rather than overwriting each other's counts. A clone's count is given back when it is dropped.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    held: Arc<Mutex<Held>>,
+    /// Which of the holders in `held` this is
+    holder: u64,
+}
+
+/// The bytes each holder of a budget holds in each [Subsystem], by [Subsystem::index]
+#[derive(Debug, Default)]
+struct Held {
+    holders: HashMap<u64, [usize; 3]>,
+    next_holder: u64,
+}
+
+impl Held {
+    /// A new holder, holding nothing yet
+    fn add_holder(&mut self) -> u64 {
+        let holder = self.next_holder;
+        self.next_holder += 1;
+        self.holders.insert(holder, [0; 3]);
+        holder
+    }
+}
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes, with nothing held yet
+    pub fn new(limit: usize) -> Self {
+        let mut held = Held::default();
+        let holder = held.add_holder();
+        Self {
+            limit,
+            held: Arc::new(Mutex::new(held)),
+            holder,
+        }
+    }
+
+    /// The limit, in bytes
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The bytes `subsystem` holds, across every clone of the budget
+    pub fn held(&self, subsystem: Subsystem) -> usize {
+        self.lock()
+            .holders
+            .values()
+            .map(|held| held[subsystem.index()])
+            .sum()
+    }
+
+    /// The bytes held by every subsystem of every clone together
+    pub fn total(&self) -> usize {
+        self.lock().holders.values().flatten().sum()
+    }
+
+    /// Record that `subsystem` now holds `bytes` for this clone, unless that would take the total
+    /// over the limit, in which case nothing changes. Holding less than before always succeeds.
+    pub fn set(&self, subsystem: Subsystem, bytes: usize) -> Result<(), MemoryError> {
+        let mut held = self.lock();
+        let previous =
+            held.holders.get(&self.holder).copied().unwrap_or_default()[subsystem.index()];
+        let others = held
+            .holders
+            .values()
+            .flatten()
+            .sum::<usize>()
+            .saturating_sub(previous);
+        if bytes > previous && others.saturating_add(bytes) > self.limit {
+            return Err(MemoryError {
+                subsystem,
+                requested: bytes,
+                others,
+                limit: self.limit,
+            });
+        }
+        held.holders.entry(self.holder).or_default()[subsystem.index()] = bytes;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Held> {
+        // the counts are always left consistent, so a panic elsewhere doesn't spoil them
+        self.held
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Clone for MemoryBudget {
+    fn clone(&self) -> Self {
+        let holder = self.lock().add_holder();
+        Self {
+            limit: self.limit,
+            held: Arc::clone(&self.held),
+            holder,
+        }
+    }
+}
+
+impl Drop for MemoryBudget {
+    fn drop(&mut self) {
+        let holder = self.holder;
+        self.lock().holders.remove(&holder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Is the limit shared between subsystems and clones, each clone counting its own, with
+    // shrinking always allowed?
+    #[test]
+    fn test_set() {
+        let budget = MemoryBudget::new(100);
+        let shared = budget.clone();
+        budget.set(Subsystem::Tape, 60).unwrap();
+        shared.set(Subsystem::Traces, 40).unwrap();
+        assert_eq!(
+            budget.set(Subsystem::Snapshots, 1),
+            Err(MemoryError {
+                subsystem: Subsystem::Snapshots,
+                requested: 1,
+                others: 100,
+                limit: 100
+            })
+        );
+        assert_eq!(budget.total(), 100);
+
+        // another clone's tape adds to this one's rather than replacing it
+        assert!(shared.set(Subsystem::Tape, 10).is_err());
+        budget.set(Subsystem::Tape, 10).unwrap();
+        shared.set(Subsystem::Snapshots, 50).unwrap();
+        assert_eq!(budget.held(Subsystem::Snapshots), 50);
+        assert!(budget.set(Subsystem::Tape, 11).is_err());
+
+        drop(shared);
+        assert_eq!(budget.total(), 10);
+    }
+}
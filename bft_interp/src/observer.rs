@@ -0,0 +1,42 @@
+//! Observation of a running [crate::VirtualMachine].
+
+use bft_types::LocalisedInstruction;
+
+use crate::HaltReason;
+
+/// Something that wants to be told what a [crate::VirtualMachine] is doing as it runs. Every
+/// callback is given the machine's clock: the number of instructions it has executed since it was
+/// created. The clock only ever goes up and does not depend on wall-clock time, so it can be used
+/// as a deterministic timestamp by anything that records or replays a run.
+///
+/// Both methods do nothing by default, so implementors only need to provide the ones they want.
+pub trait Observer {
+    /// Called after each instruction has been executed. `program_counter` is the index of the
+    /// instruction that was just executed, and `clock` includes it.
+    fn instruction_executed(
+        &mut self,
+        _clock: u64,
+        _program_counter: usize,
+        _instruction: &LocalisedInstruction,
+    ) {
+    }
+
+    /// Called when [crate::VirtualMachine::interpret] stops without an error
+    fn halted(&mut self, _clock: u64, _halt_reason: HaltReason) {}
+}
+
+/// Lets an observer be lent to a machine, so its results can be read once the machine is done
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn instruction_executed(
+        &mut self,
+        clock: u64,
+        program_counter: usize,
+        instruction: &LocalisedInstruction,
+    ) {
+        (**self).instruction_executed(clock, program_counter, instruction)
+    }
+
+    fn halted(&mut self, clock: u64, halt_reason: HaltReason) {
+        (**self).halted(clock, halt_reason)
+    }
+}
@@ -0,0 +1,218 @@
+//! Putting the terminal into raw mode, so that an interactive program reads each key as it is
+//! pressed, rather than a whole line once Enter is pressed.
+//!
+//! Raw mode here turns off line editing and echo, and leaves everything else alone: Ctrl-C still
+//! interrupts, Enter still reads as `\n`, and output is shown as usual. Programs that want to
+//! see what is typed echo it themselves. The terminal is put back as it was when the [RawMode] is
+//! dropped, or if the process is interrupted with Ctrl-C or Ctrl-\ while it is held.
+//!
+//! Only Linux on x86_64 and aarch64 is supported for now. Elsewhere, [RawMode::stdin] fails with
+//! [std::io::ErrorKind::Unsupported].
+//!
+//! ```no_run
+//!# use bft_interp::raw_mode::RawMode;
+//!# fn main() -> std::io::Result<()> {
+//!  let raw_mode = RawMode::stdin()?;
+//!  // run the program, reading from stdin
+//!  drop(raw_mode);
+//!# Ok(())
+//!# }
+//! ```
+
+use std::io;
+
+/// The terminal on stdin in raw mode, until dropped
+#[derive(Debug)]
+pub struct RawMode {
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fd: std::ffi::c_int,
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    saved: termios::Termios,
+}
+
+impl RawMode {
+    /// Put the terminal on stdin into raw mode. Fails if stdin is not a terminal.
+    pub fn stdin() -> io::Result<Self> {
+        Self::enable(0)
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn enable(fd: std::ffi::c_int) -> io::Result<Self> {
+        let saved = termios::get(fd)?;
+        termios::set(fd, &termios::raw(saved))?;
+        termios::restore_on_interrupt(fd, saved);
+        Ok(Self { fd, saved })
+    }
+
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    fn enable(_fd: i32) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "raw terminal mode is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        termios::stop_restoring_on_interrupt();
+        // there is nothing more to be done if the terminal can't be put back
+        let _ = termios::set(self.fd, &self.saved);
+    }
+}
+
+/// The terminal settings, as Linux lays them out
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod termios {
+    use std::ffi::c_int;
+    use std::io;
+    use std::sync::Mutex;
+
+    /// `struct termios` from `<termios.h>`
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy)]
+    pub struct Termios {
+        c_iflag: u32,
+        c_oflag: u32,
+        c_cflag: u32,
+        c_lflag: u32,
+        c_line: u8,
+        c_cc: [u8; 32],
+        c_ispeed: u32,
+        c_ospeed: u32,
+    }
+
+    /// Line editing, in `c_lflag`
+    const ICANON: u32 = 0o2;
+    /// Echoing typed characters, in `c_lflag`
+    const ECHO: u32 = 0o10;
+    /// The read timeout, in `c_cc`
+    const VTIME: usize = 5;
+    /// The fewest bytes a read waits for, in `c_cc`
+    const VMIN: usize = 6;
+    /// Change the settings straight away
+    const TCSANOW: c_int = 0;
+
+    const SIGINT: c_int = 2;
+    const SIGQUIT: c_int = 3;
+    const SIG_DFL: usize = 0;
+
+    extern "C" {
+        fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        fn tcsetattr(fd: c_int, optional_actions: c_int, termios: *const Termios) -> c_int;
+        fn signal(signum: c_int, handler: usize) -> usize;
+        fn raise(signum: c_int) -> c_int;
+    }
+
+    /// The settings to put back if the process is interrupted. Only ever read by the signal
+    /// handler with `try_lock`, which is safe to do there, as it never waits.
+    static ON_INTERRUPT: Mutex<Option<(c_int, Termios)>> = Mutex::new(None);
+
+    pub fn get(fd: c_int) -> io::Result<Termios> {
+        let mut termios = std::mem::MaybeUninit::<Termios>::uninit();
+        // SAFETY: tcgetattr fills in the whole struct when it succeeds
+        match unsafe { tcgetattr(fd, termios.as_mut_ptr()) } {
+            0 => Ok(unsafe { termios.assume_init() }),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub fn set(fd: c_int, termios: &Termios) -> io::Result<()> {
+        // SAFETY: the struct is a valid termios, read from the terminal
+        match unsafe { tcsetattr(fd, TCSANOW, termios) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// `termios` with line editing and echo turned off, and reads returning after each byte
+    pub fn raw(mut termios: Termios) -> Termios {
+        termios.c_lflag &= !(ICANON | ECHO);
+        termios.c_cc[VMIN] = 1;
+        termios.c_cc[VTIME] = 0;
+        termios
+    }
+
+    extern "C" fn on_interrupt(signum: c_int) {
+        if let Ok(saved) = ON_INTERRUPT.try_lock() {
+            if let Some((fd, termios)) = &*saved {
+                // SAFETY: tcsetattr is async-signal-safe
+                unsafe { tcsetattr(*fd, TCSANOW, termios) };
+            }
+        }
+        // SAFETY: signal and raise are async-signal-safe; this ends the process as the signal
+        // would have without the handler
+        unsafe {
+            signal(signum, SIG_DFL);
+            raise(signum);
+        }
+    }
+
+    /// Put `termios` back on `fd` if the process is interrupted from the keyboard
+    pub fn restore_on_interrupt(fd: c_int, termios: Termios) {
+        *ON_INTERRUPT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some((fd, termios));
+        for signum in [SIGINT, SIGQUIT] {
+            // SAFETY: the handler only does what is safe to do in a signal handler
+            unsafe { signal(signum, on_interrupt as extern "C" fn(c_int) as usize) };
+        }
+    }
+
+    /// Let interrupts end the process as usual again
+    pub fn stop_restoring_on_interrupt() {
+        for signum in [SIGINT, SIGQUIT] {
+            // SAFETY: restores the default action
+            unsafe { signal(signum, SIG_DFL) };
+        }
+        *ON_INTERRUPT
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Is a file that isn't a terminal refused?
+    #[test]
+    fn test_not_a_terminal() {
+        #[cfg(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        {
+            use std::os::fd::AsRawFd;
+            let file = std::fs::File::open(std::env::current_exe().unwrap()).unwrap();
+            assert!(RawMode::enable(file.as_raw_fd()).is_err());
+        }
+        #[cfg(not(all(
+            target_os = "linux",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        )))]
+        assert_eq!(
+            RawMode::stdin().unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! Running several [VirtualMachine]s together on one thread, taking turns, with a [Scheduler]
+//! deciding whose turn it is. Machines can be connected so that one's output becomes another's
+//! input, to run programs as a pipeline.
+//!
+//! Each turn runs a machine for up to a set number of instructions, or until it needs input that
+//! hasn't arrived yet. [RoundRobin] gives every machine a turn in order, [ByPriority] always runs
+//! the most important machine that can run, and [IoDriven] favours machines with input waiting,
+//! so data moves along a pipeline as soon as it can. Embedders can supply their own.
+//!
+//! ```
+//!# use bft_interp::scheduler::{IoDriven, Machines};
+//!# use bft_interp::{EofBehavior, VirtualMachine};
+//!# use bft_types::BfProgram;
+//!# fn main() -> Result<(), Box<dyn std::error::Error>>{
+//!  // the first adds one to each byte, the second echoes it back
+//!  let add_one = BfProgram::new("add_one.bf", ",[+.,]")?;
+//!  let echo = BfProgram::new("echo.bf", ",[.,]")?;
+//!
+//!  let mut machines = Machines::new(IoDriven::default());
+//!  let first = machines.add(VirtualMachine::<u8>::new(&add_one, None, false), 0);
+//!  let second = machines.add(
+//!      VirtualMachine::<u8>::new(&echo, None, false).with_eof_behavior(EofBehavior::SetZero),
+//!      0,
+//!  );
+//!  machines.connect(first, second);
+//!  machines.send(first, b"HAL\0");
+//!  machines.run()?;
+//!  assert_eq!(machines.output(second), b"IBM");
+//!# Ok(())
+//!# }
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read};
+use std::num::NonZeroU64;
+
+use thiserror::Error;
+
+use crate::{CellKind, HaltReason, Step, VMError, VirtualMachine};
+
+/// How many instructions a machine runs in one turn, unless set with [Machines::with_quantum]
+const DEFAULT_QUANTUM: u64 = 1024;
+
+/// Whether a machine can take a turn
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TaskState {
+    /// It has instructions to run
+    Runnable,
+    /// It is at a `,`, and none of its input has arrived yet
+    WaitingForInput,
+    /// It has stopped, and won't run again
+    Stopped(HaltReason),
+    /// It failed with an error, and won't run again
+    Failed,
+}
+
+/// A machine failed, stopping the others
+#[derive(Debug, Error)]
+#[error("machine {index} failed: {error}")]
+pub struct MachineError {
+    /// The index of the machine that failed
+    pub index: usize,
+    /// Why it failed
+    #[source]
+    pub error: VMError,
+}
+
+/// Why [Machines::run] stopped before every machine had stopped or was waiting for input
+#[derive(Debug, Error)]
+pub enum RunError {
+    /// A machine failed, stopping the others
+    #[error(transparent)]
+    Failed(#[from] MachineError),
+    /// The [Scheduler] chose a machine that does not exist
+    #[error("the scheduler chose machine {index}, but there are only {machines}")]
+    NoSuchMachine { index: usize, machines: usize },
+    /// The [Scheduler] chose a machine that cannot take a turn
+    #[error("the scheduler chose machine {index}, which is not runnable but {state:?}")]
+    NotRunnable { index: usize, state: TaskState },
+}
+
+/// What a [Scheduler] can see of each machine
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TaskView {
+    /// Whether it can take a turn
+    pub state: TaskState,
+    /// How important it is, as given to [Machines::add]. Higher runs first with [ByPriority].
+    pub priority: i32,
+    /// The bytes of input waiting for it
+    pub pending_input: usize,
+    /// The instructions it has run so far
+    pub clock: u64,
+}
+
+/// Decides which machine takes the next turn
+pub trait Scheduler {
+    /// The index of the machine to run next, which must be [TaskState::Runnable], or `None` to
+    /// stop. A machine that is waiting for input is made runnable again before this is called if
+    /// input has arrived for it.
+    fn next(&mut self, tasks: &[TaskView]) -> Option<usize>;
+}
+
+/// Each runnable machine in turn, in the order they were added
+#[derive(Debug, Default, Clone)]
+pub struct RoundRobin {
+    last: Option<usize>,
+}
+
+impl Scheduler for RoundRobin {
+    fn next(&mut self, tasks: &[TaskView]) -> Option<usize> {
+        let start = self.last.map_or(0, |last| last + 1);
+        let next = (0..tasks.len())
+            .map(|offset| (start + offset) % tasks.len())
+            .find(|index| tasks[*index].state == TaskState::Runnable)?;
+        self.last = Some(next);
+        Some(next)
+    }
+}
+
+/// The runnable machine with the highest priority, sharing turns between equals by running the
+/// one that has run the fewest instructions
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ByPriority;
+
+impl Scheduler for ByPriority {
+    fn next(&mut self, tasks: &[TaskView]) -> Option<usize> {
+        tasks
+            .iter()
+            .enumerate()
+            .filter(|(_, task)| task.state == TaskState::Runnable)
+            .max_by_key(|(index, task)| (task.priority, std::cmp::Reverse(task.clock), *index))
+            .map(|(index, _)| index)
+    }
+}
+
+/// Runnable machines with input waiting first, so that data moves along a pipeline as soon as it
+/// can, then the rest in turn
+#[derive(Debug, Default, Clone)]
+pub struct IoDriven {
+    round_robin: RoundRobin,
+}
+
+impl Scheduler for IoDriven {
+    fn next(&mut self, tasks: &[TaskView]) -> Option<usize> {
+        tasks
+            .iter()
+            .position(|task| task.state == TaskState::Runnable && task.pending_input > 0)
+            .or_else(|| self.round_robin.next(tasks))
+    }
+}
+
+/// A machine and its input and output
+struct Task<'a, T> {
+    vm: VirtualMachine<'a, T>,
+    priority: i32,
+    state: TaskState,
+    input: Inbox,
+    output: Vec<u8>,
+    /// The machine whose input this one's output goes to, if any
+    connected_to: Option<usize>,
+}
+
+/// Input that has arrived for a machine. Reading from it when it is empty blocks, so the machine
+/// waits, until it is closed.
+#[derive(Debug, Default)]
+struct Inbox {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Read for Inbox {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.bytes.is_empty() && !self.closed {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        self.bytes.read(buf)
+    }
+}
+
+/// Machines taking turns under a [Scheduler]
+pub struct Machines<'a, T, S> {
+    tasks: Vec<Task<'a, T>>,
+    scheduler: S,
+    quantum: NonZeroU64,
+}
+
+impl<'a, T: CellKind, S: Scheduler> Machines<'a, T, S> {
+    /// No machines yet, to be scheduled by `scheduler`
+    pub fn new(scheduler: S) -> Self {
+        Self {
+            tasks: Vec::new(),
+            scheduler,
+            quantum: NonZeroU64::new(DEFAULT_QUANTUM).expect("the default quantum is not zero"),
+        }
+    }
+
+    /// Run each machine for at most `quantum` instructions a turn
+    pub fn with_quantum(mut self, quantum: NonZeroU64) -> Self {
+        self.quantum = quantum;
+        self
+    }
+
+    /// Add a machine, returning its index. It gets no input until some is sent or connected to
+    /// it.
+    pub fn add(&mut self, vm: VirtualMachine<'a, T>, priority: i32) -> usize {
+        self.tasks.push(Task {
+            vm,
+            priority,
+            state: TaskState::Runnable,
+            input: Inbox::default(),
+            output: Vec::new(),
+            connected_to: None,
+        });
+        self.tasks.len() - 1
+    }
+
+    /// Send everything machine `from` outputs to machine `to` as its input, closing `to`'s input
+    /// once `from` stops
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.tasks[from].connected_to = Some(to);
+    }
+
+    /// Give machine `index` some input
+    pub fn send(&mut self, index: usize, bytes: &[u8]) {
+        self.tasks[index].input.bytes.extend(bytes);
+    }
+
+    /// Tell machine `index` that no more input is coming, so it reads the end of input once it
+    /// has used up what it has
+    pub fn close_input(&mut self, index: usize) {
+        self.tasks[index].input.closed = true;
+    }
+
+    /// Everything machine `index` has output that hasn't been passed on to a connected machine
+    pub fn output(&self, index: usize) -> &[u8] {
+        &self.tasks[index].output
+    }
+
+    /// Whether machine `index` can take a turn
+    pub fn state(&self, index: usize) -> TaskState {
+        self.tasks[index].state
+    }
+
+    /// Machine `index`
+    pub fn vm(&self, index: usize) -> &VirtualMachine<'a, T> {
+        &self.tasks[index].vm
+    }
+
+    /// Take turns until no machine can run, because they have all stopped or are waiting for
+    /// input. A machine that fails stops all of them, as does the scheduler choosing a machine
+    /// that is not [TaskState::Runnable].
+    pub fn run(&mut self) -> Result<(), RunError> {
+        loop {
+            for task in &mut self.tasks {
+                if task.state == TaskState::WaitingForInput
+                    && (!task.input.bytes.is_empty() || task.input.closed)
+                {
+                    task.state = TaskState::Runnable;
+                }
+            }
+            let views: Vec<TaskView> = self
+                .tasks
+                .iter()
+                .map(|task| TaskView {
+                    state: task.state,
+                    priority: task.priority,
+                    pending_input: task.input.bytes.len(),
+                    clock: task.vm.clock(),
+                })
+                .collect();
+            let Some(index) = self.scheduler.next(&views) else {
+                return Ok(());
+            };
+            match views.get(index) {
+                None => {
+                    return Err(RunError::NoSuchMachine {
+                        index,
+                        machines: views.len(),
+                    })
+                }
+                Some(view) if view.state != TaskState::Runnable => {
+                    return Err(RunError::NotRunnable {
+                        index,
+                        state: view.state,
+                    })
+                }
+                Some(_) => {}
+            }
+            self.turn(index)
+                .map_err(|error| MachineError { index, error })?;
+        }
+    }
+
+    /// Run machine `index` for one turn, then pass its output on
+    fn turn(&mut self, index: usize) -> Result<(), VMError> {
+        let task = &mut self.tasks[index];
+        let mut result = Ok(());
+        for _ in 0..self.quantum.get() {
+            match task.vm.step(&mut task.input, &mut task.output) {
+                Ok(Step::Executed {
+                    finished: false, ..
+                }) => {}
+                Ok(Step::Executed { finished: true, .. }) => {
+                    task.state = TaskState::Stopped(HaltReason::Completed);
+                    break;
+                }
+                Ok(Step::Halted(HaltReason::NeedsInput)) => {
+                    task.state = TaskState::WaitingForInput;
+                    break;
+                }
+                Ok(Step::Halted(halt_reason)) => {
+                    task.state = TaskState::Stopped(halt_reason);
+                    break;
+                }
+                Err(error) => {
+                    task.state = TaskState::Failed;
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+
+        let stopped = matches!(task.state, TaskState::Stopped(_) | TaskState::Failed);
+        if let Some(to) = task.connected_to {
+            let output = std::mem::take(&mut task.output);
+            let to = &mut self.tasks[to];
+            to.input.bytes.extend(output);
+            to.input.closed |= stopped;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::BfProgram;
+
+    /// A view of a runnable machine
+    fn runnable(priority: i32, pending_input: usize, clock: u64) -> TaskView {
+        TaskView {
+            state: TaskState::Runnable,
+            priority,
+            pending_input,
+            clock,
+        }
+    }
+
+    // Does each scheduler pick the machine it should, skipping those that can't run?
+    #[test]
+    fn test_schedulers() {
+        let waiting = TaskView {
+            state: TaskState::WaitingForInput,
+            ..runnable(9, 0, 0)
+        };
+        let tasks = [
+            runnable(1, 0, 50),
+            waiting,
+            runnable(1, 0, 10),
+            runnable(0, 3, 0),
+        ];
+
+        let mut round_robin = RoundRobin::default();
+        let turns: Vec<_> = (0..4).map(|_| round_robin.next(&tasks).unwrap()).collect();
+        assert_eq!(turns, vec![0, 2, 3, 0]);
+
+        assert_eq!(ByPriority.next(&tasks), Some(2));
+        assert_eq!(IoDriven::default().next(&tasks), Some(3));
+        assert_eq!(RoundRobin::default().next(&[waiting]), None);
+    }
+
+    // Do turns interleave machines that never wait, and does a pipeline pass its data along?
+    #[test]
+    fn test_machines() {
+        let counter = BfProgram::new("counter.bf", "++++[-.]").unwrap();
+        let mut machines =
+            Machines::new(RoundRobin::default()).with_quantum(NonZeroU64::new(2).unwrap());
+        let first = machines.add(VirtualMachine::<u8>::new(&counter, None, false), 0);
+        let second = machines.add(VirtualMachine::<u8>::new(&counter, None, false), 0);
+        machines.run().unwrap();
+        assert_eq!(machines.output(first), [3, 2, 1, 0]);
+        assert_eq!(machines.output(second), [3, 2, 1, 0]);
+        assert_eq!(
+            machines.state(first),
+            TaskState::Stopped(HaltReason::Completed)
+        );
+
+        let echo = BfProgram::new("echo.bf", ",[.,]").unwrap();
+        let mut machines = Machines::new(IoDriven::default());
+        let stages: Vec<_> = (0..3)
+            .map(|_| {
+                machines.add(
+                    VirtualMachine::<u8>::new(&echo, None, false)
+                        .with_eof_behavior(crate::EofBehavior::SetZero),
+                    0,
+                )
+            })
+            .collect();
+        machines.connect(stages[0], stages[1]);
+        machines.connect(stages[1], stages[2]);
+        machines.send(stages[0], b"abc");
+        machines.run().unwrap();
+        assert_eq!(machines.output(stages[2]), b"abc");
+        assert_eq!(machines.state(stages[0]), TaskState::WaitingForInput);
+
+        machines.close_input(stages[0]);
+        machines.run().unwrap();
+        assert_eq!(
+            machines.state(stages[2]),
+            TaskState::Stopped(HaltReason::Completed)
+        );
+    }
+
+    /// A scheduler that always chooses the same machine, whatever state it is in
+    struct Always(usize);
+
+    impl Scheduler for Always {
+        fn next(&mut self, _tasks: &[TaskView]) -> Option<usize> {
+            Some(self.0)
+        }
+    }
+
+    // Is a scheduler that chooses a machine that can't run refused rather than trusted?
+    #[test]
+    fn test_invalid_choice() {
+        let program = BfProgram::new("plus.bf", "+").unwrap();
+        let mut machines = Machines::new(Always(1));
+        machines.add(VirtualMachine::<u8>::new(&program, None, false), 0);
+        assert!(matches!(
+            machines.run(),
+            Err(RunError::NoSuchMachine {
+                index: 1,
+                machines: 1
+            })
+        ));
+
+        let mut machines = Machines::new(Always(0));
+        machines.add(VirtualMachine::<u8>::new(&program, None, false), 0);
+        assert!(matches!(
+            machines.run(),
+            Err(RunError::NotRunnable {
+                index: 0,
+                state: TaskState::Stopped(HaltReason::Completed)
+            })
+        ));
+    }
+}
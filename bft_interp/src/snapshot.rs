@@ -0,0 +1,228 @@
+//! Checkpoints of a [crate::VirtualMachine] part way through a program, for carrying on from the
+//! same place later, perhaps in another process.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use bft_types::messages::{self, Localise, Message};
+use thiserror::Error;
+
+/// The first line of a saved [TapeState], followed by the version of the format
+const HEADER: &str = "bft-tape";
+
+/// The first line of a saved [VmState], followed by the version of the format
+const VM_HEADER: &str = "bft-vm";
+
+/// The version of the saved formats, raised whenever either changes
+const VERSION: u32 = 1;
+
+/// Everything needed to carry on running a program from where a machine was: its tape, where its
+/// head is and which instruction is next. Taken with [crate::VirtualMachine::snapshot] and put
+/// back with [crate::VirtualMachine::restore].
+///
+/// It is saved as text with [Display] and read back with [FromStr], for any cell type that can be
+/// written and parsed:
+///
+/// ```text
+/// bft-tape 1
+/// head 2
+/// origin 0
+/// program_counter 14
+/// cells 72 101 0 0
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TapeState<T> {
+    /// Every cell on the tape, as in [crate::VirtualMachine::tape]
+    pub cells: Vec<T>,
+    /// Index in `cells` of the cell under the head
+    pub head: usize,
+    /// Index in `cells` of cell 0, as in [crate::VirtualMachine::origin]
+    pub origin: usize,
+    /// Index of the next instruction to execute
+    pub program_counter: usize,
+}
+
+/// A machine paused part way through a program, as returned in [crate::RunOutcome::Paused]: its
+/// [TapeState] and how many instructions it had executed. Given to
+/// [crate::VirtualMachine::resume] to carry on, perhaps after a restart of the process, as it can
+/// be saved as text like a [TapeState]:
+///
+/// ```text
+/// bft-vm 1
+/// clock 1000
+/// bft-tape 1
+/// ...
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VmState<T> {
+    /// The tape, head and next instruction
+    pub tape: TapeState<T>,
+    /// The machine's clock, as in [crate::VirtualMachine::clock]
+    pub clock: u64,
+}
+
+/// Problems reading a saved [TapeState], or restoring one to a machine
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The saved text could not be understood
+    Invalid { reason: String },
+    /// The state cannot be put back on this machine, for example because its program is shorter
+    Mismatch { reason: String },
+}
+
+impl SnapshotError {
+    fn invalid(reason: impl Into<String>) -> Self {
+        SnapshotError::Invalid {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Localise for SnapshotError {
+    fn message(&self) -> Message {
+        match self {
+            SnapshotError::Invalid { reason } => {
+                Message::new(messages::SNAPSHOT_INVALID, vec![("reason", reason.clone())])
+            }
+            SnapshotError::Mismatch { reason } => Message::new(
+                messages::SNAPSHOT_MISMATCH,
+                vec![("reason", reason.clone())],
+            ),
+        }
+    }
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl<T: Display> Display for TapeState<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", HEADER, VERSION)?;
+        writeln!(f, "head {}", self.head)?;
+        writeln!(f, "origin {}", self.origin)?;
+        writeln!(f, "program_counter {}", self.program_counter)?;
+        write!(f, "cells")?;
+        for cell in &self.cells {
+            write!(f, " {}", cell)?;
+        }
+        writeln!(f)
+    }
+}
+
+impl<T: FromStr> FromStr for TapeState<T> {
+    type Err = SnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        let mut field = |name: &str| {
+            let line = lines
+                .next()
+                .ok_or_else(|| SnapshotError::invalid(format!("missing '{}' line", name)))?;
+            match line.split_once(' ') {
+                Some((found, value)) if found == name => Ok(value),
+                _ if line == name => Ok(""),
+                _ => Err(SnapshotError::invalid(format!(
+                    "expected '{}', found '{}'",
+                    name, line
+                ))),
+            }
+        };
+        let number = |name: &str, value: &str| {
+            value.parse::<usize>().map_err(|_| {
+                SnapshotError::invalid(format!("{} '{}' is not a number", name, value))
+            })
+        };
+
+        let version = field(HEADER)?;
+        if version != VERSION.to_string() {
+            return Err(SnapshotError::invalid(format!(
+                "version {} is not supported, expected {}",
+                version, VERSION
+            )));
+        }
+        let head = number("head", field("head")?)?;
+        let origin = number("origin", field("origin")?)?;
+        let program_counter = number("program_counter", field("program_counter")?)?;
+        let cells = field("cells")?
+            .split_whitespace()
+            .map(|cell| {
+                cell.parse()
+                    .map_err(|_| SnapshotError::invalid(format!("invalid cell value '{}'", cell)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            cells,
+            head,
+            origin,
+            program_counter,
+        })
+    }
+}
+
+impl<T: Display> Display for VmState<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", VM_HEADER, VERSION)?;
+        writeln!(f, "clock {}", self.clock)?;
+        write!(f, "{}", self.tape)
+    }
+}
+
+impl<T: FromStr> FromStr for VmState<T> {
+    type Err = SnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.splitn(3, '\n');
+        let header = format!("{} {}", VM_HEADER, VERSION);
+        if lines.next() != Some(header.as_str()) {
+            return Err(SnapshotError::invalid(format!("expected '{}'", header)));
+        }
+        let clock = lines
+            .next()
+            .and_then(|line| line.strip_prefix("clock "))
+            .and_then(|clock| clock.parse().ok())
+            .ok_or_else(|| SnapshotError::invalid("expected 'clock' and a number"))?;
+        Ok(Self {
+            tape: lines.next().unwrap_or_default().parse()?,
+            clock,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    // Does a state read back the same as it was saved, and is damaged text refused?
+    #[test]
+    fn test_save_and_load() {
+        let state = TapeState {
+            cells: vec![-3i32, 0, 70000],
+            head: 2,
+            origin: 1,
+            program_counter: 9,
+        };
+        let saved = state.to_string();
+        assert_eq!(
+            saved,
+            "bft-tape 1\nhead 2\norigin 1\nprogram_counter 9\ncells -3 0 70000\n"
+        );
+        assert_eq!(saved.parse(), Ok(state));
+
+        assert_matches!(
+            "bft-tape 2\n".parse::<TapeState<u8>>(),
+            Err(SnapshotError::Invalid { .. })
+        );
+        assert!(
+            "bft-tape 1\nhead 0\norigin 0\nprogram_counter 0\ncells 1 256\n"
+                .parse::<TapeState<u8>>()
+                .is_err()
+        );
+        assert!("bft-tape 1\nhead 0\nprogram_counter 0\ncells 1\n"
+            .parse::<TapeState<u8>>()
+            .is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//! Statistics about the work a [crate::VirtualMachine] has done.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Running totals for a [crate::VirtualMachine], covering every call to
+/// [crate::VirtualMachine::interpret] since it was created
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct RunStats {
+    /// Number of instructions executed. This is the same as [crate::VirtualMachine::clock].
+    pub instructions_executed: u64,
+    /// Wall-clock time spent inside [crate::VirtualMachine::interpret]
+    pub elapsed: Duration,
+    /// Wall-clock time spent in [crate::VirtualMachine::warm_up], kept separate from `elapsed`
+    pub warm_up: Duration,
+    /// Number of cells currently on the tape
+    pub tape_len: usize,
+    /// The furthest cell to the right that the head has visited
+    pub peak_head: usize,
+    /// Number of bytes read by `,` instructions
+    pub bytes_input: u64,
+    /// Number of bytes written by `.` instructions
+    pub bytes_output: u64,
+}
+
+impl Display for RunStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} instructions in {:.3}ms, {} bytes in, {} bytes out, {} cells on tape (peak head {})",
+            self.instructions_executed,
+            self.elapsed.as_secs_f64() * 1000.0,
+            self.bytes_input,
+            self.bytes_output,
+            self.tape_len,
+            self.peak_head
+        )?;
+        if !self.warm_up.is_zero() {
+            write!(
+                f,
+                ", after {:.3}ms tape warm-up",
+                self.warm_up.as_secs_f64() * 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+//! Testing interactive programs, such as menus and games, by scripting a conversation with them as
+//! if through a terminal: wait for some text, type a reply, and so on.
+//!
+//! The program runs on a background thread while the script is played against it. Each expected
+//! piece of text must appear in the output, after the text matched before it, within the timeout;
+//! once the script is finished, the program's input is closed and it must complete. The outcome
+//! only depends on the program's output, not on how fast it runs, unless a step times out.
+//!
+//! ```
+//!# use bft_interp::terminal::Terminal;
+//!# use bft_interp::{EofBehavior, VirtualMachine};
+//!# use bft_types::BfProgram;
+//!# fn main() -> Result<(), Box<dyn std::error::Error>>{
+//!  // prompts with '>' and echoes each character typed at it
+//!  let echo = BfProgram::new("echo.bf", "++++++[>++++++++++<-]>++.>,[.<.>,]")?;
+//!  let output = Terminal::new()
+//!      .expect(">")
+//!      .send("a")
+//!      .expect("a>")
+//!      .send("b")
+//!      .expect("b>")
+//!      .run(&echo, |program| {
+//!          VirtualMachine::<u8>::new(program, None, false).with_eof_behavior(EofBehavior::SetZero)
+//!      })?;
+//!  assert_eq!(output, b">a>b>");
+//!# Ok(())
+//!# }
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bft_types::BfProgram;
+use thiserror::Error;
+
+use crate::{CellKind, HaltReason, VMError, VirtualMachine};
+
+/// How long each step waits by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why a scripted conversation with a program failed
+#[derive(Debug, Error)]
+pub enum TerminalError {
+    /// The expected text did not appear in time
+    #[error("timed out waiting for {expected:?}, after the program wrote {output:?}")]
+    Timeout { expected: String, output: String },
+    /// The program stopped before writing the expected text
+    #[error("the program stopped before writing {expected:?}, after writing {output:?}")]
+    Ended { expected: String, output: String },
+    /// The program did not complete in time once the script was finished
+    #[error("the program was still running at the end of the script, after writing {output:?}")]
+    StillRunning { output: String },
+    /// The program failed with an error
+    #[error(transparent)]
+    Program(#[from] VMError),
+    /// The program stopped before it completed, for example because of a limit
+    #[error("program stopped early: {0}")]
+    Stopped(HaltReason),
+}
+
+/// One step of a script
+#[derive(Debug, Clone)]
+enum Step {
+    /// Wait for this text in the output
+    Expect(String),
+    /// Type these bytes
+    Send(Vec<u8>),
+}
+
+/// A script to play against an interactive program
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    steps: Vec<Step>,
+    timeout: Duration,
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self {
+            steps: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl Terminal {
+    /// An empty script, where each step waits up to five seconds
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait up to `timeout` for each expected piece of text, and for the program to complete
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Wait for `text` to appear in the output, after anything matched before
+    pub fn expect(mut self, text: impl Into<String>) -> Self {
+        self.steps.push(Step::Expect(text.into()));
+        self
+    }
+
+    /// Type `text` as the program's input
+    pub fn send(mut self, text: impl AsRef<[u8]>) -> Self {
+        self.steps.push(Step::Send(text.as_ref().to_vec()));
+        self
+    }
+
+    /// Type `line` followed by a newline, as pressing enter would
+    pub fn send_line(self, line: &str) -> Self {
+        self.send(format!("{}\n", line))
+    }
+
+    /// Play the script against `program`, run by the machine `build` makes for it on a background
+    /// thread, so limits, the end-of-input behaviour and so on can be set. Returns everything the
+    /// program wrote if the script ran to the end and the program completed.
+    pub fn run<'p, T, F>(&self, program: &'p BfProgram, build: F) -> Result<Vec<u8>, TerminalError>
+    where
+        T: CellKind,
+        F: FnOnce(&'p BfProgram) -> VirtualMachine<'p, T> + Send,
+    {
+        let (input_sender, input_receiver) = mpsc::channel();
+        let (output_sender, output_receiver) = mpsc::channel();
+        let (token_sender, token_receiver) = mpsc::channel();
+
+        thread::scope(|scope| {
+            let machine = scope.spawn(move || {
+                let mut vm = build(program);
+                // the script can't go on without the token, so it's fine if it's gone
+                let _ = token_sender.send(vm.cancel_token());
+                let mut input = TypedInput {
+                    keys: input_receiver,
+                    typed: VecDeque::new(),
+                };
+                vm.interpret(&mut input, &mut Screen(output_sender))
+            });
+            let cancel_token = token_receiver.recv().ok();
+
+            let mut screen = Vec::new();
+            let played = self.play(input_sender, &output_receiver, &mut screen);
+            if played.is_err() {
+                if let Some(cancel_token) = cancel_token {
+                    cancel_token.cancel();
+                }
+            }
+            let halt_reason = match machine.join() {
+                Ok(result) => result,
+                Err(panic) => std::panic::resume_unwind(panic),
+            };
+
+            played?;
+            match halt_reason? {
+                HaltReason::Completed => Ok(screen),
+                halt_reason => Err(TerminalError::Stopped(halt_reason)),
+            }
+        })
+    }
+
+    /// Play each step, collecting the output in `screen`, then close the input and wait for the
+    /// program to finish writing
+    fn play(
+        &self,
+        keyboard: Sender<Vec<u8>>,
+        output: &Receiver<Vec<u8>>,
+        screen: &mut Vec<u8>,
+    ) -> Result<(), TerminalError> {
+        // how much of the screen has been matched by earlier steps
+        let mut matched = 0;
+        for step in &self.steps {
+            match step {
+                // the program may have stopped already, which the next expect will find
+                Step::Send(keys) => {
+                    let _ = keyboard.send(keys.clone());
+                }
+                Step::Expect(text) => {
+                    let deadline = Instant::now() + self.timeout;
+                    loop {
+                        if let Some(end) = find(&screen[matched..], text.as_bytes()) {
+                            matched += end;
+                            break;
+                        }
+                        let waited =
+                            output.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+                        match waited {
+                            Ok(written) => screen.extend(written),
+                            Err(RecvTimeoutError::Timeout) => {
+                                return Err(TerminalError::Timeout {
+                                    expected: text.clone(),
+                                    output: String::from_utf8_lossy(screen).into_owned(),
+                                })
+                            }
+                            Err(RecvTimeoutError::Disconnected) => {
+                                return Err(TerminalError::Ended {
+                                    expected: text.clone(),
+                                    output: String::from_utf8_lossy(screen).into_owned(),
+                                })
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        drop(keyboard);
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(written) => screen.extend(written),
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(TerminalError::StillRunning {
+                        output: String::from_utf8_lossy(screen).into_owned(),
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// The index just after the first occurrence of `text` in `screen`
+fn find(screen: &[u8], text: &[u8]) -> Option<usize> {
+    if text.is_empty() {
+        return Some(0);
+    }
+    screen
+        .windows(text.len())
+        .position(|window| window == text)
+        .map(|start| start + text.len())
+}
+
+/// The program's input: whatever the script has typed, ending once the script is finished
+struct TypedInput {
+    keys: Receiver<Vec<u8>>,
+    typed: VecDeque<u8>,
+}
+
+impl Read for TypedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.typed.is_empty() {
+            match self.keys.recv() {
+                Ok(keys) => self.typed.extend(keys),
+                Err(_) => return Ok(0),
+            }
+        }
+        self.typed.read(buf)
+    }
+}
+
+/// The program's output, passed back to the script as it is written
+struct Screen(Sender<Vec<u8>>);
+
+impl Write for Screen {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // once the script has given up, the output has nowhere to go, and isn't needed
+        let _ = self.0.send(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EofBehavior;
+    use assert_matches::assert_matches;
+
+    /// A machine that treats the end of the script as a zero byte
+    fn machine(program: &BfProgram) -> VirtualMachine<'_, u8> {
+        VirtualMachine::new(program, None, false).with_eof_behavior(EofBehavior::SetZero)
+    }
+
+    // Does a scripted conversation with a menu run through, with text split across writes?
+    #[test]
+    fn test_conversation() {
+        // asks for a digit with '?', then writes back that many '*'s
+        let text = format!(
+            "+++++++[>+++++++++<-]>.>,{}<{}>[<.>-]",
+            "-".repeat(48),
+            "-".repeat(21)
+        );
+        let stars = BfProgram::new("stars.bf", &text).unwrap();
+        let output = Terminal::new()
+            .expect("?")
+            .send("3")
+            .expect("***")
+            .run(&stars, machine)
+            .unwrap();
+        assert_eq!(output, b"?***");
+    }
+
+    // Are text that never comes, a program that stops early and one that never ends all reported?
+    #[test]
+    fn test_failures() {
+        let waits = BfProgram::new("waits.bf", ",").unwrap();
+        let short = Terminal::new().with_timeout(Duration::from_millis(50));
+        assert_matches!(
+            short.clone().expect("hello").run(&waits, machine),
+            Err(TerminalError::Timeout { expected, output }) if expected == "hello" && output.is_empty()
+        );
+
+        let quiet = BfProgram::new("quiet.bf", "+").unwrap();
+        assert_matches!(
+            short.clone().expect("hello").run(&quiet, machine),
+            Err(TerminalError::Ended { .. })
+        );
+
+        let forever = BfProgram::new("forever.bf", "+[]").unwrap();
+        assert_matches!(
+            short.clone().run(&forever, machine),
+            Err(TerminalError::StillRunning { .. })
+        );
+
+        assert_matches!(
+            short.run(&waits, |program| VirtualMachine::<u8>::new(
+                program, None, false
+            )),
+            Err(TerminalError::Program(VMError::ReadError(..)))
+        );
+    }
+}
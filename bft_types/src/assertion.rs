@@ -0,0 +1,116 @@
+//! Inline assertions about the state of the machine, written in a program as `@assert` directives.
+//!
+//! Assertions are an opt-in dialect (see [crate::ParseOptions]). A directive runs from `@assert`
+//! to the end of its line, and nothing after it on the line is treated as code. It is checked
+//! every time the program reaches the point where it appears, before the next instruction runs:
+//!
+//! ```text
+//! ++++++++[>+++++++++<-]>   @assert head=1 cell=72 cell0=0
+//! ```
+
+use std::fmt::Display;
+
+/// A single condition checked by an [Assertion]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum AssertionCheck {
+    /// `head=N`: the head is over cell N
+    Head(usize),
+    /// `cell=V`: the cell under the head holds V
+    CurrentCell(u8),
+    /// `cellN=V`: cell N holds V
+    Cell(usize, u8),
+}
+
+impl Display for AssertionCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssertionCheck::Head(head) => write!(f, "head={}", head),
+            AssertionCheck::CurrentCell(value) => write!(f, "cell={}", value),
+            AssertionCheck::Cell(cell, value) => write!(f, "cell{}={}", cell, value),
+        }
+    }
+}
+
+/// An `@assert` directive and where it appears in the program
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct Assertion {
+    /// Line of the `@` that starts the directive
+    pub line_num: usize,
+    /// Column of the `@` that starts the directive
+    pub column_num: usize,
+    /// Index of the instruction that the assertion is checked before. If the directive comes after
+    /// the last instruction, this is the length of the program, and it is checked when the program
+    /// completes.
+    pub before_instruction: usize,
+    /// Every condition that must hold
+    pub checks: Vec<AssertionCheck>,
+}
+
+/// The text that starts a directive
+pub(crate) const DIRECTIVE: &str = "@assert";
+
+/// Parse the conditions following `@assert` on a line. Returns a description of the problem if
+/// they cannot be understood.
+pub(crate) fn parse_checks(text: &str) -> Result<Vec<AssertionCheck>, String> {
+    let checks = text
+        .split_whitespace()
+        .map(|term| {
+            let (name, value) = term
+                .split_once('=')
+                .ok_or_else(|| format!("expected name=value, got '{}'", term))?;
+            let bad_number = || format!("'{}' is not a valid number in '{}'", value, term);
+            match name {
+                "head" => Ok(AssertionCheck::Head(
+                    value.parse().map_err(|_| bad_number())?,
+                )),
+                "cell" => Ok(AssertionCheck::CurrentCell(
+                    value.parse().map_err(|_| bad_number())?,
+                )),
+                name => {
+                    let cell = name
+                        .strip_prefix("cell")
+                        .and_then(|cell| cell.parse().ok())
+                        .ok_or_else(|| {
+                            format!("unknown name '{}', expected head, cell or cellN", name)
+                        })?;
+                    Ok(AssertionCheck::Cell(
+                        cell,
+                        value.parse().map_err(|_| bad_number())?,
+                    ))
+                }
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    if checks.is_empty() {
+        return Err("no conditions given".to_string());
+    }
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Are all three kinds of condition understood?
+    #[test]
+    fn test_parse_checks() {
+        assert_eq!(
+            parse_checks(" head=2 cell=7  cell10=255"),
+            Ok(vec![
+                AssertionCheck::Head(2),
+                AssertionCheck::CurrentCell(7),
+                AssertionCheck::Cell(10, 255)
+            ])
+        );
+    }
+
+    // Are bad conditions refused with a reason?
+    #[test]
+    fn test_parse_bad_checks() {
+        assert!(parse_checks("").is_err());
+        assert!(parse_checks("head").is_err());
+        assert!(parse_checks("cell=256").is_err());
+        assert!(parse_checks("tape=1").is_err());
+    }
+}
@@ -0,0 +1,584 @@
+//! A compact binary form of an analysed and optimised program, so that a large program can be
+//! parsed, analysed and optimised once and then loaded many times.
+//!
+//! Bytecode holds everything a [BfProgram] does: its instructions and where each was read from,
+//! the distance from each `[` to its `]`, its `@assert` directives and the [Op]s it was lowered
+//! to. Loading it rebuilds the jump map and [crate::loops::LoopTree] from the stored distances
+//! rather than matching brackets, and keeps the ops, so that [BfProgram::optimize] at the same
+//! level costs nothing.
+//!
+//! Numbers are LEB128 varints, signed ones zigzag-encoded first, and each instruction's line,
+//! column and offset are stored as differences from the one before, which keeps most instructions
+//! to five bytes. Bytecode starts with [MAGIC], a version byte and a block of notes, such as
+//! where the program came from, which loading skips.
+
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::assertion::{Assertion, AssertionCheck};
+use crate::loops::LoopTree;
+use crate::optimize::{Op, OptLevel, OptimizedProgram};
+use crate::{BfProgram, BftTypeError, Instruction, LocalisedInstruction};
+
+/// The bytes every bytecode file starts with
+pub const MAGIC: &[u8; 4] = b"BFTB";
+
+/// The version of the format written, and the only one read
+const VERSION: u8 = 1;
+
+/// Every instruction but [Instruction::Extension], by the code that stands for it. An extension
+/// has the code after the last of these, followed by its character.
+const INSTRUCTIONS: [Instruction; 18] = [
+    Instruction::MoveLeft,
+    Instruction::MoveRight,
+    Instruction::Increment,
+    Instruction::Decrement,
+    Instruction::Input,
+    Instruction::Output,
+    Instruction::ConditionalJumpForward,
+    Instruction::ConditionalJumpBackward,
+    Instruction::End,
+    Instruction::Store,
+    Instruction::Load,
+    Instruction::ShiftRight,
+    Instruction::ShiftLeft,
+    Instruction::Not,
+    Instruction::Xor,
+    Instruction::And,
+    Instruction::Or,
+    Instruction::DebugDump,
+];
+
+/// The code for an [Instruction::Extension]
+const EXTENSION: u8 = INSTRUCTIONS.len() as u8;
+
+/// Flags for what an instruction has beyond a line, column and offset
+const NEW_SOURCE: u8 = 1;
+const LONG: u8 = 2;
+const EXPANDED: u8 = 4;
+
+/// Write `program` as bytecode, with its ops at `level` and `notes` for anyone reading the file
+///
+/// ```
+///# use bft_types::{bytecode, BfProgram};
+///# use bft_types::optimize::OptLevel;
+///# fn main() -> Result<(), bft_types::BftTypeError>{
+///  let program = BfProgram::new("count.bf", "++++[>+<-]")?;
+///  let bytes = bytecode::encode(&program, OptLevel::Full, "made by a doctest");
+///
+///  assert!(bytes.starts_with(bytecode::MAGIC));
+///  assert_eq!(bytecode::decode(&bytes)?, program);
+///# Ok(())
+///# }
+/// ```
+pub fn encode(program: &BfProgram, level: OptLevel, notes: &str) -> Vec<u8> {
+    let mut writer = Writer::default();
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.bytes.push(VERSION);
+    writer.text(notes);
+
+    writer.text(&program.name.to_string_lossy());
+    writer.unsigned(program.sources.len());
+    for source in &program.sources {
+        writer.text(&source.to_string_lossy());
+    }
+    writer.unsigned(program.source_bytes);
+
+    writer.unsigned(program.instructions.len());
+    let mut previous = LocalisedInstruction::new(Instruction::Output, 1, 1);
+    for (index, instruction) in program.instructions.iter().enumerate() {
+        match instruction.instruction {
+            Instruction::Extension(c) => {
+                writer.bytes.push(EXTENSION);
+                writer.unsigned(c as usize);
+            }
+            other => writer.bytes.push(code_of(other)),
+        }
+        let mut flags = 0;
+        if instruction.source != previous.source {
+            flags |= NEW_SOURCE;
+        }
+        if instruction.len != 1 {
+            flags |= LONG;
+        }
+        if instruction.expanded_from.is_some() {
+            flags |= EXPANDED;
+        }
+        writer.bytes.push(flags);
+        writer.delta(previous.line_num, instruction.line_num);
+        writer.delta(previous.column_num, instruction.column_num);
+        writer.delta(
+            previous.offset.wrapping_add(previous.len),
+            instruction.offset,
+        );
+        if flags & NEW_SOURCE != 0 {
+            writer.unsigned(instruction.source);
+        }
+        if flags & LONG != 0 {
+            writer.unsigned(instruction.len);
+        }
+        if let Some((line, column)) = instruction.expanded_from {
+            writer.unsigned(line);
+            writer.unsigned(column);
+        }
+        if instruction.instruction == Instruction::ConditionalJumpForward {
+            // the jump map holds the instruction after the `]`
+            writer.unsigned(program.jump_map[index] - 1 - index);
+        }
+        previous = *instruction;
+    }
+
+    writer.unsigned(program.assertions.len());
+    for assertion in &program.assertions {
+        writer.unsigned(assertion.line_num);
+        writer.unsigned(assertion.column_num);
+        writer.unsigned(assertion.before_instruction);
+        writer.unsigned(assertion.checks.len());
+        for check in &assertion.checks {
+            match *check {
+                AssertionCheck::Head(head) => {
+                    writer.bytes.push(0);
+                    writer.unsigned(head);
+                }
+                AssertionCheck::CurrentCell(value) => writer.bytes.extend([1, value]),
+                AssertionCheck::Cell(cell, value) => {
+                    writer.bytes.push(2);
+                    writer.unsigned(cell);
+                    writer.bytes.push(value);
+                }
+            }
+        }
+    }
+
+    let optimized = program.optimize(level);
+    writer.bytes.push(match level {
+        OptLevel::None => 0,
+        OptLevel::RunLengths => 1,
+        OptLevel::Full => 2,
+    });
+    writer.unsigned(optimized.ops().len());
+    for (index, op) in optimized.ops().iter().enumerate() {
+        match *op {
+            Op::Add(amount) => {
+                writer.bytes.push(0);
+                writer.signed(amount);
+                writer.unsigned(optimized.instructions_of(index).len());
+            }
+            Op::Move(distance) => {
+                writer.bytes.push(1);
+                writer.signed(distance);
+                writer.unsigned(optimized.instructions_of(index).len());
+            }
+            Op::SetZero { up } => writer.bytes.push(2 + up as u8),
+            Op::Scan { right } => writer.bytes.push(4 + right as u8),
+            Op::JumpIfZero(target) => {
+                writer.bytes.push(6);
+                writer.unsigned(target - index);
+            }
+            Op::JumpUnlessZero(target) => {
+                // the target is the op after the `[`
+                writer.bytes.push(7);
+                writer.unsigned(index + 1 - target);
+            }
+            Op::Input => writer.bytes.push(8),
+            Op::Output => writer.bytes.push(9),
+            Op::Other(_) => writer.bytes.push(10),
+        }
+    }
+    writer.bytes
+}
+
+/// Read a program written by [encode], checking that it hangs together: that every `[` has its
+/// `]`, and that the ops cover the instructions and jump to each other's counterparts
+pub fn decode(bytes: &[u8]) -> Result<BfProgram, BftTypeError> {
+    let started = Instant::now();
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(invalid("not a bft bytecode file"));
+    }
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(invalid(format!(
+            "version {} is not known, only version {}",
+            version, VERSION
+        )));
+    }
+    reader.text()?;
+
+    let name = PathBuf::from(reader.text()?);
+    let mut sources = Vec::new();
+    for _ in 0..reader.count()? {
+        sources.push(PathBuf::from(reader.text()?));
+    }
+    let source_bytes = reader.unsigned()?;
+
+    let len = reader.count()?;
+    let mut instructions = Vec::with_capacity(len);
+    let mut jump_map = vec![0; len];
+    let mut loops = LoopTree::default();
+    // the loops opened but not yet closed, innermost last
+    let mut open_loops = Vec::<usize>::new();
+    // where the `]` of each open loop should be, by loop number
+    let mut closes = Vec::new();
+    let mut previous = LocalisedInstruction::new(Instruction::Output, 1, 1);
+    for index in 0..len {
+        let instruction = match reader.byte()? {
+            EXTENSION => {
+                let c = u32::try_from(reader.unsigned()?)
+                    .ok()
+                    .and_then(char::from_u32);
+                Instruction::Extension(c.ok_or_else(|| invalid("an extension is not a character"))?)
+            }
+            code => *INSTRUCTIONS
+                .get(usize::from(code))
+                .ok_or_else(|| invalid(format!("{} is not an instruction", code)))?,
+        };
+        let flags = reader.byte()?;
+        let line_num = reader.delta(previous.line_num)?;
+        let column_num = reader.delta(previous.column_num)?;
+        let previous_end = previous
+            .offset
+            .checked_add(previous.len)
+            .ok_or_else(|| invalid("an instruction's text ends past the largest offset"))?;
+        let offset = reader.delta(previous_end)?;
+        let source = match flags & NEW_SOURCE {
+            0 => previous.source,
+            _ => reader.unsigned()?,
+        };
+        if source >= sources.len() {
+            return Err(invalid(format!("instruction {} has no source file", index)));
+        }
+        let len = match flags & LONG {
+            0 => 1,
+            _ => reader.unsigned()?,
+        };
+        let expanded_from = match flags & EXPANDED {
+            0 => None,
+            _ => Some((reader.unsigned()?, reader.unsigned()?)),
+        };
+
+        match instruction {
+            Instruction::ConditionalJumpForward => {
+                let close = index
+                    .checked_add(reader.unsigned()?)
+                    .ok_or_else(|| invalid("a jump is too long"))?;
+                open_loops.push(loops.open(index, open_loops.last().copied()));
+                closes.push(close);
+            }
+            Instruction::ConditionalJumpBackward => match open_loops.pop() {
+                Some(number) if closes[number] == index => {
+                    let open = loops.open_of(number);
+                    loops.close(number, index);
+                    jump_map[index] = open + 1;
+                    jump_map[open] = index + 1;
+                }
+                _ => return Err(invalid(format!("the ']' at {} is not matched", index))),
+            },
+            _ => {}
+        }
+
+        previous = LocalisedInstruction {
+            instruction,
+            line_num,
+            column_num,
+            offset,
+            len,
+            source,
+            expanded_from,
+        };
+        instructions.push(previous);
+    }
+    if !open_loops.is_empty() {
+        return Err(invalid("a '[' is not matched"));
+    }
+
+    let mut assertions = Vec::new();
+    for _ in 0..reader.count()? {
+        let line_num = reader.unsigned()?;
+        let column_num = reader.unsigned()?;
+        let before_instruction = reader.unsigned()?;
+        if before_instruction > len {
+            return Err(invalid("an assertion is beyond the end of the program"));
+        }
+        let mut checks = Vec::new();
+        for _ in 0..reader.count()? {
+            checks.push(match reader.byte()? {
+                0 => AssertionCheck::Head(reader.unsigned()?),
+                1 => AssertionCheck::CurrentCell(reader.byte()?),
+                2 => AssertionCheck::Cell(reader.unsigned()?, reader.byte()?),
+                tag => return Err(invalid(format!("{} is not an assertion check", tag))),
+            });
+        }
+        assertions.push(Assertion {
+            line_num,
+            column_num,
+            before_instruction,
+            checks,
+        });
+    }
+    if assertions
+        .windows(2)
+        .any(|pair| pair[0].before_instruction > pair[1].before_instruction)
+    {
+        return Err(invalid("the assertions are out of order"));
+    }
+
+    let level = match reader.byte()? {
+        0 => OptLevel::None,
+        1 => OptLevel::RunLengths,
+        2 => OptLevel::Full,
+        level => return Err(invalid(format!("{} is not an optimisation level", level))),
+    };
+    let mut ops = Vec::new();
+    let mut starts = Vec::new();
+    // indexes of the ops for the `[`s still waiting for their `]`
+    let mut open_ops = Vec::new();
+    let mut start = 0;
+    for index in 0..reader.count()? {
+        if start >= len {
+            return Err(invalid("there are more ops than instructions"));
+        }
+        let (op, covers) = match reader.byte()? {
+            0 => (Op::Add(reader.signed()?), reader.unsigned()?),
+            1 => (Op::Move(reader.signed()?), reader.unsigned()?),
+            tag @ (2 | 3) => (Op::SetZero { up: tag == 3 }, 3),
+            tag @ (4 | 5) => (Op::Scan { right: tag == 5 }, 3),
+            6 => {
+                let target = index
+                    .checked_add(reader.unsigned()?)
+                    .ok_or_else(|| invalid("a jump is too long"))?;
+                open_ops.push(index);
+                (Op::JumpIfZero(target), 1)
+            }
+            7 => {
+                let opening = index
+                    .checked_sub(reader.unsigned()?)
+                    .ok_or_else(|| invalid("a jump goes before the first op"))?;
+                match open_ops.pop() {
+                    Some(open) if open == opening && ops[open] == Op::JumpIfZero(index + 1) => {}
+                    _ => return Err(invalid(format!("the ']' op at {} is not matched", index))),
+                }
+                (Op::JumpUnlessZero(opening + 1), 1)
+            }
+            8 => (Op::Input, 1),
+            9 => (Op::Output, 1),
+            10 => (Op::Other(instructions[start].instruction), 1),
+            tag => return Err(invalid(format!("{} is not an op", tag))),
+        };
+        let instruction = match op {
+            Op::JumpIfZero(_) => Some(Instruction::ConditionalJumpForward),
+            Op::JumpUnlessZero(_) => Some(Instruction::ConditionalJumpBackward),
+            Op::Input => Some(Instruction::Input),
+            Op::Output => Some(Instruction::Output),
+            _ => None,
+        };
+        if covers == 0
+            || covers > len - start
+            || instruction.is_some_and(|instruction| instruction != instructions[start].instruction)
+        {
+            return Err(invalid(format!(
+                "op {} covers the wrong instructions",
+                index
+            )));
+        }
+        ops.push(op);
+        starts.push(start);
+        start += covers;
+    }
+    if start != len || !open_ops.is_empty() {
+        return Err(invalid("the ops do not match the instructions"));
+    }
+    if reader.position != bytes.len() {
+        return Err(invalid("there are bytes after the program"));
+    }
+
+    let mut program = BfProgram {
+        name,
+        sources,
+        instructions,
+        jump_map,
+        loops,
+        assertions,
+        analysis_time: started.elapsed(),
+        source_bytes,
+        optimized: None,
+        macros: Vec::new(),
+    };
+    // the ops must be the ones this program lowers to, or a run would not do what it says
+    let optimized = OptimizedProgram::from_parts(ops, starts, len, level);
+    if optimized != OptimizedProgram::new(&program, level) {
+        return Err(invalid("the ops do not match the instructions"));
+    }
+    program.optimized = Some(optimized);
+    Ok(program)
+}
+
+/// The code for any instruction but an extension
+fn code_of(instruction: Instruction) -> u8 {
+    INSTRUCTIONS
+        .iter()
+        .position(|known| *known == instruction)
+        .unwrap_or_default() as u8
+}
+
+fn invalid(reason: impl Into<String>) -> BftTypeError {
+    BftTypeError::InvalidBytecode {
+        reason: reason.into(),
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn unsigned(&mut self, mut value: usize) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn signed(&mut self, value: isize) {
+        self.unsigned(((value << 1) ^ (value >> (isize::BITS - 1))) as usize);
+    }
+
+    /// `value` as its difference from `from`
+    fn delta(&mut self, from: usize, value: usize) {
+        self.signed(value.wrapping_sub(from) as isize);
+    }
+
+    fn text(&mut self, text: &str) {
+        self.unsigned(text.len());
+        self.bytes.extend_from_slice(text.as_bytes());
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    position: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8], BftTypeError> {
+        let taken = self
+            .bytes
+            .get(self.position..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or_else(|| invalid("it ends too soon"))?;
+        self.position += len;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, BftTypeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn unsigned(&mut self) -> Result<usize, BftTypeError> {
+        let mut value = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            let bits = usize::from(byte & 0x7f);
+            if bits << shift >> shift != bits {
+                break;
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("a number is too large"))
+    }
+
+    fn signed(&mut self) -> Result<isize, BftTypeError> {
+        let value = self.unsigned()?;
+        Ok((value >> 1) as isize ^ -((value & 1) as isize))
+    }
+
+    fn delta(&mut self, from: usize) -> Result<usize, BftTypeError> {
+        Ok(from.wrapping_add(self.signed()? as usize))
+    }
+
+    /// A count of things still to read, each of which takes at least a byte, so that a corrupt
+    /// count cannot ask for more memory than the bytecode could describe
+    fn count(&mut self) -> Result<usize, BftTypeError> {
+        let count = self.unsigned()?;
+        if count > self.bytes.len() - self.position {
+            return Err(invalid("a count is larger than the file"));
+        }
+        Ok(count)
+    }
+
+    fn text(&mut self) -> Result<String, BftTypeError> {
+        let len = self.count()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("text is not UTF-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseOptions;
+
+    // Does a program come back with the same instructions, locations, loops, assertions and ops,
+    // and is damaged bytecode refused rather than trusted?
+    #[test]
+    fn test_round_trip() {
+        let options = ParseOptions {
+            extended: true,
+            extensions: vec!['*'],
+            assertions: true,
+            macros: true,
+            ..ParseOptions::default()
+        };
+        let text = "#define TWO ++\n\
+                    TWO[->+<]  @assert head=0 cell=0 cell1=2\n\
+                    >[-]<<é*,[>]@.!  long comment\n";
+        let program = BfProgram::new_with_options("round.bf", text, &options).unwrap();
+
+        for level in [OptLevel::None, OptLevel::RunLengths, OptLevel::Full] {
+            let bytes = encode(&program, level, "notes");
+            let loaded = decode(&bytes).unwrap();
+            assert_eq!(loaded, program);
+            assert_eq!(loaded.sources(), program.sources());
+            assert_eq!(loaded.source_bytes(), program.source_bytes());
+            assert_eq!(loaded.jump_map, program.jump_map);
+            assert_eq!(loaded.loops, program.loops);
+            assert_eq!(loaded.optimize(level), program.optimize(level));
+            assert_eq!(
+                loaded.optimized.as_ref().map(|ops| ops.level()),
+                Some(level)
+            );
+
+            for end in 0..bytes.len() {
+                assert!(decode(&bytes[..end]).is_err(), "cut at {}", end);
+            }
+        }
+
+        let mut past_the_end = BfProgram::new("end.bf", "++").unwrap();
+        past_the_end.instructions[0].offset = usize::MAX;
+        assert!(decode(&encode(&past_the_end, OptLevel::None, "")).is_err());
+
+        let mut unmatched = program.clone();
+        unmatched.jump_map[2] += 1;
+        assert!(decode(&encode(&unmatched, OptLevel::None, "")).is_err());
+
+        // a clear loop stored as a scan to the right
+        let cleared = BfProgram::new("clear.bf", "+>++<[-]>.").unwrap();
+        let mut bytes = encode(&cleared, OptLevel::Full, "");
+        let tag = bytes.len() - 5;
+        assert_eq!(bytes[tag], 2);
+        assert!(decode(&bytes).is_ok());
+        bytes[tag] = 5;
+        assert!(decode(&bytes).is_err());
+
+        assert!(decode(b"BFTB\x02").is_err());
+        assert!(decode(b"not bytecode").is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! A program's control flow as a graph of basic blocks, for drawing with Graphviz.
+//!
+//! Each `[` and each `]` is a block of its own, since each decides where to go next from the cell
+//! under the head. The instructions between them form straight-line blocks, which always carry on
+//! to whatever follows. A `[` goes past its loop when the cell is zero and into it otherwise; a
+//! `]` goes back to the start of its loop's body unless the cell is zero.
+
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::{BfProgram, Instruction};
+
+/// Instructions shown in a straight-line block's label before it is cut short
+const MAX_LABEL_INSTRUCTIONS: usize = 32;
+
+/// What a [Block] is
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BlockKind {
+    /// Instructions that run one after the other, with no jump among them
+    Straight,
+    /// A `[`
+    LoopStart,
+    /// A `]`
+    LoopEnd,
+}
+
+/// A run of instructions that control only enters at the start of
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Block {
+    /// What the block is
+    pub kind: BlockKind,
+    /// The indexes of the block's instructions in the program
+    pub instructions: Range<usize>,
+}
+
+/// When control follows an [Edge]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Condition {
+    /// Always: the edge out of a straight-line block
+    Always,
+    /// When the cell under the head is zero
+    Zero,
+    /// When the cell under the head is not zero
+    NonZero,
+}
+
+/// A way for control to pass from one block to another
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Edge {
+    /// The index of the block control leaves
+    pub from: usize,
+    /// The index of the block control enters, or `None` where the program ends
+    pub to: Option<usize>,
+    /// When control goes this way
+    pub condition: Condition,
+}
+
+/// The blocks of a program and the edges between them, as found by
+/// [BfProgram::control_flow_graph]
+///
+/// ```
+///# use bft_types::BfProgram;
+///# use bft_types::cfg::{BlockKind, Condition, Edge};
+///# fn main() -> Result<(), bft_types::BftTypeError>{
+///  let program = BfProgram::new("cfg.bf", "+++[->+<]>.")?;
+///  let graph = program.control_flow_graph();
+///
+///  assert_eq!(graph.blocks().len(), 5);
+///  assert_eq!(graph.blocks()[1].kind, BlockKind::LoopStart);
+///  assert!(graph.edges().contains(&Edge {
+///      from: 1,
+///      to: Some(4),
+///      condition: Condition::Zero,
+///  }));
+///  assert!(graph.to_dot().starts_with("digraph \"cfg.bf\" {"));
+///# Ok(())
+///# }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph<'p> {
+    program: &'p BfProgram,
+    blocks: Vec<Block>,
+    edges: Vec<Edge>,
+}
+
+impl<'p> ControlFlowGraph<'p> {
+    /// Split `program` into blocks and join them up
+    pub(crate) fn new(program: &'p BfProgram) -> Self {
+        let instructions = program.localised_instructions();
+
+        let mut blocks = Vec::new();
+        let mut start = 0;
+        for (index, instruction) in instructions.iter().enumerate() {
+            let kind = match instruction.instruction() {
+                Instruction::ConditionalJumpForward => BlockKind::LoopStart,
+                Instruction::ConditionalJumpBackward => BlockKind::LoopEnd,
+                _ => continue,
+            };
+            if start < index {
+                blocks.push(Block {
+                    kind: BlockKind::Straight,
+                    instructions: start..index,
+                });
+            }
+            blocks.push(Block {
+                kind,
+                instructions: index..index + 1,
+            });
+            start = index + 1;
+        }
+        if start < instructions.len() {
+            blocks.push(Block {
+                kind: BlockKind::Straight,
+                instructions: start..instructions.len(),
+            });
+        }
+
+        // the block each instruction is in
+        let mut block_of = vec![0; instructions.len()];
+        for (number, block) in blocks.iter().enumerate() {
+            block_of[block.instructions.clone()].fill(number);
+        }
+        let block_at = |index: usize| block_of.get(index).copied();
+
+        let mut edges = Vec::new();
+        for (from, block) in blocks.iter().enumerate() {
+            let next = block_at(block.instructions.end);
+            let mut edge = |to, condition| {
+                edges.push(Edge {
+                    from,
+                    to,
+                    condition,
+                })
+            };
+            match block.kind {
+                BlockKind::Straight => edge(next, Condition::Always),
+                BlockKind::LoopStart => {
+                    edge(
+                        block_at(program.jump_target(block.instructions.start)),
+                        Condition::Zero,
+                    );
+                    edge(next, Condition::NonZero);
+                }
+                BlockKind::LoopEnd => {
+                    edge(
+                        block_at(program.jump_target(block.instructions.start)),
+                        Condition::NonZero,
+                    );
+                    edge(next, Condition::Zero);
+                }
+            }
+        }
+
+        Self {
+            program,
+            blocks,
+            edges,
+        }
+    }
+
+    /// The blocks, in program order
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// The edges, in the order of the blocks they leave
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// The graph in Graphviz DOT format. Each block is labelled with its instructions, cut short
+    /// if there are many, and where they are in the source.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        // writing to a String cannot fail
+        let _ = writeln!(
+            dot,
+            "digraph \"{}\" {{",
+            escape(&self.program.name().display().to_string())
+        );
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        dot.push_str("    start [shape=oval];\n");
+        dot.push_str("    end [shape=oval];\n");
+        for (number, block) in self.blocks.iter().enumerate() {
+            let shape = match block.kind {
+                BlockKind::Straight => "",
+                BlockKind::LoopStart | BlockKind::LoopEnd => ", shape=diamond",
+            };
+            let _ = writeln!(
+                dot,
+                "    b{} [label=\"{}\\n{}\"{}];",
+                number,
+                escape(&self.code(block)),
+                escape(&self.location(block)),
+                shape
+            );
+        }
+
+        let node = |block: Option<usize>| block.map_or("end".to_string(), |to| format!("b{}", to));
+        let _ = writeln!(
+            dot,
+            "    start -> {};",
+            node((!self.blocks.is_empty()).then_some(0))
+        );
+        for edge in &self.edges {
+            let label = match edge.condition {
+                Condition::Always => "",
+                Condition::Zero => " [label=\"zero\"]",
+                Condition::NonZero => " [label=\"non-zero\"]",
+            };
+            let _ = writeln!(dot, "    b{} -> {}{};", edge.from, node(edge.to), label);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The instructions of a block as they are written, cut short if there are many
+    fn code(&self, block: &Block) -> String {
+        let instructions = &self.program.localised_instructions()[block.instructions.clone()];
+        let mut code: String = instructions
+            .iter()
+            .take(MAX_LABEL_INSTRUCTIONS)
+            .map(|instruction| instruction.instruction().to_char())
+            .collect();
+        if instructions.len() > MAX_LABEL_INSTRUCTIONS {
+            let _ = write!(code, "... ({} instructions)", instructions.len());
+        }
+        code
+    }
+
+    /// Where a block's instructions are in the source, as `line:column` of the first and last,
+    /// after the name of the file where the program includes others
+    fn location(&self, block: &Block) -> String {
+        let instructions = &self.program.localised_instructions()[block.instructions.clone()];
+        let (first, last) = (&instructions[0], &instructions[instructions.len() - 1]);
+        let mut location = String::new();
+        if self.program.sources().len() > 1 {
+            if let Some(file) = self.program.file_of(block.instructions.start) {
+                let _ = write!(location, "{} ", file.display());
+            }
+        }
+        let _ = write!(location, "{}:{}", first.line_num(), first.column_num());
+        if instructions.len() > 1 {
+            let _ = write!(location, "-{}:{}", last.line_num(), last.column_num());
+        }
+        location
+    }
+}
+
+/// Escape text for a double-quoted DOT string
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Are loops, nested and empty, joined up as the machine would run them?
+    #[test]
+    fn test_control_flow_graph() {
+        let program = BfProgram::new("cfg.bf", "+[[]>\n]").unwrap();
+        let graph = program.control_flow_graph();
+
+        assert_eq!(
+            graph
+                .blocks()
+                .iter()
+                .map(|block| (block.kind, block.instructions.clone()))
+                .collect::<Vec<_>>(),
+            [
+                (BlockKind::Straight, 0..1),
+                (BlockKind::LoopStart, 1..2),
+                (BlockKind::LoopStart, 2..3),
+                (BlockKind::LoopEnd, 3..4),
+                (BlockKind::Straight, 4..5),
+                (BlockKind::LoopEnd, 5..6),
+            ]
+        );
+        let edges: Vec<_> = graph
+            .edges()
+            .iter()
+            .map(|edge| (edge.from, edge.to, edge.condition))
+            .collect();
+        assert_eq!(
+            edges,
+            [
+                (0, Some(1), Condition::Always),
+                (1, None, Condition::Zero),
+                (1, Some(2), Condition::NonZero),
+                (2, Some(4), Condition::Zero),
+                (2, Some(3), Condition::NonZero),
+                (3, Some(3), Condition::NonZero),
+                (3, Some(4), Condition::Zero),
+                (4, Some(5), Condition::Always),
+                (5, Some(2), Condition::NonZero),
+                (5, None, Condition::Zero),
+            ]
+        );
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("    b4 [label=\">\\n1:5\"];\n"));
+        assert!(dot.contains("    b5 [label=\"]\\n2:1\", shape=diamond];\n"));
+        assert!(dot.contains("    start -> b0;\n"));
+        assert!(dot.contains("    b1 -> end [label=\"zero\"];\n"));
+
+        let empty = BfProgram::new("empty.bf", "").unwrap();
+        assert!(empty
+            .control_flow_graph()
+            .to_dot()
+            .contains("start -> end;"));
+
+        let long = BfProgram::new("long.bf", &"+".repeat(40)).unwrap();
+        assert!(long.control_flow_graph().to_dot().contains(&format!(
+            "{}... (40 instructions)\\n1:1-1:40",
+            "+".repeat(32)
+        )));
+    }
+}
@@ -0,0 +1,196 @@
+//! Checking a program for problems without stopping at the first one, for editors and other tools
+//! that want every diagnostic for a file that is still being written.
+//!
+//! In recovery mode, each problem is reported along with the fix that seems most likely, and the
+//! check carries on as if that fix had been made:
+//!
+//! - an unmatched `]` is treated as a stray and skipped;
+//! - an unmatched `[` is closed where the indentation first drops back to its own level, or at
+//!   the end of the program.
+
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::line_index::LineIndex;
+use crate::messages::{self, Catalog, Message};
+use crate::{tokenise, BftTypeError, Instruction, ParseOptions};
+
+/// A problem found in a program
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    /// Line of the problem, 1-indexed
+    pub line_num: usize,
+    /// Column of the problem, 1-indexed
+    pub column_num: usize,
+    /// What is wrong
+    pub message: Message,
+    /// The fix that seems most likely, if there is one
+    pub help: Option<Message>,
+}
+
+impl Diagnostic {
+    /// Show the diagnostic as `line:column: CODE: message`, with any help after it, in the given
+    /// catalog's language
+    pub fn render(&self, catalog: &Catalog) -> String {
+        let mut rendered = format!(
+            "{}:{}: {}",
+            self.line_num,
+            self.column_num,
+            self.message.render(catalog)
+        );
+        if let Some(help) = &self.help {
+            rendered.push_str(&format!(" (help: {})", help.text(catalog)));
+        }
+        rendered
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(&Catalog::default()))
+    }
+}
+
+/// Check a program's text, returning every problem found in the order they appear. Without
+/// `recover`, only the first problem in the text is returned.
+///
+/// ```
+///# use bft_types::ParseOptions;
+///# use bft_types::check::check;
+///  let diagnostics = check("wip.bf", "+]\n[->+<\n", &ParseOptions::default(), true);
+///  assert_eq!(diagnostics.len(), 2);
+///  assert_eq!((diagnostics[0].line_num, diagnostics[0].column_num), (1, 2));
+///  assert_eq!((diagnostics[1].line_num, diagnostics[1].column_num), (2, 1));
+/// ```
+pub fn check<P: AsRef<Path>>(
+    filename: P,
+    file_contents: &str,
+    options: &ParseOptions,
+    recover: bool,
+) -> Vec<Diagnostic> {
+    let tokens = tokenise(filename.as_ref(), file_contents, options);
+    let mut diagnostics: Vec<Diagnostic> = tokens
+        .errors
+        .into_iter()
+        .filter_map(|error| match error {
+            BftTypeError::InvalidAssertion {
+                line_num,
+                column_num,
+                reason,
+                ..
+            } => Some(Diagnostic {
+                line_num,
+                column_num,
+                message: Message::new(messages::CHECK_INVALID_ASSERTION, vec![("reason", reason)]),
+                help: None,
+            }),
+            BftTypeError::UnclosedComment {
+                line_num,
+                column_num,
+                ..
+            } => Some(Diagnostic {
+                line_num,
+                column_num,
+                message: Message::new(messages::CHECK_UNCLOSED_COMMENT, Vec::new()),
+                help: None,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    let lines = LineIndex::new(file_contents);
+    let mut open_jumps = Vec::new();
+    for instruction in &tokens.instructions {
+        match instruction.instruction() {
+            Instruction::ConditionalJumpForward => open_jumps.push(*instruction),
+            Instruction::ConditionalJumpBackward if open_jumps.pop().is_none() => {
+                diagnostics.push(Diagnostic {
+                    line_num: instruction.line_num(),
+                    column_num: instruction.column_num(),
+                    message: Message::new(messages::CHECK_UNMATCHED_CLOSE, Vec::new()),
+                    help: Some(Message::new(messages::HELP_REMOVE_CLOSE, Vec::new())),
+                });
+            }
+            _ => {}
+        }
+    }
+    for unmatched in open_jumps {
+        let help = match likely_close(&lines, unmatched.line_num()) {
+            Some(line_num) => Message::new(
+                messages::HELP_CLOSE_AT_LINE,
+                vec![("line", line_num.to_string())],
+            ),
+            None => Message::new(messages::HELP_CLOSE_AT_END, Vec::new()),
+        };
+        diagnostics.push(Diagnostic {
+            line_num: unmatched.line_num(),
+            column_num: unmatched.column_num(),
+            message: Message::new(messages::CHECK_UNMATCHED_OPEN, Vec::new()),
+            help: Some(help),
+        });
+    }
+
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line_num, diagnostic.column_num));
+    if !recover {
+        diagnostics.truncate(1);
+    }
+    diagnostics
+}
+
+/// The line a loop opened on `open_line` most likely should have closed on: the last non-blank
+/// line before the indentation first drops back to the level of the opening line, which may be the
+/// opening line itself. Returns `None` if the indentation never drops back.
+fn likely_close(lines: &LineIndex, open_line: usize) -> Option<usize> {
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let open_indent = indent(lines.line(open_line)?);
+
+    let mut last_inside = open_line;
+    for line_num in open_line + 1..=lines.line_count() {
+        let line = lines.line(line_num)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if indent(line) <= open_indent {
+            return Some(last_inside);
+        }
+        last_inside = line_num;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Are all bracket problems found when recovering, but only the first one otherwise?
+    #[test]
+    fn test_check_recover() {
+        let text = "]\n[\n  +\n  [-]\n.\n]]\n";
+
+        let diagnostics = check("test.bf", text, &ParseOptions::default(), true);
+        let positions: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.line_num, diagnostic.column_num))
+            .collect();
+        assert_eq!(positions, vec![(1, 1), (6, 2)]);
+
+        assert_eq!(
+            check("test.bf", text, &ParseOptions::default(), false).len(),
+            1
+        );
+    }
+
+    // Is a missing ']' placed where the indentation drops back?
+    #[test]
+    fn test_likely_close() {
+        let text = "+\n[\n  >+\n\n  <-\n.\n";
+
+        let diagnostics = check("test.bf", text, &ParseOptions::default(), true);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "2:1: BFT0302: unmatched '[' (help: add a ']' at the end of line 5)"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! How far a program can move the head, found without running it.
+//!
+//! The head is followed through the program as a range of offsets from the cell it starts on. A
+//! loop whose body ends where it started leaves the range as it was; one whose body may end
+//! further right or left widens the range without bound that way, since the loop may run any
+//! number of times. An extension instruction may move the head anywhere. What is left is a
+//! conservative bound: the head can never go further than it, though it may not go that far.
+//!
+//! The instructions before the first loop, `@` or extension instruction are also certain to be
+//! executed, unless an error stops the program first, so how far they move the head is a bound
+//! the other way: the head is certain to go at least that far.
+
+use crate::{BfProgram, Instruction};
+
+/// The offsets from its starting cell that the head can be at, where `None` is no bound
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Offsets {
+    lowest: Option<isize>,
+    highest: Option<isize>,
+}
+
+impl Offsets {
+    /// The starting cell alone
+    const START: Offsets = Offsets {
+        lowest: Some(0),
+        highest: Some(0),
+    };
+
+    /// Anywhere on the tape
+    const ANYWHERE: Offsets = Offsets {
+        lowest: None,
+        highest: None,
+    };
+
+    /// The offsets reached by going from any of these by any of `other`
+    fn then(self, other: Offsets) -> Offsets {
+        Offsets {
+            lowest: self.lowest.zip(other.lowest).map(|(a, b)| a + b),
+            highest: self.highest.zip(other.highest).map(|(a, b)| a + b),
+        }
+    }
+
+    /// The offsets in either these or `other`, and any in between
+    fn union(self, other: Offsets) -> Offsets {
+        Offsets {
+            lowest: self.lowest.zip(other.lowest).map(|(a, b)| a.min(b)),
+            highest: self.highest.zip(other.highest).map(|(a, b)| a.max(b)),
+        }
+    }
+
+    /// The offsets reached by going from any of these by any of `drift` as many times as you
+    /// like, including none
+    fn repeat(self, drift: Offsets) -> Offsets {
+        Offsets {
+            lowest: self
+                .lowest
+                .filter(|_| drift.lowest.is_some_and(|lowest| lowest >= 0)),
+            highest: self
+                .highest
+                .filter(|_| drift.highest.is_some_and(|highest| highest <= 0)),
+        }
+    }
+}
+
+/// How far the head can move from the cell it starts on while a program runs, as found by
+/// [BfProgram::tape_extent]
+///
+/// ```
+///# use bft_types::BfProgram;
+///# fn main() -> Result<(), bft_types::BftTypeError>{
+///  let program = BfProgram::new("extent.bf", ">>+[->>[-<+>]<<]>>>")?;
+///  let extent = program.tape_extent();
+///
+///  assert_eq!(extent.right(), Some(5));
+///  assert_eq!(extent.left(), Some(0));
+///  assert_eq!(extent.certain_right(), 2);
+///
+///  let scan = BfProgram::new("scan.bf", "+[>]<<")?.tape_extent();
+///  assert_eq!(scan.right(), None);
+///  assert_eq!(scan.left(), Some(2));
+///# Ok(())
+///# }
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct TapeExtent {
+    right: Option<usize>,
+    left: Option<usize>,
+    certain_right: usize,
+    certain_left: usize,
+}
+
+impl TapeExtent {
+    /// Follow the head through `program`
+    pub(crate) fn new(program: &BfProgram) -> Self {
+        let instructions = program.localised_instructions();
+
+        // where the head is now, the cells visited so far, and the same for each enclosing
+        // block as it was at the `[` of the loop inside it
+        let mut at = Offsets::START;
+        let mut visited = Offsets::START;
+        let mut outer = Vec::new();
+        for instruction in instructions {
+            match instruction.instruction() {
+                Instruction::MoveRight => {
+                    at = at.then(Offsets {
+                        lowest: Some(1),
+                        highest: Some(1),
+                    });
+                    visited = visited.union(at);
+                }
+                Instruction::MoveLeft => {
+                    at = at.then(Offsets {
+                        lowest: Some(-1),
+                        highest: Some(-1),
+                    });
+                    visited = visited.union(at);
+                }
+                Instruction::Extension(_) => {
+                    at = Offsets::ANYWHERE;
+                    visited = Offsets::ANYWHERE;
+                }
+                Instruction::ConditionalJumpForward => {
+                    outer.push((at, visited));
+                    at = Offsets::START;
+                    visited = Offsets::START;
+                }
+                Instruction::ConditionalJumpBackward => {
+                    // the program has been analysed, so every `]` has its `[`
+                    let Some((before, visited_before)) = outer.pop() else {
+                        continue;
+                    };
+                    // each time round starts somewhere the head can be after the loop
+                    let after = before.repeat(at);
+                    visited = visited_before.union(after.then(visited));
+                    at = after;
+                }
+                _ => {}
+            }
+        }
+
+        let mut certain = 0isize;
+        let mut lowest = 0;
+        let mut highest = 0;
+        for instruction in instructions {
+            match instruction.instruction() {
+                Instruction::MoveRight => certain += 1,
+                Instruction::MoveLeft => certain -= 1,
+                Instruction::ConditionalJumpForward
+                | Instruction::End
+                | Instruction::Extension(_) => break,
+                _ => {}
+            }
+            lowest = lowest.min(certain);
+            highest = highest.max(certain);
+        }
+
+        Self {
+            right: visited.highest.map(isize::unsigned_abs),
+            left: visited.lowest.map(isize::unsigned_abs),
+            certain_right: highest.unsigned_abs(),
+            certain_left: lowest.unsigned_abs(),
+        }
+    }
+
+    /// The furthest right of its starting cell that the head can go, or `None` if there is no
+    /// telling
+    pub fn right(&self) -> Option<usize> {
+        self.right
+    }
+
+    /// The furthest left of its starting cell that the head can go, or `None` if there is no
+    /// telling
+    pub fn left(&self) -> Option<usize> {
+        self.left
+    }
+
+    /// How far right of its starting cell the head is certain to go, unless an error stops the
+    /// program first
+    pub fn certain_right(&self) -> usize {
+        self.certain_right
+    }
+
+    /// How far left of its starting cell the head is certain to go, unless an error stops the
+    /// program first
+    pub fn certain_left(&self) -> usize {
+        self.certain_left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseOptions;
+
+    // Do loops that drift, nest or stay put give the bounds they should?
+    #[test]
+    fn test_tape_extent() {
+        let extent = |text| BfProgram::new("extent.bf", text).unwrap().tape_extent();
+
+        assert_eq!(extent("").right(), Some(0));
+        assert_eq!(extent(">><<<").left(), Some(1));
+        assert_eq!(extent(">><<<").certain_left(), 1);
+
+        // a loop that stays put only reaches as far as its body does
+        let balanced = extent("<<+[>>>+<<<-]>");
+        assert_eq!((balanced.left(), balanced.right()), (Some(2), Some(1)));
+        assert_eq!((balanced.certain_left(), balanced.certain_right()), (2, 0));
+
+        // one that drifts left can go any distance left, but no further right than it starts
+        let drifting = extent(">>>[<]>");
+        assert_eq!((drifting.left(), drifting.right()), (None, Some(4)));
+        assert_eq!(drifting.certain_right(), 3);
+
+        // a drifting loop inside a balanced one still leaves the outer loop unbounded
+        let nested = extent("+[>[>]<[-]+]");
+        assert_eq!((nested.left(), nested.right()), (Some(0), None));
+
+        // `@` ends the moves that are certain, but not those that are possible
+        let options = ParseOptions {
+            extended: true,
+            ..ParseOptions::default()
+        };
+        let ended = BfProgram::new_with_options("ended.bf", ">@>>", &options)
+            .unwrap()
+            .tape_extent();
+        assert_eq!((ended.certain_right(), ended.right()), (1, Some(3)));
+    }
+}
@@ -0,0 +1,57 @@
+//! Stable fingerprints of programs and data, for use as cache keys.
+//!
+//! These use 64-bit FNV-1a rather than [std::collections::hash_map::DefaultHasher], whose output
+//! may change between Rust releases. A fingerprint is not cryptographic: it identifies content,
+//! but offers no protection against someone deliberately making two things collide.
+
+use std::hash::Hasher;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// A [Hasher] computing the 64-bit FNV-1a hash of everything written to it
+#[derive(Debug, Clone, Copy)]
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+/// The fingerprint of a sequence of bytes
+///
+/// ```
+///# use bft_types::fingerprint::fingerprint_bytes;
+///  assert_eq!(fingerprint_bytes(b"a"), 0xaf63dc4c8601ec8c);
+/// ```
+pub fn fingerprint_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Does the hasher match the published FNV-1a test vectors?
+    #[test]
+    fn test_fnv1a_vectors() {
+        assert_eq!(fingerprint_bytes(b""), 0xcbf29ce484222325);
+        assert_eq!(fingerprint_bytes(b"foobar"), 0x85944171f73967e8);
+    }
+}
@@ -0,0 +1,274 @@
+//! Spotting code that can be written in fewer bytes, for golfing programs down in size.
+//!
+//! Each run of `+`/`-` or `<`/`>` instructions is looked at as a whole. A run can be shortened if
+//! opposing instructions cancel each other out, if going the other way round an 8-bit cell is
+//! shorter, or if a long run of `+` or `-` is shorter as a multiplication loop.
+
+use std::fmt::Display;
+
+use crate::{BfProgram, Instruction};
+
+/// Values at or above this are shorter to reach by wrapping round an 8-bit cell the other way
+const WRAP_POINT: i64 = 128;
+
+/// Bytes in a multiplication loop other than the `+`/`-` runs: `>`, `[<`, `>-]<`
+const MULTIPLICATION_LOOP_OVERHEAD: usize = 7;
+
+/// A suggested replacement for part of a program
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Suggestion {
+    /// Line of the first instruction to be replaced
+    pub line_num: usize,
+    /// Column of the first instruction to be replaced
+    pub column_num: usize,
+    /// The instructions to be replaced, without any comments in between them
+    pub original: String,
+    /// The shorter equivalent
+    pub replacement: String,
+    /// Why the replacement is equivalent, including anything it relies on
+    pub reason: &'static str,
+}
+
+impl Suggestion {
+    /// How many bytes the replacement saves
+    pub fn saving(&self) -> usize {
+        self.original.len() - self.replacement.len()
+    }
+}
+
+impl Display for Suggestion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: replace {} with `{}`, saving {} bytes ({})",
+            self.line_num,
+            self.column_num,
+            abbreviate(&self.original),
+            self.replacement,
+            self.saving(),
+            self.reason
+        )
+    }
+}
+
+/// Show short code as it is, and long code by its length and first few instructions
+fn abbreviate(code: &str) -> String {
+    const SHOWN: usize = 16;
+    if code.len() <= SHOWN {
+        format!("`{}`", code)
+    } else {
+        format!("`{}...` ({} bytes)", &code[..SHOWN], code.len())
+    }
+}
+
+/// The kinds of run that can be golfed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Run {
+    Arithmetic,
+    Movement,
+}
+
+/// Which kind of run an instruction belongs to, and which way it counts
+fn run_of(instruction: Instruction) -> Option<(Run, i64)> {
+    match instruction {
+        Instruction::Increment => Some((Run::Arithmetic, 1)),
+        Instruction::Decrement => Some((Run::Arithmetic, -1)),
+        Instruction::MoveRight => Some((Run::Movement, 1)),
+        Instruction::MoveLeft => Some((Run::Movement, -1)),
+        _ => None,
+    }
+}
+
+/// Find every part of the program that has a shorter equivalent
+///
+/// ```
+///# fn main() -> Result<(), bft_types::BftTypeError>{
+///# use bft_types::BfProgram;
+///# use bft_types::golf::suggest;
+/// let bf_program = BfProgram::new("golf.bf", "+-+>><.")?;
+///
+/// let suggestions = suggest(&bf_program);
+/// assert_eq!(suggestions[0].replacement, "+");
+/// assert_eq!(suggestions[1].replacement, ">");
+///# Ok(())
+///# }
+/// ```
+pub fn suggest(program: &BfProgram) -> Vec<Suggestion> {
+    let instructions = program.localised_instructions();
+    let mut suggestions = Vec::new();
+
+    let mut index = 0;
+    while index < instructions.len() {
+        let Some((run, _)) = run_of(instructions[index].instruction()) else {
+            index += 1;
+            continue;
+        };
+
+        let start = index;
+        let mut net = 0;
+        let mut original = String::new();
+        while let Some((this_run, step)) = instructions
+            .get(index)
+            .and_then(|instruction| run_of(instruction.instruction()))
+        {
+            if this_run != run {
+                break;
+            }
+            net += step;
+            original.push(symbol(run, step));
+            index += 1;
+        }
+
+        let shortest = match run {
+            Run::Arithmetic => shortest_arithmetic(net, original.len()),
+            Run::Movement => shortest_movement(net, original.len()),
+        };
+        if let Some((replacement, reason)) = shortest {
+            suggestions.push(Suggestion {
+                line_num: instructions[start].line_num(),
+                column_num: instructions[start].column_num(),
+                original,
+                replacement,
+                reason,
+            });
+        }
+    }
+
+    suggestions
+}
+
+/// The instruction for one step of a run in the given direction
+fn symbol(run: Run, step: i64) -> char {
+    match (run, step > 0) {
+        (Run::Arithmetic, true) => '+',
+        (Run::Arithmetic, false) => '-',
+        (Run::Movement, true) => '>',
+        (Run::Movement, false) => '<',
+    }
+}
+
+/// As many copies of the instruction stepping in the direction of `value` as its size
+fn repeat(run: Run, value: i64) -> String {
+    symbol(run, value)
+        .to_string()
+        .repeat(value.unsigned_abs() as usize)
+}
+
+/// The shortest way to move the head `net` cells, if shorter than `length`
+fn shortest_movement(net: i64, length: usize) -> Option<(String, &'static str)> {
+    let replacement = repeat(Run::Movement, net);
+    (replacement.len() < length).then_some((replacement, "opposing moves cancel out"))
+}
+
+/// The shortest way to add `net` to the current cell, if shorter than `length`
+fn shortest_arithmetic(net: i64, length: usize) -> Option<(String, &'static str)> {
+    let mut candidates = vec![(repeat(Run::Arithmetic, net), "opposing + and - cancel out")];
+    candidates.push((
+        multiplication_loop(net),
+        "multiplication loop; uses the cell to the right, which must start at zero",
+    ));
+    if net.abs() >= WRAP_POINT {
+        let wrapped = net - 256 * net.signum();
+        candidates.push((
+            repeat(Run::Arithmetic, wrapped),
+            "wraps round an 8-bit cell the other way",
+        ));
+        candidates.push((
+            multiplication_loop(wrapped),
+            "multiplication loop wrapping round an 8-bit cell; uses the cell to the right, which \
+             must start at zero",
+        ));
+    }
+
+    candidates
+        .into_iter()
+        .filter(|(replacement, _)| replacement.len() < length)
+        .min_by_key(|(replacement, _)| replacement.len())
+}
+
+/// The shortest loop of the form `>+++[<+++>-]<+` that adds `value` to the current cell
+fn multiplication_loop(value: i64) -> String {
+    let target = value.abs();
+    let mut best: Option<(i64, i64)> = None;
+
+    for outer in 1..=target {
+        let inner_floor = target / outer;
+        for inner in [inner_floor, inner_floor + 1] {
+            if inner < 1 {
+                continue;
+            }
+            let cost = |(outer, inner): (i64, i64)| outer + inner + (target - outer * inner).abs();
+            if best.is_none_or(|best| cost((outer, inner)) < cost(best)) {
+                best = Some((outer, inner));
+            }
+        }
+    }
+
+    let Some((outer, inner)) = best else {
+        return String::new();
+    };
+    let correction = (target - outer * inner) * value.signum();
+    let code = format!(
+        ">{}[<{}>-]<{}",
+        "+".repeat(outer as usize),
+        repeat(Run::Arithmetic, inner * value.signum()),
+        repeat(Run::Arithmetic, correction)
+    );
+    debug_assert_eq!(
+        code.len(),
+        (outer + inner + correction.abs()) as usize + MULTIPLICATION_LOOP_OVERHEAD
+    );
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Is a long run of '+' turned into a multiplication loop that adds the same amount?
+    #[test]
+    fn test_multiplication_loop() {
+        let program = BfProgram::new("a.bf", &"+".repeat(65)).unwrap();
+
+        let suggestions = suggest(&program);
+
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].replacement, ">++++++++[<++++++++>-]<+");
+        assert_eq!(suggestions[0].saving(), 65 - 24);
+    }
+
+    // Is a run that is already as short as it can be left alone?
+    #[test]
+    fn test_nothing_to_golf() {
+        let program = BfProgram::new("a.bf", "++++[->+<]>.").unwrap();
+
+        assert!(suggest(&program).is_empty());
+    }
+
+    // Is it shorter to go the other way round an 8-bit cell?
+    #[test]
+    fn test_wrapping() {
+        let program = BfProgram::new("a.bf", &"-".repeat(254)).unwrap();
+
+        let suggestions = suggest(&program);
+
+        assert_eq!(suggestions[0].replacement, "++");
+        assert_eq!(
+            suggestions[0].reason,
+            "wraps round an 8-bit cell the other way"
+        );
+    }
+
+    // Are runs split by comments still treated as one, and reported where they start?
+    #[test]
+    fn test_cancelling_across_comments() {
+        let program = BfProgram::new("a.bf", ".\n  > move < back\n.").unwrap();
+
+        let suggestions = suggest(&program);
+
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!((suggestions[0].line_num, suggestions[0].column_num), (2, 3));
+        assert_eq!(suggestions[0].replacement, "");
+        assert_eq!(suggestions[0].saving(), 2);
+    }
+}
@@ -1,34 +1,351 @@
 //! Instruction types for the BF interpreter to use.
 
+use std::collections::HashMap;
 use std::fmt::Display;
-use std::fs;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+pub mod assertion;
+pub mod bytecode;
+pub mod cfg;
+pub mod check;
+pub mod extent;
+pub mod fingerprint;
+pub mod golf;
+pub mod line_index;
+pub mod link;
+pub mod loops;
+pub mod mapping;
+pub mod messages;
+pub mod ook;
+pub mod optimize;
+pub mod stats;
+
+use assertion::Assertion;
+use cfg::ControlFlowGraph;
+use extent::TapeExtent;
+use link::ConventionBreach;
+use loops::LoopTree;
+use messages::{Localise, Message};
+use optimize::{OptLevel, OptimizedProgram};
+use stats::ProgramStats;
+
 /// Error types that the bft_types module can yeet out.
 #[derive(Debug, Error)]
 pub enum BftTypeError {
     /// Something went wrong with file IO
-    #[error("File IO error: {}", .0)]
     IoError(std::io::Error),
 
     /// Unmatched '[' jumpinstruction in program
-    #[error("Unmatched '[' jump instruction in {program_name} at line {}, column {}", .bad_instruction.line_num, .bad_instruction.column_num)]
     UnmatchedForwardJump {
         program_name: PathBuf,
         bad_instruction: LocalisedInstruction,
     },
 
     /// Unmatched ']' jumpinstruction in program
-    #[error("Unmatched ']' jump instruction in {program_name} at line {}, column {}", .bad_instruction.line_num, .bad_instruction.column_num)]
     UnmatchedBackwardJump {
         program_name: PathBuf,
         bad_instruction: LocalisedInstruction,
     },
+
+    /// More than one unmatched '[' or ']' jump instruction in program, in the order they appear
+    UnmatchedJumps { jumps: Vec<UnmatchedJump> },
+
+    /// A library fragment does not follow the cell-0 convention required for linking
+    ConventionViolation {
+        program_name: PathBuf,
+        bad_instruction: LocalisedInstruction,
+        breach: ConventionBreach,
+    },
+
+    /// A '[' opens more loops at once than [ParseOptions::max_nesting] allows
+    NestingTooDeep {
+        program_name: PathBuf,
+        bad_instruction: LocalisedInstruction,
+        limit: usize,
+    },
+
+    /// An `@assert` directive could not be understood
+    InvalidAssertion {
+        program_name: PathBuf,
+        line_num: usize,
+        column_num: usize,
+        reason: String,
+    },
+
+    /// A block comment (see [CommentSyntax::block]) that is still open at the end of the program
+    UnclosedComment {
+        program_name: PathBuf,
+        line_num: usize,
+        column_num: usize,
+    },
+
+    /// An `#include` directive (see [ParseOptions::includes]) that could not be followed
+    InvalidInclude {
+        program_name: PathBuf,
+        line_num: usize,
+        reason: String,
+    },
+
+    /// A `#define` directive (see [ParseOptions::macros]) that could not be understood
+    InvalidMacro {
+        program_name: PathBuf,
+        line_num: usize,
+        reason: String,
+    },
+
+    /// An Ook! program with a pair of words that is not an instruction, or a word left over
+    InvalidOok {
+        program_name: PathBuf,
+        line_num: usize,
+        column_num: usize,
+        reason: String,
+    },
+
+    /// Bytecode (see [bytecode]) that could not be loaded
+    InvalidBytecode { reason: String },
+}
+
+impl Localise for BftTypeError {
+    fn message(&self) -> Message {
+        let at = |program_name: &Path, line_num: usize, column_num: usize| {
+            vec![
+                ("program", program_name.display().to_string()),
+                ("line", line_num.to_string()),
+                ("column", column_num.to_string()),
+            ]
+        };
+        match self {
+            BftTypeError::IoError(error) => {
+                Message::new(messages::FILE_ERROR, vec![("error", error.to_string())])
+            }
+            BftTypeError::UnmatchedForwardJump {
+                program_name,
+                bad_instruction,
+            } => Message::new(
+                messages::UNMATCHED_FORWARD_JUMP,
+                at(
+                    program_name,
+                    bad_instruction.line_num,
+                    bad_instruction.column_num,
+                ),
+            ),
+            BftTypeError::UnmatchedBackwardJump {
+                program_name,
+                bad_instruction,
+            } => Message::new(
+                messages::UNMATCHED_BACKWARD_JUMP,
+                at(
+                    program_name,
+                    bad_instruction.line_num,
+                    bad_instruction.column_num,
+                ),
+            ),
+            BftTypeError::UnmatchedJumps { jumps } => Message::new(
+                messages::UNMATCHED_JUMPS,
+                vec![
+                    ("count", jumps.len().to_string()),
+                    (
+                        "jumps",
+                        jumps
+                            .iter()
+                            .map(UnmatchedJump::to_string)
+                            .collect::<Vec<_>>()
+                            .join("; "),
+                    ),
+                ],
+            ),
+            BftTypeError::ConventionViolation {
+                program_name,
+                bad_instruction,
+                breach,
+            } => Message::new(
+                match breach {
+                    ConventionBreach::MovesBelowCellZero => {
+                        messages::FRAGMENT_MOVES_BELOW_CELL_ZERO
+                    }
+                    ConventionBreach::UnbalancedLoop => messages::FRAGMENT_UNBALANCED_LOOP,
+                    ConventionBreach::DoesNotReturnToCellZero => messages::FRAGMENT_DOES_NOT_RETURN,
+                },
+                at(
+                    program_name,
+                    bad_instruction.line_num,
+                    bad_instruction.column_num,
+                ),
+            ),
+            BftTypeError::NestingTooDeep {
+                program_name,
+                bad_instruction,
+                limit,
+            } => {
+                let mut args = at(
+                    program_name,
+                    bad_instruction.line_num,
+                    bad_instruction.column_num,
+                );
+                args.push(("limit", limit.to_string()));
+                Message::new(messages::NESTING_TOO_DEEP, args)
+            }
+            BftTypeError::InvalidAssertion {
+                program_name,
+                line_num,
+                column_num,
+                reason,
+            } => {
+                let mut args = at(program_name, *line_num, *column_num);
+                args.push(("reason", reason.clone()));
+                Message::new(messages::INVALID_ASSERTION, args)
+            }
+            BftTypeError::UnclosedComment {
+                program_name,
+                line_num,
+                column_num,
+            } => Message::new(
+                messages::UNCLOSED_COMMENT,
+                at(program_name, *line_num, *column_num),
+            ),
+            BftTypeError::InvalidInclude {
+                program_name,
+                line_num,
+                reason,
+            } => {
+                let mut args = at(program_name, *line_num, 1);
+                args.push(("reason", reason.clone()));
+                Message::new(messages::INVALID_INCLUDE, args)
+            }
+            BftTypeError::InvalidMacro {
+                program_name,
+                line_num,
+                reason,
+            } => {
+                let mut args = at(program_name, *line_num, 1);
+                args.push(("reason", reason.clone()));
+                Message::new(messages::INVALID_MACRO, args)
+            }
+            BftTypeError::InvalidOok {
+                program_name,
+                line_num,
+                column_num,
+                reason,
+            } => {
+                let mut args = at(program_name, *line_num, *column_num);
+                args.push(("reason", reason.clone()));
+                Message::new(messages::INVALID_OOK, args)
+            }
+            BftTypeError::InvalidBytecode { reason } => {
+                Message::new(messages::INVALID_BYTECODE, vec![("reason", reason.clone())])
+            }
+        }
+    }
+}
+
+impl Display for BftTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+/// One of the brackets in a [BftTypeError::UnmatchedJumps]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnmatchedJump {
+    /// The file the bracket is in
+    pub program_name: PathBuf,
+    /// The '[' or ']' with nothing to match it
+    pub bad_instruction: LocalisedInstruction,
+}
+
+/// As `'[' in prog.bf at line 1, column 2`
+impl Display for UnmatchedJump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' in {} at line {}, column {}",
+            if self.bad_instruction.instruction == Instruction::ConditionalJumpForward {
+                '['
+            } else {
+                ']'
+            },
+            self.program_name.display(),
+            self.bad_instruction.line_num,
+            self.bad_instruction.column_num
+        )
+    }
+}
+
+/// Options controlling which dialect of Brainfuck a program is parsed as. The default is plain
+/// Brainfuck, where every character other than the eight instructions is a comment.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ParseOptions {
+    /// Treat `@assert` directives as inline assertions (see [assertion])
+    pub assertions: bool,
+    /// Extra characters to treat as [Instruction::Extension]s rather than comments. The eight
+    /// standard instructions cannot be redefined.
+    pub extensions: Vec<char>,
+    /// The most loops that may be open at once, or `None` for no limit. Matching brackets never
+    /// recurses, so any depth can be parsed; the limit is for callers that want to refuse
+    /// pathological programs before they are run or passed on to other tools.
+    pub max_nesting: Option<usize>,
+    /// Parse Extended Brainfuck Type I, where `@`, `$`, `!`, `}`, `{`, `~`, `^`, `&` and `|` are
+    /// instructions too (see [Instruction::from_extended_char]). They take the place of any
+    /// [ParseOptions::extensions] for the same characters.
+    pub extended: bool,
+    /// Parse a trivial substitution dialect, where the eight instructions are spelt as the
+    /// [mapping::Mapping] says instead of as Brainfuck characters
+    pub mapping: Option<mapping::Mapping>,
+    /// Treat `#` as [Instruction::DebugDump], as many published programs expect, rather than as a
+    /// comment
+    pub debug_dump: bool,
+    /// Characters that start comments, so that prose in them isn't run
+    pub comments: CommentSyntax,
+    /// Read `#include "file"` lines as directives to parse the named file in their place, with
+    /// the path relative to the file the directive is in. The included file's instructions keep
+    /// where they are in that file (see [LocalisedInstruction::source]). Only programs read from
+    /// files can be sure of finding what they include.
+    pub includes: bool,
+    /// Read `#define NAME body` lines as macro definitions, and replace `NAME` wherever it appears
+    /// later in the same file with the instructions in `body`. Names are letters, digits and `_`,
+    /// and are only replaced as whole words. The instructions from a macro are located where it is
+    /// used (see [LocalisedInstruction::expanded_from]).
+    pub macros: bool,
+}
+
+/// Which characters start comments, for programs whose comments would otherwise contain
+/// instructions. With neither set, every character that isn't an instruction is a comment, as
+/// usual. Nothing inside a comment is an instruction, but `@assert` directives are still found
+/// wherever they are, as they are comments themselves.
+///
+/// ```
+///# use bft_types::{BfProgram, BftTypeError, CommentSyntax, ParseOptions};
+///# fn main() -> Result<(), BftTypeError>{
+///  let options = ParseOptions {
+///      comments: CommentSyntax {
+///          line: Some(';'),
+///          block: Some(('{', '}')),
+///      },
+///      ..ParseOptions::default()
+///  };
+///  let text = "+. ; print it, then add one more.\n{ a loop [-] }+";
+///  let program = BfProgram::new_with_options("commented.bf", text, &options)?;
+///
+///  assert_eq!(program.localised_instructions().len(), 3);
+///# Ok(())
+///# }
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct CommentSyntax {
+    /// A character that makes the rest of its line a comment
+    pub line: Option<char>,
+    /// The characters that open and close a comment, which may span lines. Comments don't nest.
+    pub block: Option<(char, char)>,
 }
 
 /// Types of Brainfuck instructions
-#[derive(Debug, PartialEq, Clone, Eq, Copy)]
+#[derive(Debug, PartialEq, Clone, Eq, Copy, Hash)]
 pub enum Instruction {
     /// Increment the data pointer by one (to point to the next cell to the left).
     MoveLeft,
@@ -48,6 +365,30 @@ pub enum Instruction {
     /// If the byte at the data pointer is nonzero, then instead of moving the instruction pointer
     /// forward to the next command, jump it back to the command after the matching [ command.
     ConditionalJumpBackward,
+    /// An extra instruction, only recognised when listed in [ParseOptions::extensions]. What it
+    /// does is up to whoever runs the program.
+    Extension(char),
+    /// Extended Type I `@`: end the program.
+    End,
+    /// Extended Type I `$`: copy the byte at the data pointer into the storage byte.
+    Store,
+    /// Extended Type I `!`: copy the storage byte into the byte at the data pointer.
+    Load,
+    /// Extended Type I `}`: shift the byte at the data pointer right by one bit.
+    ShiftRight,
+    /// Extended Type I `{`: shift the byte at the data pointer left by one bit.
+    ShiftLeft,
+    /// Extended Type I `~`: invert every bit of the byte at the data pointer.
+    Not,
+    /// Extended Type I `^`: XOR the byte at the data pointer with the storage byte.
+    Xor,
+    /// Extended Type I `&`: AND the byte at the data pointer with the storage byte.
+    And,
+    /// Extended Type I `|`: OR the byte at the data pointer with the storage byte.
+    Or,
+    /// `#`: show the position of the head and the cells around it, for debugging. Only
+    /// recognised with [ParseOptions::debug_dump].
+    DebugDump,
 }
 
 impl Instruction {
@@ -73,6 +414,74 @@ impl Instruction {
             _ => None,
         }
     }
+
+    /// Parse a single char as one of the instructions Extended Brainfuck Type I adds, only
+    /// recognised with [ParseOptions::extended].
+    ///
+    /// ```
+    ///# use bft_types::Instruction;
+    ///  assert_eq!(Instruction::from_extended_char('@'), Some(Instruction::End));
+    ///  assert_eq!(Instruction::from_extended_char('+'), None);
+    /// ```
+    pub fn from_extended_char(c: char) -> Option<Instruction> {
+        match c {
+            '@' => Some(Instruction::End),
+            '$' => Some(Instruction::Store),
+            '!' => Some(Instruction::Load),
+            '}' => Some(Instruction::ShiftRight),
+            '{' => Some(Instruction::ShiftLeft),
+            '~' => Some(Instruction::Not),
+            '^' => Some(Instruction::Xor),
+            '&' => Some(Instruction::And),
+            '|' => Some(Instruction::Or),
+            _ => None,
+        }
+    }
+
+    /// Whether this is one of the conditional jump instructions ('[' or ']')
+    ///
+    /// ```
+    ///# use bft_types::Instruction;
+    ///  assert!(Instruction::ConditionalJumpForward.is_jump());
+    ///  assert!(!Instruction::Increment.is_jump());
+    /// ```
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ConditionalJumpForward | Instruction::ConditionalJumpBackward
+        )
+    }
+
+    /// The character the instruction is written as
+    ///
+    /// ```
+    ///# use bft_types::Instruction;
+    ///  assert_eq!(Instruction::ConditionalJumpForward.to_char(), '[');
+    ///  assert_eq!(Instruction::Extension('*').to_char(), '*');
+    /// ```
+    pub fn to_char(&self) -> char {
+        match self {
+            Instruction::MoveLeft => '<',
+            Instruction::MoveRight => '>',
+            Instruction::Increment => '+',
+            Instruction::Decrement => '-',
+            Instruction::Input => ',',
+            Instruction::Output => '.',
+            Instruction::ConditionalJumpForward => '[',
+            Instruction::ConditionalJumpBackward => ']',
+            Instruction::Extension(c) => *c,
+            Instruction::End => '@',
+            Instruction::Store => '$',
+            Instruction::Load => '!',
+            Instruction::ShiftRight => '}',
+            Instruction::ShiftLeft => '{',
+            Instruction::Not => '~',
+            Instruction::Xor => '^',
+            Instruction::And => '&',
+            Instruction::Or => '|',
+            Instruction::DebugDump => '#',
+        }
+    }
 }
 
 impl Display for Instruction {
@@ -90,14 +499,25 @@ impl Display for Instruction {
             Instruction::ConditionalJumpBackward => {
                 "Jump backwards to the matching [ if the cell is not zero"
             }
+            Instruction::Extension(c) => return write!(f, "Run the '{}' extension", c),
+            Instruction::End => "End the program",
+            Instruction::Store => "Copy the value in the cell under the head into storage",
+            Instruction::Load => "Copy the value in storage into the cell under the head",
+            Instruction::ShiftRight => "Shift the value in the cell under the head right a bit",
+            Instruction::ShiftLeft => "Shift the value in the cell under the head left a bit",
+            Instruction::Not => "Invert the bits of the value in the cell under the head",
+            Instruction::Xor => "XOR the value in the cell under the head with storage",
+            Instruction::And => "AND the value in the cell under the head with storage",
+            Instruction::Or => "OR the value in the cell under the head with storage",
+            Instruction::DebugDump => "Show the head and the cells around it",
         };
 
         write!(f, "{}", description)
     }
 }
 
-/// A single program [Instruction] with the line and column number it originally appeared on. Line
-/// and column numbers are 1-indexed
+/// A single program [Instruction] with the line and column number it originally appeared on, and
+/// the bytes of the file it was read from. Line and column numbers are 1-indexed
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct LocalisedInstruction {
     /// The type of operation this instruction represents
@@ -106,6 +526,16 @@ pub struct LocalisedInstruction {
     line_num: usize,
     /// The column number of the original file in which this instruction appears, 1-indexed human-readable
     column_num: usize,
+    /// The byte offset in its file of the start of the text the instruction was read from
+    offset: usize,
+    /// How many bytes of text the instruction was read from: one for a Brainfuck character, more
+    /// for a mapped token, an Ook! pair or the name of a macro
+    len: usize,
+    /// Which of the program's [BfProgram::sources] the instruction is in
+    source: usize,
+    /// For an instruction from a macro, the line and column in the macro's `#define` it was
+    /// written at. The instruction itself is located where the macro was used.
+    expanded_from: Option<(usize, usize)>,
 }
 
 impl LocalisedInstruction {
@@ -128,9 +558,33 @@ impl LocalisedInstruction {
             instruction,
             line_num,
             column_num,
+            offset: 0,
+            len: 0,
+            source: 0,
+            expanded_from: None,
+        }
+    }
+
+    /// The same instruction, read from the bytes in `span` of its file
+    ///
+    /// ```
+    ///# use bft_types::{Instruction, LocalisedInstruction};
+    ///  let instruction = LocalisedInstruction::new(Instruction::Output, 2, 3).with_span(7..8);
+    ///  assert_eq!(instruction.span(), 7..8);
+    /// ```
+    pub fn with_span(self, span: Range<usize>) -> Self {
+        Self {
+            offset: span.start,
+            len: span.len(),
+            ..self
         }
     }
 
+    /// The same instruction, found in the program's source file with index `source`
+    pub(crate) fn in_source(self, source: usize) -> Self {
+        Self { source, ..self }
+    }
+
     /// Get the inner [Instruction]
     pub fn instruction(&self) -> Instruction {
         self.instruction
@@ -147,6 +601,36 @@ impl LocalisedInstruction {
     pub fn column_num(&self) -> usize {
         self.column_num
     }
+
+    /// The byte offsets in its file of the text this instruction was read from, for tools that
+    /// work with the source text directly. Empty for instructions made with
+    /// [LocalisedInstruction::new] rather than parsed.
+    ///
+    /// ```
+    ///# use bft_types::BfProgram;
+    ///# fn main() -> Result<(), bft_types::BftTypeError>{
+    ///  let text = "café\n+";
+    ///  let program = BfProgram::new("span.bf", text)?;
+    ///  let span = program.localised_instructions()[0].span();
+    ///  assert_eq!(&text[span], "+");
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn span(&self) -> Range<usize> {
+        self.offset..self.offset + self.len
+    }
+
+    /// The index in [BfProgram::sources] of the file this instruction appears in: 0 for the
+    /// program's own file, and above for files it includes
+    pub fn source(&self) -> usize {
+        self.source
+    }
+
+    /// If the instruction came from a macro (see [ParseOptions::macros]), the line and column in
+    /// the `#define` where it was written; its own line and column are where the macro was used.
+    pub fn expanded_from(&self) -> Option<(usize, usize)> {
+        self.expanded_from
+    }
 }
 
 impl Display for LocalisedInstruction {
@@ -160,16 +644,77 @@ impl Display for LocalisedInstruction {
 }
 
 /// Representation of a Brainfuck program, including its name and a vector of [LocalisedInstruction]s
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, Clone)]
 pub struct BfProgram {
     /// Name of the file containing the original program
     name: PathBuf,
+    /// The program's own file, then every file it includes, in the order they were included
+    sources: Vec<PathBuf>,
     /// A vector of instructions. Not sure how else to describe it
     instructions: Vec<LocalisedInstruction>,
-    /// Vector to record, for each instruction, the index of the counterpart jump (if any)
-    jump_map: Vec<Option<usize>>,
+    /// For each instruction, where its jump goes (the instruction after its counterpart), or zero
+    /// if it is not a jump. No jump can go to instruction zero, so zero is free to mean "none".
+    jump_map: Vec<usize>,
+    /// The loops of the program, built alongside the jump map
+    loops: LoopTree,
+    /// Inline assertions, in program order
+    assertions: Vec<Assertion>,
+    /// How long matching the brackets took
+    analysis_time: Duration,
+    /// Size in bytes of the text the program was read from, including any files it includes
+    source_bytes: usize,
+    /// The ops the program was lowered to before it was written as bytecode, if it was loaded
+    /// from bytecode
+    optimized: Option<OptimizedProgram>,
+    /// The macros the program defined, in the order they were defined
+    macros: Vec<MacroDefinition>,
+}
+
+/// Where a macro was defined, for naming it when its instructions are shown
+#[derive(Debug, Clone)]
+pub(crate) struct MacroDefinition {
+    pub(crate) name: String,
+    /// The index in [BfProgram::sources] of the file it was defined in
+    pub(crate) source: usize,
+    /// The line of its `#define`
+    pub(crate) line_num: usize,
 }
 
+/// The macro an instruction came from (see [BfProgram::expansion]), for showing alongside where
+/// the macro was used
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MacroExpansion<'p> {
+    /// The macro's name, if the program still knows it. Programs loaded from bytecode keep where
+    /// their instructions were written but not the names of their macros.
+    pub name: Option<&'p str>,
+    /// The line of the `#define` the instruction was written in
+    pub line_num: usize,
+    /// The column in the `#define` the instruction was written at
+    pub column_num: usize,
+}
+
+impl Display for MacroExpansion<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "from {} at ", name)?,
+            None => write!(f, "from a macro at ")?,
+        }
+        write!(f, "{}:{}", self.line_num, self.column_num)
+    }
+}
+
+/// Programs are equal if they have the same name, instructions and assertions, however long they
+/// took to analyse
+impl PartialEq for BfProgram {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.instructions == other.instructions
+            && self.assertions == other.assertions
+    }
+}
+
+impl Eq for BfProgram {}
+
 impl BfProgram {
     /// Attempt to load a valid Brainfuck program from the specified file path. Calls
     /// [BfProgram::new] internally.
@@ -186,8 +731,43 @@ impl BfProgram {
     ///# }
     /// ```
     pub fn from_file<P: AsRef<Path>>(file_path: P) -> Result<BfProgram, BftTypeError> {
-        let file_contents = fs::read_to_string(&file_path).map_err(BftTypeError::IoError)?;
-        Self::new(file_path, file_contents.as_str())
+        Self::from_file_with_options(file_path, &ParseOptions::default())
+    }
+
+    /// Load a program from the specified file path, parsing it as the dialect chosen in `options`.
+    /// The file is read a line at a time, as with [BfProgram::from_reader].
+    pub fn from_file_with_options<P: AsRef<Path>>(
+        file_path: P,
+        options: &ParseOptions,
+    ) -> Result<BfProgram, BftTypeError> {
+        let file = File::open(&file_path).map_err(BftTypeError::IoError)?;
+        Self::from_reader(file_path, file, options)
+    }
+
+    /// Parse a program as it is read from `reader`, as [BfProgram::new_with_options] does, with
+    /// `filename` as its name. Only a line of the text is held at a time, so very large generated
+    /// programs can be loaded without keeping their text in memory alongside their instructions.
+    ///
+    /// ```
+    ///# use bft_types::{BfProgram, BftTypeError, ParseOptions};
+    ///# use std::io::Read;
+    ///# fn main() -> Result<(), BftTypeError>{
+    ///  let generated = std::io::repeat(b'+').take(1_000_000);
+    ///  let program = BfProgram::from_reader("big.bf", generated, &ParseOptions::default())?;
+    ///
+    ///  assert_eq!(program.localised_instructions().len(), 1_000_000);
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn from_reader<P: AsRef<Path>>(
+        filename: P,
+        reader: impl Read,
+        options: &ParseOptions,
+    ) -> Result<BfProgram, BftTypeError> {
+        Self::from_tokens(
+            tokenise_reader(filename.as_ref(), BufReader::new(reader), options),
+            options,
+        )
     }
 
     /// Construct a new [BfProgram] from a file path and a [str] that contains the program text.
@@ -211,32 +791,206 @@ impl BfProgram {
         filename: P,
         file_contents: &str,
     ) -> Result<BfProgram, BftTypeError> {
-        let mut instructions: Vec<LocalisedInstruction> = Vec::new();
-        let jump_map = Vec::new();
-
-        for (line_number, file_line) in file_contents.lines().enumerate() {
-            for (col_number, character) in file_line.chars().enumerate() {
-                if let Some(new_instruction) = Instruction::from_char(character) {
-                    instructions.push(LocalisedInstruction::new(
-                        new_instruction,
-                        line_number + 1,
-                        col_number + 1,
-                    ));
-                }
-            }
+        Self::new_with_options(filename, file_contents, &ParseOptions::default())
+    }
+
+    /// Construct a new [BfProgram] as [BfProgram::new] does, parsing it as the dialect chosen in
+    /// `options`
+    ///
+    /// ```
+    ///# use bft_types::{BfProgram, BftTypeError, ParseOptions};
+    ///# fn main() -> Result<(), BftTypeError>{
+    ///  let options = ParseOptions {
+    ///      assertions: true,
+    ///      extensions: vec!['!'],
+    ///      ..ParseOptions::default()
+    ///  };
+    ///  let program = BfProgram::new_with_options("test.bf", "++! @assert cell=2\n.", &options)?;
+    ///
+    ///  assert_eq!(program.localised_instructions().len(), 4);
+    ///  assert_eq!(program.assertions_at(3).len(), 1);
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn new_with_options<P: AsRef<Path>>(
+        filename: P,
+        file_contents: &str,
+        options: &ParseOptions,
+    ) -> Result<BfProgram, BftTypeError> {
+        Self::from_tokens(tokenise(filename.as_ref(), file_contents, options), options)
+    }
+
+    /// Build a program from what a front-end found in its text, matching its brackets. Fails with
+    /// the first error the front-end found, if there were any.
+    pub(crate) fn from_tokens(
+        tokens: Tokens,
+        options: &ParseOptions,
+    ) -> Result<BfProgram, BftTypeError> {
+        if let Some(error) = tokens.errors.into_iter().next() {
+            return Err(error);
         }
 
         let mut new_program = Self {
-            name: filename.as_ref().to_path_buf(),
-            instructions,
-            jump_map,
+            name: tokens.sources[0].clone(),
+            sources: tokens.sources,
+            instructions: tokens.instructions,
+            jump_map: Vec::new(),
+            loops: LoopTree::default(),
+            assertions: tokens.assertions,
+            analysis_time: Duration::ZERO,
+            source_bytes: tokens.source_bytes,
+            optimized: None,
+            macros: tokens.macros,
         };
 
-        new_program.analyse_program()?;
+        new_program.analyse_program(options.max_nesting)?;
 
         Ok(new_program)
     }
 
+    /// The `@assert` directives to check before the instruction at `program_index` runs, or when
+    /// the program completes if `program_index` is the length of the program
+    pub fn assertions_at(&self, program_index: usize) -> &[Assertion] {
+        let start = self
+            .assertions
+            .partition_point(|assertion| assertion.before_instruction < program_index);
+        let end = self
+            .assertions
+            .partition_point(|assertion| assertion.before_instruction <= program_index);
+        &self.assertions[start..end]
+    }
+
+    /// A fingerprint of what the program does: its instructions and assertions, but not its name
+    /// or comments, or where the instructions appear. Two programs with the same fingerprint
+    /// almost certainly behave the same way. See [fingerprint] for how it is computed.
+    ///```
+    ///# use bft_types::BfProgram;
+    ///# use bft_types::BftTypeError;
+    ///# fn main() -> Result<(), BftTypeError>{
+    ///  let program = BfProgram::new("a.bf", "+[-] count down")?;
+    ///  let reformatted = BfProgram::new("b.bf", "+\n[\n  -\n]")?;
+    ///  assert_eq!(program.fingerprint(), reformatted.fingerprint());
+    ///# Ok(())
+    ///# }
+    ///```
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = fingerprint::Fnv1a::default();
+        for instruction in &self.instructions {
+            instruction.instruction.hash(&mut hasher);
+        }
+        self.assertions.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether the program has any `@assert` directives
+    pub fn has_assertions(&self) -> bool {
+        !self.assertions.is_empty()
+    }
+
+    /// Lower the program to an [OptimizedProgram] of [optimize::Op]s, running the passes that
+    /// `level` asks for. [OptLevel::None] gives one op per instruction.
+    ///```
+    ///# use bft_types::BfProgram;
+    ///# use bft_types::optimize::OptLevel;
+    ///#
+    ///# let my_bf_program = BfProgram::new("filename.bf","++[-]>>").unwrap();
+    ///  let optimized = my_bf_program.optimize(OptLevel::Full);
+    ///  assert_eq!(optimized.ops().len(), 3);
+    ///```
+    pub fn optimize(&self, level: OptLevel) -> OptimizedProgram {
+        match &self.optimized {
+            Some(optimized) if optimized.level() == level => optimized.clone(),
+            _ => OptimizedProgram::new(self, level),
+        }
+    }
+
+    /// Lower the program at `level` now and keep the ops with it, so that optimising it at that
+    /// level later, as each machine running it does, only copies them
+    ///```
+    ///# use bft_types::BfProgram;
+    ///# use bft_types::optimize::OptLevel;
+    ///#
+    ///# let mut my_bf_program = BfProgram::new("filename.bf","++[-]>>").unwrap();
+    ///  my_bf_program.keep_optimized(OptLevel::Full);
+    ///  assert_eq!(my_bf_program.stored_opt_level(), Some(OptLevel::Full));
+    ///```
+    pub fn keep_optimized(&mut self, level: OptLevel) {
+        self.optimized = Some(self.optimize(level));
+    }
+
+    /// The [OptLevel] of the ops stored with the program, if it was loaded from bytecode or kept
+    /// with [BfProgram::keep_optimized]. Optimising it at that level only copies them.
+    pub fn stored_opt_level(&self) -> Option<OptLevel> {
+        self.optimized.as_ref().map(OptimizedProgram::level)
+    }
+
+    /// Write the program as bytecode (see [bytecode]), with its ops at `level`, to load again with
+    /// [BfProgram::from_bytecode] without parsing or analysing it
+    ///```
+    ///# use bft_types::BfProgram;
+    ///# use bft_types::optimize::OptLevel;
+    ///# fn main() -> Result<(), bft_types::BftTypeError>{
+    ///  let program = BfProgram::new("filename.bf", "++[-]>>")?;
+    ///  let loaded = BfProgram::from_bytecode(&program.to_bytecode(OptLevel::Full))?;
+    ///  assert_eq!(loaded, program);
+    ///  assert_eq!(loaded.stored_opt_level(), Some(OptLevel::Full));
+    ///# Ok(())
+    ///# }
+    ///```
+    pub fn to_bytecode(&self, level: OptLevel) -> Vec<u8> {
+        bytecode::encode(self, level, "")
+    }
+
+    /// Load a program written by [BfProgram::to_bytecode]. Fails with
+    /// [BftTypeError::InvalidBytecode] if the bytes are not bytecode or do not hang together.
+    pub fn from_bytecode(bytes: &[u8]) -> Result<BfProgram, BftTypeError> {
+        bytecode::decode(bytes)
+    }
+
+    /// How far the head can move from the cell it starts on, and how far it is certain to, found
+    /// without running the program (see [TapeExtent])
+    ///```
+    ///# use bft_types::BfProgram;
+    ///#
+    ///# let my_bf_program = BfProgram::new("filename.bf","+[>+]<<").unwrap();
+    ///  let extent = my_bf_program.tape_extent();
+    ///  assert_eq!(extent.right(), None);
+    ///```
+    pub fn tape_extent(&self) -> TapeExtent {
+        TapeExtent::new(self)
+    }
+
+    /// Count the program's instructions by type, its loops and how deeply they nest, without
+    /// running it (see [ProgramStats])
+    ///```
+    ///# use bft_types::BfProgram;
+    ///#
+    ///# let my_bf_program = BfProgram::new("filename.bf","+[->+<]").unwrap();
+    ///  let stats = my_bf_program.stats();
+    ///  assert_eq!((stats.instruction_count, stats.loops), (7, 1));
+    ///```
+    pub fn stats(&self) -> ProgramStats {
+        ProgramStats::new(self)
+    }
+
+    /// Split the program into basic blocks joined by the ways control can pass between them,
+    /// which [ControlFlowGraph::to_dot] can write out for Graphviz
+    ///```
+    ///# use bft_types::BfProgram;
+    ///#
+    ///# let my_bf_program = BfProgram::new("filename.bf","+[-]").unwrap();
+    ///  let graph = my_bf_program.control_flow_graph();
+    ///  assert_eq!(graph.blocks().len(), 4);
+    ///```
+    pub fn control_flow_graph(&self) -> ControlFlowGraph<'_> {
+        ControlFlowGraph::new(self)
+    }
+
+    /// Size in bytes of the text the program was read from, including any files it includes
+    pub fn source_bytes(&self) -> usize {
+        self.source_bytes
+    }
+
     /// Get the name of the program
     ///```
     ///# use bft_types::BfProgram;
@@ -248,6 +1002,49 @@ impl BfProgram {
         &self.name
     }
 
+    /// The files the program was read from: its own file first, then any it includes (see
+    /// [ParseOptions::includes]), which [LocalisedInstruction::source] indexes
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// The macro `instruction` came from, if it came from one (see [ParseOptions::macros])
+    ///
+    /// ```
+    ///# use bft_types::{BfProgram, BftTypeError, ParseOptions};
+    ///# fn main() -> Result<(), BftTypeError>{
+    ///  let options = ParseOptions { macros: true, ..ParseOptions::default() };
+    ///  let program = BfProgram::new_with_options("m.bf", "#define CLEAR [-]\n+CLEAR", &options)?;
+    ///  let expansion = program.expansion(&program.localised_instructions()[2]).unwrap();
+    ///  assert_eq!(expansion.to_string(), "from CLEAR at 1:16");
+    ///  assert_eq!(program.expansion(&program.localised_instructions()[0]), None);
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn expansion(&self, instruction: &LocalisedInstruction) -> Option<MacroExpansion<'_>> {
+        let (line_num, column_num) = instruction.expanded_from?;
+        let name = self
+            .macros
+            .iter()
+            .find(|definition| {
+                definition.source == instruction.source && definition.line_num == line_num
+            })
+            .map(|definition| definition.name.as_str());
+        Some(MacroExpansion {
+            name,
+            line_num,
+            column_num,
+        })
+    }
+
+    /// The file the instruction at `program_index` was read from, or `None` if the index is past
+    /// the end of the program
+    pub fn file_of(&self, program_index: usize) -> Option<&Path> {
+        self.instructions
+            .get(program_index)
+            .map(|instruction| self.sources[instruction.source].as_path())
+    }
+
     /// The [LocalisedInstruction]s that make up this program
     ///```
     ///# use bft_types::BfProgram;
@@ -273,56 +1070,516 @@ impl BfProgram {
     ///# }
     ///```
     pub fn jump_target(&self, program_index: usize) -> usize {
-        match &self.jump_map[program_index] {
-            Some(target) => *target,
-            None => 0,
+        self.jump_map[program_index]
+    }
+
+    /// The loops of the program and how they nest
+    pub fn loops(&self) -> &LoopTree {
+        &self.loops
+    }
+
+    /// How long it took to match the program's brackets and build its [LoopTree] when it was
+    /// parsed
+    pub fn analysis_time(&self) -> Duration {
+        self.analysis_time
+    }
+
+    /// Get the (line, column) in the original file of the instruction at the given index, or
+    /// `None` if the index is past the end of the program. Both are 1-indexed.
+    ///```
+    ///# use bft_types::BfProgram;
+    ///# use bft_types::BftTypeError;
+    ///# fn main() -> Result<(), BftTypeError>{
+    ///  let my_bf_program = BfProgram::new("filename.bf","++\n [-]")?;
+    ///  assert_eq!(my_bf_program.location(3), Some((2, 3)));
+    ///  assert_eq!(my_bf_program.location(5), None);
+    ///# Ok(())
+    ///# }
+    ///```
+    pub fn location(&self, program_index: usize) -> Option<(usize, usize)> {
+        self.instructions
+            .get(program_index)
+            .map(|instruction| (instruction.line_num, instruction.column_num))
+    }
+
+    /// Get the index of the instruction at the given (1-indexed) line and column of the original
+    /// file, or `None` if there is no instruction there (for example, if it is a comment).
+    ///```
+    ///# use bft_types::BfProgram;
+    ///# use bft_types::BftTypeError;
+    ///# fn main() -> Result<(), BftTypeError>{
+    ///  let my_bf_program = BfProgram::new("filename.bf","++\n [-]")?;
+    ///  assert_eq!(my_bf_program.instruction_at(2, 3), Some(3));
+    ///  assert_eq!(my_bf_program.instruction_at(2, 1), None);
+    ///# Ok(())
+    ///# }
+    ///```
+    pub fn instruction_at(&self, line_num: usize, column_num: usize) -> Option<usize> {
+        let position = |instruction: &LocalisedInstruction| {
+            (instruction.line_num, instruction.column_num).cmp(&(line_num, column_num))
+        };
+        if self.sources.len() == 1 {
+            // instructions are stored in the order they appear in the file, so can be searched
+            // by position
+            // macros can put several instructions in the same place, so look for the first
+            let first = self
+                .instructions
+                .partition_point(|instruction| position(instruction).is_lt());
+            self.instructions
+                .get(first)
+                .filter(|instruction| position(instruction).is_eq())
+                .map(|_| first)
+        } else {
+            // ... but instructions from included files are mixed in among them
+            self.instructions
+                .iter()
+                .position(|instruction| instruction.source == 0 && position(instruction).is_eq())
         }
     }
 
-    /// Analyse the program to ensure that it is syntactically valid, and record where the jumps map to.
-    fn analyse_program(&mut self) -> Result<(), BftTypeError> {
-        let mut jump_instructions = Vec::<(usize, &LocalisedInstruction)>::new();
+    /// Analyse the program to ensure that it is syntactically valid, recording where the jumps map
+    /// to and building the [LoopTree], all in one pass.
+    fn analyse_program(&mut self, max_nesting: Option<usize>) -> Result<(), BftTypeError> {
+        let started = Instant::now();
+        let mut jump_map = vec![0; self.instructions.len()];
+        let mut loops = LoopTree::default();
+        // the loops opened but not yet closed, innermost last
+        let mut open_loops = Vec::<usize>::new();
+        // instruction indexes of the `]`s found with no loop to close, then of the `[`s of any
+        // loops never closed
+        let mut unmatched = Vec::new();
 
         for (program_index, program_instruction) in self.instructions.iter().enumerate() {
-            // to begin with, store program_indexes and jump-forward instructuctions...
-            if program_instruction.instruction == Instruction::ConditionalJumpForward {
-                jump_instructions.push((program_index, program_instruction));
-                self.jump_map.push(None); // push a placeholder
-            }
-            // ...and pop them back off their vector as we find their matches.
-            // If we can't pop the corresponding {, we've got unmatched jumps
-            else if program_instruction.instruction == Instruction::ConditionalJumpBackward {
-                match jump_instructions.pop() {
-                    Some(popped_jump) => {
-                        let counterpart_index = popped_jump.0;
-                        // add a new element pointing this jump back toward the next instruction after its counterpart ']'
-                        self.jump_map.push(Some(counterpart_index + 1));
-                        // and just update the existing entry for the initial '[' to point to the instruction after this one
-                        self.jump_map[counterpart_index] = Some(program_index + 1);
-                    }
-                    None => {
-                        return Err(BftTypeError::UnmatchedBackwardJump {
-                            program_name: self.name.clone(),
+            match program_instruction.instruction {
+                Instruction::ConditionalJumpForward => {
+                    if let Some(limit) = max_nesting.filter(|&limit| open_loops.len() >= limit) {
+                        return Err(BftTypeError::NestingTooDeep {
+                            program_name: self.sources[program_instruction.source].clone(),
                             bad_instruction: *program_instruction,
+                            limit,
                         });
                     }
+                    open_loops.push(loops.open(program_index, open_loops.last().copied()));
                 }
-            } else {
-                self.jump_map.push(None);
+                // if there is no open loop to close, we've got unmatched jumps
+                Instruction::ConditionalJumpBackward => match open_loops.pop() {
+                    Some(number) => {
+                        let counterpart_index = loops.open_of(number);
+                        loops.close(number, program_index);
+                        // each jump goes to the instruction after its counterpart
+                        jump_map[program_index] = counterpart_index + 1;
+                        jump_map[counterpart_index] = program_index + 1;
+                    }
+                    None => unmatched.push(program_index),
+                },
+                _ => {}
             }
         }
 
-        if let Some(unmatched_jump) = jump_instructions.pop() {
-            return Err(BftTypeError::UnmatchedForwardJump {
-                program_name: self.name.clone(),
-                bad_instruction: *unmatched_jump.1,
-            });
+        unmatched.extend(open_loops.iter().map(|&number| loops.open_of(number)));
+        unmatched.sort_unstable();
+        let mut jumps: Vec<_> = unmatched
+            .into_iter()
+            .map(|index| UnmatchedJump {
+                program_name: self.sources[self.instructions[index].source].clone(),
+                bad_instruction: self.instructions[index],
+            })
+            .collect();
+        match jumps.len() {
+            0 => {}
+            // a lone bracket keeps its own error, as it always has
+            1 => {
+                let UnmatchedJump {
+                    program_name,
+                    bad_instruction,
+                } = jumps.remove(0);
+                return Err(
+                    if bad_instruction.instruction == Instruction::ConditionalJumpForward {
+                        BftTypeError::UnmatchedForwardJump {
+                            program_name,
+                            bad_instruction,
+                        }
+                    } else {
+                        BftTypeError::UnmatchedBackwardJump {
+                            program_name,
+                            bad_instruction,
+                        }
+                    },
+                );
+            }
+            _ => return Err(BftTypeError::UnmatchedJumps { jumps }),
         }
 
+        self.jump_map = jump_map;
+        self.loops = loops;
+        self.analysis_time = started.elapsed();
         Ok(())
     }
 }
 
+/// The instructions and assertions found in a program's text, before its brackets are matched
+pub(crate) struct Tokens {
+    pub(crate) instructions: Vec<LocalisedInstruction>,
+    pub(crate) assertions: Vec<Assertion>,
+    /// Every problem found, such as an `@assert` directive that could not be understood. The rest
+    /// of the text is still read.
+    pub(crate) errors: Vec<BftTypeError>,
+    /// The program's own file, then every file it includes
+    pub(crate) sources: Vec<PathBuf>,
+    /// Bytes of text read, from every source
+    pub(crate) source_bytes: usize,
+    /// Every macro defined, from every source
+    pub(crate) macros: Vec<MacroDefinition>,
+}
+
+impl Tokens {
+    /// No tokens yet, for the program in `filename`
+    pub(crate) fn new(filename: &Path) -> Self {
+        Self {
+            instructions: Vec::new(),
+            assertions: Vec::new(),
+            errors: Vec::new(),
+            sources: vec![filename.to_path_buf()],
+            source_bytes: 0,
+            macros: Vec::new(),
+        }
+    }
+}
+
+/// Find the instructions (and, if enabled, the assertions and included files) in a program's text
+pub(crate) fn tokenise(filename: &Path, file_contents: &str, options: &ParseOptions) -> Tokens {
+    tokenise_reader(filename, file_contents.as_bytes(), options)
+}
+
+/// As [tokenise], reading the text a line at a time
+pub(crate) fn tokenise_reader(
+    filename: &Path,
+    reader: impl BufRead,
+    options: &ParseOptions,
+) -> Tokens {
+    let mut tokens = Tokens::new(filename);
+    let mut including = vec![identity(filename)];
+    tokenise_source(&mut tokens, 0, reader, options, &mut including);
+    tokens
+}
+
+/// What `#include` is followed by in a directive
+const INCLUDE: &str = "#include";
+
+/// A path that is the same for every way of naming the same file, where it can be found
+fn identity(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Find the tokens in the text of the program's source file with index `source`, adding them to
+/// `tokens`. `including` holds the files being read, outermost first, to refuse an include cycle.
+/// Only one line of the text is held at a time.
+fn tokenise_source(
+    tokens: &mut Tokens,
+    source: usize,
+    mut reader: impl BufRead,
+    options: &ParseOptions,
+    including: &mut Vec<PathBuf>,
+) {
+    let filename = tokens.sources[source].clone();
+    let mut scan = Scan {
+        source,
+        options,
+        open_comment: None,
+        macros: HashMap::new(),
+    };
+
+    let mut buffer = String::new();
+    // the byte offset of the start of the line being read
+    let mut line_start = 0;
+    for line_number in 0.. {
+        buffer.clear();
+        let line_offset = line_start;
+        match reader.read_line(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                line_start += read;
+                tokens.source_bytes += read;
+            }
+            Err(error) => {
+                tokens.errors.push(BftTypeError::IoError(error));
+                break;
+            }
+        }
+        // leave out the line ending, which is "\n" or "\r\n"
+        let file_line = buffer.strip_suffix('\n').map_or(buffer.as_str(), |line| {
+            line.strip_suffix('\r').unwrap_or(line)
+        });
+        let directive_text = file_line.trim_start();
+        if options.includes && scan.open_comment.is_none() {
+            if let Some(included) = directive_text.strip_prefix(INCLUDE) {
+                include(
+                    tokens,
+                    &filename,
+                    line_number + 1,
+                    included,
+                    options,
+                    including,
+                );
+                continue;
+            }
+        }
+        if options.macros && scan.open_comment.is_none() && directive_text.starts_with(DEFINE) {
+            match scan.define(file_line, line_number + 1, line_offset) {
+                Ok(name) => tokens.macros.push(MacroDefinition {
+                    name,
+                    source,
+                    line_num: line_number + 1,
+                }),
+                Err(reason) => tokens.errors.push(BftTypeError::InvalidMacro {
+                    program_name: filename.clone(),
+                    line_num: line_number + 1,
+                    reason,
+                }),
+            }
+            continue;
+        }
+        let (code, directive) = match file_line.find(assertion::DIRECTIVE) {
+            Some(start) if options.assertions => (&file_line[..start], Some(start)),
+            _ => (file_line, None),
+        };
+
+        scan.code(
+            code,
+            line_number + 1,
+            1,
+            line_offset,
+            &mut tokens.instructions,
+        );
+
+        if let Some(start) = directive {
+            let column_num = code.chars().count() + 1;
+            match assertion::parse_checks(&file_line[start + assertion::DIRECTIVE.len()..]) {
+                Ok(checks) => tokens.assertions.push(Assertion {
+                    line_num: line_number + 1,
+                    column_num,
+                    before_instruction: tokens.instructions.len(),
+                    checks,
+                }),
+                Err(reason) => tokens.errors.push(BftTypeError::InvalidAssertion {
+                    program_name: filename.clone(),
+                    line_num: line_number + 1,
+                    column_num,
+                    reason,
+                }),
+            }
+        }
+    }
+
+    if let Some((line_num, column_num)) = scan.open_comment {
+        tokens.errors.push(BftTypeError::UnclosedComment {
+            program_name: filename,
+            line_num,
+            column_num,
+        });
+    }
+}
+
+/// What a `#define` directive starts with
+const DEFINE: &str = "#define";
+
+/// Whether a macro name can start with `c`
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+/// Whether a macro name can go on with `c`
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// The state kept while reading one of the program's source files
+struct Scan<'o> {
+    /// The file's index in [Tokens::sources]
+    source: usize,
+    options: &'o ParseOptions,
+    /// Where the block comment being read was opened
+    open_comment: Option<(usize, usize)>,
+    /// The instructions each macro defined so far in the file stands for, located where they are
+    /// written in its `#define`
+    macros: HashMap<String, Vec<LocalisedInstruction>>,
+}
+
+impl Scan<'_> {
+    /// Find the instructions in `code`, which is on line `line_num` starting at column
+    /// `first_column` and byte offset `first_offset`, and add them to `instructions`
+    fn code(
+        &mut self,
+        code: &str,
+        line_num: usize,
+        first_column: usize,
+        first_offset: usize,
+        instructions: &mut Vec<LocalisedInstruction>,
+    ) {
+        let options = self.options;
+        // the end of the last mapped token or macro name, which the characters up to are part of
+        let mut token_end = 0;
+        for (col_number, (byte, character)) in code.char_indices().enumerate() {
+            let column_num = first_column + col_number;
+            if byte < token_end {
+                continue;
+            }
+            match options.comments.block {
+                Some((_, close)) if self.open_comment.is_some() => {
+                    if character == close {
+                        self.open_comment = None;
+                    }
+                    continue;
+                }
+                Some((open, _)) if character == open => {
+                    self.open_comment = Some((line_num, column_num));
+                    continue;
+                }
+                _ if options.comments.line == Some(character) => break,
+                _ => {}
+            }
+            if options.macros && is_name_start(character) && !code[..byte].ends_with(is_name_char) {
+                let name_end = code[byte..]
+                    .find(|c| !is_name_char(c))
+                    .map_or(code.len(), |len| byte + len);
+                if let Some(body) = self.macros.get(&code[byte..name_end]) {
+                    instructions.extend(body.iter().map(|instruction| LocalisedInstruction {
+                        line_num,
+                        column_num,
+                        offset: first_offset + byte,
+                        len: name_end - byte,
+                        expanded_from: Some((instruction.line_num, instruction.column_num)),
+                        ..*instruction
+                    }));
+                    token_end = name_end;
+                    continue;
+                }
+            }
+            let mut len = character.len_utf8();
+            let instruction = match &options.mapping {
+                Some(mapping) => {
+                    mapping
+                        .match_start(&code[byte..])
+                        .map(|(instruction, token_len)| {
+                            token_end = byte + token_len;
+                            len = token_len;
+                            instruction
+                        })
+                }
+                None => Instruction::from_char(character),
+            }
+            .or_else(|| (options.debug_dump && character == '#').then_some(Instruction::DebugDump))
+            .or_else(|| {
+                options
+                    .extended
+                    .then(|| Instruction::from_extended_char(character))
+                    .flatten()
+            })
+            .or_else(|| {
+                options
+                    .extensions
+                    .contains(&character)
+                    .then_some(Instruction::Extension(character))
+            });
+            if let Some(new_instruction) = instruction {
+                instructions.push(
+                    LocalisedInstruction::new(new_instruction, line_num, column_num)
+                        .with_span(first_offset + byte..first_offset + byte + len)
+                        .in_source(self.source),
+                );
+            }
+        }
+    }
+
+    /// Define the macro in a `#define NAME body` line, returning its name. The body is read as
+    /// code straight away, so it can only use macros defined before it, and no macro can expand to
+    /// itself.
+    fn define(
+        &mut self,
+        file_line: &str,
+        line_num: usize,
+        offset: usize,
+    ) -> Result<String, String> {
+        let after_define = file_line.len() - file_line.trim_start().len() + DEFINE.len();
+        let rest = &file_line[after_define..];
+        if !rest.starts_with(char::is_whitespace) {
+            return Err(format!("expected {} NAME body", DEFINE));
+        }
+        let name_start = after_define + rest.len() - rest.trim_start().len();
+        let name_end = file_line[name_start..]
+            .find(char::is_whitespace)
+            .map_or(file_line.len(), |len| name_start + len);
+        let name = &file_line[name_start..name_end];
+        if !name.starts_with(is_name_start) || !name.chars().all(is_name_char) {
+            return Err(format!(
+                "'{}' is not a macro name, which is letters, digits and _, not starting with a digit",
+                name
+            ));
+        }
+        if self.macros.contains_key(name) {
+            return Err(format!("{} is already defined", name));
+        }
+
+        let mut body = Vec::new();
+        let first_column = file_line[..name_end].chars().count() + 1;
+        self.code(
+            &file_line[name_end..],
+            line_num,
+            first_column,
+            offset + name_end,
+            &mut body,
+        );
+        self.macros.insert(name.to_string(), body);
+        Ok(name.to_string())
+    }
+}
+
+/// Read the tokens from the file named in an `#include` directive, given the text that follows
+/// `#include` on line `line_num` of `filename`. The path is relative to the including file.
+fn include(
+    tokens: &mut Tokens,
+    filename: &Path,
+    line_num: usize,
+    directive: &str,
+    options: &ParseOptions,
+    including: &mut Vec<PathBuf>,
+) {
+    let mut invalid = |reason: String| {
+        tokens.errors.push(BftTypeError::InvalidInclude {
+            program_name: filename.to_path_buf(),
+            line_num,
+            reason,
+        })
+    };
+    let Some(path) = directive
+        .trim()
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    else {
+        return invalid(format!("expected {} \"file\"", INCLUDE));
+    };
+    let path = filename.parent().unwrap_or(Path::new("")).join(path);
+    let identity = identity(&path);
+    if including.contains(&identity) {
+        return invalid(format!("{} includes itself", path.display()));
+    }
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(error) => return invalid(format!("could not read {}: {}", path.display(), error)),
+    };
+
+    tokens.sources.push(path);
+    including.push(identity);
+    tokenise_source(
+        tokens,
+        tokens.sources.len() - 1,
+        BufReader::new(file),
+        options,
+        including,
+    );
+    including.pop();
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -352,20 +1609,7 @@ mod tests {
     fn parse_program() {
         let filename = Path::new("test_file.bf");
         let lines = "..[<>]..[]..";
-        let expected_jump_map: Vec<Option<usize>> = vec![
-            None,
-            None,
-            Some(6),
-            None,
-            None,
-            Some(3),
-            None,
-            None,
-            Some(10),
-            Some(9),
-            None,
-            None,
-        ];
+        let expected_jump_map: Vec<usize> = vec![0, 0, 6, 0, 0, 3, 0, 0, 10, 9, 0, 0];
 
         let bf_program = BfProgram::new(filename, lines).unwrap();
 
@@ -373,6 +1617,24 @@ mod tests {
         assert_eq!(bf_program.jump_map, expected_jump_map);
     }
 
+    /// Check that locations and instruction indexes map to each other in both directions
+    #[test]
+    fn test_location_round_trip() {
+        let bf_program = BfProgram::new("test_file.bf", "a+b-\n\n  [c>]\n.").unwrap();
+
+        for program_index in 0..bf_program.localised_instructions().len() {
+            let (line_num, column_num) = bf_program.location(program_index).unwrap();
+            assert_eq!(
+                bf_program.instruction_at(line_num, column_num),
+                Some(program_index)
+            );
+        }
+        assert_eq!(bf_program.location(3), Some((3, 5)));
+        assert_eq!(bf_program.instruction_at(1, 1), None);
+        assert_eq!(bf_program.instruction_at(2, 1), None);
+        assert_eq!(bf_program.instruction_at(9, 1), None);
+    }
+
     /// check that we find an unmatched [
     #[test]
     fn test_analyse_unmatched_open_square_bracket() {
@@ -398,6 +1660,7 @@ mod tests {
             assert_eq!(
                 bad_instruction,
                 LocalisedInstruction::new(Instruction::ConditionalJumpForward, 2, 2)
+                    .with_span(7..8)
             )
         }
     }
@@ -427,7 +1690,330 @@ mod tests {
             assert_eq!(
                 bad_instruction,
                 LocalisedInstruction::new(Instruction::ConditionalJumpBackward, 2, 2)
+                    .with_span(7..8)
             )
         }
     }
+
+    /// check that every unmatched [ and ] is reported at once, in order
+    #[test]
+    fn test_analyse_several_unmatched_brackets() {
+        let result = BfProgram::new("test_file.bf", "]+[[-]\n[>]]]\n[");
+
+        let Err(BftTypeError::UnmatchedJumps { jumps }) = result else {
+            panic!("expected several unmatched jumps, got {:?}", result);
+        };
+        let locations: Vec<_> = jumps
+            .iter()
+            .map(|jump| {
+                (
+                    jump.bad_instruction.instruction(),
+                    jump.bad_instruction.line_num(),
+                    jump.bad_instruction.column_num(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            locations,
+            [
+                (Instruction::ConditionalJumpBackward, 1, 1),
+                (Instruction::ConditionalJumpBackward, 2, 5),
+                (Instruction::ConditionalJumpForward, 3, 1),
+            ]
+        );
+        assert_eq!(
+            BftTypeError::UnmatchedJumps { jumps }.to_string(),
+            "BFT0013: 3 unmatched jump instructions: ']' in test_file.bf at line 1, column 1; \
+             ']' in test_file.bf at line 2, column 5; '[' in test_file.bf at line 3, column 1"
+        );
+    }
+
+    /// check that the Extended Type I characters are only instructions in that dialect
+    #[test]
+    fn test_extended() {
+        let text = "+$!}{~^&|@ comment";
+        assert_eq!(
+            BfProgram::new("plain.bf", text).unwrap().instructions.len(),
+            1
+        );
+
+        let options = ParseOptions {
+            extended: true,
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("extended.bf", text, &options).unwrap();
+        let instructions: Vec<_> = program
+            .localised_instructions()
+            .iter()
+            .map(|instruction| instruction.instruction())
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::Increment,
+                Instruction::Store,
+                Instruction::Load,
+                Instruction::ShiftRight,
+                Instruction::ShiftLeft,
+                Instruction::Not,
+                Instruction::Xor,
+                Instruction::And,
+                Instruction::Or,
+                Instruction::End,
+            ]
+        );
+    }
+
+    /// check that `#` is only an instruction when debug dumps are asked for
+    #[test]
+    fn test_debug_dump() {
+        let text = "+# dump #";
+        assert_eq!(
+            BfProgram::new("plain.bf", text).unwrap().instructions.len(),
+            1
+        );
+
+        let options = ParseOptions {
+            debug_dump: true,
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("debug.bf", text, &options).unwrap();
+        assert_eq!(program.instructions.len(), 3);
+        assert_eq!(
+            program.instructions[2].instruction(),
+            Instruction::DebugDump
+        );
+        assert_eq!(program.location(2), Some((1, 9)));
+    }
+
+    /// check that nothing in a comment is an instruction, and that an unclosed block is refused
+    #[test]
+    fn test_comments() {
+        let options = ParseOptions {
+            assertions: true,
+            comments: CommentSyntax {
+                line: Some(';'),
+                block: Some(('{', '}')),
+            },
+            ..ParseOptions::default()
+        };
+        let text = "+ ; add one, then -\n{ [multi-line\n  comment] }-; @assert cell=0";
+        let program = BfProgram::new_with_options("commented.bf", text, &options).unwrap();
+        let instructions: Vec<_> = program
+            .localised_instructions()
+            .iter()
+            .map(|instruction| instruction.instruction())
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![Instruction::Increment, Instruction::Decrement]
+        );
+        assert_eq!(program.location(1), Some((3, 13)));
+        assert!(program.has_assertions());
+
+        assert_matches!(
+            BfProgram::new_with_options("open.bf", "+\n {+}{-", &options),
+            Err(BftTypeError::UnclosedComment {
+                line_num: 2,
+                column_num: 5,
+                ..
+            })
+        );
+    }
+
+    /// check that included files are parsed in place, with their instructions located in them
+    #[test]
+    fn test_includes() {
+        let directory =
+            std::env::temp_dir().join(format!("bft-include-test-{}", std::process::id()));
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        let main = directory.join("main.bf");
+        fs::write(&main, "+\n  #include \"lib/clear.bf\"\n.").unwrap();
+        fs::write(
+            directory.join("lib/clear.bf"),
+            "[\n#include \"minus.bf\"\n]",
+        )
+        .unwrap();
+        fs::write(directory.join("lib/minus.bf"), "-").unwrap();
+        let options = ParseOptions {
+            includes: true,
+            ..ParseOptions::default()
+        };
+
+        let program = BfProgram::from_file_with_options(&main, &options).unwrap();
+        assert_eq!(
+            program.fingerprint(),
+            BfProgram::new("flat.bf", "+[-].").unwrap().fingerprint()
+        );
+        assert_eq!(
+            program.sources(),
+            [
+                main.clone(),
+                directory.join("lib/clear.bf"),
+                directory.join("lib/minus.bf")
+            ]
+        );
+        assert_eq!(
+            program.file_of(2),
+            Some(directory.join("lib/minus.bf").as_path())
+        );
+        assert_eq!(program.location(3), Some((3, 1)));
+        assert_eq!(program.file_of(4), Some(main.as_path()));
+        assert_eq!(program.instruction_at(3, 1), Some(4));
+
+        // errors point at the file they are in
+        fs::write(directory.join("lib/minus.bf"), "-]]").unwrap();
+        assert_matches!(
+            BfProgram::from_file_with_options(&main, &options),
+            Err(BftTypeError::UnmatchedJumps { jumps })
+                if jumps[0].program_name == directory.join("lib/minus.bf")
+                    && jumps[1].program_name == directory.join("lib/clear.bf")
+        );
+        fs::write(directory.join("lib/minus.bf"), "#include \"clear.bf\"").unwrap();
+        assert_matches!(
+            BfProgram::from_file_with_options(&main, &options),
+            Err(BftTypeError::InvalidInclude { line_num: 1, reason, .. })
+                if reason.ends_with("clear.bf includes itself")
+        );
+        fs::write(&main, "#include lib/clear.bf").unwrap();
+        assert_matches!(
+            BfProgram::from_file_with_options(&main, &options),
+            Err(BftTypeError::InvalidInclude { line_num: 1, .. })
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// check that macros expand where they are used, as whole words, to what they were defined as
+    #[test]
+    fn test_macros() {
+        let options = ParseOptions {
+            macros: true,
+            ..ParseOptions::default()
+        };
+        let text = "#define CLEAR [-]\n#define  RESET CLEAR>CLEAR<\n+ RESET UNCLEARED .";
+        let program = BfProgram::new_with_options("macros.bf", text, &options).unwrap();
+        assert_eq!(
+            program.fingerprint(),
+            BfProgram::new("flat.bf", "+[-]>[-]<.")
+                .unwrap()
+                .fingerprint()
+        );
+        let instruction = program.localised_instructions()[6];
+        assert_eq!((instruction.line_num(), instruction.column_num()), (3, 3));
+        assert_eq!(instruction.expanded_from(), Some((2, 22)));
+        assert_eq!(program.localised_instructions()[0].expanded_from(), None);
+        assert_eq!(program.instruction_at(3, 3), Some(1));
+        assert_eq!(program.instruction_at(3, 19), Some(9));
+
+        // without macros, the definitions are comments holding instructions
+        assert_eq!(
+            BfProgram::new("plain.bf", text).unwrap().instructions.len(),
+            7
+        );
+
+        for bad in [
+            "#define",
+            "#define 2X +",
+            "#define X +\n#define X -",
+            "#defineX +",
+        ] {
+            assert_matches!(
+                BfProgram::new_with_options("bad.bf", bad, &options),
+                Err(BftTypeError::InvalidMacro { .. })
+            );
+        }
+    }
+
+    /// check that each instruction's span is the text it was read from, whatever the line endings
+    #[test]
+    fn test_spans() {
+        let spans = |text: &str, options: &ParseOptions| -> Vec<String> {
+            let program = BfProgram::new_with_options("spans.bf", text, options).unwrap();
+            program
+                .localised_instructions()
+                .iter()
+                .map(|instruction| text[instruction.span()].to_string())
+                .collect()
+        };
+        let text = "é+\r\n\r\n-\n.";
+        assert_eq!(spans(text, &ParseOptions::default()), ["+", "-", "."]);
+
+        let options = ParseOptions {
+            macros: true,
+            mapping: Some(
+                mapping::Mapping::parse(
+                    "right = 'R'\nleft = 'L'\nincrement = 'inc'\ndecrement = 'dec'\n\
+                     output = 'out'\ninput = 'in'\nopen = 'while'\nclose = 'end'",
+                )
+                .unwrap(),
+            ),
+            ..ParseOptions::default()
+        };
+        let text = "#define TWICE inc inc\r\nwhile TWICE end out";
+        assert_eq!(
+            spans(text, &options),
+            ["while", "TWICE", "TWICE", "end", "out"]
+        );
+    }
+
+    /// check that reading a program a line at a time finds what parsing its whole text does
+    #[test]
+    fn test_from_reader() {
+        let text = "+[\r\n-]\n\n>.é,\n<";
+        let options = ParseOptions::default();
+        let whole = BfProgram::new_with_options("stream.bf", text, &options).unwrap();
+        let streamed = BfProgram::from_reader("stream.bf", text.as_bytes(), &options).unwrap();
+        assert_eq!(
+            streamed.localised_instructions(),
+            whole.localised_instructions()
+        );
+        for (streamed, whole) in streamed
+            .localised_instructions()
+            .iter()
+            .zip(whole.localised_instructions())
+        {
+            assert_eq!(streamed.span(), whole.span());
+        }
+
+        let invalid = [b'+', 0xff, b'\n'];
+        assert_matches!(
+            BfProgram::from_reader("stream.bf", &invalid[..], &options),
+            Err(BftTypeError::IoError(_))
+        );
+    }
+
+    /// check that matching brackets copes with pathological nesting, and that a limit refuses it
+    #[test]
+    fn test_analyse_deep_nesting() {
+        let depth = 1_000_000;
+        let text = format!("+{}-{}", "[".repeat(depth), "]".repeat(depth));
+
+        let program = BfProgram::new("deep.bf", &text).unwrap();
+        assert_eq!(program.loops().len(), depth);
+        assert_eq!(program.loops().max_depth(), Some(depth - 1));
+        assert_eq!(program.loops().innermost_at(depth + 1), Some(depth - 1));
+        assert_eq!(program.jump_target(1), 2 * depth + 2);
+
+        let options = ParseOptions {
+            max_nesting: Some(depth),
+            ..ParseOptions::default()
+        };
+        assert!(BfProgram::new_with_options("deep.bf", &text, &options).is_ok());
+
+        let options = ParseOptions {
+            max_nesting: Some(1000),
+            ..ParseOptions::default()
+        };
+        let result = BfProgram::new_with_options("deep.bf", &text, &options);
+        assert_matches!(
+            result,
+            Err(BftTypeError::NestingTooDeep {
+                program_name: _,
+                bad_instruction,
+                limit: 1000,
+            }) if bad_instruction
+                == LocalisedInstruction::new(Instruction::ConditionalJumpForward, 1, 1002)
+                    .with_span(1001..1002)
+        );
+    }
 }
@@ -0,0 +1,133 @@
+//! Mapping between byte offsets in a program's text and the 1-indexed line and column positions
+//! used in diagnostics, so that editors, the checker and other tools all count positions the same
+//! way as the parser.
+//!
+//! Columns count characters, not bytes, and lines end at `\n` or `\r\n`, as with [str::lines].
+//!
+//! ```
+//!# use bft_types::line_index::LineIndex;
+//!  let index = LineIndex::new("+[\r\n  é.]\n");
+//!  assert_eq!(index.line_count(), 2);
+//!  assert_eq!(index.location(8), Some((2, 4)));
+//!  assert_eq!(index.offset(2, 4), Some(8));
+//!  assert_eq!(index.line(2), Some("  é.]"));
+//! ```
+
+/// The start of every line in a text, worked out once so positions can be looked up quickly
+#[derive(Debug, Clone)]
+pub struct LineIndex<'t> {
+    text: &'t str,
+    /// Byte offset of the start of each line, in order
+    line_starts: Vec<usize>,
+}
+
+impl<'t> LineIndex<'t> {
+    /// Index the lines of `text`
+    pub fn new(text: &'t str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(offset, _)| offset + 1))
+            .filter(|start| *start < text.len() || *start == 0)
+            .collect();
+        Self { text, line_starts }
+    }
+
+    /// The number of lines, counted as [str::lines] does
+    pub fn line_count(&self) -> usize {
+        if self.text.is_empty() {
+            0
+        } else {
+            self.line_starts.len()
+        }
+    }
+
+    /// The byte offset of the start of line `line_num`, if there is such a line
+    pub fn line_start(&self, line_num: usize) -> Option<usize> {
+        if line_num > self.line_count() {
+            return None;
+        }
+        self.line_starts.get(line_num.checked_sub(1)?).copied()
+    }
+
+    /// The text of line `line_num`, without its line ending
+    pub fn line(&self, line_num: usize) -> Option<&'t str> {
+        let start = self.line_start(line_num)?;
+        let end = self
+            .line_starts
+            .get(line_num)
+            .copied()
+            .unwrap_or(self.text.len());
+        let line = &self.text[start..end];
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        Some(line.strip_suffix('\r').unwrap_or(line))
+    }
+
+    /// The line and column of the character at byte `offset`. An offset at the end of a line, on
+    /// its line ending, is the column after its last character. Returns `None` for offsets past the
+    /// end of the text or inside a character.
+    pub fn location(&self, offset: usize) -> Option<(usize, usize)> {
+        if offset > self.text.len() || !self.text.is_char_boundary(offset) {
+            return None;
+        }
+        let line_num = self.line_starts.partition_point(|start| *start <= offset);
+        let start = self.line_starts[line_num - 1];
+        let line = self.line(line_num).unwrap_or_default();
+        let within = (offset - start).min(line.len());
+        Some((line_num, line[..within].chars().count() + 1))
+    }
+
+    /// The byte offset of the character at `line_num`, `column_num`. The column after a line's last
+    /// character is its line ending. Returns `None` for positions past the end of their line.
+    pub fn offset(&self, line_num: usize, column_num: usize) -> Option<usize> {
+        let start = self.line_start(line_num)?;
+        let line = self.line(line_num)?;
+        let column = column_num.checked_sub(1)?;
+        line.char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(line.len()))
+            .nth(column)
+            .map(|offset| start + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Do offsets and locations map to each other for every character, whatever the line endings?
+    #[test]
+    fn test_round_trip() {
+        let text = "+é\r\n\n>\t.\n";
+        let index = LineIndex::new(text);
+
+        assert_eq!(index.line_count(), text.lines().count());
+        for (line_num, line) in text.lines().enumerate() {
+            assert_eq!(index.line(line_num + 1), Some(line));
+        }
+        for (offset, _) in text.char_indices() {
+            let (line_num, column_num) = index.location(offset).unwrap();
+            let back = index.offset(line_num, column_num).unwrap();
+            assert!(back == offset || text[back..].starts_with(['\r', '\n']));
+        }
+
+        assert_eq!(index.location(1), Some((1, 2)));
+        assert_eq!(index.location(2), None);
+        assert_eq!(index.location(5), Some((2, 1)));
+        assert_eq!(index.location(6), Some((3, 1)));
+        assert_eq!(index.location(text.len()), Some((3, 4)));
+        assert_eq!(index.offset(3, 3), Some(8));
+        assert_eq!(index.offset(3, 4), Some(9));
+        assert_eq!(index.offset(3, 5), None);
+        assert_eq!(index.offset(4, 1), None);
+        assert_eq!(index.line(0), None);
+    }
+
+    // Is an empty text, or one without a final newline, counted as str::lines counts it?
+    #[test]
+    fn test_line_count() {
+        assert_eq!(LineIndex::new("").line_count(), 0);
+        assert_eq!(LineIndex::new("+").line_count(), 1);
+        assert_eq!(LineIndex::new("+\n").line_count(), 1);
+        assert_eq!(LineIndex::new("+\n\n-").line_count(), 3);
+        assert_eq!(LineIndex::new("").location(0), Some((1, 1)));
+    }
+}
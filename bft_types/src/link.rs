@@ -0,0 +1,349 @@
+//! Linking of Brainfuck library fragments into a single program.
+//!
+//! A program is composed by concatenating a main program with any number of library fragments.
+//! Because Brainfuck has no calling mechanism, a fragment can only be safely dropped into the
+//! middle of another program if it follows the cell-0 convention: it treats the cell under the
+//! head when it starts as its own cell 0, never moves the head to the left of that cell, and
+//! leaves the head back on that cell when it finishes. The main program is exempt from the
+//! convention, since nothing runs after it has been placed.
+
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::line_index::LineIndex;
+use crate::{BfProgram, BftTypeError, Instruction, LocalisedInstruction};
+
+/// The ways in which a library fragment can break the cell-0 convention
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConventionBreach {
+    /// The fragment moves the head to the left of the cell it started on
+    MovesBelowCellZero,
+    /// A loop in the fragment does not return the head to where it was at the start of the loop,
+    /// so the head position after the loop cannot be known without running it
+    UnbalancedLoop,
+    /// The fragment finishes with the head somewhere other than the cell it started on
+    DoesNotReturnToCellZero,
+}
+
+impl Display for ConventionBreach {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            ConventionBreach::MovesBelowCellZero => "moves the head to the left of cell 0",
+            ConventionBreach::UnbalancedLoop => {
+                "has a loop that does not return the head to where it started"
+            }
+            ConventionBreach::DoesNotReturnToCellZero => "does not leave the head on cell 0",
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+/// A verified piece of a program, along with the source text it was loaded from
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Fragment {
+    /// The parsed and analysed program
+    program: BfProgram,
+    /// The original text of the program, kept so that the linked output preserves comments and
+    /// line numbers
+    source: String,
+}
+
+impl Fragment {
+    /// Load and verify a fragment from the specified file path
+    pub fn from_file<P: AsRef<Path>>(file_path: P) -> Result<Fragment, BftTypeError> {
+        let source = fs::read_to_string(&file_path).map_err(BftTypeError::IoError)?;
+        Self::new(file_path, source)
+    }
+
+    /// Construct a new [Fragment] from a file path and the text of the program. The text must be a
+    /// valid [BfProgram].
+    ///
+    /// ```
+    ///# use bft_types::link::Fragment;
+    ///# fn main() -> Result<(), bft_types::BftTypeError>{
+    ///  let fragment = Fragment::new("clear.bf", "[-]".to_string())?;
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn new<P: AsRef<Path>>(filename: P, source: String) -> Result<Fragment, BftTypeError> {
+        let program = BfProgram::new(filename, &source)?;
+        Ok(Self { program, source })
+    }
+
+    /// The analysed program in this fragment
+    pub fn program(&self) -> &BfProgram {
+        &self.program
+    }
+
+    /// Check that this fragment follows the cell-0 convention, returning an error pointing at the
+    /// first instruction that breaks it.
+    ///
+    /// ```
+    ///# use bft_types::link::Fragment;
+    ///# fn main() -> Result<(), bft_types::BftTypeError>{
+    ///  let good = Fragment::new("move_right.bf", "[->+<]".to_string())?;
+    ///  assert!(good.check_convention().is_ok());
+    ///
+    ///  let bad = Fragment::new("wander.bf", "[>]".to_string())?;
+    ///  assert!(bad.check_convention().is_err());
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn check_convention(&self) -> Result<(), BftTypeError> {
+        let instructions = self.program.localised_instructions();
+        // offset of the head from cell 0, and the offset at the start of each open loop
+        let mut offset: isize = 0;
+        let mut loop_offsets = Vec::<isize>::new();
+
+        for instruction in instructions {
+            match instruction.instruction() {
+                Instruction::MoveLeft => {
+                    offset -= 1;
+                    if offset < 0 {
+                        return Err(self.breach(instruction, ConventionBreach::MovesBelowCellZero));
+                    }
+                }
+                Instruction::MoveRight => offset += 1,
+                Instruction::ConditionalJumpForward => loop_offsets.push(offset),
+                Instruction::ConditionalJumpBackward => {
+                    // the program has been analysed, so every ']' has a '[' to pop
+                    let loop_start_offset = loop_offsets.pop().unwrap_or_default();
+                    if loop_start_offset != offset {
+                        return Err(self.breach(instruction, ConventionBreach::UnbalancedLoop));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        match instructions.last() {
+            Some(last_instruction) if offset != 0 => {
+                Err(self.breach(last_instruction, ConventionBreach::DoesNotReturnToCellZero))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Build a convention error for this fragment
+    fn breach(&self, instruction: &LocalisedInstruction, breach: ConventionBreach) -> BftTypeError {
+        BftTypeError::ConventionViolation {
+            program_name: self.program.name().to_path_buf(),
+            bad_instruction: *instruction,
+            breach,
+        }
+    }
+}
+
+/// Records which lines of a linked program came from which source file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ProvenanceEntry {
+    /// The file the lines were originally loaded from
+    source: PathBuf,
+    /// First line of the linked program taken from the source, 1-indexed
+    first_line: usize,
+    /// Last line of the linked program taken from the source, 1-indexed and inclusive
+    last_line: usize,
+}
+
+impl ProvenanceEntry {
+    /// The file the lines were originally loaded from
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// First line of the linked program taken from the source, 1-indexed
+    pub fn first_line(&self) -> usize {
+        self.first_line
+    }
+
+    /// Last line of the linked program taken from the source, 1-indexed and inclusive
+    pub fn last_line(&self) -> usize {
+        self.last_line
+    }
+}
+
+impl Display for ProvenanceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{} {}",
+            self.first_line,
+            self.last_line,
+            self.source.display()
+        )
+    }
+}
+
+/// The result of linking fragments together: the text of a single program, and a map recording
+/// where each part of it came from
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LinkedProgram {
+    /// Text of the combined program
+    text: String,
+    /// One entry per fragment, in the order they appear in the text
+    provenance: Vec<ProvenanceEntry>,
+}
+
+impl LinkedProgram {
+    /// Text of the combined program
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// One entry per fragment, in the order they appear in the text
+    pub fn provenance(&self) -> &[ProvenanceEntry] {
+        &self.provenance
+    }
+
+    /// Render the provenance map as text, with one `first-last path` line per fragment
+    pub fn provenance_map(&self) -> String {
+        let mut map = String::from("# bft provenance map\n");
+        for entry in &self.provenance {
+            map.push_str(&format!("{}\n", entry));
+        }
+        map
+    }
+}
+
+/// Link a main program and its library fragments into a single program. Every library fragment
+/// must follow the cell-0 convention. The fragments are concatenated in the order given, with the
+/// main program first, and each one starts on a new line so that line numbers in the provenance
+/// map line up with the original files.
+///
+/// ```
+///# use bft_types::link::{link, Fragment};
+///# fn main() -> Result<(), bft_types::BftTypeError>{
+///  let main = Fragment::new("main.bf", "+++++".to_string())?;
+///  let double = Fragment::new("double.bf", "[->++<]>[-<+>]<".to_string())?;
+///
+///  let linked = link(&main, &[double])?;
+///  assert_eq!(linked.text(), "+++++\n[->++<]>[-<+>]<\n");
+///# Ok(())
+///# }
+/// ```
+pub fn link(main: &Fragment, libraries: &[Fragment]) -> Result<LinkedProgram, BftTypeError> {
+    for library in libraries {
+        library.check_convention()?;
+    }
+
+    let mut text = String::new();
+    let mut provenance = Vec::new();
+    let mut next_line = 1;
+
+    for fragment in std::iter::once(main).chain(libraries) {
+        let line_count = LineIndex::new(&fragment.source).line_count().max(1);
+        text.push_str(&fragment.source);
+        if !fragment.source.ends_with('\n') {
+            text.push('\n');
+        }
+
+        provenance.push(ProvenanceEntry {
+            source: fragment.program.name().to_path_buf(),
+            first_line: next_line,
+            last_line: next_line + line_count - 1,
+        });
+        next_line += line_count;
+    }
+
+    Ok(LinkedProgram { text, provenance })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn fragment(name: &str, source: &str) -> Fragment {
+        Fragment::new(name, source.to_string()).unwrap()
+    }
+
+    /// A fragment that stays on and returns to cell 0 follows the convention
+    #[test]
+    fn test_convention_good() {
+        let lib = fragment("copy.bf", ">[-]>[-]<<[->+>+<<]>>[-<<+>>]<<");
+
+        assert!(lib.check_convention().is_ok());
+    }
+
+    /// Moving left of cell 0 is reported at the offending instruction
+    #[test]
+    fn test_convention_below_cell_zero() {
+        let lib = fragment("left.bf", "><<>");
+
+        let result = lib.check_convention();
+
+        assert_matches!(
+            result,
+            Err(BftTypeError::ConventionViolation {
+                breach: ConventionBreach::MovesBelowCellZero,
+                ..
+            })
+        );
+        if let Err(BftTypeError::ConventionViolation {
+            bad_instruction, ..
+        }) = result
+        {
+            assert_eq!(bad_instruction.column_num(), 3);
+        }
+    }
+
+    /// A loop that moves the head makes the final position unknowable
+    #[test]
+    fn test_convention_unbalanced_loop() {
+        let lib = fragment("scan.bf", ">[>]<");
+
+        assert_matches!(
+            lib.check_convention(),
+            Err(BftTypeError::ConventionViolation {
+                breach: ConventionBreach::UnbalancedLoop,
+                ..
+            })
+        );
+    }
+
+    /// Finishing away from cell 0 is a breach
+    #[test]
+    fn test_convention_does_not_return() {
+        let lib = fragment("drift.bf", "+>+");
+
+        assert_matches!(
+            lib.check_convention(),
+            Err(BftTypeError::ConventionViolation {
+                breach: ConventionBreach::DoesNotReturnToCellZero,
+                ..
+            })
+        );
+    }
+
+    /// Fragments are joined on new lines and the provenance map tracks their line ranges
+    #[test]
+    fn test_link_provenance() {
+        let main = fragment("main.bf", ">>>\n+++");
+        let lib_a = fragment("a.bf", "[-]\n");
+        let lib_b = fragment("b.bf", "a comment\n>+<\n\n");
+
+        let linked = link(&main, &[lib_a, lib_b]).unwrap();
+
+        assert_eq!(linked.text(), ">>>\n+++\n[-]\na comment\n>+<\n\n");
+        assert_eq!(
+            linked.provenance_map(),
+            "# bft provenance map\n1-2 main.bf\n3-3 a.bf\n4-6 b.bf\n"
+        );
+        assert!(BfProgram::new("linked.bf", linked.text()).is_ok());
+    }
+
+    /// The main program may do as it likes, but libraries may not
+    #[test]
+    fn test_link_rejects_bad_library() {
+        let main = fragment("main.bf", "<");
+        let lib = fragment("bad.bf", ">");
+
+        assert!(link(&main, &[]).is_ok());
+        assert_matches!(
+            link(&main, &[lib]),
+            Err(BftTypeError::ConventionViolation { .. })
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! The loops of a program and how they nest, found while matching its brackets.
+//!
+//! Loops are numbered in the order their `[` appears, which is also a pre-order walk of the tree:
+//! a loop's parent always has a lower number, and every loop nested inside it follows it
+//! directly. Each property is kept in its own array, indexed by loop number, so that walking one
+//! property over many loops touches as little memory as possible.
+
+/// Marks a loop with no enclosing loop in `LoopTree::parents`
+const NO_PARENT: usize = usize::MAX;
+
+/// The loops of a [crate::BfProgram], as a tree of which loop each one is nested inside
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct LoopTree {
+    /// Instruction index of each loop's `[`
+    opens: Vec<usize>,
+    /// Instruction index of each loop's `]`
+    closes: Vec<usize>,
+    /// Number of the loop each loop is directly inside, or [NO_PARENT]
+    parents: Vec<usize>,
+    /// How many loops each loop is inside
+    depths: Vec<usize>,
+}
+
+impl LoopTree {
+    /// Start a new loop whose `[` is at `open`, inside the loop `parent` if there is one. Returns
+    /// the number of the new loop. Its `]` must be given to [LoopTree::close] once it is found.
+    pub(crate) fn open(&mut self, open: usize, parent: Option<usize>) -> usize {
+        let number = self.opens.len();
+        self.opens.push(open);
+        self.closes.push(open);
+        self.parents.push(parent.unwrap_or(NO_PARENT));
+        self.depths
+            .push(parent.map_or(0, |parent| self.depths[parent] + 1));
+        number
+    }
+
+    /// Record that the `]` of loop `number` is at `close`
+    pub(crate) fn close(&mut self, number: usize, close: usize) {
+        self.closes[number] = close;
+    }
+
+    /// The number of loops in the program
+    pub fn len(&self) -> usize {
+        self.opens.len()
+    }
+
+    /// Whether the program has no loops
+    pub fn is_empty(&self) -> bool {
+        self.opens.is_empty()
+    }
+
+    /// Instruction index of the `[` of loop `number`
+    pub fn open_of(&self, number: usize) -> usize {
+        self.opens[number]
+    }
+
+    /// Instruction index of the `]` of loop `number`
+    pub fn close_of(&self, number: usize) -> usize {
+        self.closes[number]
+    }
+
+    /// The loop that loop `number` is directly inside, if any
+    pub fn parent_of(&self, number: usize) -> Option<usize> {
+        Some(self.parents[number]).filter(|parent| *parent != NO_PARENT)
+    }
+
+    /// How many loops loop `number` is inside. Outermost loops have a depth of zero.
+    pub fn depth_of(&self, number: usize) -> usize {
+        self.depths[number]
+    }
+
+    /// The depth of the most deeply nested loop, or `None` if there are no loops
+    pub fn max_depth(&self) -> Option<usize> {
+        self.depths.iter().copied().max()
+    }
+
+    /// The innermost loop containing the instruction at `program_index`, counting a loop's own
+    /// brackets as inside it
+    ///
+    /// ```
+    ///# use bft_types::BfProgram;
+    ///# use bft_types::BftTypeError;
+    ///# fn main() -> Result<(), BftTypeError>{
+    ///  let program = BfProgram::new("nested.bf", "+[>[-]<-]+")?;
+    ///  let loops = program.loops();
+    ///  assert_eq!(loops.innermost_at(0), None);
+    ///  assert_eq!(loops.innermost_at(2), Some(0));
+    ///  assert_eq!(loops.innermost_at(4), Some(1));
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn innermost_at(&self, program_index: usize) -> Option<usize> {
+        // the last loop opened at or before the instruction is the innermost candidate; if it has
+        // already closed, one of its ancestors may still contain the instruction
+        let mut candidate = self
+            .opens
+            .partition_point(|open| *open <= program_index)
+            .checked_sub(1)?;
+        loop {
+            if self.closes[candidate] >= program_index {
+                return Some(candidate);
+            }
+            candidate = self.parent_of(candidate)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BfProgram;
+
+    // Are loops numbered in order of their `[`, with the right parents and depths?
+    #[test]
+    fn test_loop_tree() {
+        let program = BfProgram::new("test.bf", "[[][[]]][]").unwrap();
+        let loops = program.loops();
+
+        assert_eq!(loops.len(), 5);
+        assert_eq!(
+            (0..5)
+                .map(|number| loops.parent_of(number))
+                .collect::<Vec<_>>(),
+            vec![None, Some(0), Some(0), Some(2), None]
+        );
+        assert_eq!(
+            (0..5)
+                .map(|number| loops.depth_of(number))
+                .collect::<Vec<_>>(),
+            vec![0, 1, 1, 2, 0]
+        );
+        assert_eq!(loops.close_of(2), 6);
+        assert_eq!(loops.max_depth(), Some(2));
+        assert_eq!(loops.innermost_at(6), Some(2));
+        assert_eq!(loops.innermost_at(7), Some(0));
+        assert_eq!(loops.innermost_at(8), Some(4));
+    }
+}
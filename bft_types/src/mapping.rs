@@ -0,0 +1,267 @@
+//! Trivial substitution dialects: languages that are Brainfuck with each of the eight instructions
+//! spelt differently. Rather than a front-end for each, a [Mapping] gives the spelling of each
+//! instruction, and programs are parsed with it set in [crate::ParseOptions::mapping].
+//!
+//! A mapping file is a small subset of TOML, with one instruction per line as `name = "token"`.
+//! The names are `right`, `left`, `increment`, `decrement`, `output`, `input`, `open` and
+//! `close`, and all eight must be given. Tokens may be any text that fits on one line, but no two
+//! may be the same. Blank lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! # Ook!, with exactly one space between the words of each pair
+//! right = "Ook. Ook?"
+//! left = "Ook? Ook."
+//! increment = "Ook. Ook."
+//! decrement = "Ook! Ook!"
+//! output = "Ook! Ook."
+//! input = "Ook. Ook!"
+//! open = "Ook! Ook?"
+//! close = "Ook? Ook!"
+//! ```
+//!
+//! When parsing, the longest token that starts at each point in the text is taken, and anything
+//! that doesn't start a token is a comment. The usual Brainfuck characters are comments too, unless
+//! a token says otherwise.
+
+use std::fs;
+use std::path::Path;
+
+use crate::Instruction;
+
+/// The names of the instructions in a mapping file, in the order they are listed
+const NAMES: [(&str, Instruction); 8] = [
+    ("right", Instruction::MoveRight),
+    ("left", Instruction::MoveLeft),
+    ("increment", Instruction::Increment),
+    ("decrement", Instruction::Decrement),
+    ("output", Instruction::Output),
+    ("input", Instruction::Input),
+    ("open", Instruction::ConditionalJumpForward),
+    ("close", Instruction::ConditionalJumpBackward),
+];
+
+/// The token that stands for each instruction in a substitution dialect
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Mapping {
+    /// Longest first, so that the first token to match is the longest
+    tokens: Vec<(String, Instruction)>,
+}
+
+impl Mapping {
+    /// Load a mapping from a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Mapping, String> {
+        let text = fs::read_to_string(&path).map_err(|error| {
+            format!(
+                "could not read mapping {}: {}",
+                path.as_ref().display(),
+                error
+            )
+        })?;
+        Self::parse(&text).map_err(|error| format!("{}: {}", path.as_ref().display(), error))
+    }
+
+    /// Parse a mapping from the text of a mapping file
+    ///
+    /// ```
+    ///# use bft_types::{BfProgram, ParseOptions};
+    ///# use bft_types::mapping::Mapping;
+    ///# fn main() -> Result<(), Box<dyn std::error::Error>>{
+    ///  let mapping = Mapping::parse(
+    ///      r#"right = "R"
+    ///         left = "L"
+    ///         increment = "inc"
+    ///         decrement = "dec"
+    ///         output = "out"
+    ///         input = "in"
+    ///         open = "while"
+    ///         close = "end""#,
+    ///  )?;
+    ///  let options = ParseOptions {
+    ///      mapping: Some(mapping),
+    ///      ..ParseOptions::default()
+    ///  };
+    ///  let program = BfProgram::new_with_options("add.txt", "inc inc out", &options)?;
+    ///  assert_eq!(program.fingerprint(), BfProgram::new("add.bf", "++.")?.fingerprint());
+    ///# Ok(())
+    ///# }
+    /// ```
+    pub fn parse(text: &str) -> Result<Mapping, String> {
+        let mut tokens: Vec<(String, Instruction)> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at = |reason: String| format!("line {}: {}", index + 1, reason);
+            let (name, token) = line
+                .split_once('=')
+                .ok_or_else(|| at("expected name = \"token\"".to_string()))?;
+            let name = name.trim().trim_matches(['"', '\'']);
+            let instruction = NAMES
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|(_, instruction)| *instruction)
+                .ok_or_else(|| at(format!("unknown instruction '{}'", name)))?;
+            let token = string(token.trim())
+                .ok_or_else(|| at(format!("the token for {} should be in quotes", name)))?
+                .map_err(at)?;
+            if token.is_empty() {
+                return Err(at(format!("the token for {} is empty", name)));
+            }
+            if let Some((_, other)) = tokens
+                .iter()
+                .find(|(existing, other)| *existing == token || *other == instruction)
+            {
+                return Err(at(if *other == instruction {
+                    format!("{} is given more than once", name)
+                } else {
+                    format!("'{}' is already the token for {}", token, name_of(*other))
+                }));
+            }
+            tokens.push((token, instruction));
+        }
+
+        let missing: Vec<_> = NAMES
+            .iter()
+            .filter(|(_, instruction)| !tokens.iter().any(|(_, given)| given == instruction))
+            .map(|(name, _)| *name)
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("no token given for {}", missing.join(", ")));
+        }
+
+        tokens.sort_by_key(|(token, _)| std::cmp::Reverse(token.len()));
+        Ok(Mapping { tokens })
+    }
+
+    /// The instruction whose token `text` starts with, and the length of that token in bytes
+    pub(crate) fn match_start(&self, text: &str) -> Option<(Instruction, usize)> {
+        self.tokens
+            .iter()
+            .find(|(token, _)| text.starts_with(token.as_str()))
+            .map(|(token, instruction)| (*instruction, token.len()))
+    }
+}
+
+/// The name an instruction is given in mapping files
+fn name_of(instruction: Instruction) -> &'static str {
+    NAMES
+        .iter()
+        .find(|(_, known)| *known == instruction)
+        .map_or("?", |(name, _)| name)
+}
+
+/// The value of a TOML `"basic"` or `'literal'` string, or `None` if `text` is neither
+fn string(text: &str) -> Option<Result<String, String>> {
+    if let Some(literal) = text
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+    {
+        return Some(Ok(literal.to_string()));
+    }
+    text.strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .map(unescape)
+}
+
+/// Undo the escapes TOML allows in basic strings that a token is likely to need
+fn unescape(text: &str) -> Result<String, String> {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('"') => unescaped.push('"'),
+            Some('t') => unescaped.push('\t'),
+            other => {
+                return Err(format!(
+                    "unsupported escape '\\{}'",
+                    other.map(String::from).unwrap_or_default()
+                ))
+            }
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BfProgram, ParseOptions};
+
+    const OOK: &str = "# Ook!\n\
+                       right = \"Ook. Ook?\"\n\
+                       left = \"Ook? Ook.\"\n\
+                       increment = \"Ook. Ook.\"\n\
+                       decrement = \"Ook! Ook!\"\n\
+                       output = \"Ook! Ook.\"\n\
+                       input = \"Ook. Ook!\"\n\
+                       open = \"Ook! Ook?\"\n\
+                       close = \"Ook? Ook!\"\n";
+
+    /// check that a program in a mapped dialect parses as the Brainfuck it stands for
+    #[test]
+    fn test_mapped_program() {
+        let options = ParseOptions {
+            mapping: Some(Mapping::parse(OOK).unwrap()),
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options(
+            "loop.ook",
+            "Ook. Ook. +[ Ook! Ook? Ook! Ook!\nOok? Ook!",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            program.fingerprint(),
+            BfProgram::new("loop.bf", "+[-]").unwrap().fingerprint()
+        );
+        assert_eq!(program.location(3), Some((2, 1)));
+
+        // the longest token wins where one starts another
+        let mapping = Mapping::parse(
+            &OOK.replace("\"Ook. Ook.\"", "'a'")
+                .replace("\"Ook! Ook!\"", "'aa'"),
+        )
+        .unwrap();
+        assert_eq!(
+            mapping.match_start("aaa"),
+            Some((Instruction::Decrement, 2))
+        );
+        assert_eq!(mapping.match_start("ab"), Some((Instruction::Increment, 1)));
+        assert_eq!(mapping.match_start("b"), None);
+    }
+
+    /// check that mapping files that don't give one token for each instruction are refused
+    #[test]
+    fn test_invalid_mapping() {
+        assert_eq!(
+            Mapping::parse("right = \">\"").unwrap_err(),
+            "no token given for left, increment, decrement, output, input, open, close"
+        );
+        assert_eq!(
+            Mapping::parse(&format!("{}up = \"^\"", OOK)).unwrap_err(),
+            "line 10: unknown instruction 'up'"
+        );
+        assert_eq!(
+            Mapping::parse(&format!("{}left = \"<\"", OOK)).unwrap_err(),
+            "line 10: left is given more than once"
+        );
+        assert_eq!(
+            Mapping::parse(&OOK.replace("\"Ook? Ook!\"", "\"Ook. Ook.\"")).unwrap_err(),
+            "line 9: 'Ook. Ook.' is already the token for increment"
+        );
+        assert_eq!(
+            Mapping::parse("right = >").unwrap_err(),
+            "line 1: the token for right should be in quotes"
+        );
+        assert_eq!(
+            Mapping::parse("right = \"\"").unwrap_err(),
+            "line 1: the token for right is empty"
+        );
+    }
+}
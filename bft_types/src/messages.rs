@@ -0,0 +1,453 @@
+//! The text of every diagnostic the tools show, behind stable message codes.
+//!
+//! Each diagnostic is a [Message]: a code such as `BFT0001` and the values to fill in to its
+//! template, like the line and column of the problem. The English templates are built in, and a
+//! [Catalog] can replace any of them with a translation. Codes never change meaning once
+//! published, so tools and documentation can refer to them.
+//!
+//! A catalog file has one template per line, as `CODE = template`. Values are filled in where the
+//! template names them in braces. Blank lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! # French
+//! BFT0001 = Crochet '[' sans correspondance dans {program}, ligne {line}, colonne {column}
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+/// Unmatched `[` found when parsing
+pub const UNMATCHED_FORWARD_JUMP: &str = "BFT0001";
+/// Unmatched `]` found when parsing
+pub const UNMATCHED_BACKWARD_JUMP: &str = "BFT0002";
+/// An `@assert` directive that could not be understood
+pub const INVALID_ASSERTION: &str = "BFT0003";
+/// A library fragment that moves below its cell 0
+pub const FRAGMENT_MOVES_BELOW_CELL_ZERO: &str = "BFT0004";
+/// A library fragment with a loop that does not return the head to where it started
+pub const FRAGMENT_UNBALANCED_LOOP: &str = "BFT0005";
+/// A library fragment that does not finish on its cell 0
+pub const FRAGMENT_DOES_NOT_RETURN: &str = "BFT0006";
+/// A `[` nested deeper than the parse options allow
+pub const NESTING_TOO_DEEP: &str = "BFT0007";
+/// An Ook! program that does not pair up into instructions
+pub const INVALID_OOK: &str = "BFT0008";
+/// A block comment that is still open at the end of the program
+pub const UNCLOSED_COMMENT: &str = "BFT0010";
+/// An `#include` directive that could not be followed
+pub const INVALID_INCLUDE: &str = "BFT0011";
+/// A `#define` directive that could not be understood
+pub const INVALID_MACRO: &str = "BFT0012";
+/// A program file could not be read
+pub const FILE_ERROR: &str = "BFT0009";
+/// More than one unmatched `[` or `]` found when parsing
+pub const UNMATCHED_JUMPS: &str = "BFT0013";
+/// Bytecode that could not be loaded
+pub const INVALID_BYTECODE: &str = "BFT0014";
+
+/// The head ran off the start of the tape
+pub const HEAD_UNDERRUN: &str = "BFT0101";
+/// The head ran off the end of a fixed tape
+pub const HEAD_OVERRUN: &str = "BFT0102";
+/// Reading input failed
+pub const READ_ERROR: &str = "BFT0103";
+/// Writing output failed
+pub const WRITE_ERROR: &str = "BFT0104";
+/// A write to a protected cell
+pub const WRITE_PROTECTED: &str = "BFT0105";
+/// An extension instruction failed
+pub const EXTENSION_FAILED: &str = "BFT0106";
+/// An `@assert` directive did not hold
+pub const ASSERTION_FAILED: &str = "BFT0107";
+/// A `+` on a cell at its largest value, with checked arithmetic
+pub const CELL_OVERFLOW: &str = "BFT0108";
+/// A `-` on a cell at its smallest value, with checked arithmetic
+pub const CELL_UNDERFLOW: &str = "BFT0109";
+/// The tape would have grown beyond its limit
+pub const TAPE_LIMIT_EXCEEDED: &str = "BFT0110";
+/// A pre-step hook aborted the run
+pub const ABORTED: &str = "BFT0111";
+/// The tape, a snapshot or the traces went over the memory budget
+pub const MEMORY_LIMIT_EXCEEDED: &str = "BFT0112";
+
+/// Hint: a head underrun before the head has ever moved right
+pub const HINT_UNDERRUN_AT_START: &str = "BFT0151";
+/// Hint: a head underrun, which a bidirectional tape would allow
+pub const HINT_BIDIRECTIONAL: &str = "BFT0152";
+/// Hint: a head overrun, which an extensible or longer tape would allow
+pub const HINT_EXTENSIBLE: &str = "BFT0153";
+/// Hint: a read past the end of input, which another end-of-input behaviour would allow
+pub const HINT_EOF: &str = "BFT0154";
+/// Hint: the tape reached its `--max-cells` limit
+pub const HINT_MAX_CELLS: &str = "BFT0155";
+/// Hint: the machine reached its `--max-memory` budget
+pub const HINT_MAX_MEMORY: &str = "BFT0156";
+/// Hint: a cell overflowed or underflowed with checked arithmetic
+pub const HINT_ARITHMETIC: &str = "BFT0157";
+/// Note: the instruction an error is at came from a macro
+pub const NOTE_FROM_MACRO: &str = "BFT0158";
+/// Note: the instruction an error is at came from a macro whose name is not known, as in a
+/// program loaded from bytecode
+pub const NOTE_FROM_UNNAMED_MACRO: &str = "BFT0159";
+
+/// A layout file could not be read
+pub const LAYOUT_FILE_ERROR: &str = "BFT0201";
+/// A line of a layout file could not be understood
+pub const LAYOUT_INVALID_LINE: &str = "BFT0202";
+/// Two regions of a layout overlap
+pub const LAYOUT_OVERLAP: &str = "BFT0203";
+
+/// A saved tape state that could not be understood
+pub const SNAPSHOT_INVALID: &str = "BFT0401";
+/// A tape state that cannot be restored to the machine it was given to
+pub const SNAPSHOT_MISMATCH: &str = "BFT0402";
+
+/// A breakpoint on an instruction index beyond the end of the program
+pub const BREAKPOINT_OUT_OF_RANGE: &str = "BFT0501";
+/// A breakpoint on a place in the source with no instructions at or after it
+pub const BREAKPOINT_NO_INSTRUCTION: &str = "BFT0502";
+
+/// `bft check`: an unmatched `]`
+pub const CHECK_UNMATCHED_CLOSE: &str = "BFT0301";
+/// `bft check`: an unmatched `[`
+pub const CHECK_UNMATCHED_OPEN: &str = "BFT0302";
+/// `bft check`: an `@assert` directive that could not be understood
+pub const CHECK_INVALID_ASSERTION: &str = "BFT0303";
+/// `bft check`: a block comment is never closed
+pub const CHECK_UNCLOSED_COMMENT: &str = "BFT0304";
+/// `bft check` help: remove a stray `]`
+pub const HELP_REMOVE_CLOSE: &str = "BFT0351";
+/// `bft check` help: close a loop at the end of a line
+pub const HELP_CLOSE_AT_LINE: &str = "BFT0352";
+/// `bft check` help: close a loop at the end of the program
+pub const HELP_CLOSE_AT_END: &str = "BFT0353";
+
+/// The built-in English template for every code
+const ENGLISH: &[(&str, &str)] = &[
+    (
+        UNMATCHED_FORWARD_JUMP,
+        "Unmatched '[' jump instruction in {program} at line {line}, column {column}",
+    ),
+    (
+        UNMATCHED_BACKWARD_JUMP,
+        "Unmatched ']' jump instruction in {program} at line {line}, column {column}",
+    ),
+    (
+        UNMATCHED_JUMPS,
+        "{count} unmatched jump instructions: {jumps}",
+    ),
+    (
+        INVALID_ASSERTION,
+        "Invalid assertion in {program} at line {line}, column {column}: {reason}",
+    ),
+    (
+        FRAGMENT_MOVES_BELOW_CELL_ZERO,
+        "Library fragment {program} moves the head to the left of cell 0 at line {line}, column {column}",
+    ),
+    (
+        FRAGMENT_UNBALANCED_LOOP,
+        "Library fragment {program} has a loop that does not return the head to where it started at line {line}, column {column}",
+    ),
+    (
+        FRAGMENT_DOES_NOT_RETURN,
+        "Library fragment {program} does not leave the head on cell 0 at line {line}, column {column}",
+    ),
+    (
+        NESTING_TOO_DEEP,
+        "Loops in {program} are nested more than {limit} deep at line {line}, column {column}",
+    ),
+    (
+        INVALID_OOK,
+        "Invalid Ook! in {program} at line {line}, column {column}: {reason}",
+    ),
+    (FILE_ERROR, "File IO error: {error}"),
+    (INVALID_BYTECODE, "Invalid bytecode: {reason}"),
+    (
+        UNCLOSED_COMMENT,
+        "Comment in {program} opened at line {line}, column {column} is never closed",
+    ),
+    (
+        INVALID_INCLUDE,
+        "Cannot include a file in {program} at line {line}: {reason}",
+    ),
+    (
+        INVALID_MACRO,
+        "Invalid macro definition in {program} at line {line}: {reason}",
+    ),
+    (
+        HEAD_UNDERRUN,
+        "Head underrun error occurred at line {line} column {column}",
+    ),
+    (
+        HEAD_OVERRUN,
+        "Head overrun error occurred at line {line} column {column}",
+    ),
+    (
+        READ_ERROR,
+        "Read error occurred at line {line} column {column}: {error}",
+    ),
+    (
+        WRITE_ERROR,
+        "Write error occurred at line {line} column {column}: {error}",
+    ),
+    (
+        WRITE_PROTECTED,
+        "Write to protected cell {cell} occurred at line {line} column {column}",
+    ),
+    (
+        EXTENSION_FAILED,
+        "Extension instruction failed at line {line} column {column}: {error}",
+    ),
+    (
+        ASSERTION_FAILED,
+        "Assertion failed at line {line} column {column}: expected {expected}, found {actual}",
+    ),
+    (
+        CELL_OVERFLOW,
+        "Cell overflow occurred at line {line} column {column}",
+    ),
+    (
+        CELL_UNDERFLOW,
+        "Cell underflow occurred at line {line} column {column}",
+    ),
+    (
+        TAPE_LIMIT_EXCEEDED,
+        "Tape limit of {cells} cells exceeded at line {line} column {column}",
+    ),
+    (ABORTED, "Aborted by a hook at line {line} column {column}"),
+    (
+        MEMORY_LIMIT_EXCEEDED,
+        "Memory limit of {limit} bytes exceeded: the {subsystem} needed {requested} bytes, with {others} bytes held elsewhere",
+    ),
+    (
+        HINT_UNDERRUN_AT_START,
+        "hint: the program moves left before it ever moves right; if it expects to start in the middle of the tape, re-run with --bidirectional",
+    ),
+    (
+        HINT_BIDIRECTIONAL,
+        "hint: re-run with --bidirectional to let the tape grow to the left of cell 0",
+    ),
+    (
+        HINT_EXTENSIBLE,
+        "hint: re-run with --extensible to let the tape grow, or give it more than {cells} cells with --cells",
+    ),
+    (
+        HINT_EOF,
+        "hint: the program read past the end of its input; consider --eof zero, or --eof max for programs that expect EOF=-1",
+    ),
+    (
+        HINT_MAX_CELLS,
+        "hint: raise the limit of {limit} cells with --max-cells",
+    ),
+    (
+        HINT_MAX_MEMORY,
+        "hint: raise the budget of {limit} bytes with --max-memory",
+    ),
+    (
+        HINT_ARITHMETIC,
+        "hint: re-run with --arithmetic wrapping or --arithmetic saturating if the program relies on cells wrapping",
+    ),
+    (
+        NOTE_FROM_MACRO,
+        "note: the instruction is from the macro {macro}, written at line {line} column {column}",
+    ),
+    (
+        NOTE_FROM_UNNAMED_MACRO,
+        "note: the instruction is from a macro, written at line {line} column {column}",
+    ),
+    (LAYOUT_FILE_ERROR, "Could not read layout file: {error}"),
+    (
+        LAYOUT_INVALID_LINE,
+        "Invalid layout on line {line}: {reason}",
+    ),
+    (
+        LAYOUT_OVERLAP,
+        "Region '{second}' overlaps region '{first}'",
+    ),
+    (SNAPSHOT_INVALID, "Invalid tape state: {reason}"),
+    (
+        SNAPSHOT_MISMATCH,
+        "Tape state cannot be restored: {reason}",
+    ),
+    (
+        BREAKPOINT_OUT_OF_RANGE,
+        "No instruction {index} for a breakpoint: the program has {instructions}",
+    ),
+    (
+        BREAKPOINT_NO_INSTRUCTION,
+        "No instruction at or after line {line} column {column} for a breakpoint",
+    ),
+    (CHECK_UNMATCHED_CLOSE, "unmatched ']'"),
+    (CHECK_UNMATCHED_OPEN, "unmatched '['"),
+    (CHECK_INVALID_ASSERTION, "invalid assertion: {reason}"),
+    (CHECK_UNCLOSED_COMMENT, "comment is never closed"),
+    (HELP_REMOVE_CLOSE, "remove it, or add a '[' before it"),
+    (HELP_CLOSE_AT_LINE, "add a ']' at the end of line {line}"),
+    (HELP_CLOSE_AT_END, "add a ']' at the end of the program"),
+];
+
+/// A diagnostic, as its code and the values for its template
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Message {
+    /// The message code, one of the constants in this module
+    pub code: &'static str,
+    /// The values named in the template
+    pub args: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    /// Create a message from its code and the values for its template
+    pub fn new(code: &'static str, args: Vec<(&'static str, String)>) -> Self {
+        Self { code, args }
+    }
+
+    /// The message in the given catalog's language, without its code
+    pub fn text(&self, catalog: &Catalog) -> String {
+        let template = catalog.template(self.code);
+        let mut text = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            text.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let value = after.find('}').and_then(|end| {
+                self.args
+                    .iter()
+                    .find(|(name, _)| *name == &after[..end])
+                    .map(|(_, value)| (value, end))
+            });
+            match value {
+                Some((value, end)) => {
+                    text.push_str(value);
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    text.push('{');
+                    rest = after;
+                }
+            }
+        }
+        text.push_str(rest);
+        text
+    }
+
+    /// The message in the given catalog's language, after its code
+    pub fn render(&self, catalog: &Catalog) -> String {
+        format!("{}: {}", self.code, self.text(catalog))
+    }
+}
+
+/// In English, after the code
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(&Catalog::default()))
+    }
+}
+
+/// Something that can be shown to the user as a [Message]
+pub trait Localise {
+    /// The message describing this
+    fn message(&self) -> Message;
+}
+
+/// Templates that replace some or all of the English ones
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Catalog {
+    templates: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Load a catalog from a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Catalog, String> {
+        let text = fs::read_to_string(&path).map_err(|error| {
+            format!(
+                "could not read message catalog {}: {}",
+                path.as_ref().display(),
+                error
+            )
+        })?;
+        Self::parse(&text)
+    }
+
+    /// Parse a catalog from the text of a catalog file. Fails if a line is not a template for a
+    /// known code.
+    pub fn parse(text: &str) -> Result<Catalog, String> {
+        let mut templates = HashMap::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (code, template) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected CODE = template", index + 1))?;
+            let code = code.trim();
+            if !ENGLISH.iter().any(|(known, _)| *known == code) {
+                return Err(format!(
+                    "line {}: unknown message code '{}'",
+                    index + 1,
+                    code
+                ));
+            }
+            templates.insert(code.to_string(), template.trim().to_string());
+        }
+        Ok(Catalog { templates })
+    }
+
+    /// The template for a code: the catalog's own if it has one, otherwise the English one
+    pub fn template<'c>(&'c self, code: &'c str) -> &'c str {
+        self.templates
+            .get(code)
+            .map(String::as_str)
+            .or_else(|| {
+                ENGLISH
+                    .iter()
+                    .find(|(known, _)| *known == code)
+                    .map(|(_, template)| *template)
+            })
+            .unwrap_or(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Are values filled in, with unknown names and stray braces left alone?
+    #[test]
+    fn test_render() {
+        let message = Message::new(
+            UNMATCHED_FORWARD_JUMP,
+            vec![
+                ("program", "a.bf".to_string()),
+                ("line", "2".to_string()),
+                ("column", "7".to_string()),
+            ],
+        );
+        assert_eq!(
+            message.to_string(),
+            "BFT0001: Unmatched '[' jump instruction in a.bf at line 2, column 7"
+        );
+
+        let catalog =
+            Catalog::parse("# test\nBFT0001 = {program} {line}:{column} {nope} {").unwrap();
+        assert_eq!(message.render(&catalog), "BFT0001: a.bf 2:7 {nope} {");
+    }
+
+    // Are catalogs with unknown codes or malformed lines refused?
+    #[test]
+    fn test_parse_catalog_errors() {
+        assert!(Catalog::parse("BFT9999 = what").is_err());
+        assert!(Catalog::parse("BFT0001").is_err());
+    }
+
+    // Does every code have exactly one English template?
+    #[test]
+    fn test_codes_unique() {
+        let mut codes: Vec<_> = ENGLISH.iter().map(|(code, _)| *code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), ENGLISH.len());
+    }
+}
@@ -0,0 +1,192 @@
+//! Reading programs written in Ook!, a Brainfuck dialect for orang-utans.
+//!
+//! An Ook! program is a series of the words `Ook.`, `Ook?` and `Ook!`, read in pairs. Each pair
+//! stands for one Brainfuck instruction:
+//!
+//! | Ook!        | Brainfuck |
+//! |-------------|-----------|
+//! | `Ook. Ook?` | `>`       |
+//! | `Ook? Ook.` | `<`       |
+//! | `Ook. Ook.` | `+`       |
+//! | `Ook! Ook!` | `-`       |
+//! | `Ook! Ook.` | `.`       |
+//! | `Ook. Ook!` | `,`       |
+//! | `Ook! Ook?` | `[`       |
+//! | `Ook? Ook!` | `]`       |
+//!
+//! Everything else is a comment, and a pair may be split across lines. Each instruction is
+//! located at the first word of its pair, so errors point into the Ook! source.
+
+use std::path::Path;
+
+use crate::{BfProgram, BftTypeError, Instruction, LocalisedInstruction, ParseOptions, Tokens};
+
+/// The word every Ook! token starts with
+const OOK: &str = "Ook";
+
+/// One `Ook` word: its punctuation, and where it is in the source
+#[derive(Debug, Clone, Copy)]
+struct Word {
+    punctuation: char,
+    line_num: usize,
+    column_num: usize,
+    /// The byte offset of the word's start
+    offset: usize,
+}
+
+/// How long an `Ook` word is, with its punctuation
+const WORD_LEN: usize = OOK.len() + 1;
+
+/// Parse an Ook! program into the same [BfProgram] the equivalent Brainfuck would give. Of the
+/// `options`, only [ParseOptions::max_nesting] applies, as Ook! has no room for directives or
+/// extra instructions.
+///
+/// ```
+///# use bft_types::{BfProgram, BftTypeError, ParseOptions};
+///# fn main() -> Result<(), BftTypeError>{
+///  let ook = bft_types::ook::parse("add.ook", "Ook. Ook. Ook! Ook.", &ParseOptions::default())?;
+///  let bf = BfProgram::new("add.bf", "+.")?;
+///
+///  assert_eq!(ook.fingerprint(), bf.fingerprint());
+///# Ok(())
+///# }
+/// ```
+pub fn parse<P: AsRef<Path>>(
+    filename: P,
+    file_contents: &str,
+    options: &ParseOptions,
+) -> Result<BfProgram, BftTypeError> {
+    BfProgram::from_tokens(tokenise(filename.as_ref(), file_contents), options)
+}
+
+/// Find the instructions in an Ook! program's text
+fn tokenise(filename: &Path, file_contents: &str) -> Tokens {
+    let mut tokens = Tokens::new(filename);
+    tokens.source_bytes = file_contents.len();
+    let invalid = |word: &Word, reason: String| BftTypeError::InvalidOok {
+        program_name: filename.to_path_buf(),
+        line_num: word.line_num,
+        column_num: word.column_num,
+        reason,
+    };
+
+    let words = words(file_contents);
+    let mut pairs = words.chunks_exact(2);
+    for pair in &mut pairs {
+        let (first, second) = (pair[0], pair[1]);
+        match instruction(first.punctuation, second.punctuation) {
+            Some(instruction) => tokens.instructions.push(
+                LocalisedInstruction::new(instruction, first.line_num, first.column_num)
+                    .with_span(first.offset..second.offset + WORD_LEN),
+            ),
+            None => tokens.errors.push(invalid(
+                &first,
+                format!(
+                    "'{OOK}{} {OOK}{}' is not an instruction",
+                    first.punctuation, second.punctuation
+                ),
+            )),
+        }
+    }
+    if let [unpaired] = pairs.remainder() {
+        tokens.errors.push(invalid(
+            unpaired,
+            format!("'{OOK}{}' has no word to pair with", unpaired.punctuation),
+        ));
+    }
+
+    tokens
+}
+
+/// Every `Ook` word in the text, in order
+fn words(file_contents: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut line_start = 0;
+    for (line_number, file_line) in file_contents.split_inclusive('\n').enumerate() {
+        let mut byte = 0;
+        let mut col_number = 0;
+        while let Some(start) = file_line[byte..].find(OOK) {
+            col_number += file_line[byte..byte + start].chars().count();
+            byte += start;
+            match file_line[byte + OOK.len()..].chars().next() {
+                Some(punctuation @ ('.' | '?' | '!')) => {
+                    words.push(Word {
+                        punctuation,
+                        line_num: line_number + 1,
+                        column_num: col_number + 1,
+                        offset: line_start + byte,
+                    });
+                    byte += WORD_LEN;
+                    col_number += WORD_LEN;
+                }
+                _ => {
+                    byte += OOK.len();
+                    col_number += OOK.len();
+                }
+            }
+        }
+        line_start += file_line.len();
+    }
+    words
+}
+
+/// The Brainfuck instruction for a pair of words
+fn instruction(first: char, second: char) -> Option<Instruction> {
+    match (first, second) {
+        ('.', '?') => Some(Instruction::MoveRight),
+        ('?', '.') => Some(Instruction::MoveLeft),
+        ('.', '.') => Some(Instruction::Increment),
+        ('!', '!') => Some(Instruction::Decrement),
+        ('!', '.') => Some(Instruction::Output),
+        ('.', '!') => Some(Instruction::Input),
+        ('!', '?') => Some(Instruction::ConditionalJumpForward),
+        ('?', '!') => Some(Instruction::ConditionalJumpBackward),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// check that every pair reads as its Brainfuck instruction, located at its first word
+    #[test]
+    fn test_parse() {
+        let text = "Ook. Ook? Ook? Ook. Ook. Ook. Ook! Ook!\n\
+                    Ook! Ook. Ook. Ook! banana Ook! Ook?\nOok? Ook!";
+        let program = parse("all.ook", text, &ParseOptions::default()).unwrap();
+        let bf = BfProgram::new("all.bf", "><+-.,[]").unwrap();
+        assert_eq!(program.fingerprint(), bf.fingerprint());
+        assert_eq!(program.location(6), Some((2, 28)));
+        assert_eq!(program.location(7), Some((3, 1)));
+        assert_eq!(program.jump_target(6), 8);
+        let span = program.localised_instructions()[6].span();
+        assert_eq!(&text[span], "Ook! Ook?");
+    }
+
+    /// check that the only pair that isn't an instruction, and a word left over, are refused
+    #[test]
+    fn test_invalid() {
+        let options = ParseOptions::default();
+        assert!(matches!(
+            parse("bad.ook", "Ook. Ook.\n  Ook? Ook?", &options),
+            Err(BftTypeError::InvalidOok {
+                line_num: 2,
+                column_num: 3,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("odd.ook", "Ook. Ook. Ook!", &options),
+            Err(BftTypeError::InvalidOok {
+                line_num: 1,
+                column_num: 11,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse("open.ook", "Ook! Ook?", &options),
+            Err(BftTypeError::UnmatchedForwardJump { .. })
+        ));
+    }
+}
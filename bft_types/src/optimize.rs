@@ -0,0 +1,506 @@
+//! The optimised form of a program: a list of [Op]s, each doing the work of one or more of the
+//! program's instructions, for machines and back-ends that would rather not go round their main
+//! loop once per instruction.
+//!
+//! [BfProgram::optimize] lowers a parsed program to an [OptimizedProgram], running the passes its
+//! [OptLevel] asks for over the instructions, in this order:
+//!
+//! 1. Clear loops ([OptLevel::Full]): `[-]` and `[+]` become [Op::SetZero]
+//! 2. Scan loops ([OptLevel::Full]): `[>]` and `[<]` become [Op::Scan]
+//! 3. Run-length encoding ([OptLevel::RunLengths] and up): runs of `+`, `-`, `>` or `<` become
+//!    one [Op::Add] or [Op::Move]
+//!
+//! Each pass leaves alone any instruction that an earlier pass has already covered. Whatever is
+//! left is lowered one instruction to one op. Every op remembers the instructions it stands in
+//! for, so a machine can map its position back to the program: jumps between ops land on the op
+//! after the matching bracket, just as [BfProgram::jump_target] does between instructions.
+
+use std::fmt::Display;
+use std::ops::Range;
+
+use crate::{BfProgram, Instruction};
+
+/// Marks an instruction that does not start an op in `OptimizedProgram::op_starts`
+const NOT_A_START: usize = usize::MAX;
+
+/// How hard [BfProgram::optimize] works
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OptLevel {
+    /// No passes: each instruction becomes one op
+    #[default]
+    None,
+    /// Run-length encoding only
+    RunLengths,
+    /// Every pass
+    Full,
+}
+
+impl Display for OptLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptLevel::None => write!(f, "none"),
+            OptLevel::RunLengths => write!(f, "run-length encoding"),
+            OptLevel::Full => write!(f, "clear loops, scan loops and run-length encoding"),
+        }
+    }
+}
+
+/// One step of an [OptimizedProgram]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Op {
+    /// Add to the cell under the head: a run of `+` gives a positive amount, a run of `-` a
+    /// negative one
+    Add(isize),
+    /// Move the head: a run of `>` gives a positive distance, a run of `<` a negative one
+    Move(isize),
+    /// Set the cell under the head to zero: a `[-]` or `[+]` loop, which counts the cell down or
+    /// up until it wraps round to zero. `up` is set for `[+]`.
+    SetZero { up: bool },
+    /// Move the head to the nearest zero cell, starting with the one under it: a `[>]` or `[<]`
+    /// loop. `right` is set for `[>]`.
+    Scan { right: bool },
+    /// `[`: if the cell under the head is zero, carry on from the op with the given index, just
+    /// after the matching [Op::JumpUnlessZero]
+    JumpIfZero(usize),
+    /// `]`: unless the cell under the head is zero, carry on from the op with the given index,
+    /// just after the matching [Op::JumpIfZero]
+    JumpUnlessZero(usize),
+    /// `,`
+    Input,
+    /// `.`
+    Output,
+    /// Any other instruction, such as those of Extended Brainfuck Type I, extensions and `#`,
+    /// which is executed as it is
+    Other(Instruction),
+}
+
+/// A program lowered to [Op]s by [BfProgram::optimize]
+///
+/// ```
+///# use bft_types::BfProgram;
+///# use bft_types::optimize::{Op, OptLevel};
+///# fn main() -> Result<(), bft_types::BftTypeError>{
+///  let program = BfProgram::new("runs.bf", "+++[>>-<<--[-]].")?;
+///  let optimized = program.optimize(OptLevel::Full);
+///
+///  assert_eq!(
+///      optimized.ops(),
+///      [
+///          Op::Add(3),
+///          Op::JumpIfZero(8),
+///          Op::Move(2),
+///          Op::Add(-1),
+///          Op::Move(-2),
+///          Op::Add(-2),
+///          Op::SetZero { up: false },
+///          Op::JumpUnlessZero(2),
+///          Op::Output,
+///      ]
+///  );
+///  assert_eq!(optimized.instructions_of(6), 11..14);
+///  assert_eq!(optimized.op_starting_at(11), Some(6));
+///  assert_eq!(optimized.op_starting_at(12), None);
+///# Ok(())
+///# }
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct OptimizedProgram {
+    ops: Vec<Op>,
+    /// Instruction index of the first instruction each op stands in for, followed by the number
+    /// of instructions in the program, so that op `i` stands in for `starts[i]..starts[i + 1]`
+    starts: Vec<usize>,
+    /// The index of the op starting at each instruction, or [NOT_A_START]
+    op_starts: Vec<usize>,
+    level: OptLevel,
+}
+
+impl OptimizedProgram {
+    /// Lower `program` to ops, with the passes `level` asks for
+    pub(crate) fn new(program: &BfProgram, level: OptLevel) -> Self {
+        let instructions: Vec<_> = program
+            .localised_instructions()
+            .iter()
+            .map(|instruction| instruction.instruction())
+            .collect();
+
+        let mut passes = Passes {
+            instructions: &instructions,
+            fused: vec![None; instructions.len()],
+            covered: vec![false; instructions.len()],
+        };
+        if level == OptLevel::Full {
+            passes.clear_loops();
+            passes.scan_loops();
+        }
+        if level != OptLevel::None {
+            passes.run_lengths();
+        }
+
+        let mut optimized = Self {
+            ops: Vec::new(),
+            starts: Vec::new(),
+            op_starts: vec![NOT_A_START; instructions.len()],
+            level,
+        };
+        // indexes of the ops for the `[`s still waiting for their `]`
+        let mut open = Vec::new();
+        let mut start = 0;
+        while start < instructions.len() {
+            let index = optimized.ops.len();
+            let (op, len) = passes.fused[start].unwrap_or((lower(instructions[start]), 1));
+            let op = match op {
+                Op::JumpIfZero(_) => {
+                    open.push(index);
+                    op
+                }
+                Op::JumpUnlessZero(_) => {
+                    // the program has been analysed, so every `]` has its `[`
+                    let opening = open.pop().unwrap_or_default();
+                    optimized.ops[opening] = Op::JumpIfZero(index + 1);
+                    Op::JumpUnlessZero(opening + 1)
+                }
+                op => op,
+            };
+            optimized.ops.push(op);
+            optimized.starts.push(start);
+            optimized.op_starts[start] = index;
+            start += len;
+        }
+        optimized.starts.push(instructions.len());
+        optimized
+    }
+
+    /// The ops of a program already lowered, such as one loaded from bytecode, where op `i` stands
+    /// in for the instructions from `starts[i]` up to the next op's
+    pub(crate) fn from_parts(
+        ops: Vec<Op>,
+        mut starts: Vec<usize>,
+        len: usize,
+        level: OptLevel,
+    ) -> Self {
+        let mut op_starts = vec![NOT_A_START; len];
+        for (index, start) in starts.iter().enumerate() {
+            op_starts[*start] = index;
+        }
+        starts.push(len);
+        Self {
+            ops,
+            starts,
+            op_starts,
+            level,
+        }
+    }
+
+    /// The ops, in program order
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// The indexes of the instructions in the program that op `op_index` stands in for
+    pub fn instructions_of(&self, op_index: usize) -> Range<usize> {
+        self.starts[op_index]..self.starts[op_index + 1]
+    }
+
+    /// The index of the op that starts with the instruction at `program_index`, if one does.
+    /// Looking one up costs no more than fetching the instruction.
+    pub fn op_starting_at(&self, program_index: usize) -> Option<usize> {
+        self.op_starts
+            .get(program_index)
+            .copied()
+            .filter(|index| *index != NOT_A_START)
+    }
+
+    /// The number of ops that stand in for more than one instruction
+    pub fn fused(&self) -> usize {
+        self.starts
+            .windows(2)
+            .filter(|op| op[1] - op[0] > 1)
+            .count()
+    }
+
+    /// The [OptLevel] the program was optimised at
+    pub fn level(&self) -> OptLevel {
+        self.level
+    }
+}
+
+/// The op for a single instruction, with any jump target still to be filled in
+fn lower(instruction: Instruction) -> Op {
+    match instruction {
+        Instruction::Increment => Op::Add(1),
+        Instruction::Decrement => Op::Add(-1),
+        Instruction::MoveRight => Op::Move(1),
+        Instruction::MoveLeft => Op::Move(-1),
+        Instruction::ConditionalJumpForward => Op::JumpIfZero(0),
+        Instruction::ConditionalJumpBackward => Op::JumpUnlessZero(0),
+        Instruction::Input => Op::Input,
+        Instruction::Output => Op::Output,
+        other => Op::Other(other),
+    }
+}
+
+/// The passes that find ops standing in for more than one instruction
+struct Passes<'a> {
+    instructions: &'a [Instruction],
+    /// The ops found so far, by the index of the first instruction each stands in for, with how
+    /// many instructions that is
+    fused: Vec<Option<(Op, usize)>>,
+    /// Which instructions the ops found so far cover
+    covered: Vec<bool>,
+}
+
+impl Passes<'_> {
+    /// Turn each `[-]` or `[+]` into an [Op::SetZero]
+    fn clear_loops(&mut self) {
+        for (start, body) in single_instruction_loops(self.instructions) {
+            let up = match body {
+                Instruction::Decrement => false,
+                Instruction::Increment => true,
+                _ => continue,
+            };
+            self.insert(start, Op::SetZero { up }, 3);
+        }
+    }
+
+    /// Turn each `[>]` or `[<]` into an [Op::Scan]
+    fn scan_loops(&mut self) {
+        for (start, body) in single_instruction_loops(self.instructions) {
+            let right = match body {
+                Instruction::MoveRight => true,
+                Instruction::MoveLeft => false,
+                _ => continue,
+            };
+            self.insert(start, Op::Scan { right }, 3);
+        }
+    }
+
+    /// Turn each run of two or more `+`, `-`, `>` or `<` in a row into one [Op::Add] or
+    /// [Op::Move]. Runs that mix `+` with `-`, or `>` with `<`, are split where the instruction
+    /// changes, since the instructions in between can matter: `<>` stops at the start of a fixed
+    /// tape, and `+-` stops on a cell that cannot be taken any higher with checked arithmetic.
+    fn run_lengths(&mut self) {
+        let mut start = 0;
+        while start < self.instructions.len() {
+            let instruction = self.instructions[start];
+            let run = self.instructions[start..]
+                .iter()
+                .take_while(|other| **other == instruction)
+                .count();
+            let op = match instruction {
+                Instruction::Increment => Some(Op::Add(run as isize)),
+                Instruction::Decrement => Some(Op::Add(-(run as isize))),
+                Instruction::MoveRight => Some(Op::Move(run as isize)),
+                Instruction::MoveLeft => Some(Op::Move(-(run as isize))),
+                _ => None,
+            };
+            if let Some(op) = op.filter(|_| run > 1) {
+                self.insert(start, op, run);
+            }
+            start += run;
+        }
+    }
+
+    /// Record `op` as standing in for the `instructions` instructions from `start`, unless an
+    /// earlier pass has already covered any of them
+    fn insert(&mut self, start: usize, op: Op, instructions: usize) {
+        let run = &mut self.covered[start..start + instructions];
+        if run.contains(&true) {
+            return;
+        }
+        run.fill(true);
+        self.fused[start] = Some((op, instructions));
+    }
+}
+
+/// The index of the `[` and the instruction inside each loop with only one instruction inside
+fn single_instruction_loops(
+    instructions: &[Instruction],
+) -> impl Iterator<Item = (usize, Instruction)> + '_ {
+    instructions
+        .windows(3)
+        .enumerate()
+        .filter_map(|(start, window)| match window {
+            [Instruction::ConditionalJumpForward, body, Instruction::ConditionalJumpBackward] => {
+                Some((start, *body))
+            }
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The ops `text` lowers to at `level`, each with the instructions it stands in for, having
+    /// checked that those instructions cover the program in order and lead back to their ops
+    fn lowered(text: &str, level: OptLevel) -> Vec<(Op, Range<usize>)> {
+        let program = BfProgram::new("test.bf", text).unwrap();
+        let optimized = program.optimize(level);
+        let len = program.localised_instructions().len();
+        let mut next = 0;
+        for index in 0..optimized.ops().len() {
+            let instructions = optimized.instructions_of(index);
+            assert_eq!(instructions.start, next, "{} at {}", text, level);
+            assert!(!instructions.is_empty());
+            assert_eq!(optimized.op_starting_at(instructions.start), Some(index));
+            for inside in instructions.start + 1..instructions.end {
+                assert_eq!(optimized.op_starting_at(inside), None);
+            }
+            next = instructions.end;
+        }
+        assert_eq!(next, len);
+        assert_eq!(optimized.op_starting_at(len), None);
+        (0..optimized.ops().len())
+            .map(|index| (optimized.ops()[index], optimized.instructions_of(index)))
+            .collect()
+    }
+
+    // Does each level run its passes, leaving the others' instructions alone?
+    #[test]
+    fn test_passes() {
+        let program = BfProgram::new("passes.bf", "++[-]>>[+][--][<]").unwrap();
+        let ops = |level| {
+            let optimized = program.optimize(level);
+            (0..optimized.ops().len())
+                .map(|index| (optimized.ops()[index], optimized.instructions_of(index)))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ops(OptLevel::Full),
+            [
+                (Op::Add(2), 0..2),
+                (Op::SetZero { up: false }, 2..5),
+                (Op::Move(2), 5..7),
+                (Op::SetZero { up: true }, 7..10),
+                (Op::JumpIfZero(7), 10..11),
+                (Op::Add(-2), 11..13),
+                (Op::JumpUnlessZero(5), 13..14),
+                (Op::Scan { right: false }, 14..17),
+            ]
+        );
+        assert_eq!(program.optimize(OptLevel::Full).fused(), 6);
+
+        let run_lengths = ops(OptLevel::RunLengths);
+        assert_eq!(run_lengths.len(), 14);
+        assert_eq!(run_lengths[1], (Op::JumpIfZero(4), 2..3));
+        assert_eq!(run_lengths[11], (Op::JumpIfZero(14), 14..15));
+
+        let none = ops(OptLevel::None);
+        assert_eq!(none.len(), program.localised_instructions().len());
+        assert_eq!(none[0], (Op::Add(1), 0..1));
+        assert_eq!(program.optimize(OptLevel::None).fused(), 0);
+    }
+
+    // Are runs of one instruction fused, split where the instruction changes, and single
+    // instructions left as they are, inside loops as well as out?
+    #[test]
+    fn test_run_lengths() {
+        assert_eq!(
+            lowered("+++-->>><.", OptLevel::RunLengths),
+            [
+                (Op::Add(3), 0..3),
+                (Op::Add(-2), 3..5),
+                (Op::Move(3), 5..8),
+                (Op::Move(-1), 8..9),
+                (Op::Output, 9..10),
+            ]
+        );
+        assert_eq!(
+            lowered("[>>+<<-]", OptLevel::RunLengths),
+            [
+                (Op::JumpIfZero(6), 0..1),
+                (Op::Move(2), 1..3),
+                (Op::Add(1), 3..4),
+                (Op::Move(-2), 4..6),
+                (Op::Add(-1), 6..7),
+                (Op::JumpUnlessZero(1), 7..8),
+            ]
+        );
+        assert_eq!(lowered("+-+-", OptLevel::RunLengths).len(), 4);
+        assert_eq!(lowered("+++", OptLevel::None).len(), 3);
+    }
+
+    // Are `[-]` and `[+]` cleared in one op at full optimisation, nested or not, and other loops
+    // with a single `+` or `-` run left alone?
+    #[test]
+    fn test_clear_loops() {
+        assert_eq!(
+            lowered("[-][+]", OptLevel::Full),
+            [
+                (Op::SetZero { up: false }, 0..3),
+                (Op::SetZero { up: true }, 3..6),
+            ]
+        );
+        assert_eq!(
+            lowered("+[>[-]<-]", OptLevel::Full),
+            [
+                (Op::Add(1), 0..1),
+                (Op::JumpIfZero(7), 1..2),
+                (Op::Move(1), 2..3),
+                (Op::SetZero { up: false }, 3..6),
+                (Op::Move(-1), 6..7),
+                (Op::Add(-1), 7..8),
+                (Op::JumpUnlessZero(2), 8..9),
+            ]
+        );
+        assert_eq!(
+            lowered("[[-]]", OptLevel::Full),
+            [
+                (Op::JumpIfZero(3), 0..1),
+                (Op::SetZero { up: false }, 1..4),
+                (Op::JumpUnlessZero(1), 4..5),
+            ]
+        );
+        assert_eq!(lowered("[--]", OptLevel::Full)[1], (Op::Add(-2), 1..3));
+        assert_eq!(lowered("[-]", OptLevel::RunLengths).len(), 3);
+    }
+
+    // Are `[>]` and `[<]` scanned in one op at full optimisation, nested or not, and loops that
+    // move further each time left alone?
+    #[test]
+    fn test_scan_loops() {
+        assert_eq!(
+            lowered("[>][<]", OptLevel::Full),
+            [
+                (Op::Scan { right: true }, 0..3),
+                (Op::Scan { right: false }, 3..6),
+            ]
+        );
+        assert_eq!(
+            lowered("+[[<]>-]", OptLevel::Full),
+            [
+                (Op::Add(1), 0..1),
+                (Op::JumpIfZero(6), 1..2),
+                (Op::Scan { right: false }, 2..5),
+                (Op::Move(1), 5..6),
+                (Op::Add(-1), 6..7),
+                (Op::JumpUnlessZero(2), 7..8),
+            ]
+        );
+        assert_eq!(lowered("[>>]", OptLevel::Full)[1], (Op::Move(2), 1..3));
+        assert_eq!(lowered("[<]", OptLevel::RunLengths).len(), 3);
+    }
+
+    // Does each jump lead just past its partner, however the ops inside and around the loops are
+    // fused?
+    #[test]
+    fn test_jump_targets() {
+        for text in ["+[[-]>[>]+[<<]<-]>[.[-]]", "[[[[]]][]]", "[>>[-]<<[+]]"] {
+            for level in [OptLevel::None, OptLevel::RunLengths, OptLevel::Full] {
+                let ops = lowered(text, level);
+                for (index, (op, _)) in ops.iter().enumerate() {
+                    match *op {
+                        Op::JumpIfZero(target) => {
+                            assert_eq!(ops[target - 1].0, Op::JumpUnlessZero(index + 1));
+                        }
+                        Op::JumpUnlessZero(target) => {
+                            assert_eq!(ops[target - 1].0, Op::JumpIfZero(index + 1));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+}
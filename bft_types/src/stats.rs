@@ -0,0 +1,118 @@
+//! What a program is made of, counted without running it.
+
+use std::fmt::Display;
+
+use crate::{BfProgram, Instruction};
+
+/// The usual order of the Brainfuck instructions, which [ProgramStats::instructions] follows
+const ORDER: &str = "><+-.,[]";
+
+/// Counts describing a [BfProgram], as found by [BfProgram::stats]
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ProgramStats {
+    /// How many of each instruction the program has: the Brainfuck instructions in the usual
+    /// order `><+-.,[]`, then any others in the order they first appear. Instructions the program
+    /// does not use are left out.
+    pub instructions: Vec<(Instruction, usize)>,
+    /// Number of instructions in the program
+    pub instruction_count: usize,
+    /// Number of loops in the program
+    pub loops: usize,
+    /// How many loops deep the most deeply nested instruction is, or 0 with no loops
+    pub max_nesting: usize,
+    /// Size of the text the program was read from in bytes, including any files it includes
+    pub source_bytes: usize,
+}
+
+impl ProgramStats {
+    /// Count the instructions and loops of `program`
+    pub(crate) fn new(program: &BfProgram) -> Self {
+        let mut instructions: Vec<(Instruction, usize)> = Vec::new();
+        for instruction in program.localised_instructions() {
+            let instruction = instruction.instruction();
+            match instructions
+                .iter_mut()
+                .find(|(counted, _)| *counted == instruction)
+            {
+                Some((_, count)) => *count += 1,
+                None => instructions.push((instruction, 1)),
+            }
+        }
+        // a stable sort keeps the others in the order they first appear
+        instructions.sort_by_key(|(instruction, _)| {
+            ORDER.find(instruction.to_char()).unwrap_or(ORDER.len())
+        });
+
+        Self {
+            instructions,
+            instruction_count: program.localised_instructions().len(),
+            loops: program.loops().len(),
+            max_nesting: program.loops().max_depth().map_or(0, |depth| depth + 1),
+            source_bytes: program.source_bytes(),
+        }
+    }
+}
+
+impl Display for ProgramStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instructions", self.instruction_count)?;
+        if !self.instructions.is_empty() {
+            let counts: Vec<_> = self
+                .instructions
+                .iter()
+                .map(|(instruction, count)| format!("'{}' {}", instruction.to_char(), count))
+                .collect();
+            write!(f, " ({})", counts.join(", "))?;
+        }
+        write!(
+            f,
+            ", {} loops nested {} deep, {} bytes of source",
+            self.loops, self.max_nesting, self.source_bytes
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseOptions;
+
+    // Are instructions counted in the usual order, with others after them?
+    #[test]
+    fn test_stats() {
+        let options = ParseOptions {
+            extended: true,
+            ..ParseOptions::default()
+        };
+        let program =
+            BfProgram::new_with_options("stats.bf", "$+[->[-]<]\n@ comment\n", &options).unwrap();
+        let stats = program.stats();
+
+        assert_eq!(
+            stats.instructions,
+            [
+                (Instruction::MoveRight, 1),
+                (Instruction::MoveLeft, 1),
+                (Instruction::Increment, 1),
+                (Instruction::Decrement, 2),
+                (Instruction::ConditionalJumpForward, 2),
+                (Instruction::ConditionalJumpBackward, 2),
+                (Instruction::Store, 1),
+                (Instruction::End, 1),
+            ]
+        );
+        assert_eq!((stats.instruction_count, stats.loops), (11, 2));
+        assert_eq!((stats.max_nesting, stats.source_bytes), (2, 21));
+        assert_eq!(
+            stats.to_string(),
+            "11 instructions ('>' 1, '<' 1, '+' 1, '-' 2, '[' 2, ']' 2, '$' 1, '@' 1), 2 loops \
+             nested 2 deep, 21 bytes of source"
+        );
+
+        let empty = BfProgram::new("empty.bf", "").unwrap().stats();
+        assert_eq!(
+            empty.to_string(),
+            "0 instructions, 0 loops nested 0 deep, 0 bytes of source"
+        );
+    }
+}
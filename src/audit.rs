@@ -0,0 +1,169 @@
+//! `--audit-determinism`: checking that a program does the same thing every time it is run.
+
+use std::error::Error;
+use std::io::{Cursor, Read};
+
+use bft_interp::FlushPolicy;
+use bft_types::BfProgram;
+
+use crate::cli::Args;
+use crate::program_input;
+use crate::report::Reporter;
+
+/// Everything a run is compared on
+#[derive(Debug, PartialEq, Eq)]
+struct RunResult {
+    /// How the run ended: the halt reason, or the error
+    ending: String,
+    output: Vec<u8>,
+    tape: Vec<u8>,
+    head: usize,
+}
+
+/// The settings that can make runs of the same program on the same input differ, and why
+fn nondeterminism_sources(args: &Args) -> Vec<&'static str> {
+    let mut sources = Vec::new();
+    if args.timeout_ms.is_some() {
+        sources.push("--timeout-ms: where the program is stopped depends on how fast it runs");
+    }
+    sources
+}
+
+/// Read the program's input once, then run the program `runs` times on it, checking that every
+/// run ends the same way with the same output and the same final tape. Fails, naming the first
+/// difference, if they do not.
+pub fn audit_determinism(
+    args: &Args,
+    bf_program: &BfProgram,
+    runs: usize,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn Error>> {
+    for source in nondeterminism_sources(args) {
+        reporter.info(format!("Warning: possible nondeterminism from {}", source));
+    }
+
+    let mut input = Vec::new();
+    program_input(args)?.read_to_end(&mut input)?;
+
+    let first = run_once(args, bf_program, &input);
+    for run in 2..=runs {
+        let result = run_once(args, bf_program, &input);
+        if let Some(difference) = difference(&first, &result) {
+            return Err(format!("run {} differed from run 1: {}", run, difference).into());
+        }
+    }
+
+    reporter.info(format!(
+        "All {} runs were identical: {}, {} bytes of output, head at cell {}",
+        runs,
+        first.ending,
+        first.output.len(),
+        first.head
+    ));
+    Ok(())
+}
+
+/// Run the program once, capturing everything the runs are compared on
+fn run_once(args: &Args, bf_program: &BfProgram, input: &[u8]) -> RunResult {
+    let mut output = Vec::new();
+    let mut bf_interpreter = args
+        .virtual_machine(bf_program)
+        .with_flush_policy(FlushPolicy::Manual);
+    let ending = match bf_interpreter.interpret(&mut Cursor::new(input), &mut output) {
+        Ok(halt_reason) => halt_reason.to_string(),
+        Err(error) => error.to_string(),
+    };
+    RunResult {
+        ending,
+        output,
+        tape: bf_interpreter.tape().to_vec(),
+        head: bf_interpreter.head(),
+    }
+}
+
+/// Describe the first way in which a run differs from the first one, if it does
+fn difference(first: &RunResult, other: &RunResult) -> Option<String> {
+    if first.ending != other.ending {
+        return Some(format!(
+            "it ended with '{}' rather than '{}'",
+            other.ending, first.ending
+        ));
+    }
+    if first.output != other.output {
+        let offset = first
+            .output
+            .iter()
+            .zip(&other.output)
+            .position(|(a, b)| a != b)
+            .unwrap_or(first.output.len().min(other.output.len()));
+        return Some(format!("its output differed from byte {}", offset));
+    }
+    if first.head != other.head {
+        return Some(format!(
+            "its head stopped at cell {} rather than {}",
+            other.head, first.head
+        ));
+    }
+    if first.tape != other.tape {
+        let cell = first
+            .tape
+            .iter()
+            .zip(&other.tape)
+            .position(|(a, b)| a != b)
+            .unwrap_or(first.tape.len().min(other.tape.len()));
+        return Some(format!("its tape differed from cell {}", cell));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Are differences between runs found and described?
+    #[test]
+    fn test_difference() {
+        let first = RunResult {
+            ending: "Completed".to_string(),
+            output: b"abc".to_vec(),
+            tape: vec![1, 2, 3],
+            head: 0,
+        };
+        assert_eq!(
+            difference(
+                &first,
+                &RunResult {
+                    ending: "Completed".to_string(),
+                    output: b"abc".to_vec(),
+                    tape: vec![1, 2, 3],
+                    head: 0
+                }
+            ),
+            None
+        );
+        assert_eq!(
+            difference(
+                &first,
+                &RunResult {
+                    ending: "Completed".to_string(),
+                    output: b"abd".to_vec(),
+                    tape: vec![1, 2, 3],
+                    head: 0
+                }
+            ),
+            Some("its output differed from byte 2".to_string())
+        );
+        assert_eq!(
+            difference(
+                &first,
+                &RunResult {
+                    ending: "Completed".to_string(),
+                    output: b"abc".to_vec(),
+                    tape: vec![1, 2, 3, 4],
+                    head: 0
+                }
+            ),
+            Some("its tape differed from cell 3".to_string())
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Running every program in a directory, writing a report for each one and an index of them all.
+
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use bft::safe_write::{self, Existing};
+use bft_interp::{HaltReason, Limits};
+
+use crate::cli::Args;
+use crate::frontend::{self, Frontend};
+use crate::json;
+use crate::metrics::Metrics;
+use crate::report::Reporter;
+use crate::schema::SCHEMA_VERSION;
+use crate::shutdown::{self, Drain};
+
+/// Time limit applied to each program in a batch run unless --timeout-ms is given
+const DEFAULT_BATCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Output limit applied to each program in a batch run unless --max-output is given
+const DEFAULT_BATCH_MAX_OUTPUT: u64 = 16 * 1024 * 1024;
+
+/// How a single program in the batch finished
+#[derive(Debug)]
+enum Outcome {
+    /// The program was run, and stopped for the given reason
+    Halted(HaltReason),
+    /// The program could not be loaded, or hit a runtime error
+    Failed(String),
+}
+
+/// What happened when a single program in the batch was run
+#[derive(Debug)]
+struct ProgramReport {
+    /// File name of the program, relative to the batch directory
+    program: String,
+    outcome: Outcome,
+    instructions: u64,
+    output_bytes: usize,
+    elapsed: Duration,
+}
+
+impl ProgramReport {
+    /// Short name for the outcome, used in the stats files and index
+    fn status(&self) -> &'static str {
+        match self.outcome {
+            Outcome::Halted(HaltReason::Completed) => "completed",
+            Outcome::Halted(_) => "stopped",
+            Outcome::Failed(_) => "failed",
+        }
+    }
+
+    /// Render the report as a human-readable stats file
+    fn stats(&self) -> String {
+        let mut stats = format!("program: {}\nstatus: {}\n", self.program, self.status());
+        match &self.outcome {
+            Outcome::Halted(halt_reason) => {
+                stats.push_str(&format!("halt_reason: {}\n", halt_reason))
+            }
+            Outcome::Failed(error) => stats.push_str(&format!("error: {}\n", error)),
+        }
+        stats.push_str(&format!(
+            "instructions: {}\noutput_bytes: {}\nelapsed_ms: {:.3}\n",
+            self.instructions,
+            self.output_bytes,
+            self.elapsed.as_secs_f64() * 1000.0
+        ));
+        stats
+    }
+
+    /// Render the report as a JSON object for the index
+    fn to_json(&self) -> String {
+        let (halt_reason, error) = match &self.outcome {
+            Outcome::Halted(halt_reason) => (json::string(&halt_reason.to_string()), "null".into()),
+            Outcome::Failed(error) => ("null".into(), json::string(error)),
+        };
+        format!(
+            "{{\"program\": {}, \"status\": {}, \"halt_reason\": {}, \"error\": {}, \"instructions\": {}, \"output_bytes\": {}, \"elapsed_ms\": {:.3}}}",
+            json::string(&self.program),
+            json::string(self.status()),
+            halt_reason,
+            error,
+            self.instructions,
+            self.output_bytes,
+            self.elapsed.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Run every `.b` and `.bf` file in `directory` with no input, writing `<name>.out`,
+/// `<name>.stats` and (if it failed) `<name>.err` for each into `report_dir`, along with an
+/// `index.json` covering them all. Programs are given a time and output limit even if none were
+/// asked for, so that one runaway program cannot hold up the rest of the batch. On SIGTERM, the
+/// batch drains: no more programs are started, and the one running is given the grace period to
+/// finish.
+pub fn run_directory(
+    args: &Args,
+    directory: &Path,
+    report_dir: &Path,
+    reporter: &Reporter,
+    metrics: &mut Metrics,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(report_dir)?;
+
+    let mut programs: Vec<PathBuf> = fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    programs.retain(|path| path.is_file() && Frontend::detect(path).is_some());
+    programs.sort();
+
+    let limits = Limits {
+        timeout: args.limits().timeout.or(Some(DEFAULT_BATCH_TIMEOUT)),
+        max_output: args.limits().max_output.or(Some(DEFAULT_BATCH_MAX_OUTPUT)),
+        ..args.limits()
+    };
+
+    shutdown::listen_for_sigterm();
+    let drain = Drain::new(
+        args.grace_ms
+            .map_or(shutdown::DEFAULT_GRACE, Duration::from_millis),
+        shutdown::requested,
+    );
+    let mut reports = Vec::with_capacity(programs.len());
+    for program in &programs {
+        if shutdown::requested() {
+            break;
+        }
+        let report = run_one(args, limits, program, report_dir, &drain, metrics)?;
+        reporter.verbose(format!(
+            "{}: {} ({} instructions in {:.3}ms)",
+            report.program,
+            report.status(),
+            report.instructions,
+            report.elapsed.as_secs_f64() * 1000.0
+        ));
+        reports.push(report);
+    }
+
+    let index = format!(
+        "{{\n  \"schema_version\": {},\n  \"directory\": {},\n  \"programs\": [\n{}\n  ]\n}}\n",
+        SCHEMA_VERSION,
+        json::string(&directory.display().to_string()),
+        reports
+            .iter()
+            .map(|report| format!("    {}", report.to_json()))
+            .collect::<Vec<_>>()
+            .join(",\n")
+    );
+    safe_write::write(report_dir.join("index.json"), index, Existing::Overwrite)?;
+
+    let failures = reports
+        .iter()
+        .filter(|report| matches!(report.outcome, Outcome::Failed(_)))
+        .count();
+    let stopped = reports
+        .iter()
+        .filter(|report| report.status() == "stopped")
+        .count();
+    reporter.info(format!(
+        "Ran {} programs: {} completed, {} stopped early, {} failed",
+        reports.len(),
+        reports.len() - failures - stopped,
+        stopped,
+        failures
+    ));
+    if shutdown::requested() {
+        reporter.info(format!(
+            "Shut down by SIGTERM: {} of {} programs were not run",
+            programs.len() - reports.len(),
+            programs.len()
+        ));
+    }
+
+    match failures {
+        0 => Ok(()),
+        failures => Err(format!("{} of {} programs failed", failures, reports.len()).into()),
+    }
+}
+
+/// Run a single program from the batch and write its report files
+fn run_one(
+    args: &Args,
+    limits: Limits,
+    program_path: &Path,
+    report_dir: &Path,
+    drain: &Drain,
+    metrics: &mut Metrics,
+) -> Result<ProgramReport, Box<dyn Error>> {
+    let program = program_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let started = Instant::now();
+    let mut output = Vec::new();
+    let mut instructions = 0;
+    let outcome = match frontend::select(program_path, args.lang)
+        .frontend
+        .parse(program_path, &args.parse_options())
+    {
+        Ok(bf_program) => {
+            let mut bf_interpreter = args.virtual_machine(&bf_program).with_limits(limits);
+            drain.running(bf_interpreter.cancel_token());
+            let result = bf_interpreter.interpret(&mut Cursor::new([]), &mut output);
+            instructions = bf_interpreter.clock();
+            metrics.record_run(instructions, output.len() as u64, &result);
+            match result {
+                Ok(halt_reason) => Outcome::Halted(halt_reason),
+                Err(error) => Outcome::Failed(error.to_string()),
+            }
+        }
+        Err(error) => {
+            metrics.record_load_error();
+            Outcome::Failed(error.to_string())
+        }
+    };
+
+    let report = ProgramReport {
+        program,
+        outcome,
+        instructions,
+        output_bytes: output.len(),
+        elapsed: started.elapsed(),
+    };
+
+    // the report directory is written afresh on every run
+    let write = |extension: &str, contents: &[u8]| {
+        safe_write::write(
+            report_dir.join(format!("{}.{}", report.program, extension)),
+            contents,
+            Existing::Overwrite,
+        )
+    };
+    write("out", &output)?;
+    write("stats", report.stats().as_bytes())?;
+    if let Outcome::Failed(error) = &report.outcome {
+        write("err", format!("{}\n", error).as_bytes())?;
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,158 @@
+//! Running Brainfuck programs at build time, to embed what they generate in a crate.
+//!
+//! From a downstream crate's build script:
+//!
+//! ```no_run
+//! // in build.rs's main()
+//! bft::build::run_for_build("assets/banner.bf", "banner.txt").unwrap();
+//! ```
+//!
+//! and then in the crate itself:
+//!
+//! ```ignore
+//! static BANNER: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/banner.txt"));
+//! ```
+//!
+//! The same thing is available on the command line as `bft generate-include`.
+
+use std::error::Error;
+use std::fmt::Display;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bft_interp::{FlushPolicy, HaltReason, Limits, VirtualMachine};
+use bft_types::ParseOptions;
+
+use crate::frontend;
+use crate::safe_write::{self, Existing};
+
+/// How long a program run from a build script may take before the build fails
+pub const DEFAULT_BUILD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What was generated, and by what
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generated {
+    /// The program that was run
+    pub program: PathBuf,
+    /// The [bft_types::BfProgram::fingerprint] of the program
+    pub fingerprint: u64,
+    /// The file the output was written to
+    pub path: PathBuf,
+    /// Number of bytes of output
+    pub bytes: usize,
+    /// Number of instructions the program executed
+    pub instructions: u64,
+}
+
+impl Display for Generated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "program: {}", self.program.display())?;
+        writeln!(f, "fingerprint: {:016x}", self.fingerprint)?;
+        writeln!(f, "instructions: {}", self.instructions)?;
+        writeln!(f, "bytes: {}", self.bytes)?;
+        write!(f, "include: include_bytes!({:?})", self.path)
+    }
+}
+
+/// Run a program to completion on the given input, and write its output to `destination`. Fails
+/// if the program cannot be loaded, hits an error, or is stopped by a limit, or if `destination`
+/// exists and `existing` says to leave it. `destination` is only written once the program has
+/// completed, and never left half written.
+pub fn generate(
+    program: &Path,
+    input: &[u8],
+    destination: &Path,
+    limits: Limits,
+    existing: Existing,
+) -> Result<Generated, Box<dyn Error>> {
+    let bf_program = frontend::select(program, None)
+        .frontend
+        .parse(program, &ParseOptions::default())?;
+    let mut output = Vec::new();
+    let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&bf_program, None, true)
+        .with_limits(limits)
+        .with_flush_policy(FlushPolicy::Manual);
+
+    match bf_interpreter.interpret(&mut Cursor::new(input), &mut output)? {
+        HaltReason::Completed => {}
+        halt_reason => {
+            return Err(format!("{} stopped early: {}", program.display(), halt_reason).into())
+        }
+    }
+    safe_write::write(destination, &output, existing)?;
+
+    Ok(Generated {
+        program: program.to_path_buf(),
+        fingerprint: bf_program.fingerprint(),
+        path: destination.to_path_buf(),
+        bytes: output.len(),
+        instructions: bf_interpreter.clock(),
+    })
+}
+
+/// For use in a build script: run a program with no input, and write its output to `output_name`
+/// in the build's `OUT_DIR`, ready for `include_bytes!`. Cargo is told to rerun the build script
+/// if the program changes. The program is given an extensible tape and [DEFAULT_BUILD_TIMEOUT].
+pub fn run_for_build(
+    program: impl AsRef<Path>,
+    output_name: &str,
+) -> Result<Generated, Box<dyn Error>> {
+    let program = program.as_ref();
+    let out_dir = std::env::var_os("OUT_DIR")
+        .ok_or("OUT_DIR is not set; run_for_build should be called from a build script")?;
+    println!("cargo:rerun-if-changed={}", program.display());
+
+    generate(
+        program,
+        &[],
+        &Path::new(&out_dir).join(output_name),
+        Limits {
+            timeout: Some(DEFAULT_BUILD_TIMEOUT),
+            ..Limits::default()
+        },
+        Existing::Overwrite,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Is the output written where asked, and is a program that does not finish refused?
+    #[test]
+    fn test_generate() {
+        let directory = std::env::temp_dir().join(format!("bft-build-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let program = directory.join("a.bf");
+        fs::write(&program, "++++++++[>++++++++<-]>+.").unwrap();
+
+        let generated = generate(
+            &program,
+            &[],
+            &directory.join("a.txt"),
+            Limits::default(),
+            Existing::Refuse,
+        );
+        assert_eq!(generated.unwrap().bytes, 1);
+        assert_eq!(fs::read(directory.join("a.txt")).unwrap(), b"A");
+
+        fs::write(&program, "+[]").unwrap();
+        let limits = Limits {
+            max_instructions: Some(100),
+            ..Limits::default()
+        };
+        assert!(generate(
+            &program,
+            &[],
+            &directory.join("b.txt"),
+            limits,
+            Existing::Refuse
+        )
+        .is_err());
+        assert!(!directory.join("b.txt").exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
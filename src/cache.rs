@@ -0,0 +1,167 @@
+//! `--cached`: serving the output of repeated identical runs from a cache.
+//!
+//! A run can be cached when its result depends only on the program, its input and the options it
+//! was run with: the input must come from a file (or the program must never read any), and there
+//! must be no timeout. The cache key combines the program's fingerprint, a fingerprint of the input
+//! and the options, and only runs that complete are stored.
+
+use std::env;
+use std::fs;
+use std::hash::Hasher;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use bft::safe_write::{self, Existing};
+use bft_types::fingerprint::{fingerprint_bytes, Fnv1a};
+use bft_types::{BfProgram, Instruction};
+
+use crate::cli::Args;
+
+/// A run that can be cached, and where its output is or would be stored
+#[derive(Debug)]
+pub struct CacheEntry {
+    /// The whole of the program's input, read in advance so that it can be fingerprinted
+    pub input: Vec<u8>,
+    path: PathBuf,
+}
+
+impl CacheEntry {
+    /// Work out the cache entry for running the program as asked for on the command line. Returns
+    /// the reason the run cannot be cached if it cannot.
+    pub fn for_run(args: &Args, bf_program: &BfProgram) -> Result<Self, String> {
+        if args.timeout_ms.is_some() {
+            return Err("--timeout-ms makes the result depend on how fast the program runs".into());
+        }
+        let reads_input = bf_program
+            .localised_instructions()
+            .iter()
+            .any(|instruction| instruction.instruction() == Instruction::Input);
+        let input = match (&args.input_str, &args.input) {
+            _ if !reads_input => Vec::new(),
+            (Some(text), _) => text.clone().into_bytes(),
+            (None, Some(path)) if !args.then_stdin => fs::read(path)
+                .map_err(|error| format!("could not read {}: {}", path.display(), error))?,
+            _ => return Err("the program reads from stdin".into()),
+        };
+        let directory = cache_directory().ok_or("no cache directory could be found")?;
+
+        let mut key = Fnv1a::default();
+        key.write(env!("CARGO_PKG_VERSION").as_bytes());
+        key.write_u64(bf_program.fingerprint());
+        key.write_u64(fingerprint_bytes(&input));
+        key.write(run_options(args).as_bytes());
+
+        Ok(Self {
+            input,
+            path: directory.join(format!("{:016x}.out", key.finish())),
+        })
+    }
+
+    /// The output stored for this run, if it has been cached
+    pub fn load(&self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(output) => Ok(Some(output)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Store the output of a completed run. The file is written in full before it is moved into
+    /// place, so a concurrent run never sees a partial entry.
+    pub fn store(&self, output: &[u8]) -> io::Result<()> {
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        safe_write::write(&self.path, output, Existing::Overwrite)
+    }
+}
+
+/// The options that can change what a run outputs, in a stable form for the cache key
+fn run_options(args: &Args) -> String {
+    format!(
+        "cells={:?} extensible={} bidirectional={} circular={} max_cells={:?} max_memory={:?} max_instructions={:?} max_output={:?} max_loop_iterations={:?} arithmetic={:?} eof={:?} protect={:?} assertions={} extended={} mapping={:?} comments={:?} macros={}",
+        args.cells,
+        args.extensible,
+        args.bidirectional,
+        args.circular,
+        args.max_cells,
+        args.max_memory,
+        args.max_instructions,
+        args.max_output,
+        args.max_loop_iterations,
+        args.arithmetic,
+        args.eof,
+        args.protect,
+        args.assertions,
+        args.extended,
+        args.mapping,
+        args.comment_syntax(),
+        args.macros
+    )
+}
+
+/// Where cached output is kept: `$BFT_CACHE_DIR`, or `bft` in the user's cache directory
+fn cache_directory() -> Option<PathBuf> {
+    if let Some(directory) = env::var_os("BFT_CACHE_DIR") {
+        return Some(directory.into());
+    }
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|cache| cache.join("bft"))
+}
+
+/// A writer that passes everything on, keeping a copy if asked to
+pub struct Recorder<W: Write> {
+    inner: W,
+    recorded: Option<Vec<u8>>,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Wrap a writer, recording what is written to it only if `record` is set
+    pub fn new(inner: W, record: bool) -> Self {
+        Self {
+            inner,
+            recorded: record.then(Vec::new),
+        }
+    }
+
+    /// The wrapped writer, and a copy of everything written if it was recorded
+    pub fn into_parts(self) -> (W, Option<Vec<u8>>) {
+        (self.inner, self.recorded)
+    }
+}
+
+impl<W: Write> Write for Recorder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(recorded) = &mut self.recorded {
+            recorded.extend_from_slice(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Is a copy kept only when recording?
+    #[test]
+    fn test_recorder() {
+        let mut recorder = Recorder::new(Vec::new(), true);
+        recorder.write_all(b"abc").unwrap();
+        assert_eq!(
+            recorder.into_parts(),
+            (b"abc".to_vec(), Some(b"abc".to_vec()))
+        );
+
+        let mut recorder = Recorder::new(Vec::new(), false);
+        recorder.write_all(b"abc").unwrap();
+        assert_eq!(recorder.into_parts(), (b"abc".to_vec(), None));
+    }
+}
@@ -1,16 +1,1106 @@
 //! CLI arguments for the Brainfuck interpreter
 
-use std::num::NonZeroUsize;
+use std::io::{stderr, stdout, IsTerminal};
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::ops::Range;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::Parser;
+use bft::compile::{CompileOptions, Tape, Target};
+use bft_interp::{
+    Arithmetic, CycleCosts, EofBehavior, FlushPolicy, Limits, MemoryBudget, VirtualClock,
+    VirtualMachine,
+};
+use bft_types::mapping::Mapping;
+use bft_types::optimize::OptLevel;
+use bft_types::{BfProgram, CommentSyntax, ParseOptions};
+use clap::{Parser, Subcommand};
 
+use crate::frontend::{parse_lang, Frontend};
+use crate::schema::{parse_schema, Schema};
+use crate::stream::parse_byte_size;
+
+/// The instruction that writes to stderr with --stderr-channel
+const STDERR_CHANNEL: char = ';';
+
+/// Brainfuck interpreter and tools. With no subcommand, the given program is run as with `bft run`.
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Cli {
+    /// The subcommand to run, if any
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Arguments for running a program when no subcommand is given
+    #[command(flatten)]
+    pub run: Option<Args>,
+
+    /// Only show program output and hard errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Show timings and statistics. Repeat (-vv) for more detail.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Show diagnostics using the message templates in this catalog file, falling back to English
+    /// for any it does not have
+    #[arg(long, global = true, value_name = "FILE")]
+    pub messages: Option<PathBuf>,
+}
+
+/// Subcommands of the bft tool
+#[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)] // parsed once per process, so the size does not matter
+pub enum Command {
+    /// Run a Brainfuck program
+    Run(Args),
+
+    /// Run a program compiled with `bft compile --target bytecode`, whatever its file is called,
+    /// with the same options as `bft run`
+    RunBytecode(Args),
+
+    /// Link a main program and library fragments into a single program
+    Link(LinkArgs),
+
+    /// Run programs with their `@assert` directives checked, and report which pass
+    Test(TestArgs),
+
+    /// Check a program for problems without running it
+    Check(CheckArgs),
+
+    /// Suggest shorter ways of writing parts of a program
+    Golf(GolfArgs),
+
+    /// Write a program's control-flow graph in Graphviz DOT format, one node for each run of
+    /// instructions without a jump and for each `[` and `]`
+    Cfg(CfgArgs),
+
+    /// Compile a program ahead of time, to run without bft
+    Compile(CompileArgs),
+
+    /// Run one program over many input files, writing an output file for each
+    Map(MapArgs),
+
+    /// Run a program and write its output to a file for `include_bytes!`, reporting what was
+    /// generated
+    GenerateInclude(GenerateIncludeArgs),
+
+    /// Control the interpreter over stdin and stdout with JSON-RPC, one message per line
+    Session,
+
+    /// Keep a program loaded and run it for each client of a Unix socket, reloading it when its
+    /// file changes
+    Daemon(DaemonArgs),
+
+    /// Report the configuration, features and terminal bft sees, and run a self-test
+    Doctor,
+
+    /// Run a built-in suite of programs with the given tape, cell and EOF options, and report
+    /// which semantics they give
+    Selftest(SelftestArgs),
+
+    /// Show what is known about a file, such as where a compiled artifact came from
+    Inspect(InspectArgs),
+
+    /// Summarise the runs of a program recorded with --profile-db
+    Profile(ProfileArgs),
+}
+
+/// Arguments for running a program
+#[derive(clap::Args, Debug)]
 pub struct Args {
-    /// Path to the file containing the brainfuck program. Required.
+    /// Path to the file containing the brainfuck program. Required unless --all or --schema is
+    /// given.
+    #[arg(required_unless_present_any = ["all", "schema"])]
+    pub program: Option<PathBuf>,
+
+    /// Run every .b and .bf file in this directory instead of a single program, with no input.
+    /// Each program is limited to 10 seconds and 16MiB of output unless other limits are given.
+    #[arg(long, conflicts_with = "program", requires = "report_dir")]
+    pub all: Option<PathBuf>,
+
+    /// Print the versioned JSON Schema for one of the JSON documents bft writes (index or session)
+    /// and exit
+    #[arg(long, conflicts_with_all = ["program", "all"], value_parser = parse_schema)]
+    pub schema: Option<Schema>,
+
+    /// With --all, how long the running program may carry on after SIGTERM before it is
+    /// cancelled. No new programs are started once SIGTERM arrives.
+    #[arg(long, requires = "all")]
+    pub grace_ms: Option<u64>,
+
+    /// Directory to write the per-program output, stats and error files and index.json into
+    /// when running with --all
+    #[arg(long, requires = "all")]
+    pub report_dir: Option<PathBuf>,
+
+    /// The language the program is written in (bf, ook or bytecode). Worked out from the file
+    /// extension if not given: .b and .bf are Brainfuck, .ook is Ook!, and .bfc is bytecode.
+    #[arg(long, visible_alias = "dialect", value_parser = parse_lang)]
+    pub lang: Option<Frontend>,
+
+    /// Initial size of the VM's tape.
+    #[arg(short, long)]
+    pub cells: Option<NonZeroUsize>,
+
+    /// Controls whether the end of tape will be extended automatically
+    #[arg(short, long)]
+    pub extensible: bool,
+
+    /// Extend the start of the tape too, so the head can move left of cell 0 instead of failing
+    /// with a head underrun
+    #[arg(long)]
+    pub bidirectional: bool,
+
+    /// Stop the program with an error if the tape would grow beyond this many cells
+    #[arg(long)]
+    pub max_cells: Option<NonZeroUsize>,
+
+    /// Stop the program with an error if the memory held by the tape, snapshots and traces
+    /// together would go over this, in bytes or with a K, M or G suffix
+    #[arg(long, value_parser = parse_byte_size)]
+    pub max_memory: Option<u64>,
+
+    /// Join the ends of the tape, so the head wraps round from the last cell to the first and
+    /// back instead of failing. The tape keeps the size given by --cells.
+    #[arg(long, conflicts_with_all = ["extensible", "bidirectional"])]
+    pub circular: bool,
+
+    /// Stop the program after this many instructions have been executed
+    #[arg(long)]
+    pub max_instructions: Option<u64>,
+
+    /// Stop the program after it has run for this many milliseconds
+    #[arg(long)]
+    pub timeout_ms: Option<u64>,
+
+    /// Stop the program before it outputs more than this many bytes
+    #[arg(long)]
+    pub max_output: Option<u64>,
+
+    /// Stop the program, showing where the loop is, if any one loop runs more than this many
+    /// iterations each time it is entered
+    #[arg(long)]
+    pub max_loop_iterations: Option<u64>,
+
+    /// Remember this many of the most recent jumps, and show them if the program fails
+    #[arg(long)]
+    pub jump_history: Option<NonZeroUsize>,
+
+    /// Journal the last this-many changes to cells, and once the program stops show which cells
+    /// changed and how recently. With -vv, every change in the journal is listed too.
+    #[arg(long)]
+    pub tape_history: Option<NonZeroUsize>,
+
+    /// Read the program's input from this file instead of stdin
+    #[arg(long)]
+    pub input: Option<PathBuf>,
+
+    /// Once the --input file has been used up, carry on reading from stdin rather than treating
+    /// it as the end of the input
+    #[arg(long, requires = "input")]
+    pub then_stdin: bool,
+
+    /// Give the program this text as its input instead of reading stdin. Once it has all been
+    /// read, the program sees the end of its input, as set with --eof.
+    #[arg(long, conflicts_with_all = ["input", "all"])]
+    pub input_str: Option<String>,
+
+    /// Put the terminal into raw mode while the program runs, so it reads each key as it is
+    /// pressed instead of waiting for Enter. Typed keys are not echoed. Ignored if stdin is not a
+    /// terminal.
+    #[arg(long, conflicts_with_all = ["input", "input_str", "all", "filter"])]
+    pub raw: bool,
+
+    /// Treat `;` as an instruction that writes the cell under the head to stderr, giving the
+    /// program a diagnostics channel separate from its output
+    #[arg(long)]
+    pub stderr_channel: bool,
+
+    /// Treat `#` as an instruction that shows the head and the cells around it on stderr, as many
+    /// published programs expect for debugging
+    #[arg(long)]
+    pub debug_dump: bool,
+
+    // The parse arguments are repeated from ParseArgs rather than flattened, as clap can't tell
+    // whether an optional group such as this one was given if it has another group inside it.
+    /// Replace `#include "file"` lines with the program in that file, found relative to the file
+    /// the line is in
+    #[arg(long)]
+    pub includes: bool,
+
+    /// Read `#define NAME body` lines as macros, replacing NAME later in the file with the
+    /// instructions in body
+    #[arg(long)]
+    pub macros: bool,
+
+    /// Parse the program as Extended Brainfuck Type I, adding `@` (end), `$` (store), `!` (load),
+    /// `}` and `{` (shift right and left), `~` (not), and `^`, `&` and `|` (xor, and, or with the
+    /// stored byte)
+    #[arg(long)]
+    pub extended: bool,
+
+    /// Parse the program as a trivial substitution dialect, spelling each instruction as given in
+    /// this mapping file, e.g. `increment = "Ook. Ook."`. See bft_types::mapping for the format.
+    #[arg(long, value_name = "FILE", value_parser = parse_mapping)]
+    pub mapping: Option<Mapping>,
+
+    /// Treat the rest of the line after this character as a comment, even if it contains
+    /// instructions, e.g. --line-comment ';'
+    #[arg(long, value_name = "CHAR")]
+    pub line_comment: Option<char>,
+
+    /// Treat everything between these two characters as a comment, even across lines,
+    /// e.g. --block-comment '{}'
+    #[arg(long, value_name = "OPEN_CLOSE", value_parser = parse_block_comment)]
+    pub block_comment: Option<(char, char)>,
+
+    /// Check `@assert` directives in the program as it runs
+    #[arg(long)]
+    pub assertions: bool,
+
+    /// Refuse programs with more than this many loops open at once
+    #[arg(long)]
+    pub max_nesting: Option<usize>,
+
+    /// Behave as a filter in a shell pipeline: buffer output in blocks, don't add a trailing
+    /// newline, and stop quietly when input runs out or the reader of the output goes away
+    #[arg(long)]
+    pub filter: bool,
+
+    /// Write the program's output straight to this file through a large buffer, instead of to
+    /// stdout. Nothing is added at the end. For programs with huge output; --max-output sets how
+    /// much it may write.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["all", "filter"])]
+    pub stream_to: Option<PathBuf>,
+
+    /// Append a copy of everything the program writes to this log file, as well as writing it
+    /// as usual
+    #[arg(long, value_name = "FILE", conflicts_with = "all")]
+    pub tee: Option<PathBuf>,
+
+    /// Write the program's output to this file instead of stdout. The file only replaces an
+    /// existing one once the program stops, and nothing is added at the end unless
+    /// --trailing-newline is given.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["all", "filter", "stream_to"])]
+    pub output: Option<PathBuf>,
+
+    /// Replace the --output file if it already exists
+    #[arg(long, requires = "output")]
+    pub force: bool,
+
+    /// End the --output file with a newline if the program's output doesn't, as is done on a
+    /// terminal
+    #[arg(long, requires = "output")]
+    pub trailing_newline: bool,
+
+    /// The size of the --stream-to buffer, in bytes or with a K, M or G suffix
+    #[arg(long, requires = "stream_to", value_parser = parse_byte_size, default_value = "8M")]
+    pub stream_buffer: u64,
+
+    /// Sync the --stream-to file to disk each time this much more has been written
+    #[arg(long, requires = "stream_to", conflicts_with = "sandbox", value_parser = parse_byte_size)]
+    pub fsync_every: Option<u64>,
+
+    /// Report how much has been written to the --stream-to file each time this much more has been
+    #[arg(long, requires = "stream_to", value_parser = parse_byte_size)]
+    pub progress_every: Option<u64>,
+
+    /// When to flush output: every-byte, line (at newlines and before reading input) or manual.
+    /// Defaults to line when stdout is a terminal, manual with --filter, --stream-to or
+    /// --output, and every-byte otherwise.
+    #[arg(long, value_parser = parse_flush_policy)]
+    pub flush: Option<FlushPolicy>,
+
+    /// What `+` and `-` do at the ends of a cell's range: wrapping (the default), saturating, or
+    /// checked, which stops the program with an error
+    #[arg(long, value_parser = parse_arithmetic, default_value = "wrapping")]
+    pub arithmetic: Arithmetic,
+
+    /// What `,` does once input has run out: error (the default), zero, max (255, or -1 to
+    /// programs that expect EOF=-1), or unchanged
+    #[arg(long, value_parser = parse_eof_behavior, default_value = "error")]
+    pub eof: EofBehavior,
+
+    /// Make a range of cells read-only, e.g. 0..16 or 4..=7. May be given more than once.
+    #[arg(long, value_parser = parse_cell_range)]
+    pub protect: Vec<Range<usize>>,
+
+    /// Layout file giving names to regions of the tape, used when showing cells
+    #[arg(long)]
+    pub layout: Option<PathBuf>,
+
+    /// Touch every cell of the tape before starting the program, so that allocation costs are
+    /// not counted in the run time
+    #[arg(long)]
+    pub warm_up: bool,
+
+    /// With --extensible, grow the tape to this many cells before starting. Implies --warm-up.
+    #[arg(long, requires = "extensible")]
+    pub pre_grow: Option<usize>,
+
+    /// Optimise the program before running it: --optimize=1 executes each run of `+`, `-`, `>`
+    /// or `<` in one step, and --optimize=2 (or --optimize alone) also each `[-]` or `[+]` clear
+    /// loop and each `[>]` or `[<]` scan loop. Output, the final tape and any error are the same
+    /// as without it.
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = parse_opt_level,
+        num_args = 0..=1,
+        require_equals = true,
+        default_value = "0",
+        default_missing_value = "2"
+    )]
+    pub optimize: OptLevel,
+
+    /// Compile the program to native code with Cranelift and run that, which is much faster for
+    /// long-running programs. Always optimises fully. Needs a build with the `jit` feature; without
+    /// it the program is interpreted.
+    #[arg(long, conflicts_with_all = [
+        "all", "bidirectional", "max_cells", "max_memory", "max_instructions", "timeout_ms",
+        "max_output", "max_loop_iterations", "jump_history", "tape_history", "raw",
+        "stderr_channel", "debug_dump", "assertions", "protect", "cycle_costs", "metrics_file",
+        "audit_determinism", "profile_db", "cached", "summary", "dump_tape", "assert_cell",
+        "assert_head", "assert_halted",
+    ])]
+    pub jit: bool,
+
+    /// Count cycles as the program runs, charging each kind of operation the given number of
+    /// cycles, e.g. move=1,arith=1,in=20,out=20,jump=2,ext=5. Operations not listed cost one cycle.
+    #[arg(long, value_parser = parse_cycle_costs)]
+    pub cycle_costs: Option<CycleCosts>,
+
+    /// Run no faster than this many cycles per second, as counted with --cycle-costs
+    #[arg(long)]
+    pub clock_hz: Option<NonZeroU64>,
+
+    /// Once the program and its input are loaded, lock bft down so that it can only use the files
+    /// it already has open. Needs a Linux build with the `sandbox` feature.
+    #[arg(long, conflicts_with_all = ["all", "metrics_file"])]
+    pub sandbox: bool,
+
+    /// Write counters for the programs run (instructions executed, halts and errors by type) to
+    /// this file in the Prometheus text format, for a node exporter's textfile collector
+    #[arg(long)]
+    pub metrics_file: Option<PathBuf>,
+
+    /// Run the program this many times on the same input, and check that every run ends the same
+    /// way with the same output and final tape
+    #[arg(long, conflicts_with_all = ["all", "dry_run", "sandbox"], value_parser = clap::value_parser!(u64).range(2..))]
+    pub audit_determinism: Option<u64>,
+
+    /// Add this run's statistics and hottest loops to a profile database, keyed by the program's
+    /// fingerprint, for `bft profile report`. Needs a build with the `profile-db` feature.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["all", "sandbox", "cached"])]
+    pub profile_db: Option<PathBuf>,
+
+    /// Serve the output from a cache if this program has been run before with the same input file
+    /// and options, and store it if not. The cache is kept in $BFT_CACHE_DIR, or in bft under
+    /// $XDG_CACHE_HOME or ~/.cache.
+    #[arg(long, conflicts_with_all = ["all", "sandbox", "stderr_channel", "debug_dump", "includes", "assert_cell", "assert_head", "assert_halted"])]
+    pub cached: bool,
+
+    /// Print a short table describing the run once it stops: the program, engine, instructions
+    /// executed, time taken, tape used and why it stopped
+    #[arg(long, conflicts_with = "all")]
+    pub summary: bool,
+
+    /// Parse the program and print how it would be run (engine, tape, input and output, limits)
+    /// without running it
+    #[arg(long, conflicts_with = "all")]
+    pub dry_run: bool,
+
+    /// Parse the program and print what it is made of (instructions of each type, loops and how
+    /// deeply they nest, size of the source) without running it
+    #[arg(long, conflicts_with_all = ["all", "dry_run"])]
+    pub stats: bool,
+
+    /// Show the values of a range of cells once the program stops, e.g. 0..16
+    #[arg(long, value_parser = parse_cell_range)]
+    pub dump_tape: Option<Range<usize>>,
+
+    /// Once the program stops, fail unless a cell holds a value, given as CELL=VALUE, e.g. 0=72.
+    /// May be given more than once.
+    #[arg(long, value_parser = parse_cell_assertion, conflicts_with = "all")]
+    pub assert_cell: Vec<(usize, u8)>,
+
+    /// Once the program stops, fail unless the head is over this cell
+    #[arg(long, conflicts_with = "all")]
+    pub assert_head: Option<usize>,
+
+    /// Fail unless the program ran to completion, giving the limit that stopped it if not
+    #[arg(long, conflicts_with = "all")]
+    pub assert_halted: bool,
+}
+
+/// Parse a range of cells given as `start..end` or `start..=end`
+fn parse_cell_range(value: &str) -> Result<Range<usize>, String> {
+    let invalid = || format!("expected a range like 0..16 or 0..=15, got '{}'", value);
+
+    let (start, end) = value.split_once("..").ok_or_else(invalid)?;
+    let start: usize = start.parse().map_err(|_| invalid())?;
+    let end: usize = match end.strip_prefix('=') {
+        Some(inclusive_end) => inclusive_end
+            .parse::<usize>()
+            .map_err(|_| invalid())?
+            .checked_add(1)
+            .ok_or_else(invalid)?,
+        None => end.parse().map_err(|_| invalid())?,
+    };
+
+    if start < end {
+        Ok(start..end)
+    } else {
+        Err(format!("the range '{}' contains no cells", value))
+    }
+}
+
+/// Load the mapping file given with --mapping
+fn parse_mapping(value: &str) -> Result<Mapping, String> {
+    Mapping::from_file(value)
+}
+
+/// Parse the pair of characters given to --block-comment
+fn parse_block_comment(value: &str) -> Result<(char, char), String> {
+    match value.chars().collect::<Vec<_>>()[..] {
+        [open, close] if open != close => Ok((open, close)),
+        _ => Err(format!(
+            "expected two different characters to open and close a comment, like {{}}, got '{}'",
+            value
+        )),
+    }
+}
+
+/// Parse a `CELL=VALUE` condition for --assert-cell
+fn parse_cell_assertion(value: &str) -> Result<(usize, u8), String> {
+    let invalid = || format!("expected CELL=VALUE like 0=72, got '{}'", value);
+
+    let (cell, cell_value) = value.split_once('=').ok_or_else(invalid)?;
+    Ok((
+        cell.parse().map_err(|_| invalid())?,
+        cell_value
+            .parse()
+            .map_err(|_| format!("'{}' is not a cell value from 0 to 255", cell_value))?,
+    ))
+}
+
+/// Parse an [OptLevel] from its number
+fn parse_opt_level(value: &str) -> Result<OptLevel, String> {
+    match value {
+        "0" => Ok(OptLevel::None),
+        "1" => Ok(OptLevel::RunLengths),
+        "2" => Ok(OptLevel::Full),
+        value => Err(format!(
+            "unknown optimisation level '{}', expected 0, 1 or 2",
+            value
+        )),
+    }
+}
+
+/// Parse the name of a [FlushPolicy]
+fn parse_flush_policy(value: &str) -> Result<FlushPolicy, String> {
+    match value {
+        "every-byte" => Ok(FlushPolicy::EveryByte),
+        "line" => Ok(FlushPolicy::Line),
+        "manual" => Ok(FlushPolicy::Manual),
+        value => Err(format!(
+            "unknown flush policy '{}', expected every-byte, line or manual",
+            value
+        )),
+    }
+}
+
+/// Parse the name of an [Arithmetic] mode
+fn parse_arithmetic(value: &str) -> Result<Arithmetic, String> {
+    match value {
+        "wrapping" => Ok(Arithmetic::Wrapping),
+        "saturating" => Ok(Arithmetic::Saturating),
+        "checked" => Ok(Arithmetic::Checked),
+        value => Err(format!(
+            "unknown arithmetic '{}', expected wrapping, saturating or checked",
+            value
+        )),
+    }
+}
+
+/// Parse the name of an [EofBehavior]
+fn parse_eof_behavior(value: &str) -> Result<EofBehavior, String> {
+    match value {
+        "error" => Ok(EofBehavior::Error),
+        "zero" => Ok(EofBehavior::SetZero),
+        "max" => Ok(EofBehavior::SetMax),
+        "unchanged" => Ok(EofBehavior::LeaveUnchanged),
+        value => Err(format!(
+            "unknown EOF behaviour '{}', expected error, zero, max or unchanged",
+            value
+        )),
+    }
+}
+
+/// Parse a comma-separated list of `operation=cycles` pairs into [CycleCosts]
+fn parse_cycle_costs(value: &str) -> Result<CycleCosts, String> {
+    let mut costs = CycleCosts::default();
+
+    for pair in value.split(',') {
+        let (operation, cycles) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("expected operation=cycles, got '{}'", pair))?;
+        let cycles: u64 = cycles
+            .parse()
+            .map_err(|_| format!("'{}' is not a number of cycles", cycles))?;
+        match operation {
+            "move" => costs.move_head = cycles,
+            "arith" => costs.arithmetic = cycles,
+            "in" => costs.input = cycles,
+            "out" => costs.output = cycles,
+            "jump" => costs.jump = cycles,
+            "ext" => costs.extension = cycles,
+            operation => {
+                return Err(format!(
+                    "unknown operation '{}', expected one of move, arith, in, out, jump, ext",
+                    operation
+                ))
+            }
+        }
+    }
+
+    Ok(costs)
+}
+
+impl Args {
+    /// How the program should be parsed
+    pub fn parse_options(&self) -> ParseOptions {
+        let parse = ParseArgs {
+            includes: self.includes,
+            macros: self.macros,
+            extended: self.extended,
+            mapping: self.mapping.clone(),
+            line_comment: self.line_comment,
+            block_comment: self.block_comment,
+            max_nesting: self.max_nesting,
+        };
+        ParseOptions {
+            assertions: self.assertions,
+            extensions: if self.stderr_channel {
+                vec![STDERR_CHANNEL]
+            } else {
+                Vec::new()
+            },
+            debug_dump: self.debug_dump,
+            ..parse.parse_options()
+        }
+    }
+
+    /// The [CommentSyntax] asked for on the command line
+    pub fn comment_syntax(&self) -> CommentSyntax {
+        CommentSyntax {
+            line: self.line_comment,
+            block: self.block_comment,
+        }
+    }
+
+    /// The run [Limits] asked for on the command line
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_instructions: self.max_instructions,
+            timeout: self.timeout_ms.map(Duration::from_millis),
+            max_output: self.max_output,
+            max_loop_iterations: self.max_loop_iterations,
+        }
+    }
+
+    /// The [VirtualClock] asked for on the command line, if any
+    pub fn virtual_clock(&self) -> Option<VirtualClock> {
+        (self.cycle_costs.is_some() || self.clock_hz.is_some())
+            .then(|| VirtualClock::new(self.cycle_costs.unwrap_or_default(), self.clock_hz))
+    }
+
+    /// The [FlushPolicy] asked for on the command line, or the one that suits where output goes
+    pub fn flush_policy(&self) -> FlushPolicy {
+        match self.flush {
+            Some(flush_policy) => flush_policy,
+            None if self.filter || self.stream_to.is_some() || self.output.is_some() => {
+                FlushPolicy::Manual
+            }
+            None if stdout().is_terminal() => FlushPolicy::Line,
+            None => FlushPolicy::EveryByte,
+        }
+    }
+
+    /// Create a [VirtualMachine] to run the given program, configured as asked for on the command
+    /// line
+    pub fn virtual_machine<'a>(&self, program: &'a BfProgram) -> VirtualMachine<'a, u8> {
+        let mut bf_interpreter = VirtualMachine::new(program, self.cells, self.extensible)
+            .with_limits(self.limits())
+            .with_flush_policy(self.flush_policy())
+            .with_arithmetic(self.arithmetic)
+            .with_eof_behavior(self.eof);
+        if self.bidirectional {
+            bf_interpreter = bf_interpreter.with_bidirectional_tape();
+        }
+        if self.stderr_channel {
+            bf_interpreter = bf_interpreter.with_diagnostic_channel(STDERR_CHANNEL, stderr());
+        }
+        if let Some(max_cells) = self.max_cells {
+            bf_interpreter = bf_interpreter.with_max_cells(max_cells);
+        }
+        if let Some(max_memory) = self.max_memory {
+            bf_interpreter = bf_interpreter.with_memory_budget(MemoryBudget::new(
+                usize::try_from(max_memory).unwrap_or(usize::MAX),
+            ));
+        }
+        if self.circular {
+            bf_interpreter = bf_interpreter.with_circular_tape();
+        }
+        for cells in &self.protect {
+            bf_interpreter = bf_interpreter.with_write_protection(cells.clone());
+        }
+        if let Some(capacity) = self.jump_history {
+            bf_interpreter = bf_interpreter.with_jump_history(capacity);
+        }
+        if let Some(capacity) = self.tape_history {
+            bf_interpreter = bf_interpreter.with_cell_journal(capacity);
+        }
+        // a program loaded from bytecode brings its ops with it, so they cost nothing to use
+        let level = match self.optimize {
+            OptLevel::None => program.stored_opt_level().unwrap_or(OptLevel::None),
+            level => level,
+        };
+        if level != OptLevel::None {
+            bf_interpreter = bf_interpreter.with_optimizations(level);
+        }
+        if self.warm_up || self.pre_grow.is_some() {
+            bf_interpreter.warm_up(self.pre_grow);
+        }
+        bf_interpreter
+    }
+}
+
+/// Arguments for linking library fragments onto a main program
+#[derive(clap::Args, Debug)]
+pub struct LinkArgs {
+    /// Path to the main program. This is placed first in the output.
+    pub main: PathBuf,
+
+    /// Paths to the library fragments, in the order they should be appended. Each must follow the
+    /// cell-0 convention.
+    pub libraries: Vec<PathBuf>,
+
+    /// File to write the linked program to. Written to stdout if not given.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// File to write the provenance map to. Defaults to the output path with `.map` appended, and
+    /// is not written if neither this nor --output is given.
+    #[arg(short, long)]
+    pub map: Option<PathBuf>,
+
+    /// Replace the output and map files if they already exist
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Arguments for inspecting a file
+#[derive(clap::Args, Debug)]
+pub struct InspectArgs {
+    #[command(subcommand)]
+    pub target: InspectTarget,
+}
+
+/// The kinds of file that can be inspected
+#[derive(Subcommand, Debug)]
+pub enum InspectTarget {
+    /// Show the provenance embedded in a compiled artifact: its source, the source's fingerprint,
+    /// and the bft, optimisation and dialect it was compiled with
+    Artifact {
+        /// Path to the artifact
+        path: PathBuf,
+    },
+}
+
+/// Arguments for reading a profile database
+#[derive(clap::Args, Debug)]
+pub struct ProfileArgs {
+    #[command(subcommand)]
+    pub command: ProfileCommand,
+}
+
+/// What to do with a profile database
+#[derive(Subcommand, Debug)]
+pub enum ProfileCommand {
+    /// Show how the runs of the current version of a program have changed, its hottest loops,
+    /// and the other versions of it that have been run
+    Report {
+        /// Path to the program
+        program: PathBuf,
+
+        /// The profile database the runs were recorded in
+        #[arg(long, value_name = "FILE")]
+        db: PathBuf,
+    },
+}
+
+/// Arguments for running programs as tests
+#[derive(clap::Args, Debug)]
+pub struct TestArgs {
+    /// Paths to the programs to test
+    #[arg(required = true)]
+    pub programs: Vec<PathBuf>,
+
+    /// Stop each program after it has run for this many milliseconds
+    #[arg(long, default_value_t = 10_000)]
+    pub timeout_ms: u64,
+}
+
+/// Arguments for checking a program
+#[derive(clap::Args, Debug)]
+pub struct CheckArgs {
+    /// Path to the program to check
+    pub program: PathBuf,
+
+    /// Carry on past each problem, assuming the most likely fix, and report every problem found
+    #[arg(long)]
+    pub recover: bool,
+
+    /// Check `@assert` directives too
+    #[arg(long)]
+    pub assertions: bool,
+
+    /// Treat the rest of the line after this character as a comment, even if it contains
+    /// instructions, e.g. --line-comment ';'
+    #[arg(long, value_name = "CHAR")]
+    pub line_comment: Option<char>,
+
+    /// Treat everything between these two characters as a comment, even across lines,
+    /// e.g. --block-comment '{}'
+    #[arg(long, value_name = "OPEN_CLOSE", value_parser = parse_block_comment)]
+    pub block_comment: Option<(char, char)>,
+}
+
+/// Arguments for looking for shorter equivalents in a program
+#[derive(clap::Args, Debug)]
+pub struct GolfArgs {
+    /// Path to the program to golf
+    pub program: PathBuf,
+
+    /// The language the program is written in (bf, ook or bytecode). Worked out from the file
+    /// extension if not given.
+    #[arg(long, visible_alias = "dialect", value_parser = parse_lang)]
+    pub lang: Option<Frontend>,
+}
+
+/// Arguments for writing out a program's control-flow graph
+#[derive(clap::Args, Debug)]
+pub struct CfgArgs {
+    /// Path to the program to draw
+    pub program: PathBuf,
+
+    /// File to write the graph to. Written to stdout if not given.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Replace the output file if it already exists
+    #[arg(long)]
+    pub force: bool,
+
+    /// The language the program is written in (bf, ook or bytecode). Worked out from the file
+    /// extension if not given.
+    #[arg(long, visible_alias = "dialect", value_parser = parse_lang)]
+    pub lang: Option<Frontend>,
+}
+
+/// Arguments for how a program is parsed, for subcommands that parse one as `bft run` does
+#[derive(clap::Args, Debug)]
+pub struct ParseArgs {
+    /// Replace `#include "file"` lines with the program in that file, found relative to the file
+    /// the line is in
+    #[arg(long)]
+    pub includes: bool,
+
+    /// Read `#define NAME body` lines as macros, replacing NAME later in the file with the
+    /// instructions in body
+    #[arg(long)]
+    pub macros: bool,
+
+    /// Parse the program as Extended Brainfuck Type I, adding `@` (end), `$` (store), `!` (load),
+    /// `}` and `{` (shift right and left), `~` (not), and `^`, `&` and `|` (xor, and, or with the
+    /// stored byte)
+    #[arg(long)]
+    pub extended: bool,
+
+    /// Parse the program as a trivial substitution dialect, spelling each instruction as given in
+    /// this mapping file, e.g. `increment = "Ook. Ook."`. See bft_types::mapping for the format.
+    #[arg(long, value_name = "FILE", value_parser = parse_mapping)]
+    pub mapping: Option<Mapping>,
+
+    /// Treat the rest of the line after this character as a comment, even if it contains
+    /// instructions, e.g. --line-comment ';'
+    #[arg(long, value_name = "CHAR")]
+    pub line_comment: Option<char>,
+
+    /// Treat everything between these two characters as a comment, even across lines,
+    /// e.g. --block-comment '{}'
+    #[arg(long, value_name = "OPEN_CLOSE", value_parser = parse_block_comment)]
+    pub block_comment: Option<(char, char)>,
+
+    /// Refuse programs with more than this many loops open at once
+    #[arg(long)]
+    pub max_nesting: Option<usize>,
+}
+
+impl ParseArgs {
+    /// How the program should be parsed, leaving the options for running it unset
+    pub fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            max_nesting: self.max_nesting,
+            extended: self.extended,
+            mapping: self.mapping.clone(),
+            comments: CommentSyntax {
+                line: self.line_comment,
+                block: self.block_comment,
+            },
+            includes: self.includes,
+            macros: self.macros,
+            ..ParseOptions::default()
+        }
+    }
+}
+
+/// Arguments for compiling a program ahead of time
+#[derive(clap::Args, Debug)]
+pub struct CompileArgs {
+    /// Path to the program to compile
+    pub program: PathBuf,
+
+    /// What to compile the program to: c, rust, wasm, llvm (an object file) or bytecode
+    #[arg(long, value_parser = parse_target, default_value = "c")]
+    pub target: Target,
+
+    /// File to write the compiled program to. Written to stdout if not given.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Replace the output file if it already exists
+    #[arg(long)]
+    pub force: bool,
+
+    /// The language the program is written in (bf, ook or bytecode). Worked out from the file
+    /// extension if not given.
+    #[arg(long, visible_alias = "dialect", value_parser = parse_lang)]
+    pub lang: Option<Frontend>,
+
+    /// How the program is parsed: its dialect, directives and comments
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    /// Initial size of the tape
+    #[arg(short, long)]
+    pub cells: Option<NonZeroUsize>,
+
+    /// Extend the end of the tape as the head reaches it
+    #[arg(short, long)]
+    pub extensible: bool,
+
+    /// Join the ends of the tape, so the head wraps round from the last cell to the first
+    #[arg(long, conflicts_with = "extensible")]
+    pub circular: bool,
+
+    /// Width of each cell in bits: 8, 16 or 32
+    #[arg(long, default_value = "8")]
+    pub cell_bits: u32,
+
+    /// What `,` does once input has run out: error, zero, max or unchanged
+    #[arg(long, value_parser = parse_eof_behavior, default_value = "error")]
+    pub eof: EofBehavior,
+
+    /// Optimise the program as it is compiled, as with `bft run --optimize`
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = parse_opt_level,
+        num_args = 0..=1,
+        require_equals = true,
+        default_value = "0",
+        default_missing_value = "2"
+    )]
+    pub optimize: OptLevel,
+}
+
+impl CompileArgs {
+    /// The semantics asked for on the command line
+    pub fn compile_options(&self) -> CompileOptions {
+        let defaults = CompileOptions::default();
+        CompileOptions {
+            cell_bits: self.cell_bits,
+            cells: self.cells.map_or(defaults.cells, |cells| cells.get()),
+            tape: if self.extensible {
+                Tape::Extensible
+            } else if self.circular {
+                Tape::Circular
+            } else {
+                Tape::Fixed
+            },
+            eof_behavior: self.eof,
+            opt_level: self.optimize,
+        }
+    }
+}
+
+/// Parse the name of a compile [Target]
+fn parse_target(value: &str) -> Result<Target, String> {
+    Target::ALL
+        .into_iter()
+        .find(|target| target.name() == value)
+        .ok_or_else(|| {
+            format!(
+                "unknown target '{}', expected {}",
+                value,
+                Target::ALL.map(|target| target.name()).join(", ")
+            )
+        })
+}
+
+/// Arguments for running one program over many inputs
+#[derive(clap::Args, Debug)]
+pub struct MapArgs {
+    /// Path to the program to run
+    pub program: PathBuf,
+
+    /// Paths to the input files. The program is run once for each.
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Directory to write `<input name>.out` (and `<input name>.err` for failed runs) into
+    #[arg(long)]
+    pub out_dir: PathBuf,
+
+    /// The language the program is written in (bf, ook or bytecode). Worked out from the file
+    /// extension if not given.
+    #[arg(long, visible_alias = "dialect", value_parser = parse_lang)]
+    pub lang: Option<Frontend>,
+
+    /// Initial size of the VM's tape.
+    #[arg(short, long)]
+    pub cells: Option<NonZeroUsize>,
+
+    /// Controls whether the end of tape will be extended automatically
+    #[arg(short, long)]
+    pub extensible: bool,
+
+    /// Stop each run after this many instructions have been executed
+    #[arg(long)]
+    pub max_instructions: Option<u64>,
+
+    /// Stop each run after it has run for this many milliseconds
+    #[arg(long)]
+    pub timeout_ms: Option<u64>,
+
+    /// Replace output files that already exist
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Arguments for serving a program on a Unix socket
+#[derive(clap::Args, Debug)]
+pub struct DaemonArgs {
+    /// Path to the program to serve. It is reloaded whenever the file changes, if it is valid.
+    #[arg(long)]
+    pub program: PathBuf,
+
+    /// Path of the Unix socket to listen on
+    #[arg(long)]
+    pub socket: PathBuf,
+
+    /// The language the program is written in (bf, ook or bytecode). Worked out from the file
+    /// extension if not given.
+    #[arg(long, visible_alias = "dialect", value_parser = parse_lang)]
+    pub lang: Option<Frontend>,
+
+    /// Initial size of the VM's tape.
+    #[arg(short, long)]
+    pub cells: Option<NonZeroUsize>,
+
+    /// Controls whether the end of tape will be extended automatically
+    #[arg(short, long)]
+    pub extensible: bool,
+
+    /// What `,` does once a client's input has run out: zero (the default), max, unchanged or
+    /// error
+    #[arg(long, value_parser = parse_eof_behavior, default_value = "zero")]
+    pub eof: EofBehavior,
+
+    /// Stop each run after this many instructions have been executed
+    #[arg(long)]
+    pub max_instructions: Option<u64>,
+
+    /// Stop each run after it has run for this many milliseconds. Runs are limited to 10 seconds
+    /// unless this is given.
+    #[arg(long)]
+    pub timeout_ms: Option<u64>,
+
+    /// Stop each run once it has written this many bytes. Runs are limited to 16MiB of output
+    /// unless this is given.
+    #[arg(long)]
+    pub max_output: Option<u64>,
+
+    /// Refuse clients that send more than this many bytes of input. Input is limited to 16MiB
+    /// unless this is given.
+    #[arg(long)]
+    pub max_input: Option<u64>,
+
+    /// Serve at most this many clients at once, each with a machine kept ready for it and reset
+    /// between clients. Others wait until one of them has been served.
+    #[arg(long, default_value = "16")]
+    pub max_clients: NonZeroUsize,
+
+    /// How long the runs in progress may carry on after SIGTERM before they are cancelled. No new
+    /// clients are accepted once SIGTERM arrives.
+    #[arg(long)]
+    pub grace_ms: Option<u64>,
+
+    /// How often to check whether the program file has changed, in milliseconds
+    #[arg(long, default_value_t = 500)]
+    pub reload_ms: u64,
+
+    /// Write the size of the pool of machines and how often they were reused to this file in the
+    /// Prometheus text format, each time the program file is checked
+    #[arg(long)]
+    pub metrics_file: Option<PathBuf>,
+}
+
+/// Arguments for generating a file to embed with `include_bytes!`
+#[derive(clap::Args, Debug)]
+pub struct GenerateIncludeArgs {
+    /// Path to the program to run
     pub program: PathBuf,
 
+    /// File to write the program's output to
+    #[arg(short, long)]
+    pub out: PathBuf,
+
+    /// File to give the program as input. It gets no input if this is not given.
+    #[arg(long)]
+    pub input: Option<PathBuf>,
+
+    /// Fail if the program runs for longer than this many milliseconds
+    #[arg(long, default_value_t = 60_000)]
+    pub timeout_ms: u64,
+
+    /// Fail if the program executes more than this many instructions
+    #[arg(long)]
+    pub max_instructions: Option<u64>,
+
+    /// Replace the output file if it already exists
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Arguments for the self-test suite: the options that change how programs behave
+#[derive(clap::Args, Debug, Default)]
+pub struct SelftestArgs {
     /// Initial size of the VM's tape.
     #[arg(short, long)]
     pub cells: Option<NonZeroUsize>,
@@ -18,4 +1108,100 @@ pub struct Args {
     /// Controls whether the end of tape will be extended automatically
     #[arg(short, long)]
     pub extensible: bool,
+
+    /// Extend the start of the tape too, so the head can move left of cell 0
+    #[arg(long)]
+    pub bidirectional: bool,
+
+    /// Join the ends of the tape, so the head wraps round from the last cell to the first
+    #[arg(long, conflicts_with_all = ["extensible", "bidirectional"])]
+    pub circular: bool,
+
+    /// What `+` and `-` do at the ends of a cell's range: wrapping, saturating or checked
+    #[arg(long, value_parser = parse_arithmetic, default_value = "wrapping")]
+    pub arithmetic: Arithmetic,
+
+    /// What `,` does once input has run out: error, zero, max or unchanged
+    #[arg(long, value_parser = parse_eof_behavior, default_value = "error")]
+    pub eof: EofBehavior,
+}
+
+impl SelftestArgs {
+    /// Create a [VirtualMachine] to run one of the suite's programs, configured as asked for on
+    /// the command line
+    pub fn virtual_machine<'a>(&self, program: &'a BfProgram) -> VirtualMachine<'a, u8> {
+        let mut bf_interpreter = VirtualMachine::new(program, self.cells, self.extensible)
+            .with_arithmetic(self.arithmetic)
+            .with_eof_behavior(self.eof);
+        if self.bidirectional {
+            bf_interpreter = bf_interpreter.with_bidirectional_tape();
+        }
+        if self.circular {
+            bf_interpreter = bf_interpreter.with_circular_tape();
+        }
+        bf_interpreter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cell_range() {
+        assert_eq!(parse_cell_range("0..16"), Ok(0..16));
+        assert_eq!(parse_cell_range("4..=7"), Ok(4..8));
+        assert!(parse_cell_range("5..5").is_err());
+        assert!(parse_cell_range("5").is_err());
+        assert!(parse_cell_range("a..b").is_err());
+        assert!(parse_cell_range(&format!("0..={}", usize::MAX)).is_err());
+    }
+
+    #[test]
+    fn test_parse_cycle_costs() {
+        assert_eq!(
+            parse_cycle_costs("in=20,jump=2"),
+            Ok(CycleCosts {
+                input: 20,
+                jump: 2,
+                ..CycleCosts::default()
+            })
+        );
+        assert!(parse_cycle_costs("teleport=3").is_err());
+        assert!(parse_cycle_costs("move").is_err());
+        assert!(parse_cycle_costs("move=fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_cell_assertion() {
+        assert_eq!(parse_cell_assertion("3=72"), Ok((3, 72)));
+        assert!(parse_cell_assertion("3=256").is_err());
+        assert!(parse_cell_assertion("3").is_err());
+        assert!(parse_cell_assertion("head=3").is_err());
+    }
+
+    #[test]
+    fn test_parse_flush_policy() {
+        assert_eq!(parse_flush_policy("line"), Ok(FlushPolicy::Line));
+        assert_eq!(parse_flush_policy("every-byte"), Ok(FlushPolicy::EveryByte));
+        assert!(parse_flush_policy("sometimes").is_err());
+    }
+
+    // Does `bft compile` take the same parse arguments as `bft run`?
+    #[test]
+    fn test_compile_parse_options() {
+        let arguments = ["--macros", "--extended", "--line-comment", ";", "prog.bf"];
+        let cli = Cli::parse_from(["bft", "compile"].into_iter().chain(arguments));
+        let Some(Command::Compile(compile)) = cli.command else {
+            panic!("expected the compile subcommand");
+        };
+        let run = Cli::parse_from(["bft"].into_iter().chain(arguments))
+            .run
+            .unwrap();
+
+        let options = compile.parse.parse_options();
+        assert!(options.macros && options.extended && !options.includes);
+        assert_eq!(options.comments.line, Some(';'));
+        assert_eq!(options, run.parse_options());
+    }
 }
@@ -0,0 +1,163 @@
+//! Compiling programs ahead of time, to run without bft.
+//!
+//! Each target takes the program as lowered by [BfProgram::optimize] and writes it out with the
+//! tape, cells and end-of-input behaviour given in [CompileOptions], so that the compiled program
+//! behaves as `bft run` would with the same options. Every artifact starts with a [Provenance]
+//! block, so that `bft inspect artifact` can trace it back to its source.
+//!
+//! - [c]: a self-contained C program
+//! - [rust]: Rust source, to build on its own or include in a crate
+//! - [wasm]: a WebAssembly module, which imports its input and output from the host
+//! - llvm: a native object file, optimised by LLVM, in builds with the `llvm` feature
+//! - bytecode: the program already parsed, analysed and optimised, for `bft run-bytecode` (see
+//!   [bft_types::bytecode]). Only the optimisation level applies; the rest of [CompileOptions] is
+//!   given when it is run.
+
+use std::fmt::Display;
+
+use bft_interp::EofBehavior;
+use bft_types::optimize::OptLevel;
+use bft_types::{bytecode, BfProgram, Instruction};
+
+use crate::provenance::Provenance;
+
+pub mod c;
+#[cfg(feature = "llvm")]
+pub mod llvm;
+pub mod rust;
+pub mod wasm;
+
+/// What a program can be compiled to
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Target {
+    /// C source, see [c]
+    #[default]
+    C,
+    /// Rust source, see [rust]
+    Rust,
+    /// A WebAssembly module, see [wasm]
+    Wasm,
+    /// A native object file built with LLVM, in builds with the `llvm` feature
+    Llvm,
+    /// bft's own bytecode, see [bft_types::bytecode]
+    Bytecode,
+}
+
+impl Target {
+    /// Every target, in the order they are listed in help
+    pub const ALL: [Target; 5] = [
+        Target::C,
+        Target::Rust,
+        Target::Wasm,
+        Target::Llvm,
+        Target::Bytecode,
+    ];
+
+    /// The name given to `--target`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::C => "c",
+            Target::Rust => "rust",
+            Target::Wasm => "wasm",
+            Target::Llvm => "llvm",
+            Target::Bytecode => "bytecode",
+        }
+    }
+
+    /// Compile `program` for this target
+    pub fn compile(
+        &self,
+        program: &BfProgram,
+        options: &CompileOptions,
+        provenance: &Provenance,
+    ) -> Result<Vec<u8>, String> {
+        match self {
+            Target::C => c::compile(program, options, provenance).map(String::into_bytes),
+            Target::Rust => rust::compile(program, options, provenance).map(String::into_bytes),
+            Target::Wasm => wasm::compile(program, options, provenance),
+            #[cfg(feature = "llvm")]
+            Target::Llvm => llvm::compile(program, options, provenance),
+            #[cfg(not(feature = "llvm"))]
+            Target::Llvm => {
+                Err("this build of bft cannot compile to llvm (the `llvm` feature)".to_string())
+            }
+            // the header on a line of its own, for Provenance::find
+            Target::Bytecode => Ok(bytecode::encode(
+                program,
+                options.opt_level,
+                &format!("\n{}", provenance.header("#")),
+            )),
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What the head does at the ends of the tape
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Tape {
+    /// Moving off either end is an error
+    #[default]
+    Fixed,
+    /// The tape grows to the right as the head reaches its end. Moving left of cell 0 is an error.
+    Extensible,
+    /// The ends are joined, so the head wraps round from the last cell to the first
+    Circular,
+}
+
+/// The semantics a compiled program has
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CompileOptions {
+    /// Width of a cell in bits: 8, 16 or 32. Cells wrap round at the ends of their range.
+    pub cell_bits: u32,
+    /// Number of cells on the tape to start with
+    pub cells: usize,
+    /// What the head does at the ends of the tape
+    pub tape: Tape,
+    /// What `,` does once input has run out
+    pub eof_behavior: EofBehavior,
+    /// How hard to optimise the program first
+    pub opt_level: OptLevel,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            cell_bits: 8,
+            cells: 30_000,
+            tape: Tape::default(),
+            eof_behavior: EofBehavior::default(),
+            opt_level: OptLevel::default(),
+        }
+    }
+}
+
+/// The error for an instruction that a target has no way to compile, such as an extension
+/// instruction, whose meaning is up to whoever runs the program
+pub(crate) fn unsupported(
+    program: &BfProgram,
+    program_index: usize,
+    target: impl Display,
+) -> String {
+    let instruction = program.localised_instructions()[program_index];
+    format!(
+        "'{}' at line {}, column {} cannot be compiled to {}",
+        instruction.instruction().to_char(),
+        instruction.line_num(),
+        instruction.column_num(),
+        target
+    )
+}
+
+/// Whether an [Instruction] that [bft_types::optimize::Op::Other] carries can be compiled: the
+/// instructions of Extended Brainfuck Type I can, but extensions and `#` cannot
+pub(crate) fn is_supported(instruction: Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::Extension(_) | Instruction::DebugDump
+    )
+}
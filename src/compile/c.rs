@@ -0,0 +1,318 @@
+//! Compiling programs to a self-contained C program, which needs only the C standard library.
+//!
+//! Each loop becomes a `while` loop over the cell under the head, and the tape is an array of
+//! `uint8_t`, `uint16_t` or `uint32_t` cells, grown with `realloc` on an extensible tape. Moving
+//! off the end of a fixed tape, or left of cell 0 on any tape but a circular one, stops the
+//! program with an error on stderr and exit status 1, as does reading past the end of input with
+//! [EofBehavior::Error].
+
+use std::fmt::Write;
+
+use bft_interp::EofBehavior;
+use bft_types::optimize::Op;
+use bft_types::{BfProgram, Instruction};
+
+use super::{is_supported, unsupported, CompileOptions, Tape, Target};
+use crate::provenance::Provenance;
+
+/// Everything before the program itself, once the cell type and tape length are filled in
+const PRELUDE: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef CELL cell;
+
+static cell *tape;
+static size_t len = CELLS;
+static size_t head = 0;
+/* the storage byte of Extended Brainfuck Type I */
+static unsigned char storage = 0;
+
+static void fail(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "Error: %s\n", message);
+    exit(1);
+}
+
+/* make room for the head on an extensible tape, doubling its length as often as it takes */
+static void grow(void) {
+    size_t old = len;
+    while (head >= len) {
+        len *= 2;
+    }
+    tape = realloc(tape, len * sizeof(cell));
+    if (!tape) {
+        fail("out of memory");
+    }
+    memset(tape + old, 0, (len - old) * sizeof(cell));
+}
+
+int main(void) {
+    tape = calloc(len, sizeof(cell));
+    if (!tape) {
+        fail("out of memory");
+    }
+"#;
+
+/// Compile `program` to the source of a C program that runs it with the given options
+///
+/// ```
+///# use bft::compile::{c, CompileOptions};
+///# use bft::provenance::Provenance;
+///# use bft_types::BfProgram;
+///# use std::path::Path;
+/// let program = BfProgram::new("hello.bf", "+++[>++<-]>.")?;
+/// let provenance = Provenance::new(Path::new("hello.bf"), &program, "bf", "none");
+///
+/// let source = c::compile(&program, &CompileOptions::default(), &provenance)?;
+/// assert!(source.starts_with("// bft-provenance 1\n"));
+/// assert!(source.contains("    while (tape[head]) {\n"));
+///# Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn compile(
+    program: &BfProgram,
+    options: &CompileOptions,
+    provenance: &Provenance,
+) -> Result<String, String> {
+    let cell = match options.cell_bits {
+        8 => "uint8_t",
+        16 => "uint16_t",
+        32 => "uint32_t",
+        bits => return Err(format!("cells must be 8, 16 or 32 bits wide, not {}", bits)),
+    };
+
+    let mut source = provenance.header("//");
+    source.push('\n');
+    source.push_str(
+        &PRELUDE
+            .replace("CELLS", &options.cells.to_string())
+            .replace("CELL", cell),
+    );
+
+    let optimized = program.optimize(options.opt_level);
+    let mut depth = 1;
+    for (index, op) in optimized.ops().iter().enumerate() {
+        // the op's statements, indented from the op's own level
+        let mut code = Vec::new();
+        match *op {
+            Op::Add(amount) if amount >= 0 => code.push(format!("tape[head] += {};", amount)),
+            Op::Add(amount) => code.push(format!("tape[head] -= {};", amount.unsigned_abs())),
+            Op::Move(distance) => move_head(&mut code, distance, options),
+            Op::SetZero { .. } => code.push("tape[head] = 0;".to_string()),
+            Op::Scan { right } => {
+                let mut step = Vec::new();
+                move_head(&mut step, if right { 1 } else { -1 }, options);
+                code.push("while (tape[head]) {".to_string());
+                code.extend(step.iter().map(|statement| format!("    {}", statement)));
+                code.push("}".to_string());
+            }
+            Op::JumpIfZero(_) => code.push("while (tape[head]) {".to_string()),
+            Op::JumpUnlessZero(_) => {
+                depth -= 1;
+                code.push("}".to_string());
+            }
+            Op::Input => {
+                code.push("fflush(stdout);".to_string());
+                code.push("{".to_string());
+                code.push("    int c = getchar();".to_string());
+                code.push("    if (c != EOF) {".to_string());
+                code.push("        tape[head] = (cell)c;".to_string());
+                let at_eof = match options.eof_behavior {
+                    EofBehavior::Error => Some("fail(\"read past the end of input\");"),
+                    EofBehavior::SetZero => Some("tape[head] = 0;"),
+                    EofBehavior::SetMax => Some("tape[head] = (cell)-1;"),
+                    EofBehavior::LeaveUnchanged => None,
+                };
+                if let Some(at_eof) = at_eof {
+                    code.push("    } else {".to_string());
+                    code.push(format!("        {}", at_eof));
+                }
+                code.push("    }".to_string());
+                code.push("}".to_string());
+            }
+            Op::Output => code.push("putchar((unsigned char)tape[head]);".to_string()),
+            Op::Other(instruction) if !is_supported(instruction) => {
+                return Err(unsupported(
+                    program,
+                    optimized.instructions_of(index).start,
+                    Target::C,
+                ));
+            }
+            Op::Other(instruction) => code.push(extended(instruction).to_string()),
+        }
+        for statement in code {
+            // writing to a String cannot fail
+            let _ = writeln!(source, "{}{}", "    ".repeat(depth), statement);
+        }
+        if let Op::JumpIfZero(_) = op {
+            depth += 1;
+        }
+    }
+    source.push_str("    return 0;\n}\n");
+    Ok(source)
+}
+
+/// Add the statements that move the head `distance` cells to `code`
+fn move_head(code: &mut Vec<String>, distance: isize, options: &CompileOptions) {
+    let steps = distance.unsigned_abs();
+    match (options.tape, distance >= 0) {
+        (Tape::Circular, right) => {
+            // the tape never changes length, so the wrap can be worked out now
+            let steps = steps % options.cells;
+            if steps > 0 {
+                let step = if right { steps } else { options.cells - steps };
+                code.push(format!("head = (head + {}) % len;", step));
+            }
+        }
+        (Tape::Fixed, true) => {
+            code.push(format!(
+                "if ({} >= len - head) fail(\"head ran off the end of the tape\");",
+                steps
+            ));
+            code.push(format!("head += {};", steps));
+        }
+        (Tape::Extensible, true) => {
+            code.push(format!("head += {};", steps));
+            code.push("if (head >= len) grow();".to_string());
+        }
+        (Tape::Fixed | Tape::Extensible, false) => {
+            code.push(format!(
+                "if (head < {}) fail(\"head ran off the start of the tape\");",
+                steps
+            ));
+            code.push(format!("head -= {};", steps));
+        }
+    }
+}
+
+/// The statement for an instruction of Extended Brainfuck Type I
+fn extended(instruction: Instruction) -> &'static str {
+    match instruction {
+        Instruction::End => "return 0;",
+        Instruction::Store => "storage = (unsigned char)tape[head];",
+        Instruction::Load => "tape[head] = storage;",
+        Instruction::ShiftRight => "tape[head] >>= 1;",
+        Instruction::ShiftLeft => "tape[head] = (cell)(tape[head] << 1);",
+        Instruction::Not => "tape[head] = (cell)~tape[head];",
+        Instruction::Xor => "tape[head] ^= storage;",
+        Instruction::And => "tape[head] &= storage;",
+        Instruction::Or => "tape[head] |= storage;",
+        // every other instruction has an op of its own, or is refused by is_supported
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    use bft_types::optimize::OptLevel;
+
+    // Does the compiled program behave as the interpreter does, on each kind of tape? Skipped
+    // where there is no C compiler.
+    #[test]
+    fn test_compiled_programs_run() {
+        if Command::new("cc").arg("--version").output().is_err() {
+            return;
+        }
+        let directory = std::env::temp_dir().join(format!("bft-c-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let run = |text: &str, options: CompileOptions, input: &[u8]| {
+            let program = BfProgram::new("test.bf", text).unwrap();
+            let provenance = Provenance::new(Path::new("test.bf"), &program, "bf", "none");
+            let source = compile(&program, &options, &provenance).unwrap();
+            fs::write(directory.join("test.c"), source).unwrap();
+            let status = Command::new("cc")
+                .arg("-o")
+                .arg(directory.join("test"))
+                .arg(directory.join("test.c"))
+                .status()
+                .unwrap();
+            assert!(status.success(), "compiling {}", text);
+            let mut child = Command::new(directory.join("test"))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(input).unwrap();
+            let output = child.wait_with_output().unwrap();
+            (output.status.success(), output.stdout)
+        };
+
+        // echo the input back with each byte one higher, until the end of input gives 0
+        let echo = ",[+.,]";
+        let eof_zero = CompileOptions {
+            eof_behavior: EofBehavior::SetZero,
+            ..CompileOptions::default()
+        };
+        assert_eq!(run(echo, eof_zero, b"HAL"), (true, b"IBM".to_vec()));
+        assert!(!run(echo, CompileOptions::default(), b"HAL").0);
+
+        let full = CompileOptions {
+            opt_level: OptLevel::Full,
+            ..CompileOptions::default()
+        };
+        assert_eq!(
+            run("++++++++[>++++++++<-]>+.[-]>>+<<[>]>.", full, b""),
+            (true, b"A\x00".to_vec())
+        );
+
+        // a cell wraps at its width, and only its low byte is written
+        let wide = CompileOptions {
+            cell_bits: 16,
+            ..CompileOptions::default()
+        };
+        assert_eq!(run("-.", wide, b""), (true, b"\xff".to_vec()));
+        let overflows = "++++++++++++++++[>++++++++++++++++<-]>[[-]>+<]>.";
+        assert_eq!(run(overflows, wide, b""), (true, b"\x01".to_vec()));
+        assert_eq!(
+            run(overflows, CompileOptions::default(), b""),
+            (true, b"\x00".to_vec())
+        );
+
+        let tape = |tape, cells| CompileOptions {
+            tape,
+            cells,
+            ..CompileOptions::default()
+        };
+        assert!(!run(">>>+.", tape(Tape::Fixed, 3), b"").0);
+        assert!(!run("<", tape(Tape::Extensible, 3), b"").0);
+        assert_eq!(
+            run(">>>>>>>+.", tape(Tape::Extensible, 3), b""),
+            (true, b"\x01".to_vec())
+        );
+        assert_eq!(
+            run("+<<<.>+++.", tape(Tape::Circular, 3), b""),
+            (true, b"\x01\x03".to_vec())
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    // Are instructions whose meaning is not known refused, and odd cell widths?
+    #[test]
+    fn test_unsupported() {
+        let options = bft_types::ParseOptions {
+            extensions: vec!['*'],
+            ..bft_types::ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("test.bf", "+\n +*", &options).unwrap();
+        let provenance = Provenance::new(Path::new("test.bf"), &program, "bf", "none");
+
+        assert_eq!(
+            compile(&program, &CompileOptions::default(), &provenance),
+            Err("'*' at line 2, column 3 cannot be compiled to c".to_string())
+        );
+        let odd = CompileOptions {
+            cell_bits: 12,
+            ..CompileOptions::default()
+        };
+        assert!(compile(&program, &odd, &provenance).is_err());
+    }
+}
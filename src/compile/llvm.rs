@@ -0,0 +1,691 @@
+//! Compiling programs to a native object file with LLVM, for long-running programs that need the
+//! fastest code bft can give them.
+//!
+//! The program is written out as LLVM IR, much as [super::c] writes C, then optimised with LLVM's
+//! `opt -O3` and turned into an object file for this machine with `llc`, both found on the `PATH`.
+//! The object file defines `main` and needs only the C standard library, so it links with
+//! `cc program.o -o program`. It behaves as the C target's program does: each loop is a loop over
+//! the cell under the head, and moving off the tape or reading past the end of input with
+//! [EofBehavior::Error] stops the program with an error on stderr and exit status 1. The IR calls
+//! the C library with 64-bit sizes, so only 64-bit machines are supported.
+
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+
+use bft_interp::EofBehavior;
+use bft_types::optimize::Op;
+use bft_types::{BfProgram, Instruction};
+
+use super::{is_supported, unsupported, CompileOptions, Tape, Target};
+use crate::provenance::Provenance;
+
+/// The errors the program can stop with, by the name of the global that holds each
+const MESSAGES: [(&str, &str); 4] = [
+    ("off_end", "head ran off the end of the tape"),
+    ("off_start", "head ran off the start of the tape"),
+    ("past_end_of_input", "read past the end of input"),
+    ("out_of_memory", "out of memory"),
+];
+
+/// Everything before `main` but the messages and provenance, once the cell type and size are
+/// filled in
+const PRELUDE: &str = r#"declare i8* @calloc(i64, i64)
+declare i8* @realloc(i8*, i64)
+declare i8* @memset(i8*, i32, i64)
+declare i32 @getchar()
+declare i32 @putchar(i32)
+declare i32 @fflush(i8*)
+declare i64 @write(i32, i8*, i64)
+declare void @exit(i32) noreturn
+
+define internal void @bft_fail(i8* %message, i64 %len) noreturn cold {
+  %flushed = call i32 @fflush(i8* null)
+  %written = call i64 @write(i32 2, i8* %message, i64 %len)
+  call void @exit(i32 1)
+  unreachable
+}
+
+; make room for the head on an extensible tape, doubling its length as often as it takes
+define internal { CELL*, i64 } @bft_grow(CELL* %tape, i64 %len, i64 %head) cold {
+entry:
+  br label %double
+double:
+  %old = phi i64 [ %len, %entry ], [ %new, %double ]
+  %new = shl i64 %old, 1
+  %short = icmp uge i64 %head, %new
+  br i1 %short, label %double, label %resize
+resize:
+  %bytes = mul i64 %new, SIZE
+  %old_bytes = bitcast CELL* %tape to i8*
+  %grown = call i8* @realloc(i8* %old_bytes, i64 %bytes)
+  %failed = icmp eq i8* %grown, null
+  br i1 %failed, label %out_of_memory, label %clear
+out_of_memory:
+  call void @bft_fail(OUT_OF_MEMORY)
+  unreachable
+clear:
+  %kept = mul i64 %len, SIZE
+  %added = getelementptr inbounds i8, i8* %grown, i64 %kept
+  %added_bytes = sub i64 %bytes, %kept
+  %cleared = call i8* @memset(i8* %added, i32 0, i64 %added_bytes)
+  %cells = bitcast i8* %grown to CELL*
+  %with_tape = insertvalue { CELL*, i64 } undef, CELL* %cells, 0
+  %grew = insertvalue { CELL*, i64 } %with_tape, i64 %new, 1
+  ret { CELL*, i64 } %grew
+}
+
+define i32 @main() {
+entry:
+  %head = alloca i64
+  %tape = alloca CELL*
+  %len = alloca i64
+  %storage = alloca i8
+  store i64 0, i64* %head
+  store i64 CELLS, i64* %len
+  store i8 0, i8* %storage
+  %allocated = call i8* @calloc(i64 CELLS, i64 SIZE)
+  %cells = bitcast i8* %allocated to CELL*
+  store CELL* %cells, CELL** %tape
+  %no_tape = icmp eq i8* %allocated, null
+  br i1 %no_tape, label %no_tape_fail, label %start
+no_tape_fail:
+  call void @bft_fail(OUT_OF_MEMORY)
+  unreachable
+start:
+"#;
+
+/// Compile `program` to an object file that runs it with the given options
+pub fn compile(
+    program: &BfProgram,
+    options: &CompileOptions,
+    provenance: &Provenance,
+) -> Result<Vec<u8>, String> {
+    let mut ir = ir(program, options, provenance)?;
+    // LLVM 17 and later read only the opaque `ptr` type, which LLVM 14 and before handle badly
+    if llvm_version()? >= 15 {
+        ir = opaque_pointers(&ir);
+    }
+    let bitcode = run_tool("opt", &["-O3"], ir.as_bytes())?;
+    run_tool(
+        "llc",
+        &["-O3", "-filetype=obj", "-relocation-model=pic"],
+        &bitcode,
+    )
+}
+
+/// Write `program` as LLVM IR that runs it with the given options, with the typed pointers that
+/// LLVM up to version 16 reads
+///
+/// ```
+///# use bft::compile::{llvm, CompileOptions};
+///# use bft::provenance::Provenance;
+///# use bft_types::BfProgram;
+///# use std::path::Path;
+/// let program = BfProgram::new("hello.bf", "+++[>++<-]>.")?;
+/// let provenance = Provenance::new(Path::new("hello.bf"), &program, "bf", "none");
+///
+/// let ir = llvm::ir(&program, &CompileOptions::default(), &provenance)?;
+/// assert!(ir.contains("define i32 @main() {\n"));
+/// assert!(ir.contains(", label %body3, label %done3\n"));
+///# Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn ir(
+    program: &BfProgram,
+    options: &CompileOptions,
+    provenance: &Provenance,
+) -> Result<String, String> {
+    let cell = match options.cell_bits {
+        8 | 16 | 32 => format!("i{}", options.cell_bits),
+        bits => return Err(format!("cells must be 8, 16 or 32 bits wide, not {}", bits)),
+    };
+
+    let mut ir = String::new();
+    for (name, message) in MESSAGES {
+        let text = format!("Error: {}\n", message);
+        let _ = writeln!(ir, "@{} = private constant {}", name, constant(&text));
+    }
+    // on a line of its own, for Provenance::find, and kept in the object file by llvm.used
+    let header = format!("\n{}", provenance.header("#"));
+    let _ = writeln!(ir, "@provenance = private constant {}", constant(&header));
+    let _ = writeln!(
+        ir,
+        "@llvm.used = appending global [1 x i8*] [i8* {}], section \"llvm.metadata\"\n",
+        array_pointer("provenance", header.len())
+    );
+    let out_of_memory = format!(
+        "{}, i64 {}",
+        message_pointer("out_of_memory"),
+        message_len("out_of_memory")
+    );
+    ir.push_str(
+        &PRELUDE
+            .replace("CELLS", &options.cells.to_string())
+            .replace("CELL", &cell)
+            .replace("SIZE", &(options.cell_bits / 8).to_string())
+            .replace("OUT_OF_MEMORY", &out_of_memory),
+    );
+
+    let mut emitter = Emitter {
+        ir,
+        cell,
+        options,
+        next: 0,
+    };
+    let optimized = program.optimize(options.opt_level);
+    for (index, op) in optimized.ops().iter().enumerate() {
+        match *op {
+            Op::Add(amount) => {
+                // reduced to the range of a cell, as a signed value so that it fits the cell type
+                let modulus = 1i64 << options.cell_bits;
+                let amount = (amount as i64).rem_euclid(modulus);
+                let amount = if amount >= modulus / 2 {
+                    amount - modulus
+                } else {
+                    amount
+                };
+                if amount != 0 {
+                    emitter.update(|emitter, value| {
+                        emitter.emit(format!("add {}, {}", value, amount))
+                    });
+                }
+            }
+            Op::Move(distance) => emitter.move_head(distance),
+            Op::SetZero { .. } => {
+                let cell = emitter.cell_pointer();
+                emitter.line(format!("store {0} 0, {0}* {1}", emitter.cell, cell));
+            }
+            Op::Scan { right } => {
+                let label = emitter.value();
+                emitter.open_loop(&label);
+                emitter.move_head(if right { 1 } else { -1 });
+                emitter.close_loop(&label);
+            }
+            Op::JumpIfZero(_) => emitter.open_loop(&index.to_string()),
+            Op::JumpUnlessZero(after_opening) => {
+                emitter.close_loop(&(after_opening - 1).to_string())
+            }
+            Op::Input => emitter.input(),
+            Op::Output => {
+                let value = emitter.load_cell();
+                let byte = emitter.extend(&value, "i32");
+                let written = emitter.value();
+                emitter.line(format!("{} = call i32 @putchar(i32 {})", written, byte));
+            }
+            Op::Other(instruction) if !is_supported(instruction) => {
+                return Err(unsupported(
+                    program,
+                    optimized.instructions_of(index).start,
+                    Target::Llvm,
+                ));
+            }
+            Op::Other(instruction) => emitter.extended(instruction),
+        }
+    }
+    emitter.line("ret i32 0".to_string());
+    emitter.ir.push_str("}\n");
+    Ok(emitter.ir)
+}
+
+/// Writes the body of `main`, naming each value and label it makes with a number of its own
+struct Emitter<'o> {
+    ir: String,
+    /// The LLVM type of a cell
+    cell: String,
+    options: &'o CompileOptions,
+    next: usize,
+}
+
+impl Emitter<'_> {
+    fn line(&mut self, line: String) {
+        self.ir.push_str("  ");
+        self.ir.push_str(&line);
+        self.ir.push('\n');
+    }
+
+    fn label(&mut self, label: &str) {
+        self.ir.push_str(label);
+        self.ir.push_str(":\n");
+    }
+
+    /// A new value name
+    fn value(&mut self) -> String {
+        self.next += 1;
+        format!("%v{}", self.next)
+    }
+
+    /// Emit `instruction` as a new value, and return its name
+    fn emit(&mut self, instruction: String) -> String {
+        let value = self.value();
+        self.line(format!("{} = {}", value, instruction));
+        value
+    }
+
+    fn load_head(&mut self) -> String {
+        self.emit("load i64, i64* %head".to_string())
+    }
+
+    fn cell_pointer(&mut self) -> String {
+        let head = self.load_head();
+        let tape = self.emit(format!("load {0}*, {0}** %tape", self.cell));
+        self.emit(format!(
+            "getelementptr inbounds {0}, {0}* {1}, i64 {2}",
+            self.cell, tape, head
+        ))
+    }
+
+    fn load_cell(&mut self) -> String {
+        let cell = self.cell_pointer();
+        self.emit(format!("load {0}, {0}* {1}", self.cell, cell))
+    }
+
+    /// Replace the cell under the head with what `change` makes of it, given its type and value
+    /// together, such as `i8 %v3`
+    fn update(&mut self, change: impl FnOnce(&mut Self, String) -> String) {
+        let cell = self.cell_pointer();
+        let value = self.emit(format!("load {0}, {0}* {1}", self.cell, cell));
+        let typed = format!("{} {}", self.cell, value);
+        let changed = change(self, typed);
+        self.line(format!("store {0} {1}, {0}* {2}", self.cell, changed, cell));
+    }
+
+    /// `value`, a cell, widened to `to` if it is narrower
+    fn extend(&mut self, value: &str, to: &str) -> String {
+        match self.cell == to {
+            true => value.to_string(),
+            false => self.emit(format!("zext {} {} to {}", self.cell, value, to)),
+        }
+    }
+
+    /// `value`, of type `from`, narrowed to a cell if it is wider
+    fn truncate(&mut self, value: &str, from: &str) -> String {
+        match self.cell == from {
+            true => value.to_string(),
+            false => self.emit(format!("trunc {} {} to {}", from, value, self.cell)),
+        }
+    }
+
+    /// Stop the program with one of [MESSAGES] if `condition` holds
+    fn fail_if(&mut self, condition: &str, message: &str) {
+        let label = self.value();
+        let label = &label[1..];
+        self.line(format!(
+            "br i1 {}, label %fail_{}, label %ok_{}",
+            condition, label, label
+        ));
+        self.label(&format!("fail_{}", label));
+        self.line(format!(
+            "call void @bft_fail({}, i64 {})",
+            message_pointer(message),
+            message_len(message)
+        ));
+        self.line("unreachable".to_string());
+        self.label(&format!("ok_{}", label));
+    }
+
+    fn move_head(&mut self, distance: isize) {
+        let steps = distance.unsigned_abs();
+        let head = self.load_head();
+        let moved = match (self.options.tape, distance >= 0) {
+            (Tape::Circular, right) => {
+                // the tape never changes length, so the wrap can be worked out now
+                let cells = self.options.cells;
+                let steps = steps % cells;
+                let step = if right { steps } else { cells - steps };
+                let added = self.emit(format!("add i64 {}, {}", head, step));
+                self.emit(format!("urem i64 {}, {}", added, cells))
+            }
+            (Tape::Fixed, true) => {
+                let len = self.emit("load i64, i64* %len".to_string());
+                let room = self.emit(format!("sub i64 {}, {}", len, head));
+                let off = self.emit(format!("icmp uge i64 {}, {}", steps, room));
+                self.fail_if(&off, "off_end");
+                self.emit(format!("add i64 {}, {}", head, steps))
+            }
+            (Tape::Extensible, true) => {
+                let moved = self.emit(format!("add i64 {}, {}", head, steps));
+                let len = self.emit("load i64, i64* %len".to_string());
+                let beyond = self.emit(format!("icmp uge i64 {}, {}", moved, len));
+                let label = self.value();
+                let label = &label[1..];
+                self.line(format!(
+                    "br i1 {}, label %grow_{}, label %grown_{}",
+                    beyond, label, label
+                ));
+                self.label(&format!("grow_{}", label));
+                let cell = self.cell.clone();
+                let tape = self.emit(format!("load {0}*, {0}** %tape", cell));
+                let grew = self.emit(format!(
+                    "call {{ {0}*, i64 }} @bft_grow({0}* {1}, i64 {2}, i64 {3})",
+                    cell, tape, len, moved
+                ));
+                let tape = self.emit(format!("extractvalue {{ {}*, i64 }} {}, 0", cell, grew));
+                let len = self.emit(format!("extractvalue {{ {}*, i64 }} {}, 1", cell, grew));
+                self.line(format!("store {0}* {1}, {0}** %tape", cell, tape));
+                self.line(format!("store i64 {}, i64* %len", len));
+                self.line(format!("br label %grown_{}", label));
+                self.label(&format!("grown_{}", label));
+                moved
+            }
+            (Tape::Fixed | Tape::Extensible, false) => {
+                let off = self.emit(format!("icmp ult i64 {}, {}", head, steps));
+                self.fail_if(&off, "off_start");
+                self.emit(format!("sub i64 {}, {}", head, steps))
+            }
+        };
+        self.line(format!("store i64 {}, i64* %head", moved));
+    }
+
+    /// Start a loop over the cell under the head, labelled with `label`
+    fn open_loop(&mut self, label: &str) {
+        let label = label.trim_start_matches('%');
+        self.line(format!("br label %loop{}", label));
+        self.label(&format!("loop{}", label));
+        let value = self.load_cell();
+        let nonzero = self.emit(format!("icmp ne {} {}, 0", self.cell, value));
+        self.line(format!(
+            "br i1 {}, label %body{}, label %done{}",
+            nonzero, label, label
+        ));
+        self.label(&format!("body{}", label));
+    }
+
+    /// End the loop started with the same `label`
+    fn close_loop(&mut self, label: &str) {
+        let label = label.trim_start_matches('%');
+        self.line(format!("br label %loop{}", label));
+        self.label(&format!("done{}", label));
+    }
+
+    fn input(&mut self) {
+        self.emit("call i32 @fflush(i8* null)".to_string());
+        let read = self.emit("call i32 @getchar()".to_string());
+        let at_eof = self.emit(format!("icmp eq i32 {}, -1", read));
+        let label = self.value();
+        let label = &label[1..];
+        self.line(format!(
+            "br i1 {}, label %eof_{}, label %byte_{}",
+            at_eof, label, label
+        ));
+        self.label(&format!("byte_{}", label));
+        let byte = self.truncate(&read, "i32");
+        let cell = self.cell_pointer();
+        self.line(format!("store {0} {1}, {0}* {2}", self.cell, byte, cell));
+        self.line(format!("br label %read_{}", label));
+        self.label(&format!("eof_{}", label));
+        let value = match self.options.eof_behavior {
+            EofBehavior::Error => {
+                self.line(format!(
+                    "call void @bft_fail({}, i64 {})",
+                    message_pointer("past_end_of_input"),
+                    message_len("past_end_of_input")
+                ));
+                self.line("unreachable".to_string());
+                None
+            }
+            EofBehavior::SetZero => Some("0"),
+            EofBehavior::SetMax => Some("-1"),
+            EofBehavior::LeaveUnchanged => None,
+        };
+        if let Some(value) = value {
+            let cell = self.cell_pointer();
+            self.line(format!("store {0} {1}, {0}* {2}", self.cell, value, cell));
+        }
+        if self.options.eof_behavior != EofBehavior::Error {
+            self.line(format!("br label %read_{}", label));
+        }
+        self.label(&format!("read_{}", label));
+    }
+
+    /// An instruction of Extended Brainfuck Type I
+    fn extended(&mut self, instruction: Instruction) {
+        let operator = match instruction {
+            Instruction::End => {
+                self.line("ret i32 0".to_string());
+                // the rest of the program is never reached, but still needs a block to go in
+                let label = self.value();
+                self.label(&format!("ended_{}", &label[1..]));
+                return;
+            }
+            Instruction::Store => {
+                let value = self.load_cell();
+                let byte = match self.cell.as_str() {
+                    "i8" => value,
+                    cell => self.emit(format!("trunc {} {} to i8", cell, value)),
+                };
+                self.line(format!("store i8 {}, i8* %storage", byte));
+                return;
+            }
+            Instruction::Load => {
+                let storage = self.emit("load i8, i8* %storage".to_string());
+                let value = match self.cell.as_str() {
+                    "i8" => storage,
+                    cell => self.emit(format!("zext i8 {} to {}", storage, cell)),
+                };
+                let cell = self.cell_pointer();
+                self.line(format!("store {0} {1}, {0}* {2}", self.cell, value, cell));
+                return;
+            }
+            Instruction::ShiftRight => "lshr",
+            Instruction::ShiftLeft => "shl",
+            Instruction::Not => "xor",
+            Instruction::Xor => "xor",
+            Instruction::And => "and",
+            Instruction::Or => "or",
+            // every other instruction has an op of its own, or is refused by is_supported
+            _ => return,
+        };
+        let operand = match instruction {
+            Instruction::ShiftRight | Instruction::ShiftLeft => "1".to_string(),
+            Instruction::Not => "-1".to_string(),
+            _ => {
+                let storage = self.emit("load i8, i8* %storage".to_string());
+                match self.cell.as_str() {
+                    "i8" => storage,
+                    cell => self.emit(format!("zext i8 {} to {}", storage, cell)),
+                }
+            }
+        };
+        self.update(|emitter, value| emitter.emit(format!("{} {}, {}", operator, value, operand)));
+    }
+}
+
+/// A pointer to the first byte of the global `name`, an array of `len` bytes
+fn array_pointer(name: &str, len: usize) -> String {
+    format!(
+        "getelementptr inbounds ([{0} x i8], [{0} x i8]* @{1}, i64 0, i64 0)",
+        len, name
+    )
+}
+
+/// A pointer to the message in the global `name`, to give to `bft_fail`
+fn message_pointer(name: &str) -> String {
+    format!("i8* {}", array_pointer(name, message_len(name)))
+}
+
+/// The length of the message in the global `name`
+fn message_len(name: &str) -> usize {
+    MESSAGES
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map_or(0, |(_, message)| format!("Error: {}\n", message).len())
+}
+
+/// `text` as an LLVM array constant with its type
+fn constant(text: &str) -> String {
+    let mut escaped = String::new();
+    for byte in text.bytes() {
+        match byte {
+            // `*` too, so that only pointer types have one for opaque_pointers to find
+            b' '..=b'~' if !b"\"\\*".contains(&byte) => escaped.push(byte as char),
+            _ => {
+                let _ = write!(escaped, "\\{:02X}", byte);
+            }
+        }
+    }
+    format!("[{} x i8] c\"{}\"", text.len(), escaped)
+}
+
+/// `ir` with every pointer type, such as `i8*` or `[4 x i8]**`, replaced by the opaque `ptr`
+fn opaque_pointers(ir: &str) -> String {
+    let mut opaque = String::with_capacity(ir.len());
+    for c in ir.chars() {
+        if c != '*' {
+            opaque.push(c);
+            continue;
+        }
+        // drop the type pointed to, which ends just before the `*`
+        if opaque.ends_with(']') {
+            let start = opaque.rfind('[').unwrap_or(opaque.len());
+            opaque.truncate(start);
+        } else {
+            let kept = opaque
+                .trim_end_matches(|c: char| c.is_ascii_alphanumeric())
+                .len();
+            opaque.truncate(kept);
+        }
+        opaque.push_str("ptr");
+    }
+    opaque
+}
+
+/// The major version of the LLVM whose `opt` is on the `PATH`
+fn llvm_version() -> Result<u32, String> {
+    let output = run_tool("opt", &["--version"], b"")?;
+    let output = String::from_utf8_lossy(&output);
+    output
+        .split("LLVM version ")
+        .nth(1)
+        .and_then(|version| version.split('.').next())
+        .and_then(|major| major.trim().parse().ok())
+        .ok_or_else(|| "cannot tell which version of LLVM `opt` is from".to_string())
+}
+
+/// Run one of LLVM's tools with `input` on its stdin, and return what it writes to stdout
+fn run_tool(tool: &str, args: &[&str], input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut child = Command::new(tool)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("cannot run `{}`, which comes with LLVM: {}", tool, error))?;
+    // the tools read all their input before writing anything, so this cannot deadlock
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input)
+            .map_err(|error| format!("cannot write to `{}`: {}", tool, error))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|error| format!("`{}` failed: {}", tool, error))?;
+    if !output.status.success() {
+        return Err(format!(
+            "`{}` failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    use bft_types::optimize::OptLevel;
+    use bft_types::ParseOptions;
+
+    // Are pointer types made opaque, and nothing else?
+    #[test]
+    fn test_opaque_pointers() {
+        assert_eq!(
+            opaque_pointers("store i8** %v1, i8*** %tape\n  %v2 = load i8, i8* %v3"),
+            "store ptr %v1, ptr %tape\n  %v2 = load i8, ptr %v3"
+        );
+        assert_eq!(
+            opaque_pointers("getelementptr inbounds ([4 x i8], [4 x i8]* @a, i64 0, i64 0)"),
+            "getelementptr inbounds ([4 x i8], ptr @a, i64 0, i64 0)"
+        );
+        assert_eq!(opaque_pointers("{ i16*, i64 }"), "{ ptr, i64 }");
+    }
+
+    // Does the object file link and behave as the interpreter does, on each kind of tape? Skipped
+    // where there is no LLVM or C compiler to link with.
+    #[test]
+    fn test_compiled_programs_run() {
+        if llvm_version().is_err() || Command::new("cc").arg("--version").output().is_err() {
+            return;
+        }
+        let directory = std::env::temp_dir().join(format!("bft-llvm-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let run = |program: BfProgram, options: CompileOptions, input: &[u8]| {
+            let provenance = Provenance::new(Path::new("test.bf"), &program, "bf", "none");
+            let object = compile(&program, &options, &provenance).unwrap();
+            assert!(Provenance::find(&String::from_utf8_lossy(&object)).is_ok());
+            fs::write(directory.join("test.o"), object).unwrap();
+            let status = Command::new("cc")
+                .arg("-o")
+                .arg(directory.join("test"))
+                .arg(directory.join("test.o"))
+                .status()
+                .unwrap();
+            assert!(status.success(), "linking {:?}", program);
+            let mut child = Command::new(directory.join("test"))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(input).unwrap();
+            let output = child.wait_with_output().unwrap();
+            (output.status.success(), output.stdout)
+        };
+        let bf = |text: &str| BfProgram::new("test.bf", text).unwrap();
+
+        let eof_zero = CompileOptions {
+            eof_behavior: EofBehavior::SetZero,
+            opt_level: OptLevel::Full,
+            ..CompileOptions::default()
+        };
+        assert_eq!(run(bf(",[+.,]"), eof_zero, b"HAL"), (true, b"IBM".to_vec()));
+        assert!(!run(bf(","), CompileOptions::default(), b"").0);
+
+        let wide = CompileOptions {
+            cell_bits: 16,
+            opt_level: OptLevel::Full,
+            ..CompileOptions::default()
+        };
+        let overflows = "++++++++++++++++[>++++++++++++++++<-]>[[-]>+<]>.-.";
+        assert_eq!(run(bf(overflows), wide, b""), (true, b"\x01\x00".to_vec()));
+
+        let tape = |tape, cells| CompileOptions {
+            tape,
+            cells,
+            ..CompileOptions::default()
+        };
+        assert!(!run(bf(">>>+."), tape(Tape::Fixed, 3), b"").0);
+        assert_eq!(
+            run(bf(">>>>>>>+.<<<<<<<<"), tape(Tape::Extensible, 3), b""),
+            (false, b"\x01".to_vec())
+        );
+        assert_eq!(
+            run(bf("+<<<.>+++."), tape(Tape::Circular, 3), b""),
+            (true, b"\x01\x03".to_vec())
+        );
+
+        let extended = ParseOptions {
+            extended: true,
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("test.bf", "+++$>!{^~.@.", &extended);
+        assert_eq!(
+            run(program.unwrap(), CompileOptions::default(), b""),
+            (true, b"\xfa".to_vec())
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! Compiling programs to Rust source, to bake a program into a Rust project.
+//!
+//! The program becomes a function `run`, which reads from any [std::io::Read] and writes to any
+//! [std::io::Write], and a `main` that runs it over stdin and stdout, so the file builds with
+//! `rustc` as it is or can be included in a crate as a module. The tape is a `Vec` of `u8`, `u16`
+//! or `u32` cells, each loop a `while` loop over the cell under the head. Moving off the tape, or
+//! reading past the end of input with [EofBehavior::Error], makes `run` return an error.
+
+use std::fmt::Write;
+
+use bft_interp::EofBehavior;
+use bft_types::optimize::Op;
+use bft_types::{BfProgram, Instruction};
+
+use super::{is_supported, unsupported, CompileOptions, Tape, Target};
+use crate::provenance::Provenance;
+
+/// Everything before the program itself, once the cell type and tape length are filled in
+const PRELUDE: &str = r#"/// Run the program, reading input from `input` and writing output to `output`
+#[allow(unused, unreachable_code)]
+pub fn run(
+    input: &mut dyn std::io::Read,
+    output: &mut dyn std::io::Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    type Cell = CELL;
+
+    const OFF_END: &str = "head ran off the end of the tape";
+    const OFF_START: &str = "head ran off the start of the tape";
+
+    /// Read a byte, or `None` at the end of input
+    fn read(
+        input: &mut dyn std::io::Read,
+        output: &mut dyn std::io::Write,
+    ) -> std::io::Result<Option<u8>> {
+        output.flush()?;
+        let mut byte = [0];
+        match input.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    let mut tape: Vec<Cell> = vec![0; CELLS];
+    let mut head = 0;
+    // the storage byte of Extended Brainfuck Type I
+    let mut storage: u8 = 0;
+
+"#;
+
+/// Everything after the program
+const POSTLUDE: &str = r#"    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    use std::io::Write;
+
+    let mut output = std::io::BufWriter::new(std::io::stdout().lock());
+    let result = run(&mut std::io::stdin().lock(), &mut output);
+    if let Err(error) = result.and_then(|()| Ok(output.flush()?)) {
+        let _ = output.flush();
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
+"#;
+
+/// Compile `program` to Rust source that runs it with the given options
+///
+/// ```
+///# use bft::compile::{rust, CompileOptions};
+///# use bft::provenance::Provenance;
+///# use bft_types::BfProgram;
+///# use std::path::Path;
+/// let program = BfProgram::new("hello.bf", "+++[>++<-]>.")?;
+/// let provenance = Provenance::new(Path::new("hello.bf"), &program, "bf", "none");
+///
+/// let source = rust::compile(&program, &CompileOptions::default(), &provenance)?;
+/// assert!(source.starts_with("// bft-provenance 1\n"));
+/// assert!(source.contains("    while tape[head] != 0 {\n"));
+///# Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn compile(
+    program: &BfProgram,
+    options: &CompileOptions,
+    provenance: &Provenance,
+) -> Result<String, String> {
+    let cell = match options.cell_bits {
+        8 => "u8",
+        16 => "u16",
+        32 => "u32",
+        bits => return Err(format!("cells must be 8, 16 or 32 bits wide, not {}", bits)),
+    };
+
+    let mut source = provenance.header("//");
+    source.push('\n');
+    source.push_str(
+        &PRELUDE
+            .replace("CELLS", &options.cells.to_string())
+            .replace("CELL", cell),
+    );
+
+    let optimized = program.optimize(options.opt_level);
+    let mut depth = 1;
+    for (index, op) in optimized.ops().iter().enumerate() {
+        // the op's statements, indented from the op's own level
+        let mut code = Vec::new();
+        match *op {
+            Op::Add(amount) => {
+                let method = if amount >= 0 { "add" } else { "sub" };
+                // reduced to the range of a cell, so that the literal fits the cell type
+                let amount = amount.unsigned_abs() as u64 % (1 << options.cell_bits);
+                if amount > 0 {
+                    code.push(format!(
+                        "tape[head] = tape[head].wrapping_{}({});",
+                        method, amount
+                    ));
+                }
+            }
+            Op::Move(distance) => move_head(&mut code, distance, options),
+            Op::SetZero { .. } => code.push("tape[head] = 0;".to_string()),
+            Op::Scan { right } => {
+                let mut step = Vec::new();
+                move_head(&mut step, if right { 1 } else { -1 }, options);
+                code.push("while tape[head] != 0 {".to_string());
+                code.extend(step.iter().map(|statement| format!("    {}", statement)));
+                code.push("}".to_string());
+            }
+            Op::JumpIfZero(_) => code.push("while tape[head] != 0 {".to_string()),
+            Op::JumpUnlessZero(_) => {
+                depth -= 1;
+                code.push("}".to_string());
+            }
+            Op::Input => {
+                let at_eof = match options.eof_behavior {
+                    EofBehavior::Error => "return Err(\"read past the end of input\".into()),",
+                    EofBehavior::SetZero => "tape[head] = 0,",
+                    EofBehavior::SetMax => "tape[head] = Cell::MAX,",
+                    EofBehavior::LeaveUnchanged => "{}",
+                };
+                code.push("match read(input, output)? {".to_string());
+                code.push("    Some(byte) => tape[head] = Cell::from(byte),".to_string());
+                code.push(format!("    None => {}", at_eof));
+                code.push("}".to_string());
+            }
+            Op::Output => code.push("output.write_all(&[tape[head] as u8])?;".to_string()),
+            Op::Other(instruction) if !is_supported(instruction) => {
+                return Err(unsupported(
+                    program,
+                    optimized.instructions_of(index).start,
+                    Target::Rust,
+                ));
+            }
+            Op::Other(instruction) => code.push(extended(instruction).to_string()),
+        }
+        for statement in code {
+            // writing to a String cannot fail
+            let _ = writeln!(source, "{}{}", "    ".repeat(depth), statement);
+        }
+        if let Op::JumpIfZero(_) = op {
+            depth += 1;
+        }
+    }
+    source.push_str(POSTLUDE);
+    Ok(source)
+}
+
+/// Add the statements that move the head `distance` cells to `code`
+fn move_head(code: &mut Vec<String>, distance: isize, options: &CompileOptions) {
+    let steps = distance.unsigned_abs();
+    match (options.tape, distance >= 0) {
+        (Tape::Circular, right) => {
+            // the tape never changes length, so the wrap can be worked out now
+            let steps = steps % options.cells;
+            if steps > 0 {
+                let step = if right { steps } else { options.cells - steps };
+                code.push(format!("head = (head + {}) % tape.len();", step));
+            }
+        }
+        (Tape::Fixed, true) => {
+            code.push(format!("head += {};", steps));
+            code.push("if head >= tape.len() {".to_string());
+            code.push("    return Err(OFF_END.into());".to_string());
+            code.push("}".to_string());
+        }
+        (Tape::Extensible, true) => {
+            code.push(format!("head += {};", steps));
+            code.push("if head >= tape.len() {".to_string());
+            code.push("    tape.resize((head + 1).max(tape.len() * 2), 0);".to_string());
+            code.push("}".to_string());
+        }
+        (Tape::Fixed | Tape::Extensible, false) => {
+            code.push(format!(
+                "head = head.checked_sub({}).ok_or(OFF_START)?;",
+                steps
+            ));
+        }
+    }
+}
+
+/// The statement for an instruction of Extended Brainfuck Type I
+fn extended(instruction: Instruction) -> &'static str {
+    match instruction {
+        Instruction::End => "return Ok(());",
+        Instruction::Store => "storage = tape[head] as u8;",
+        Instruction::Load => "tape[head] = Cell::from(storage);",
+        Instruction::ShiftRight => "tape[head] >>= 1;",
+        Instruction::ShiftLeft => "tape[head] <<= 1;",
+        Instruction::Not => "tape[head] = !tape[head];",
+        Instruction::Xor => "tape[head] ^= Cell::from(storage);",
+        Instruction::And => "tape[head] &= Cell::from(storage);",
+        Instruction::Or => "tape[head] |= Cell::from(storage);",
+        // every other instruction has an op of its own, or is refused by is_supported
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    use bft_types::optimize::OptLevel;
+    use bft_types::ParseOptions;
+
+    // Does the compiled program build without warnings and behave as the interpreter does?
+    // Skipped where there is no Rust compiler.
+    #[test]
+    fn test_compiled_programs_run() {
+        if Command::new("rustc").arg("--version").output().is_err() {
+            return;
+        }
+        let directory = std::env::temp_dir().join(format!("bft-rust-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let run = |program: BfProgram, options: CompileOptions, input: &[u8]| {
+            let provenance = Provenance::new(Path::new("test.bf"), &program, "bf", "none");
+            let source = compile(&program, &options, &provenance).unwrap();
+            fs::write(directory.join("test.rs"), source).unwrap();
+            let output = Command::new("rustc")
+                .args(["--edition", "2021", "-o"])
+                .arg(directory.join("test"))
+                .arg(directory.join("test.rs"))
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "compiling {:?}", program);
+            assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+            let mut child = Command::new(directory.join("test"))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(input).unwrap();
+            let output = child.wait_with_output().unwrap();
+            (output.status.success(), output.stdout)
+        };
+        let bf = |text: &str| BfProgram::new("test.bf", text).unwrap();
+
+        let eof_zero = CompileOptions {
+            eof_behavior: EofBehavior::SetZero,
+            opt_level: OptLevel::Full,
+            ..CompileOptions::default()
+        };
+        assert_eq!(run(bf(",[+.,]"), eof_zero, b"HAL"), (true, b"IBM".to_vec()));
+        assert!(!run(bf(","), CompileOptions::default(), b"").0);
+
+        let wide = CompileOptions {
+            cell_bits: 16,
+            opt_level: OptLevel::Full,
+            ..CompileOptions::default()
+        };
+        let overflows = "++++++++++++++++[>++++++++++++++++<-]>[[-]>+<]>.-.";
+        assert_eq!(run(bf(overflows), wide, b""), (true, b"\x01\x00".to_vec()));
+
+        let tape = |tape, cells| CompileOptions {
+            tape,
+            cells,
+            ..CompileOptions::default()
+        };
+        assert!(!run(bf(">>>+."), tape(Tape::Fixed, 3), b"").0);
+        assert_eq!(
+            run(bf(">>>>>>>+.<<<<<<<<"), tape(Tape::Extensible, 3), b""),
+            (false, b"\x01".to_vec())
+        );
+        assert_eq!(
+            run(bf("+<<<.>+++."), tape(Tape::Circular, 3), b""),
+            (true, b"\x01\x03".to_vec())
+        );
+
+        let extended = ParseOptions {
+            extended: true,
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("test.bf", "+++$>!{^~.@.", &extended);
+        assert_eq!(
+            run(program.unwrap(), CompileOptions::default(), b""),
+            (true, b"\xfa".to_vec())
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
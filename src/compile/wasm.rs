@@ -0,0 +1,601 @@
+//! Compiling programs to a WebAssembly module, to run in a browser or any other WebAssembly host.
+//!
+//! The module imports two functions from `env`: `read`, which returns the next byte of input or
+//! -1 at the end of input, and `write`, which is given each byte of output. It exports its
+//! `memory`, which holds the tape from address 0, and a function `run`, which runs the program
+//! and returns 0, or one of these if the program fails:
+//!
+//! - 1: the head ran off the end of the tape
+//! - 2: the head ran off the start of the tape
+//! - 3: the program read past the end of input, with [EofBehavior::Error]
+//! - 4: an extensible tape could not grow
+//!
+//! In JavaScript, for example:
+//!
+//! ```text
+//! const { instance } = await WebAssembly.instantiate(bytes, {
+//!     env: { read: () => -1, write: (byte) => output.push(byte) },
+//! });
+//! const status = instance.exports.run();
+//! ```
+
+use bft_interp::EofBehavior;
+use bft_types::optimize::Op;
+use bft_types::{BfProgram, Instruction};
+
+use super::{is_supported, unsupported, CompileOptions, Tape, Target};
+use crate::provenance::Provenance;
+
+/// Bytes in a page of WebAssembly memory
+const PAGE: u64 = 65_536;
+
+/// The value `run` returns when the head runs off the end of the tape
+const OFF_END: i32 = 1;
+/// The value `run` returns when the head runs off the start of the tape
+const OFF_START: i32 = 2;
+/// The value `run` returns when the program reads past the end of input
+const PAST_END_OF_INPUT: i32 = 3;
+/// The value `run` returns when the tape cannot grow
+const OUT_OF_MEMORY: i32 = 4;
+
+// the function indexes, imports first
+const READ: u32 = 0;
+const WRITE: u32 = 1;
+const RUN: u32 = 2;
+
+// the locals of `run`: the address of the cell under the head, the byte just read, and the
+// storage byte of Extended Brainfuck Type I
+const HEAD: u32 = 0;
+const BYTE: u32 = 1;
+const STORAGE: u32 = 2;
+
+/// The type of a block that leaves nothing on the stack
+const VOID: u8 = 0x40;
+
+// the instructions used
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const RETURN: u8 = 0x0f;
+const CALL: u8 = 0x10;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const MEMORY_SIZE: u8 = 0x3f;
+const MEMORY_GROW: u8 = 0x40;
+const I32_CONST: u8 = 0x41;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I32_LT_S: u8 = 0x48;
+const I32_LT_U: u8 = 0x49;
+const I32_GE_U: u8 = 0x4f;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+const I32_REM_U: u8 = 0x70;
+const I32_AND: u8 = 0x71;
+const I32_OR: u8 = 0x72;
+const I32_XOR: u8 = 0x73;
+const I32_SHL: u8 = 0x74;
+const I32_SHR_U: u8 = 0x76;
+
+/// Compile `program` to a WebAssembly module that runs it with the given options
+///
+/// ```
+///# use bft::compile::{wasm, CompileOptions};
+///# use bft::provenance::Provenance;
+///# use bft_types::BfProgram;
+///# use std::path::Path;
+/// let program = BfProgram::new("hello.bf", "+++[>++<-]>.")?;
+/// let provenance = Provenance::new(Path::new("hello.bf"), &program, "bf", "none");
+///
+/// let module = wasm::compile(&program, &CompileOptions::default(), &provenance)?;
+/// assert!(module.starts_with(b"\0asm\x01\0\0\0"));
+///# Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn compile(
+    program: &BfProgram,
+    options: &CompileOptions,
+    provenance: &Provenance,
+) -> Result<Vec<u8>, String> {
+    let cell = match options.cell_bits {
+        8 | 16 | 32 => Cell::new(options.cell_bits),
+        bits => return Err(format!("cells must be 8, 16 or 32 bits wide, not {}", bits)),
+    };
+    let tape_bytes = options.cells as u64 * cell.size as u64;
+    if tape_bytes > i32::MAX as u64 {
+        return Err(format!(
+            "a tape of {} {} bit cells is too big for WebAssembly",
+            options.cells, options.cell_bits
+        ));
+    }
+
+    let mut body = Body {
+        code: Vec::new(),
+        cell,
+        tape: options.tape,
+        tape_bytes: tape_bytes as i32,
+    };
+    let optimized = program.optimize(options.opt_level);
+    for (index, op) in optimized.ops().iter().enumerate() {
+        match *op {
+            Op::Add(amount) => {
+                // the store keeps only the bits of the cell, so the sum wraps as it should
+                body.change_cell(|body| {
+                    body.i32_const(amount as i32);
+                    body.code.push(I32_ADD);
+                });
+            }
+            Op::Move(distance) => body.move_head(distance),
+            Op::SetZero { .. } => body.set_cell(0),
+            Op::Scan { right } => {
+                body.loop_start();
+                body.move_head(if right { 1 } else { -1 });
+                body.loop_end();
+            }
+            Op::JumpIfZero(_) => body.loop_start(),
+            Op::JumpUnlessZero(_) => body.loop_end(),
+            Op::Input => {
+                body.call(READ);
+                body.local(LOCAL_TEE, BYTE);
+                body.i32_const(0);
+                body.code.extend([I32_LT_S, IF, VOID]);
+                match options.eof_behavior {
+                    EofBehavior::Error => body.fail(PAST_END_OF_INPUT),
+                    EofBehavior::SetZero => body.set_cell(0),
+                    EofBehavior::SetMax => body.set_cell(-1),
+                    EofBehavior::LeaveUnchanged => {}
+                }
+                body.code.push(ELSE);
+                body.local(LOCAL_GET, HEAD);
+                body.local(LOCAL_GET, BYTE);
+                body.store();
+                body.code.push(END);
+            }
+            Op::Output => {
+                body.load();
+                body.i32_const(0xff);
+                body.code.push(I32_AND);
+                body.call(WRITE);
+            }
+            Op::Other(instruction) if !is_supported(instruction) => {
+                return Err(unsupported(
+                    program,
+                    optimized.instructions_of(index).start,
+                    Target::Wasm,
+                ));
+            }
+            Op::Other(instruction) => body.extended(instruction),
+        }
+    }
+    body.i32_const(0);
+    body.code.push(END);
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+    // first, so that the provenance is near the start of the file
+    let mut custom = Vec::new();
+    name(&mut custom, "bft-provenance");
+    custom.push(b'\n');
+    custom.extend(provenance.header(";;").bytes());
+    section(&mut module, 0, &custom);
+
+    // () -> i32 for `read` and `run`, (i32) -> () for `write`
+    section(&mut module, 1, &[2, 0x60, 0, 1, 0x7f, 0x60, 1, 0x7f, 0]);
+
+    let mut imports = vec![2];
+    for (function, type_index) in [("read", 0), ("write", 1)] {
+        name(&mut imports, "env");
+        name(&mut imports, function);
+        imports.extend([0, type_index]);
+    }
+    section(&mut module, 2, &imports);
+
+    section(&mut module, 3, &[1, 0]);
+
+    let mut memory = vec![1, 0];
+    unsigned(&mut memory, tape_bytes.div_ceil(PAGE).max(1) as u32);
+    section(&mut module, 5, &memory);
+
+    let mut exports = vec![2];
+    name(&mut exports, "run");
+    exports.push(0);
+    unsigned(&mut exports, RUN);
+    name(&mut exports, "memory");
+    exports.extend([2, 0]);
+    section(&mut module, 7, &exports);
+
+    // three i32 locals, then the code
+    let mut function = vec![1, 3, 0x7f];
+    function.extend(body.code);
+    let mut code = vec![1];
+    unsigned(&mut code, function.len() as u32);
+    code.extend(function);
+    section(&mut module, 10, &code);
+
+    Ok(module)
+}
+
+/// How a cell is loaded and stored
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    /// Bytes in a cell
+    size: i32,
+    load: u8,
+    store: u8,
+    /// The alignment of a cell, as a power of 2
+    align: u32,
+}
+
+impl Cell {
+    fn new(bits: u32) -> Self {
+        match bits {
+            8 => Cell {
+                size: 1,
+                load: 0x2d,
+                store: 0x3a,
+                align: 0,
+            },
+            16 => Cell {
+                size: 2,
+                load: 0x2f,
+                store: 0x3b,
+                align: 1,
+            },
+            _ => Cell {
+                size: 4,
+                load: 0x28,
+                store: 0x36,
+                align: 2,
+            },
+        }
+    }
+}
+
+/// The code of `run`, as it is written
+struct Body {
+    code: Vec<u8>,
+    cell: Cell,
+    tape: Tape,
+    /// Bytes in the tape, or in the tape to start with if it is extensible
+    tape_bytes: i32,
+}
+
+impl Body {
+    fn i32_const(&mut self, value: i32) {
+        self.code.push(I32_CONST);
+        signed(&mut self.code, value);
+    }
+
+    fn local(&mut self, instruction: u8, local: u32) {
+        self.code.push(instruction);
+        unsigned(&mut self.code, local);
+    }
+
+    fn call(&mut self, function: u32) {
+        self.code.push(CALL);
+        unsigned(&mut self.code, function);
+    }
+
+    /// Return `status` from `run`
+    fn fail(&mut self, status: i32) {
+        self.i32_const(status);
+        self.code.push(RETURN);
+    }
+
+    /// Push the cell under the head
+    fn load(&mut self) {
+        self.local(LOCAL_GET, HEAD);
+        self.code.push(self.cell.load);
+        unsigned(&mut self.code, self.cell.align);
+        unsigned(&mut self.code, 0);
+    }
+
+    /// Store the value on the stack in the cell under the head, with the head beneath it
+    fn store(&mut self) {
+        self.code.push(self.cell.store);
+        unsigned(&mut self.code, self.cell.align);
+        unsigned(&mut self.code, 0);
+    }
+
+    fn set_cell(&mut self, value: i32) {
+        self.local(LOCAL_GET, HEAD);
+        self.i32_const(value);
+        self.store();
+    }
+
+    /// Replace the cell under the head by what `change` leaves on the stack, given the cell
+    fn change_cell(&mut self, change: impl FnOnce(&mut Self)) {
+        self.local(LOCAL_GET, HEAD);
+        self.load();
+        change(self);
+        self.store();
+    }
+
+    /// Start a loop that is skipped, or left, when the cell under the head is zero
+    fn loop_start(&mut self) {
+        self.code.extend([BLOCK, VOID, LOOP, VOID]);
+        self.load();
+        self.code.extend([I32_EQZ, BR_IF, 1]);
+    }
+
+    /// Go back to the check at the start of the loop
+    fn loop_end(&mut self) {
+        self.code.extend([BR, 0, END, END]);
+    }
+
+    fn move_head(&mut self, distance: isize) {
+        let bytes = (distance.unsigned_abs() as i32).wrapping_mul(self.cell.size);
+        match (self.tape, distance >= 0) {
+            (Tape::Circular, right) => {
+                // the tape never changes length, so the wrap can be worked out now
+                let bytes = bytes % self.tape_bytes;
+                if bytes > 0 {
+                    self.local(LOCAL_GET, HEAD);
+                    self.i32_const(if right {
+                        bytes
+                    } else {
+                        self.tape_bytes - bytes
+                    });
+                    self.code.push(I32_ADD);
+                    self.i32_const(self.tape_bytes);
+                    self.code.push(I32_REM_U);
+                    self.local(LOCAL_SET, HEAD);
+                }
+            }
+            (Tape::Fixed, true) => {
+                self.local(LOCAL_GET, HEAD);
+                self.i32_const(bytes);
+                self.code.push(I32_ADD);
+                self.local(LOCAL_TEE, HEAD);
+                self.i32_const(self.tape_bytes);
+                self.code.extend([I32_GE_U, IF, VOID]);
+                self.fail(OFF_END);
+                self.code.push(END);
+            }
+            (Tape::Extensible, true) => {
+                self.local(LOCAL_GET, HEAD);
+                self.i32_const(bytes);
+                self.code.push(I32_ADD);
+                self.local(LOCAL_SET, HEAD);
+                // the page the last byte of the cell is in, and whether memory reaches it
+                self.local(LOCAL_GET, HEAD);
+                self.i32_const(self.cell.size - 1);
+                self.code.push(I32_ADD);
+                self.i32_const(16);
+                self.code.push(I32_SHR_U);
+                self.local(LOCAL_TEE, BYTE);
+                self.code.extend([MEMORY_SIZE, 0, I32_GE_U, IF, VOID]);
+                self.local(LOCAL_GET, BYTE);
+                self.i32_const(1);
+                self.code.push(I32_ADD);
+                self.code.extend([MEMORY_SIZE, 0, I32_SUB, MEMORY_GROW, 0]);
+                self.i32_const(-1);
+                self.code.extend([I32_EQ, IF, VOID]);
+                self.fail(OUT_OF_MEMORY);
+                self.code.extend([END, END]);
+            }
+            (Tape::Fixed | Tape::Extensible, false) => {
+                self.local(LOCAL_GET, HEAD);
+                self.i32_const(bytes);
+                self.code.extend([I32_LT_U, IF, VOID]);
+                self.fail(OFF_START);
+                self.code.push(END);
+                self.local(LOCAL_GET, HEAD);
+                self.i32_const(bytes);
+                self.code.push(I32_SUB);
+                self.local(LOCAL_SET, HEAD);
+            }
+        }
+    }
+
+    /// The code for an instruction of Extended Brainfuck Type I
+    fn extended(&mut self, instruction: Instruction) {
+        let with_storage = |operation| {
+            move |body: &mut Self| {
+                body.local(LOCAL_GET, STORAGE);
+                body.code.push(operation);
+            }
+        };
+        match instruction {
+            Instruction::End => self.fail(0),
+            Instruction::Store => {
+                self.load();
+                self.i32_const(0xff);
+                self.code.push(I32_AND);
+                self.local(LOCAL_SET, STORAGE);
+            }
+            Instruction::Load => {
+                self.local(LOCAL_GET, HEAD);
+                self.local(LOCAL_GET, STORAGE);
+                self.store();
+            }
+            Instruction::ShiftRight => self.change_cell(|body| {
+                body.i32_const(1);
+                body.code.push(I32_SHR_U);
+            }),
+            Instruction::ShiftLeft => self.change_cell(|body| {
+                body.i32_const(1);
+                body.code.push(I32_SHL);
+            }),
+            Instruction::Not => self.change_cell(|body| {
+                body.i32_const(-1);
+                body.code.push(I32_XOR);
+            }),
+            Instruction::Xor => self.change_cell(with_storage(I32_XOR)),
+            Instruction::And => self.change_cell(with_storage(I32_AND)),
+            Instruction::Or => self.change_cell(with_storage(I32_OR)),
+            // every other instruction has an op of its own, or is refused by is_supported
+            _ => {}
+        }
+    }
+}
+
+/// Add a section with the given id and contents to `module`
+fn section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    module.push(id);
+    unsigned(module, contents.len() as u32);
+    module.extend(contents);
+}
+
+/// Add a name, prefixed by its length
+fn name(bytes: &mut Vec<u8>, name: &str) {
+    unsigned(bytes, name.len() as u32);
+    bytes.extend(name.bytes());
+}
+
+/// Add `value` in unsigned LEB128
+fn unsigned(bytes: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Add `value` in signed LEB128
+fn signed(bytes: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    use bft_types::optimize::OptLevel;
+    use bft_types::ParseOptions;
+
+    /// Runs the module named on the command line over stdin, exiting with what `run` returns
+    const HOST: &str = r#"
+        const fs = require("fs");
+        const input = fs.readFileSync(0);
+        const output = [];
+        let at = 0;
+        WebAssembly.instantiate(fs.readFileSync(process.argv[2]), {
+            env: {
+                read: () => (at < input.length ? input[at++] : -1),
+                write: (byte) => output.push(byte),
+            },
+        }).then(({ instance }) => {
+            const status = instance.exports.run();
+            process.stdout.write(Buffer.from(output));
+            process.exitCode = status;
+        });
+    "#;
+
+    // Is LEB128 written as the WebAssembly spec gives it?
+    #[test]
+    fn test_leb128() {
+        let leb = |write: &dyn Fn(&mut Vec<u8>)| {
+            let mut bytes = Vec::new();
+            write(&mut bytes);
+            bytes
+        };
+        assert_eq!(leb(&|bytes| unsigned(bytes, 624_485)), [0xe5, 0x8e, 0x26]);
+        assert_eq!(leb(&|bytes| signed(bytes, -123_456)), [0xc0, 0xbb, 0x78]);
+        assert_eq!(leb(&|bytes| signed(bytes, 64)), [0xc0, 0x00]);
+        assert_eq!(leb(&|bytes| signed(bytes, -1)), [0x7f]);
+    }
+
+    // Does the module behave as the interpreter does? Skipped where there is no node.
+    #[test]
+    fn test_modules_run() {
+        if Command::new("node").arg("--version").output().is_err() {
+            return;
+        }
+        let directory = std::env::temp_dir().join(format!("bft-wasm-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("host.js"), HOST).unwrap();
+        let run = |program: BfProgram, options: CompileOptions, input: &[u8]| {
+            let provenance = Provenance::new(Path::new("test.bf"), &program, "bf", "none");
+            let module = compile(&program, &options, &provenance).unwrap();
+            fs::write(directory.join("test.wasm"), module).unwrap();
+            let mut child = Command::new("node")
+                .arg(directory.join("host.js"))
+                .arg(directory.join("test.wasm"))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(input).unwrap();
+            let output = child.wait_with_output().unwrap();
+            (output.status.code(), output.stdout)
+        };
+        let bf = |text: &str| BfProgram::new("test.bf", text).unwrap();
+
+        let eof_zero = CompileOptions {
+            eof_behavior: EofBehavior::SetZero,
+            opt_level: OptLevel::Full,
+            ..CompileOptions::default()
+        };
+        assert_eq!(
+            run(bf(",[+.,]"), eof_zero, b"HAL"),
+            (Some(0), b"IBM".to_vec())
+        );
+        assert_eq!(run(bf(","), CompileOptions::default(), b"").0, Some(3));
+
+        let wide = CompileOptions {
+            cell_bits: 16,
+            opt_level: OptLevel::Full,
+            ..CompileOptions::default()
+        };
+        let overflows = "++++++++++++++++[>++++++++++++++++<-]>[[-]>+<]>.-.";
+        assert_eq!(
+            run(bf(overflows), wide, b""),
+            (Some(0), b"\x01\x00".to_vec())
+        );
+        assert_eq!(
+            run(bf("-.>+[<]<"), CompileOptions::default(), b""),
+            (Some(2), b"\xff".to_vec())
+        );
+
+        let tape = |tape, cells| CompileOptions {
+            tape,
+            cells,
+            cell_bits: 32,
+            ..CompileOptions::default()
+        };
+        assert_eq!(run(bf(">>>+."), tape(Tape::Fixed, 3), b"").0, Some(1));
+        let far = format!("{}+.", ">".repeat(70_000));
+        assert_eq!(
+            run(bf(&far), tape(Tape::Extensible, 3), b""),
+            (Some(0), b"\x01".to_vec())
+        );
+        assert_eq!(
+            run(bf("+<<<.>+++."), tape(Tape::Circular, 3), b""),
+            (Some(0), b"\x01\x03".to_vec())
+        );
+
+        let extended = ParseOptions {
+            extended: true,
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("test.bf", "+++$>!{^~.@.", &extended);
+        assert_eq!(
+            run(program.unwrap(), CompileOptions::default(), b""),
+            (Some(0), b"\xfa".to_vec())
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
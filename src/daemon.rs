@@ -0,0 +1,493 @@
+//! `bft daemon`: keeping a program parsed and ready, and running it for each client that connects
+//! to a Unix socket, so tools that use a program as a filter don't pay for starting bft each time.
+//!
+//! A client writes the program's input and then shuts down its side of the connection. The daemon
+//! runs the program on that input and replies with a status line, `ok` or `error: <reason>`,
+//! followed on success by everything the program wrote, then closes the connection. Clients are
+//! served by a pool of --max-clients workers, each with a machine built ahead of time that is reset
+//! between clients, so a client does not wait for its tape to be allocated. Runs are limited in
+//! time, output and input as batch runs are, unless other limits are given, so that one client
+//! cannot hold the daemon up for everyone else.
+//!
+//! The program is read with the front-end [frontend::select] picks and kept optimised fully, so
+//! each run starts from ops that are already lowered. The program file is watched while the daemon
+//! runs. When it changes it is parsed again, and the new program replaces the old one only if it
+//! is valid; runs that have already started finish with the program they started with, and each
+//! worker builds its machine again for the new program.
+//!
+//! SIGTERM stops the daemon accepting clients and removes the socket. The runs in progress are
+//! drained as batch runs are: given a grace period to finish and reply, then cancelled.
+
+use std::error::Error;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use bft_interp::{CancelToken, FlushPolicy, HaltReason, Limits, VirtualMachine};
+use bft_types::optimize::OptLevel;
+use bft_types::{BfProgram, BftTypeError, ParseOptions};
+
+use crate::cli::DaemonArgs;
+use crate::frontend;
+use crate::metrics::Metrics;
+use crate::report::Reporter;
+use crate::shutdown::{self, Drain};
+
+/// How often the listener checks for shutdown when no client is connecting
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long each run may take unless --timeout-ms is given
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How much each run may write unless --max-output is given
+const DEFAULT_MAX_OUTPUT: u64 = 16 * 1024 * 1024;
+
+/// How much input a client may send unless --max-input is given
+const DEFAULT_MAX_INPUT: u64 = 16 * 1024 * 1024;
+
+/// How long a client may take to send its input
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the program file looked like when it was last read, to tell when it has changed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Version {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Version {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// What the listener shares with the workers serving its clients
+struct Pool {
+    /// The program to serve, replaced when its file is reloaded
+    program: Mutex<Arc<BfProgram>>,
+    /// Clients accepted and not yet taken by a worker
+    waiting: Mutex<mpsc::Receiver<UnixStream>>,
+    /// How many clients have been accepted and not yet served
+    busy: AtomicUsize,
+    metrics: Mutex<Metrics>,
+}
+
+impl Pool {
+    /// The program to serve now
+    fn program(&self) -> Arc<BfProgram> {
+        Arc::clone(
+            &self
+                .program
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        )
+    }
+
+    /// Wait for the next client, or `None` once the listener has stopped
+    fn next_client(&self) -> Option<UnixStream> {
+        self.waiting
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .recv()
+            .ok()
+    }
+
+    fn metrics(&self) -> MutexGuard<'_, Metrics> {
+        self.metrics
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Serve the program on the socket until SIGTERM
+pub fn run_daemon(args: &DaemonArgs, reporter: &Reporter) -> Result<(), Box<dyn Error>> {
+    shutdown::listen_for_sigterm();
+    serve(args, reporter, shutdown::requested)
+}
+
+/// Serve the program on the socket until `stop` returns true, then remove the socket
+fn serve(
+    args: &DaemonArgs,
+    reporter: &Reporter,
+    stop: impl Fn() -> bool + Clone + Send + 'static,
+) -> Result<(), Box<dyn Error>> {
+    let mut version = Version::of(&args.program)?;
+    let mut program = Arc::new(load(args)?);
+
+    let listener = bind(&args.socket)?;
+    listener.set_nonblocking(true)?;
+    reporter.info(format!(
+        "Serving {} on {}",
+        args.program.display(),
+        args.socket.display()
+    ));
+
+    let workers = args.max_clients.get();
+    let (sender, receiver) = mpsc::channel();
+    let pool = Pool {
+        program: Mutex::new(Arc::clone(&program)),
+        waiting: Mutex::new(receiver),
+        busy: AtomicUsize::new(0),
+        metrics: Mutex::default(),
+    };
+    pool.metrics().record_pool_size(workers);
+    let cancel_tokens: Vec<_> = (0..workers).map(|_| CancelToken::new()).collect();
+    let drain = Drain::new(
+        args.grace_ms
+            .map_or(shutdown::DEFAULT_GRACE, Duration::from_millis),
+        stop.clone(),
+    );
+    drain.running_all(cancel_tokens.iter().cloned());
+
+    let reload_interval = Duration::from_millis(args.reload_ms);
+    let served = thread::scope(|scope| {
+        for cancel_token in &cancel_tokens {
+            let pool = &pool;
+            scope.spawn(move || work(args, pool, cancel_token));
+        }
+
+        let mut last_checked = Instant::now();
+        let served = loop {
+            if stop() {
+                break Ok(());
+            }
+            if last_checked.elapsed() >= reload_interval {
+                last_checked = Instant::now();
+                reload(args, reporter, &mut version, &mut program);
+                *pool
+                    .program
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::clone(&program);
+                write_metrics(args, &pool, reporter);
+            }
+            // leave further clients waiting to be accepted until a worker is free for them
+            if pool.busy.load(Ordering::SeqCst) >= workers {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            match listener.accept() {
+                Ok((client, _)) => {
+                    pool.busy.fetch_add(1, Ordering::SeqCst);
+                    // the workers only stop once the sender is dropped, so this cannot fail
+                    let _ = sender.send(client);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(error) => break Err(error),
+            }
+        };
+
+        // no more clients, but those already accepted are answered before the workers stop
+        drop(sender);
+        served
+    });
+
+    fs::remove_file(&args.socket)?;
+    drop(drain);
+    write_metrics(args, &pool, reporter);
+    reporter.info("Stopped");
+    Ok(served?)
+}
+
+/// Serve clients one at a time until the listener stops, with a machine built before the first
+/// arrives and reset between them. The machine is only built again when the program is reloaded.
+fn work(args: &DaemonArgs, pool: &Pool, cancel_token: &CancelToken) {
+    let max_input = args.max_input.unwrap_or(DEFAULT_MAX_INPUT);
+    // a client taken while the machine was for a program that has since been replaced
+    let mut next = None;
+    loop {
+        let program = pool.program();
+        let mut vm = VirtualMachine::new(&program, args.cells, args.extensible)
+            .with_limits(limits(args))
+            .with_eof_behavior(args.eof)
+            .with_flush_policy(FlushPolicy::Manual)
+            .with_optimizations(OptLevel::Full)
+            .with_cancel_token(cancel_token.clone());
+        pool.metrics().record_machine_built();
+
+        let mut used = false;
+        loop {
+            let Some(client) = next.take().or_else(|| pool.next_client()) else {
+                return;
+            };
+            if !Arc::ptr_eq(&program, &pool.program()) {
+                next = Some(client);
+                break;
+            }
+            if used {
+                vm.reset();
+                pool.metrics().record_machine_reused();
+            }
+            used = true;
+            // the client may have gone away, in which case there is no one to tell
+            let _ = serve_client(client, &mut vm, max_input);
+            pool.busy.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Write the pool's metrics to the --metrics-file, if one was given
+fn write_metrics(args: &DaemonArgs, pool: &Pool, reporter: &Reporter) {
+    if let Some(path) = &args.metrics_file {
+        if let Err(error) = pool.metrics().write_textfile(path) {
+            reporter.error(format!("Could not write {}: {}", path.display(), error));
+        }
+    }
+}
+
+/// Listen on `socket`, replacing a socket file left behind by a daemon that is no longer running
+fn bind(socket: &Path) -> Result<UnixListener, Box<dyn Error>> {
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(
+                format!("another daemon is already serving on {}", socket.display()).into(),
+            );
+        }
+        fs::remove_file(socket)?;
+    }
+    Ok(UnixListener::bind(socket)?)
+}
+
+/// Read the program with the front-end for it, lowered to ops ready for every run
+fn load(args: &DaemonArgs) -> Result<BfProgram, BftTypeError> {
+    let mut program = frontend::select(&args.program, args.lang)
+        .frontend
+        .parse(&args.program, &ParseOptions::default())?;
+    program.keep_optimized(OptLevel::Full);
+    Ok(program)
+}
+
+/// The limits on each run
+fn limits(args: &DaemonArgs) -> Limits {
+    Limits {
+        max_instructions: args.max_instructions,
+        timeout: Some(
+            args.timeout_ms
+                .map_or(DEFAULT_TIMEOUT, Duration::from_millis),
+        ),
+        max_output: Some(args.max_output.unwrap_or(DEFAULT_MAX_OUTPUT)),
+        ..Limits::default()
+    }
+}
+
+/// Parse the program again if its file has changed, and start serving it if it is valid
+fn reload(
+    args: &DaemonArgs,
+    reporter: &Reporter,
+    version: &mut Version,
+    program: &mut Arc<BfProgram>,
+) {
+    let current = match Version::of(&args.program) {
+        Ok(current) => current,
+        Err(error) => {
+            reporter.verbose(format!(
+                "Not reloading {}: {}",
+                args.program.display(),
+                error
+            ));
+            return;
+        }
+    };
+    if current == *version {
+        return;
+    }
+    *version = current;
+    match load(args) {
+        Ok(reloaded) => {
+            *program = Arc::new(reloaded);
+            reporter.info(format!("Reloaded {}", args.program.display()));
+        }
+        Err(error) => reporter.error(format!(
+            "Not reloading {}, still serving the previous version: {}",
+            args.program.display(),
+            error
+        )),
+    }
+}
+
+/// Run the program on everything the client sends, up to `max_input` bytes, and reply with how it
+/// went
+fn serve_client(
+    mut client: UnixStream,
+    vm: &mut VirtualMachine<u8>,
+    max_input: u64,
+) -> io::Result<()> {
+    client.set_nonblocking(false)?;
+    client.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut input = Vec::new();
+    (&client)
+        .take(max_input.saturating_add(1))
+        .read_to_end(&mut input)?;
+    if input.len() as u64 > max_input {
+        writeln!(
+            client,
+            "error: the input is longer than {} bytes",
+            max_input
+        )?;
+        return client.flush();
+    }
+
+    let mut output = Vec::new();
+    let result = vm.interpret(&mut input.as_slice(), &mut output);
+    match result {
+        Ok(HaltReason::Completed) => {
+            client.write_all(b"ok\n")?;
+            client.write_all(&output)?;
+        }
+        Ok(halt_reason) => writeln!(client, "error: stopped early: {}", halt_reason)?,
+        Err(error) => writeln!(client, "error: {}", error)?,
+    }
+    client.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_interp::EofBehavior;
+    use std::net::Shutdown;
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::AtomicBool;
+
+    /// Send `input` to the daemon on `socket` and return its reply
+    fn request(socket: &Path, input: &[u8]) -> String {
+        let mut client = UnixStream::connect(socket).unwrap();
+        client.write_all(input).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        reply
+    }
+
+    // Is the program read with the front-end for its extension, and kept optimised?
+    #[test]
+    fn test_load() {
+        let directory =
+            std::env::temp_dir().join(format!("bft-daemon-load-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let program = directory.join("plus.ook");
+        fs::write(&program, "Ook. Ook. Ook. Ook. Ook! Ook.").unwrap();
+        let args = DaemonArgs {
+            program,
+            socket: directory.join("bft.sock"),
+            lang: None,
+            cells: None,
+            extensible: false,
+            eof: EofBehavior::SetZero,
+            max_instructions: None,
+            timeout_ms: None,
+            max_output: None,
+            max_input: None,
+            max_clients: NonZeroUsize::new(1).unwrap(),
+            grace_ms: None,
+            reload_ms: 10,
+            metrics_file: None,
+        };
+
+        let loaded = load(&args).unwrap();
+        assert_eq!(loaded.localised_instructions().len(), 3);
+        assert_eq!(loaded.stored_opt_level(), Some(OptLevel::Full));
+        assert_eq!(loaded.optimize(OptLevel::Full).ops().len(), 2);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    // Are requests served by the pool, and is the program reloaded only when the new version is
+    // valid?
+    #[test]
+    fn test_serve() {
+        let directory =
+            std::env::temp_dir().join(format!("bft-daemon-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let program = directory.join("echo.bf");
+        fs::write(&program, ",[.,]").unwrap();
+        let args = DaemonArgs {
+            program: program.clone(),
+            socket: directory.join("bft.sock"),
+            lang: None,
+            cells: None,
+            extensible: false,
+            eof: EofBehavior::SetZero,
+            max_instructions: Some(1_000),
+            timeout_ms: None,
+            max_output: Some(6),
+            max_input: Some(8),
+            max_clients: NonZeroUsize::new(2).unwrap(),
+            grace_ms: Some(5_000),
+            reload_ms: 10,
+            metrics_file: Some(directory.join("bft.prom")),
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        thread::scope(|scope| {
+            let daemon = scope.spawn(|| {
+                let stop = Arc::clone(&stop);
+                serve(&args, &Reporter::new(true, 0), move || {
+                    stop.load(Ordering::SeqCst)
+                })
+                .map_err(|error| error.to_string())
+            });
+            while !args.socket.exists() {
+                thread::sleep(Duration::from_millis(5));
+            }
+            assert_eq!(request(&args.socket, b"hello"), "ok\nhello");
+            assert_eq!(request(&args.socket, b""), "ok\n");
+            assert_eq!(
+                request(&args.socket, b"123456789"),
+                "error: the input is longer than 8 bytes\n"
+            );
+            assert!(request(&args.socket, b"1234567").starts_with("error: stopped early"));
+
+            fs::write(&program, ",+.").unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while request(&args.socket, b"ab") != "ok\nb" && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(request(&args.socket, b"ab"), "ok\nb");
+
+            // an invalid program is not swapped in
+            fs::write(&program, "[[").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            assert_eq!(request(&args.socket, b"ab"), "ok\nb");
+
+            fs::write(&program, "+[]").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            assert!(request(&args.socket, b"").starts_with("error: stopped early"));
+
+            // a client connected before the stop is still answered
+            fs::write(&program, ",.").unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while request(&args.socket, b"a") != "ok\na" && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+            let mut client = UnixStream::connect(&args.socket).unwrap();
+            client.write_all(b"x").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            stop.store(true, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(100));
+            client.write_all(b"y").unwrap();
+            client.shutdown(Shutdown::Write).unwrap();
+            let mut reply = String::new();
+            client.read_to_string(&mut reply).unwrap();
+            assert_eq!(reply, "ok\nx");
+            daemon.join().unwrap().unwrap();
+        });
+        assert!(!args.socket.exists());
+
+        // the machines were built for the pool and each reload, and reused for the rest
+        let metrics = fs::read_to_string(args.metrics_file.as_ref().unwrap()).unwrap();
+        assert!(metrics.contains("bft_daemon_pool_size 2\n"));
+        let reused = metrics
+            .lines()
+            .find_map(|line| line.strip_prefix("bft_daemon_machines_reused_total "))
+            .unwrap();
+        assert!(reused.parse::<u64>().unwrap() > 0);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+//! `bft doctor`: a report on how bft is set up on this machine, for working out why a program
+//! behaves differently for different people.
+
+use std::env;
+use std::error::Error;
+use std::io::{stderr, stdin, stdout, Cursor, IsTerminal};
+use std::time::Duration;
+
+use bft_interp::{HaltReason, Limits, VirtualMachine};
+use bft_types::BfProgram;
+
+use crate::report::Reporter;
+
+/// Optional features that can be compiled into bft, and whether this build has them
+const FEATURES: &[(&str, bool)] = &[
+    ("sandbox", cfg!(feature = "sandbox")),
+    ("jit", cfg!(feature = "jit")),
+    ("llvm", cfg!(feature = "llvm")),
+];
+
+/// Environment variables that affect how bft or its output behave
+const ENVIRONMENT: &[&str] = &["TERM", "COLUMNS", "NO_COLOR"];
+
+/// Program run by the self-test, and the output it must produce
+pub const SELF_TEST_PROGRAM: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+pub const SELF_TEST_OUTPUT: &[u8] = b"Hello World!\n";
+
+/// Print the diagnostics report to stdout, and fail if the self-test does not pass
+pub fn run_doctor(reporter: &Reporter) -> Result<(), Box<dyn Error>> {
+    println!("bft {}", env!("CARGO_PKG_VERSION"));
+    println!("Platform: {} {}", env::consts::OS, env::consts::ARCH);
+
+    println!();
+    println!("Configuration:");
+    println!("  config file: none (all options come from the command line)");
+    println!("  tape: 30000 u8 cells, fixed size unless --extensible is given");
+    println!("  limits: {:?}", Limits::default());
+    println!("  batch runs (--all): 10s and 16MiB of output per program unless limits are given");
+
+    println!();
+    println!("Optional features:");
+    if FEATURES.is_empty() {
+        println!("  none available in this build");
+    }
+    for (feature, enabled) in FEATURES {
+        println!(
+            "  {}: {}",
+            feature,
+            if *enabled { "enabled" } else { "disabled" }
+        );
+    }
+
+    println!();
+    println!("Environment:");
+    for variable in ENVIRONMENT {
+        match env::var(variable) {
+            Ok(value) => println!("  {}={}", variable, value),
+            Err(_) => println!("  {} is not set", variable),
+        }
+    }
+
+    println!();
+    println!("Terminal:");
+    println!("  stdin is a terminal: {}", stdin().is_terminal());
+    println!("  stdout is a terminal: {}", stdout().is_terminal());
+    println!("  stderr is a terminal: {}", stderr().is_terminal());
+
+    println!();
+    let self_test = self_test();
+    match &self_test {
+        Ok(clock) => println!("Self-test: passed ({} instructions)", clock),
+        Err(error) => println!("Self-test: FAILED: {}", error),
+    }
+    reporter.debug(format!("Self-test program: {}", SELF_TEST_PROGRAM));
+
+    self_test.map(|_| ()).map_err(|error| error.into())
+}
+
+/// Run a small known program and check its output. Returns the number of instructions executed.
+fn self_test() -> Result<u64, String> {
+    let program = BfProgram::new("self-test.bf", SELF_TEST_PROGRAM).map_err(|e| e.to_string())?;
+    let mut bf_interpreter: VirtualMachine<u8> = VirtualMachine::new(&program, None, false)
+        .with_limits(Limits {
+            timeout: Some(Duration::from_secs(1)),
+            ..Limits::default()
+        });
+
+    let mut output = Vec::new();
+    match bf_interpreter.interpret(&mut Cursor::new([]), &mut output) {
+        Ok(HaltReason::Completed) if output == SELF_TEST_OUTPUT => Ok(bf_interpreter.clock()),
+        Ok(HaltReason::Completed) => Err(format!(
+            "expected output {:?}, got {:?}",
+            String::from_utf8_lossy(SELF_TEST_OUTPUT),
+            String::from_utf8_lossy(&output)
+        )),
+        Ok(halt_reason) => Err(format!("stopped early: {}", halt_reason)),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Does the built-in self-test pass on a working interpreter?
+    #[test]
+    fn test_self_test_passes() {
+        assert!(self_test().is_ok());
+    }
+}
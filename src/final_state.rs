@@ -0,0 +1,91 @@
+//! `--assert-cell`, `--assert-head` and `--assert-halted`: checking the state a program leaves
+//! the machine in once it stops, so that shell-based test suites can check more than its output.
+//! Unlike `@assert` directives, these need no changes to the program.
+
+use bft_interp::{HaltReason, VirtualMachine};
+
+use crate::cli::Args;
+
+/// Check the conditions given on the command line against a machine that has stopped. Returns a
+/// description of each one that does not hold.
+pub fn check(args: &Args, vm: &VirtualMachine<u8>, halt_reason: HaltReason) -> Vec<String> {
+    let mut failures = Vec::new();
+
+    if let Some(expected) = args.assert_head {
+        let head = vm.head_position();
+        if head != expected as isize {
+            failures.push(format!(
+                "--assert-head {} failed: the head is over cell {}",
+                expected, head
+            ));
+        }
+    }
+
+    for &(cell, expected) in &args.assert_cell {
+        // cells the tape never grew to hold 0
+        let actual = vm
+            .tape()
+            .get(vm.origin() + cell)
+            .copied()
+            .unwrap_or_default();
+        if actual != expected {
+            failures.push(format!(
+                "--assert-cell {}={} failed: cell {} holds {}",
+                cell, expected, cell, actual
+            ));
+        }
+    }
+
+    if args.assert_halted && halt_reason != HaltReason::Completed {
+        failures.push(format!(
+            "--assert-halted failed: the program stopped early: {}",
+            halt_reason
+        ));
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::BfProgram;
+    use clap::Parser;
+    use std::io::Cursor;
+
+    // Is each condition that does not hold reported, and each that does left out?
+    #[test]
+    fn test_check() {
+        let cli = crate::cli::Cli::parse_from([
+            "bft",
+            "--assert-cell",
+            "0=72",
+            "--assert-cell",
+            "1=3",
+            "--assert-cell",
+            "500=0",
+            "--assert-head",
+            "0",
+            "--assert-halted",
+            "--max-instructions",
+            "5",
+            "prog.bf",
+        ]);
+        let args = cli.run.unwrap();
+        let program = BfProgram::new("prog.bf", ">+++<").unwrap();
+        let mut vm = args.virtual_machine(&program);
+        let halt_reason = vm.interpret(&mut Cursor::new([]), &mut Vec::new()).unwrap();
+        assert_eq!(halt_reason, HaltReason::Completed);
+
+        assert_eq!(
+            check(&args, &vm, halt_reason),
+            ["--assert-cell 0=72 failed: cell 0 holds 0"]
+        );
+        assert_eq!(
+            check(&args, &vm, HaltReason::InstructionLimit),
+            [
+                "--assert-cell 0=72 failed: cell 0 holds 0",
+                "--assert-halted failed: the program stopped early: instruction limit reached"
+            ]
+        );
+    }
+}
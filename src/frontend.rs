@@ -0,0 +1,181 @@
+//! Choosing the front-end that turns a source file into a [BfProgram], so that every command picks
+//! it the same way: from `--lang` if given, otherwise from the file's extension.
+//!
+//! Plain Brainfuck is read from `.b` and `.bf` files, Ook! from `.ook` files, and programs compiled
+//! with `bft compile --target bytecode` from `.bfc` files. Other dialects are added here as
+//! variants of [Frontend], along with the extensions they claim.
+
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+use bft_types::{BfProgram, BftTypeError, ParseOptions};
+
+/// A source language bft can read
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Frontend {
+    /// Plain Brainfuck, with the options in [ParseOptions]
+    #[default]
+    Brainfuck,
+    /// Ook!, see [bft_types::ook]
+    Ook,
+    /// A program already parsed and analysed, see [bft_types::bytecode]. The [ParseOptions] it
+    /// was compiled with are kept, and those given to [Frontend::parse] ignored.
+    Bytecode,
+}
+
+impl Frontend {
+    /// Every front-end, in the order they are listed in help
+    pub const ALL: [Frontend; 3] = [Frontend::Brainfuck, Frontend::Ook, Frontend::Bytecode];
+
+    /// The name given to `--lang`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Frontend::Brainfuck => "bf",
+            Frontend::Ook => "ook",
+            Frontend::Bytecode => "bytecode",
+        }
+    }
+
+    /// The file extensions that select this front-end
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Frontend::Brainfuck => &["b", "bf"],
+            Frontend::Ook => &["ook"],
+            Frontend::Bytecode => &["bfc"],
+        }
+    }
+
+    /// The front-end for a file, going by its extension alone
+    pub fn detect(path: &Path) -> Option<Frontend> {
+        let extension = path.extension()?.to_str()?;
+        Self::ALL
+            .into_iter()
+            .find(|frontend| frontend.extensions().contains(&extension))
+    }
+
+    /// Read and parse the program at `path`
+    pub fn parse(&self, path: &Path, options: &ParseOptions) -> Result<BfProgram, BftTypeError> {
+        match self {
+            Frontend::Brainfuck => BfProgram::from_file_with_options(path, options),
+            Frontend::Ook => {
+                let text = fs::read_to_string(path).map_err(BftTypeError::IoError)?;
+                bft_types::ook::parse(path, &text, options)
+            }
+            Frontend::Bytecode => {
+                let bytes = fs::read(path).map_err(BftTypeError::IoError)?;
+                BfProgram::from_bytecode(&bytes)
+            }
+        }
+    }
+}
+
+impl Display for Frontend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The front-end chosen for a file, and why it was chosen
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Selection {
+    pub frontend: Frontend,
+    reason: Reason,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Reason {
+    Lang,
+    Extension,
+    Default,
+}
+
+impl Display for Selection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({})",
+            self.frontend,
+            match self.reason {
+                Reason::Lang => "chosen with --lang",
+                Reason::Extension => "from the file extension",
+                Reason::Default => "the default, as the file extension is not known",
+            }
+        )
+    }
+}
+
+/// Choose the front-end for the file at `path`: the one given with `--lang`, or else the one for
+/// its extension, or else plain Brainfuck
+pub fn select(path: &Path, lang: Option<Frontend>) -> Selection {
+    match (lang, Frontend::detect(path)) {
+        (Some(frontend), _) => Selection {
+            frontend,
+            reason: Reason::Lang,
+        },
+        (None, Some(frontend)) => Selection {
+            frontend,
+            reason: Reason::Extension,
+        },
+        (None, None) => Selection {
+            frontend: Frontend::default(),
+            reason: Reason::Default,
+        },
+    }
+}
+
+/// Parse the name of a front-end given with `--lang`
+pub fn parse_lang(value: &str) -> Result<Frontend, String> {
+    Frontend::ALL
+        .into_iter()
+        .find(|frontend| frontend.name() == value)
+        .ok_or_else(|| {
+            let names: Vec<_> = Frontend::ALL.iter().map(Frontend::name).collect();
+            format!("expected one of {}, got '{}'", names.join(", "), value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Is the front-end taken from --lang first, then the extension, then the default?
+    #[test]
+    fn test_select() {
+        assert_eq!(
+            Frontend::detect(Path::new("hello.b")),
+            Some(Frontend::Brainfuck)
+        );
+        assert_eq!(
+            Frontend::detect(Path::new("dir.bf/hello.bf")),
+            Some(Frontend::Brainfuck)
+        );
+        assert_eq!(
+            Frontend::detect(Path::new("hello.ook")),
+            Some(Frontend::Ook)
+        );
+        assert_eq!(
+            Frontend::detect(Path::new("hello.bfc")),
+            Some(Frontend::Bytecode)
+        );
+        assert_eq!(Frontend::detect(Path::new("hello.txt")), None);
+        assert_eq!(Frontend::detect(Path::new("bf")), None);
+
+        assert_eq!(
+            select(Path::new("hello.bf"), None).to_string(),
+            "bf (from the file extension)"
+        );
+        assert_eq!(
+            select(Path::new("hello.txt"), Some(Frontend::Brainfuck)).to_string(),
+            "bf (chosen with --lang)"
+        );
+        assert_eq!(
+            select(Path::new("hello"), None).frontend,
+            Frontend::Brainfuck
+        );
+
+        assert_eq!(parse_lang("bf"), Ok(Frontend::Brainfuck));
+        assert_eq!(parse_lang("ook"), Ok(Frontend::Ook));
+        assert!(parse_lang("blub").is_err());
+    }
+}
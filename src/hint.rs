@@ -0,0 +1,271 @@
+//! Hints added to the commonest runtime errors, saying which option would let the program run on,
+//! such as `--extensible` after a head overrun on a fixed tape. Each is chosen from [HINTS] by the
+//! kind of error and how the program was run, and is shown on the line after the error. An error
+//! at an instruction that came from a macro also gets a note naming the macro, as its line and
+//! column are where the macro was used.
+
+use std::fmt::Display;
+use std::io::ErrorKind;
+
+use bft_interp::{EofBehavior, VMError};
+use bft_types::messages::{self, Localise, Message};
+use bft_types::{BfProgram, Instruction};
+
+use crate::cli::Args;
+
+/// What a hint can know about the run that failed
+pub struct Context<'a> {
+    pub args: &'a Args,
+    pub program: &'a BfProgram,
+}
+
+impl Context<'_> {
+    /// Whether the program has a `>` before the instruction at `line_num`, `column_num`
+    fn moves_right_before(&self, line_num: usize, column_num: usize) -> bool {
+        let index = self
+            .program
+            .instruction_at(line_num, column_num)
+            .unwrap_or_default();
+        self.program.localised_instructions()[..index]
+            .iter()
+            .any(|instruction| instruction.instruction() == Instruction::MoveRight)
+    }
+}
+
+/// One entry in the hint table
+struct Hint {
+    /// The code of the hint's message
+    code: &'static str,
+    /// Whether the hint fits this error, run this way
+    applies: fn(&VMError, &Context) -> bool,
+    /// The values for the hint's template
+    args: fn(&VMError, &Context) -> Vec<(&'static str, String)>,
+}
+
+/// Every hint, most specific first. The first that applies is used.
+const HINTS: &[Hint] = &[
+    Hint {
+        code: messages::HINT_UNDERRUN_AT_START,
+        applies: |error, context| match error {
+            VMError::HeadUnderrun(instruction) => {
+                !context.args.bidirectional
+                    && !context.moves_right_before(instruction.line_num(), instruction.column_num())
+            }
+            _ => false,
+        },
+        args: |_, _| Vec::new(),
+    },
+    Hint {
+        code: messages::HINT_BIDIRECTIONAL,
+        applies: |error, context| {
+            matches!(error, VMError::HeadUnderrun(_)) && !context.args.bidirectional
+        },
+        args: |_, _| Vec::new(),
+    },
+    Hint {
+        code: messages::HINT_EXTENSIBLE,
+        applies: |error, context| {
+            matches!(error, VMError::HeadOverrun(_)) && !context.args.extensible
+        },
+        args: |_, context| {
+            let cells = context
+                .args
+                .cells
+                .map(|cells| cells.get())
+                .unwrap_or(30_000);
+            vec![("cells", cells.to_string())]
+        },
+    },
+    Hint {
+        code: messages::HINT_EOF,
+        applies: |error, context| match error {
+            VMError::ReadError(_, error) => {
+                error.kind() == ErrorKind::UnexpectedEof && context.args.eof == EofBehavior::Error
+            }
+            _ => false,
+        },
+        args: |_, _| Vec::new(),
+    },
+    Hint {
+        code: messages::HINT_MAX_CELLS,
+        applies: |error, _| matches!(error, VMError::TapeLimitExceeded(..)),
+        args: |error, _| match error {
+            VMError::TapeLimitExceeded(_, limit) => vec![("limit", limit.to_string())],
+            _ => Vec::new(),
+        },
+    },
+    Hint {
+        code: messages::HINT_MAX_MEMORY,
+        applies: |error, _| matches!(error, VMError::MemoryLimitExceeded(_)),
+        args: |error, _| match error {
+            VMError::MemoryLimitExceeded(error) => vec![("limit", error.limit.to_string())],
+            _ => Vec::new(),
+        },
+    },
+    Hint {
+        code: messages::HINT_ARITHMETIC,
+        applies: |error, _| matches!(error, VMError::CellOverflow(_) | VMError::CellUnderflow(_)),
+        args: |_, _| Vec::new(),
+    },
+];
+
+/// The hint for `error`, if there is one for it
+pub fn find(error: &VMError, context: &Context) -> Option<Message> {
+    HINTS
+        .iter()
+        .find(|hint| (hint.applies)(error, context))
+        .map(|hint| Message::new(hint.code, (hint.args)(error, context)))
+}
+
+/// The note saying which macro the instruction behind `error` came from, if it came from one
+pub fn note(error: &VMError, context: &Context) -> Option<Message> {
+    let expansion = context.program.expansion(error.instruction()?)?;
+    let mut args = vec![
+        ("line", expansion.line_num.to_string()),
+        ("column", expansion.column_num.to_string()),
+    ];
+    Some(match expansion.name {
+        Some(name) => {
+            args.push(("macro", name.to_string()));
+            Message::new(messages::NOTE_FROM_MACRO, args)
+        }
+        None => Message::new(messages::NOTE_FROM_UNNAMED_MACRO, args),
+    })
+}
+
+/// An error from running a program, with a note on where it came from and a hint about how it
+/// could be avoided
+#[derive(Debug)]
+pub struct Hinted {
+    pub error: VMError,
+    pub note: Option<Message>,
+    pub hint: Option<Message>,
+}
+
+impl Hinted {
+    /// `error` with its note and hint attached, or as it is if it has neither
+    pub fn attach(error: VMError, context: &Context) -> Box<dyn std::error::Error> {
+        match (note(&error, context), find(&error, context)) {
+            (None, None) => Box::new(error),
+            (note, hint) => Box::new(Hinted { error, note, hint }),
+        }
+    }
+
+    /// The error, then its note and hint, in the catalog's language
+    pub fn render(&self, catalog: &messages::Catalog) -> String {
+        let mut text = self.error.message().render(catalog);
+        for message in self.note.iter().chain(&self.hint) {
+            text.push('\n');
+            text.push_str(&message.text(catalog));
+        }
+        text
+    }
+}
+
+impl Display for Hinted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(&messages::Catalog::default()))
+    }
+}
+
+impl std::error::Error for Hinted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use bft_interp::VirtualMachine;
+    use clap::Parser;
+    use std::io::Cursor;
+
+    /// The hint for running `text` with `options`, if it fails with one
+    fn hint_for(text: &str, options: &[&str]) -> Option<&'static str> {
+        let cli = Cli::parse_from(["bft"].iter().chain(options).chain(&["program.bf"]));
+        let args = cli.run.unwrap();
+        let program = BfProgram::new("program.bf", text).unwrap();
+        let mut vm = args.virtual_machine(&program);
+        let error = vm
+            .interpret(&mut Cursor::new(Vec::new()), &mut Vec::new())
+            .unwrap_err();
+        let context = Context {
+            args: &args,
+            program: &program,
+        };
+        find(&error, &context).map(|hint| hint.code)
+    }
+
+    // Is the hint chosen by the kind of error and how the program was run?
+    #[test]
+    fn test_find() {
+        assert_eq!(hint_for("<", &[]), Some(messages::HINT_UNDERRUN_AT_START));
+        assert_eq!(hint_for("><<", &[]), Some(messages::HINT_BIDIRECTIONAL));
+        assert_eq!(
+            hint_for(">>>", &["--cells", "2"]),
+            Some(messages::HINT_EXTENSIBLE)
+        );
+        assert_eq!(hint_for(",", &[]), Some(messages::HINT_EOF));
+        assert_eq!(
+            hint_for("-", &["--arithmetic", "checked"]),
+            Some(messages::HINT_ARITHMETIC)
+        );
+        assert_eq!(
+            hint_for(">>>", &["--cells", "1", "-e", "--max-cells", "2"]),
+            Some(messages::HINT_MAX_CELLS)
+        );
+    }
+
+    // Is the hint shown on the line after the error?
+    #[test]
+    fn test_render() {
+        let program = BfProgram::new("program.bf", ">").unwrap();
+        let mut vm: VirtualMachine<u8> =
+            VirtualMachine::new(&program, std::num::NonZeroUsize::new(1), false);
+        let error = vm
+            .interpret(&mut Cursor::new(Vec::new()), &mut Vec::new())
+            .unwrap_err();
+        let cli = Cli::parse_from(["bft", "--cells", "1", "program.bf"]);
+        let args = cli.run.unwrap();
+        let context = Context {
+            args: &args,
+            program: &program,
+        };
+        let hinted = Hinted::attach(error, &context);
+        assert_eq!(
+            hinted.to_string(),
+            "BFT0102: Head overrun error occurred at line 1 column 1\n\
+             hint: re-run with --extensible to let the tape grow, or give it more than 1 cells with --cells"
+        );
+    }
+
+    // Is an error at an instruction from a macro shown with the macro's name?
+    #[test]
+    fn test_note() {
+        let cli = Cli::parse_from(["bft", "--macros", "program.bf"]);
+        let args = cli.run.unwrap();
+        let program = BfProgram::new_with_options(
+            "program.bf",
+            "#define BACK <\n>BACK BACK",
+            &args.parse_options(),
+        )
+        .unwrap();
+        let mut vm = args.virtual_machine(&program);
+        let error = vm
+            .interpret(&mut Cursor::new(Vec::new()), &mut Vec::new())
+            .unwrap_err();
+        let context = Context {
+            args: &args,
+            program: &program,
+        };
+        let hinted = Hinted::attach(error, &context);
+        assert_eq!(
+            hinted.to_string(),
+            "BFT0101: Head underrun error occurred at line 2 column 7\n\
+             note: the instruction is from the macro BACK, written at line 1 column 14\n\
+             hint: re-run with --bidirectional to let the tape grow to the left of cell 0"
+        );
+    }
+}
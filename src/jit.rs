@@ -0,0 +1,879 @@
+//! Running programs as native code, compiled just in time with Cranelift.
+//!
+//! The program is lowered by [BfProgram::optimize] and each op translated to Cranelift IR, much
+//! as the targets in [crate::compile] translate it to source, then compiled for the machine bft
+//! is running on. Input and output go through callbacks into Rust, so that any [Read] and
+//! [Write] can be used, and the tape is a buffer that the compiled code indexes directly. A
+//! program that fails stops with the same [VMError] as the interpreter would give, naming the
+//! same instruction.
+
+use std::fmt::Display;
+use std::io::{self, ErrorKind, Read, Write};
+
+use bft_interp::{EofBehavior, VMError};
+use bft_types::optimize::Op;
+use bft_types::{BfProgram, Instruction};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, FuncRef, InstBuilder, MemFlags, Type, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use crate::compile::{is_supported, unsupported, CompileOptions, Tape};
+
+// what the compiled code returns
+const COMPLETED: i64 = 0;
+const OVERRUN: i64 = 1;
+const UNDERRUN: i64 = 2;
+const END_OF_INPUT: i64 = 3;
+const READ_FAILED: i64 = 4;
+const WRITE_FAILED: i64 = 5;
+
+/// The compiled program: given the context, the tape and its length in bytes, and where to put
+/// the index of the instruction that failed, it returns one of the statuses above
+type Entry = unsafe extern "C" fn(*mut Context, *mut u8, usize, *mut usize) -> i32;
+
+/// What the callbacks need while the compiled program runs
+struct Context<'a> {
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+    tape: Vec<u8>,
+    /// Why the last read or write failed
+    error: Option<io::Error>,
+}
+
+/// The byte `,` reads, -1 at the end of input or -2 if reading failed
+extern "C" fn read_byte(context: *mut Context) -> i32 {
+    // SAFETY: the compiled code only passes on the context JitProgram::run gave it
+    let context = unsafe { &mut *context };
+    let mut byte = [0];
+    let read = context.output.flush().and_then(|()| loop {
+        match context.input.read(&mut byte) {
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            result => break result,
+        }
+    });
+    match read {
+        Ok(0) => -1,
+        Ok(_) => i32::from(byte[0]),
+        Err(error) => {
+            context.error = Some(error);
+            -2
+        }
+    }
+}
+
+/// Write the byte for `.`, returning 0, or 1 if writing failed
+extern "C" fn write_byte(context: *mut Context, byte: i32) -> i32 {
+    // SAFETY: as for read_byte
+    let context = unsafe { &mut *context };
+    match context.output.write_all(&[byte as u8]) {
+        Ok(()) => 0,
+        Err(error) => {
+            context.error = Some(error);
+            1
+        }
+    }
+}
+
+/// Grow an extensible tape to `len` bytes, returning where it now is
+extern "C" fn grow_tape(context: *mut Context, len: usize) -> *mut u8 {
+    // SAFETY: as for read_byte
+    let context = unsafe { &mut *context };
+    context.tape.resize(len, 0);
+    context.tape.as_mut_ptr()
+}
+
+/// A program compiled to native code, ready to run as often as needed
+///
+/// ```
+///# use bft::compile::CompileOptions;
+///# use bft::jit::JitProgram;
+///# use bft_types::BfProgram;
+/// let program = BfProgram::new("hello.bf", "++++++++[>++++++++<-]>+.")?;
+/// let compiled = JitProgram::new(&program, &CompileOptions::default())?;
+///
+/// let mut output = Vec::new();
+/// compiled.run(&mut std::io::empty(), &mut output)?;
+/// assert_eq!(output, b"A");
+///# Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct JitProgram<'p> {
+    program: &'p BfProgram,
+    tape_bytes: usize,
+    /// Where the compiled code lives. Only taken to be freed.
+    module: Option<JITModule>,
+    entry: Entry,
+}
+
+impl<'p> JitProgram<'p> {
+    /// Compile `program` to native code that runs it with the given options
+    pub fn new(program: &'p BfProgram, options: &CompileOptions) -> Result<Self, String> {
+        let cell = match options.cell_bits {
+            8 => types::I8,
+            16 => types::I16,
+            32 => types::I32,
+            bits => return Err(format!("cells must be 8, 16 or 32 bits wide, not {}", bits)),
+        };
+        let tape_bytes = options
+            .cells
+            .checked_mul(cell.bytes() as usize)
+            .ok_or("the tape is too big")?;
+
+        let mut flags = settings::builder();
+        // the callbacks are found by address, as they are not in the same object as the code
+        flags
+            .set("use_colocated_libcalls", "false")
+            .map_err(failed)?;
+        flags.set("is_pic", "false").map_err(failed)?;
+        flags.set("opt_level", "speed").map_err(failed)?;
+        let isa = cranelift_native::builder()
+            .map_err(failed)?
+            .finish(settings::Flags::new(flags))
+            .map_err(failed)?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbol("bft_read", read_byte as *const u8);
+        builder.symbol("bft_write", write_byte as *const u8);
+        builder.symbol("bft_grow", grow_tape as *const u8);
+        let mut module = JITModule::new(builder);
+        let pointer = module.target_config().pointer_type();
+
+        let mut import = |name, params: &[Type], returns: Type| {
+            let mut signature = module.make_signature();
+            signature
+                .params
+                .extend(params.iter().map(|&param| AbiParam::new(param)));
+            signature.returns.push(AbiParam::new(returns));
+            module
+                .declare_function(name, Linkage::Import, &signature)
+                .map_err(failed)
+        };
+        let read = import("bft_read", &[pointer], types::I32)?;
+        let write = import("bft_write", &[pointer, types::I32], types::I32)?;
+        let grow = import("bft_grow", &[pointer, pointer], pointer)?;
+
+        let mut context = module.make_context();
+        let signature = &mut context.func.signature;
+        signature.params.extend([AbiParam::new(pointer); 4]);
+        signature.returns.push(AbiParam::new(types::I32));
+        let mut function_context = FunctionBuilderContext::new();
+        let mut builder = FunctionBuilder::new(&mut context.func, &mut function_context);
+        let callbacks = [read, write, grow].map(|id| module.declare_func_in_func(id, builder.func));
+
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let params = builder.block_params(entry).to_vec();
+        let exit = builder.create_block();
+        builder.append_block_param(exit, types::I32);
+        builder.append_block_param(exit, pointer);
+
+        let mut translator = Translator {
+            builder,
+            pointer,
+            cell,
+            options: *options,
+            context: params[0],
+            head: Variable::from_u32(0),
+            tape: Variable::from_u32(1),
+            len: Variable::from_u32(2),
+            storage: Variable::from_u32(3),
+            read: callbacks[0],
+            write: callbacks[1],
+            grow: callbacks[2],
+            exit,
+            loops: Vec::new(),
+        };
+        for (variable, ty, value) in [
+            (translator.head, pointer, None),
+            (translator.tape, pointer, Some(params[1])),
+            (translator.len, pointer, Some(params[2])),
+            (translator.storage, types::I8, None),
+        ] {
+            translator.builder.declare_var(variable, ty);
+            let value = value.unwrap_or_else(|| translator.builder.ins().iconst(ty, 0));
+            translator.builder.def_var(variable, value);
+        }
+        translator.translate(program)?;
+
+        // the exit block records where the program stopped and returns the status
+        let mut builder = translator.builder;
+        let completed = builder.ins().iconst(types::I32, COMPLETED);
+        let nowhere = builder.ins().iconst(pointer, 0);
+        builder.ins().jump(exit, &[completed, nowhere]);
+        builder.switch_to_block(exit);
+        builder.seal_block(exit);
+        let (status, instruction) = (builder.block_params(exit)[0], builder.block_params(exit)[1]);
+        builder
+            .ins()
+            .store(MemFlags::trusted(), instruction, params[3], 0);
+        builder.ins().return_(&[status]);
+        builder.finalize();
+
+        let id = module
+            .declare_function("bft_run", Linkage::Local, &context.func.signature)
+            .map_err(failed)?;
+        module.define_function(id, &mut context).map_err(failed)?;
+        module.clear_context(&mut context);
+        module.finalize_definitions().map_err(failed)?;
+        // SAFETY: the function was defined with the signature of Entry
+        let entry =
+            unsafe { std::mem::transmute::<*const u8, Entry>(module.get_finalized_function(id)) };
+
+        Ok(Self {
+            program,
+            tape_bytes,
+            module: Some(module),
+            entry,
+        })
+    }
+
+    /// Run the program from the start with a fresh tape
+    pub fn run(&self, input: &mut dyn Read, output: &mut dyn Write) -> Result<(), VMError> {
+        let mut context = Context {
+            input,
+            output,
+            tape: vec![0; self.tape_bytes],
+            error: None,
+        };
+        let mut fault = 0;
+        let context: *mut Context = &mut context;
+        // SAFETY: the code checks every move of the head against the length of the tape it is
+        // given, and takes the tape back from grow_tape whenever it grows
+        let status = unsafe {
+            let tape = (*context).tape.as_mut_ptr();
+            (self.entry)(context, tape, self.tape_bytes, &mut fault)
+        };
+        // SAFETY: the compiled code has finished with the context
+        let error = unsafe { (*context).error.take() };
+
+        let instruction = || self.program.localised_instructions()[fault];
+        match i64::from(status) {
+            COMPLETED => Ok(()),
+            OVERRUN => Err(VMError::HeadOverrun(instruction())),
+            UNDERRUN => Err(VMError::HeadUnderrun(instruction())),
+            END_OF_INPUT => {
+                let error = io::Error::new(ErrorKind::UnexpectedEof, "the input has ended");
+                Err(VMError::from((instruction(), error)))
+            }
+            READ_FAILED => Err(VMError::ReadError(
+                instruction(),
+                error.unwrap_or_else(|| ErrorKind::Other.into()),
+            )),
+            _ => Err(VMError::WriteError(
+                instruction(),
+                error.unwrap_or_else(|| ErrorKind::Other.into()),
+            )),
+        }
+    }
+}
+
+impl Drop for JitProgram<'_> {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: entry, the only pointer into the module's code, goes with it
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// The error for a failure inside Cranelift
+fn failed(error: impl Display) -> String {
+    format!("could not compile to native code: {}", error)
+}
+
+/// Translates the ops of a program into the body of the compiled function
+struct Translator<'f> {
+    builder: FunctionBuilder<'f>,
+    pointer: Type,
+    cell: Type,
+    options: CompileOptions,
+    context: Value,
+    /// Offset of the cell under the head from the start of the tape, in bytes
+    head: Variable,
+    /// Where the tape is, which changes as an extensible tape grows
+    tape: Variable,
+    /// Length of the tape in bytes
+    len: Variable,
+    /// The storage byte of Extended Brainfuck Type I
+    storage: Variable,
+    read: FuncRef,
+    write: FuncRef,
+    grow: FuncRef,
+    /// Takes a status and the index of an instruction, and returns
+    exit: Block,
+    /// The block that checks the cell and the block after the loop, for each open loop
+    loops: Vec<(Block, Block)>,
+}
+
+impl Translator<'_> {
+    fn translate(&mut self, program: &BfProgram) -> Result<(), String> {
+        let optimized = program.optimize(self.options.opt_level);
+        for (index, op) in optimized.ops().iter().enumerate() {
+            let instruction = optimized.instructions_of(index).start;
+            match *op {
+                Op::Add(amount) => {
+                    let cell = self.load();
+                    let amount = self.cell_const(amount as i64);
+                    let sum = self.builder.ins().iadd(cell, amount);
+                    self.store(sum);
+                }
+                Op::Move(distance) => self.move_head(distance, instruction),
+                Op::SetZero { .. } => {
+                    let zero = self.cell_const(0);
+                    self.store(zero);
+                }
+                Op::Scan { right } => {
+                    self.loop_start();
+                    // the `>` or `<` inside the loop is the one to blame for running off the tape
+                    self.move_head(if right { 1 } else { -1 }, instruction + 1);
+                    self.loop_end();
+                }
+                Op::JumpIfZero(_) => self.loop_start(),
+                Op::JumpUnlessZero(_) => self.loop_end(),
+                Op::Input => self.input(instruction),
+                Op::Output => {
+                    let cell = self.load();
+                    let byte = self.extend(cell, types::I32);
+                    let call = self.builder.ins().call(self.write, &[self.context, byte]);
+                    let result = self.builder.inst_results(call)[0];
+                    let failed = self.builder.ins().icmp_imm(IntCC::NotEqual, result, 0);
+                    self.exit_if(failed, WRITE_FAILED, instruction);
+                }
+                Op::Other(other) if !is_supported(other) => {
+                    return Err(unsupported(program, instruction, "native code"));
+                }
+                Op::Other(other) => self.extended(other),
+            }
+        }
+        Ok(())
+    }
+
+    /// A cell holding `value`, wrapped round to fit
+    fn cell_const(&mut self, value: i64) -> Value {
+        let mask = (1i64 << self.cell.bits()) - 1;
+        self.builder.ins().iconst(self.cell, value & mask)
+    }
+
+    /// Zero-extend or truncate `value` to `ty`
+    fn extend(&mut self, value: Value, ty: Type) -> Value {
+        let from = self.builder.func.dfg.value_type(value);
+        if from.bits() < ty.bits() {
+            self.builder.ins().uextend(ty, value)
+        } else if from.bits() > ty.bits() {
+            self.builder.ins().ireduce(ty, value)
+        } else {
+            value
+        }
+    }
+
+    fn address(&mut self) -> Value {
+        let tape = self.builder.use_var(self.tape);
+        let head = self.builder.use_var(self.head);
+        self.builder.ins().iadd(tape, head)
+    }
+
+    fn load(&mut self) -> Value {
+        let address = self.address();
+        self.builder
+            .ins()
+            .load(self.cell, MemFlags::new(), address, 0)
+    }
+
+    fn store(&mut self, value: Value) {
+        let address = self.address();
+        self.builder.ins().store(MemFlags::new(), value, address, 0);
+    }
+
+    /// Leave the program with `status`, blaming `instruction`, if `condition` is not zero
+    fn exit_if(&mut self, condition: Value, status: i64, instruction: usize) {
+        self.exit_if_blaming(condition, status, |translator| {
+            translator
+                .builder
+                .ins()
+                .iconst(translator.pointer, instruction as i64)
+        });
+    }
+
+    /// Leave the program with `status` if `condition` is not zero, blaming the instruction whose
+    /// index `blame` works out on the way out
+    fn exit_if_blaming(
+        &mut self,
+        condition: Value,
+        status: i64,
+        blame: impl FnOnce(&mut Self) -> Value,
+    ) {
+        let leave = self.builder.create_block();
+        let carry_on = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, leave, &[], carry_on, &[]);
+        self.builder.seal_block(leave);
+        self.builder.switch_to_block(leave);
+        let instruction = blame(self);
+        let status = self.builder.ins().iconst(types::I32, status);
+        self.builder.ins().jump(self.exit, &[status, instruction]);
+        self.builder.seal_block(carry_on);
+        self.builder.switch_to_block(carry_on);
+    }
+
+    fn loop_start(&mut self) {
+        let check = self.builder.create_block();
+        let body = self.builder.create_block();
+        let after = self.builder.create_block();
+        self.builder.ins().jump(check, &[]);
+        self.builder.switch_to_block(check);
+        let cell = self.load();
+        self.builder.ins().brif(cell, body, &[], after, &[]);
+        self.builder.seal_block(body);
+        self.builder.switch_to_block(body);
+        self.loops.push((check, after));
+    }
+
+    fn loop_end(&mut self) {
+        // the program has been analysed, so every `]` has its `[`
+        let Some((check, after)) = self.loops.pop() else {
+            return;
+        };
+        self.builder.ins().jump(check, &[]);
+        self.builder.seal_block(check);
+        self.builder.seal_block(after);
+        self.builder.switch_to_block(after);
+    }
+
+    /// Move the head by `distance` cells, for a run of that many `>` or `<` from `instruction`.
+    /// Running off the tape blames the move in the run that went too far, as the interpreter
+    /// would.
+    fn move_head(&mut self, distance: isize, instruction: usize) {
+        let cell_bytes = self.cell.bytes() as usize;
+        let cell_shift = i64::from(cell_bytes.trailing_zeros());
+        let tape_bytes = self.options.cells * cell_bytes;
+        let bytes = (distance.unsigned_abs() * cell_bytes) as i64;
+        let head = self.builder.use_var(self.head);
+        match (self.options.tape, distance >= 0) {
+            (Tape::Circular, right) => {
+                // the tape never changes length, so the wrap can be worked out now
+                let bytes = bytes as usize % tape_bytes;
+                if bytes > 0 {
+                    let step = if right { bytes } else { tape_bytes - bytes };
+                    let moved = self.builder.ins().iadd_imm(head, step as i64);
+                    let wrapped = self.builder.ins().urem_imm(moved, tape_bytes as i64);
+                    self.builder.def_var(self.head, wrapped);
+                }
+            }
+            (Tape::Fixed, true) => {
+                let moved = self.builder.ins().iadd_imm(head, bytes);
+                self.builder.def_var(self.head, moved);
+                let len = self.builder.use_var(self.len);
+                let off_end =
+                    self.builder
+                        .ins()
+                        .icmp(IntCC::UnsignedGreaterThanOrEqual, moved, len);
+                // the move onto the cell just past the end
+                self.exit_if_blaming(off_end, OVERRUN, |translator| {
+                    let left = translator.builder.ins().isub(len, head);
+                    let cells = translator.builder.ins().ushr_imm(left, cell_shift);
+                    translator
+                        .builder
+                        .ins()
+                        .iadd_imm(cells, instruction as i64 - 1)
+                });
+            }
+            (Tape::Extensible, true) => {
+                let moved = self.builder.ins().iadd_imm(head, bytes);
+                self.builder.def_var(self.head, moved);
+                let end = self.builder.ins().iadd_imm(moved, cell_bytes as i64);
+                let len = self.builder.use_var(self.len);
+                let off_end = self
+                    .builder
+                    .ins()
+                    .icmp(IntCC::UnsignedGreaterThan, end, len);
+                let grow = self.builder.create_block();
+                let carry_on = self.builder.create_block();
+                self.builder.ins().brif(off_end, grow, &[], carry_on, &[]);
+                self.builder.seal_block(grow);
+                self.builder.switch_to_block(grow);
+                // at least double the tape, as a Vec would
+                let doubled = self.builder.ins().ishl_imm(len, 1);
+                let new_len = self.builder.ins().umax(end, doubled);
+                let call = self.builder.ins().call(self.grow, &[self.context, new_len]);
+                let tape = self.builder.inst_results(call)[0];
+                self.builder.def_var(self.tape, tape);
+                self.builder.def_var(self.len, new_len);
+                self.builder.ins().jump(carry_on, &[]);
+                self.builder.seal_block(carry_on);
+                self.builder.switch_to_block(carry_on);
+            }
+            (Tape::Fixed | Tape::Extensible, false) => {
+                let off_start = self
+                    .builder
+                    .ins()
+                    .icmp_imm(IntCC::UnsignedLessThan, head, bytes);
+                // the move onto the cell just before the start
+                self.exit_if_blaming(off_start, UNDERRUN, |translator| {
+                    let cells = translator.builder.ins().ushr_imm(head, cell_shift);
+                    translator.builder.ins().iadd_imm(cells, instruction as i64)
+                });
+                let moved = self.builder.ins().iadd_imm(head, -bytes);
+                self.builder.def_var(self.head, moved);
+            }
+        }
+    }
+
+    fn input(&mut self, instruction: usize) {
+        let call = self.builder.ins().call(self.read, &[self.context]);
+        let byte = self.builder.inst_results(call)[0];
+        let failed = self.builder.ins().icmp_imm(IntCC::Equal, byte, -2);
+        self.exit_if(failed, READ_FAILED, instruction);
+        let at_end = self.builder.ins().icmp_imm(IntCC::SignedLessThan, byte, 0);
+
+        let at_eof = match self.options.eof_behavior {
+            EofBehavior::Error => {
+                self.exit_if(at_end, END_OF_INPUT, instruction);
+                let byte = self.extend(byte, self.cell);
+                self.store(byte);
+                return;
+            }
+            EofBehavior::SetZero => Some(0),
+            EofBehavior::SetMax => Some(-1),
+            EofBehavior::LeaveUnchanged => None,
+        };
+        let eof = self.builder.create_block();
+        let read = self.builder.create_block();
+        let carry_on = self.builder.create_block();
+        self.builder.ins().brif(at_end, eof, &[], read, &[]);
+        self.builder.seal_block(eof);
+        self.builder.seal_block(read);
+
+        self.builder.switch_to_block(eof);
+        if let Some(value) = at_eof {
+            let value = self.cell_const(value);
+            self.store(value);
+        }
+        self.builder.ins().jump(carry_on, &[]);
+
+        self.builder.switch_to_block(read);
+        let byte = self.extend(byte, self.cell);
+        self.store(byte);
+        self.builder.ins().jump(carry_on, &[]);
+        self.builder.seal_block(carry_on);
+        self.builder.switch_to_block(carry_on);
+    }
+
+    /// Translate an instruction of Extended Brainfuck Type I
+    fn extended(&mut self, instruction: Instruction) {
+        if instruction == Instruction::End {
+            let completed = self.builder.ins().iconst(types::I32, COMPLETED);
+            let nowhere = self.builder.ins().iconst(self.pointer, 0);
+            self.builder.ins().jump(self.exit, &[completed, nowhere]);
+            // anything after `@` is never run, but still needs a block to go in
+            let unreachable = self.builder.create_block();
+            self.builder.seal_block(unreachable);
+            self.builder.switch_to_block(unreachable);
+            return;
+        }
+        let cell = self.load();
+        let storage = self.builder.use_var(self.storage);
+        let storage = self.extend(storage, self.cell);
+        let changed = match instruction {
+            Instruction::Store => {
+                let byte = self.extend(cell, types::I8);
+                self.builder.def_var(self.storage, byte);
+                return;
+            }
+            Instruction::Load => storage,
+            Instruction::ShiftRight => self.builder.ins().ushr_imm(cell, 1),
+            Instruction::ShiftLeft => self.builder.ins().ishl_imm(cell, 1),
+            Instruction::Not => self.builder.ins().bnot(cell),
+            Instruction::Xor => self.builder.ins().bxor(cell, storage),
+            Instruction::And => self.builder.ins().band(cell, storage),
+            Instruction::Or => self.builder.ins().bor(cell, storage),
+            // every other instruction has an op of its own, or is refused by is_supported
+            _ => return,
+        };
+        self.store(changed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_interp::{CellKind, VirtualMachine};
+    use bft_types::optimize::OptLevel;
+    use bft_types::ParseOptions;
+    use std::num::NonZeroUsize;
+
+    /// Run `program` in the interpreter, set up as the compiled code would be by `options`
+    fn interpret<T: CellKind>(
+        program: &BfProgram,
+        options: CompileOptions,
+        input: &[u8],
+    ) -> (Result<(), String>, Vec<u8>) {
+        let mut vm: VirtualMachine<T> = VirtualMachine::new(
+            program,
+            NonZeroUsize::new(options.cells),
+            options.tape == Tape::Extensible,
+        )
+        .with_eof_behavior(options.eof_behavior)
+        .with_optimizations(options.opt_level);
+        if options.tape == Tape::Circular {
+            vm = vm.with_circular_tape();
+        }
+        let mut output = Vec::new();
+        let result = vm.interpret(&mut &input[..], &mut output);
+        (
+            result.map(|_| ()).map_err(|error| error.to_string()),
+            output,
+        )
+    }
+
+    /// Run `text` compiled and interpreted at each optimisation level, checking that they write
+    /// the same and fail with the same error, and hand back what the compiled code wrote
+    fn same_as_interpreter(text: &str, options: CompileOptions, input: &[u8]) -> Vec<u8> {
+        let parse_options = ParseOptions {
+            extended: true,
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("test.bf", text, &parse_options).unwrap();
+        let mut outputs = Vec::new();
+        for opt_level in [OptLevel::None, OptLevel::RunLengths, OptLevel::Full] {
+            let options = CompileOptions {
+                opt_level,
+                ..options
+            };
+            let compiled = JitProgram::new(&program, &options).unwrap();
+            let mut output = Vec::new();
+            let result = compiled.run(&mut &input[..], &mut output);
+            let expected = match options.cell_bits {
+                8 => interpret::<u8>(&program, options, input),
+                16 => interpret::<u16>(&program, options, input),
+                _ => interpret::<u32>(&program, options, input),
+            };
+            assert_eq!(
+                (result.map_err(|error| error.to_string()), &output),
+                (expected.0, &expected.1),
+                "{} at {}",
+                text,
+                opt_level
+            );
+            outputs.push(output);
+        }
+        outputs.dedup();
+        assert_eq!(outputs.len(), 1);
+        outputs.remove(0)
+    }
+
+    fn tape(tape: Tape, cells: usize) -> CompileOptions {
+        CompileOptions {
+            tape,
+            cells,
+            ..CompileOptions::default()
+        }
+    }
+
+    // Does the compiled code behave as the interpreter does, and fail where it would?
+    #[test]
+    fn test_jit() {
+        let run = |program: &BfProgram, options: CompileOptions, input: &[u8]| {
+            let compiled = JitProgram::new(program, &options).unwrap();
+            let mut output = Vec::new();
+            let result = compiled.run(&mut &input[..], &mut output);
+            (result, output)
+        };
+        let bf = |text: &str| BfProgram::new("test.bf", text).unwrap();
+
+        let full = CompileOptions {
+            eof_behavior: EofBehavior::SetZero,
+            opt_level: OptLevel::Full,
+            ..CompileOptions::default()
+        };
+        let (result, output) = run(&bf(",[+.,]"), full, b"HAL");
+        assert!(result.is_ok());
+        assert_eq!(output, b"IBM");
+        let (result, _) = run(&bf("+\n,"), CompileOptions::default(), b"");
+        assert!(
+            matches!(result, Err(VMError::ReadError(instruction, _)) if instruction.line_num() == 2)
+        );
+
+        let wide = CompileOptions {
+            cell_bits: 16,
+            ..full
+        };
+        let overflows = bf("++++++++++++++++[>++++++++++++++++<-]>[[-]>+<]>.-.");
+        assert_eq!(run(&overflows, wide, b"").1, b"\x01\x00");
+
+        let tape = |tape, cells| CompileOptions {
+            tape,
+            cells,
+            ..full
+        };
+        assert!(matches!(
+            run(&bf(">>>+."), tape(Tape::Fixed, 3), b"").0,
+            Err(VMError::HeadOverrun(_))
+        ));
+        let (result, output) = run(&bf("+[>+]"), tape(Tape::Fixed, 30), b"");
+        assert!(matches!(result, Err(VMError::HeadOverrun(_))) && output.is_empty());
+        assert!(matches!(
+            run(&bf("<"), tape(Tape::Extensible, 3), b"").0,
+            Err(VMError::HeadUnderrun(_))
+        ));
+        let far = bf(&format!("{}+.", ">".repeat(1000)));
+        assert_eq!(run(&far, tape(Tape::Extensible, 3), b"").1, b"\x01");
+        assert_eq!(
+            run(&bf("+<<<.>+++."), tape(Tape::Circular, 3), b"").1,
+            b"\x01\x03"
+        );
+
+        let extended = ParseOptions {
+            extended: true,
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("test.bf", "+++$>!{^~.@.", &extended).unwrap();
+        assert_eq!(run(&program, full, b"").1, b"\xfa");
+        let options = ParseOptions {
+            extensions: vec!['*'],
+            ..ParseOptions::default()
+        };
+        let program = BfProgram::new_with_options("test.bf", "+*", &options).unwrap();
+        assert!(JitProgram::new(&program, &full).is_err());
+    }
+
+    // Does running off either end of a fixed tape, by a move, a run of moves or a scan, fail as
+    // it does in the interpreter, after the same output?
+    #[test]
+    fn test_tape_ends() {
+        for text in [
+            ">>>+.",
+            "+.>>>>>>.",
+            "+[>+]",
+            "+.<",
+            "+<<<<",
+            "+>+<[<]",
+            "+[.>]",
+            ">>+<<<<<",
+            "+[>]",
+        ] {
+            same_as_interpreter(text, tape(Tape::Fixed, 3), b"");
+            for cell_bits in [16, 32] {
+                let wide = CompileOptions {
+                    cell_bits,
+                    ..tape(Tape::Fixed, 3)
+                };
+                same_as_interpreter(text, wide, b"");
+            }
+        }
+        same_as_interpreter("+.>>+<<<<", tape(Tape::Extensible, 3), b"");
+        assert_eq!(
+            same_as_interpreter(">>+.<<", tape(Tape::Fixed, 3), b""),
+            [1]
+        );
+    }
+
+    // Does an extensible tape grow to wherever the head goes, keeping what its cells held?
+    #[test]
+    fn test_extensible_tape() {
+        let far = format!("+>{}+.{}.", ">".repeat(100), "<".repeat(101));
+        assert_eq!(
+            same_as_interpreter(&far, tape(Tape::Extensible, 3), b""),
+            [1, 1]
+        );
+        let scanned = "+>+>+>+>+<<<<[>]+.<<<<.";
+        assert_eq!(
+            same_as_interpreter(scanned, tape(Tape::Extensible, 3), b""),
+            [1, 1]
+        );
+        let wide = CompileOptions {
+            cell_bits: 16,
+            ..tape(Tape::Extensible, 2)
+        };
+        assert_eq!(same_as_interpreter("->>>>-[<]>.", wide, b""), [255]);
+    }
+
+    // Does the head wrap round a circular tape, by single moves, runs of moves and scans?
+    #[test]
+    fn test_circular_tape() {
+        let circular = tape(Tape::Circular, 3);
+        assert_eq!(same_as_interpreter("+<<<.>+++.", circular, b""), [1, 3]);
+        assert_eq!(same_as_interpreter("+>>>>>>>++.", circular, b""), [2]);
+        assert_eq!(same_as_interpreter("<+[>]+.", circular, b""), [1]);
+        assert_eq!(same_as_interpreter("+>+<<[<]++.", circular, b""), [2]);
+        let wide = CompileOptions {
+            cell_bits: 32,
+            ..circular
+        };
+        assert_eq!(same_as_interpreter("-<<<+.", wide, b""), [0]);
+    }
+
+    // Does `,` do what each EOF behaviour says once input runs out?
+    #[test]
+    fn test_eof() {
+        for eof_behavior in [
+            EofBehavior::Error,
+            EofBehavior::SetZero,
+            EofBehavior::SetMax,
+            EofBehavior::LeaveUnchanged,
+        ] {
+            for cell_bits in [8, 16, 32] {
+                let options = CompileOptions {
+                    cell_bits,
+                    eof_behavior,
+                    ..CompileOptions::default()
+                };
+                same_as_interpreter("+++,.,.", options, b"A");
+                same_as_interpreter("+++,.,.,.", options, b"AB");
+                same_as_interpreter("+++,+.", options, b"");
+            }
+        }
+    }
+
+    // Do 16- and 32-bit cells hold values past 255, wrap at their own ends, and write their
+    // lowest byte?
+    #[test]
+    fn test_wide_cells() {
+        let past_a_byte = "++++++++++++++++[>++++++++++++++++<-]>[[-]>+<]>.-.";
+        for cell_bits in [16, 32] {
+            let options = CompileOptions {
+                cell_bits,
+                ..CompileOptions::default()
+            };
+            assert_eq!(same_as_interpreter(past_a_byte, options, b""), [1, 0]);
+            assert_eq!(same_as_interpreter("-.+.+.", options, b""), [255, 0, 1]);
+            assert_eq!(same_as_interpreter(",[->+<]>.", options, b"\xc8"), [200]);
+        }
+    }
+
+    // Do the instructions of Extended Brainfuck Type I compiled as Op::Other match the
+    // interpreter's, on cells of each width?
+    #[test]
+    fn test_extended() {
+        let options = CompileOptions::default();
+        assert_eq!(same_as_interpreter("+++$>!{^~.@.", options, b""), [0xfa]);
+        for cell_bits in [8, 16, 32] {
+            let options = CompileOptions {
+                cell_bits,
+                ..options
+            };
+            for text in [
+                "++++++$>+++!.",
+                "+++++$>+++&.",
+                "+++++$>++++++|.",
+                "+++++$>++++^.",
+                "++++++++++{.}.",
+                "-}.{{.",
+                "++++~.",
+                "+.@.",
+                "-{}}}}}}}}.",
+                "-$>~}|}}}}}}}}.",
+            ] {
+                same_as_interpreter(text, options, b"");
+            }
+        }
+        let wide = CompileOptions {
+            cell_bits: 16,
+            ..options
+        };
+        assert_eq!(same_as_interpreter("-{}}}}}}}}.", wide, b""), [0xff]);
+    }
+}
@@ -0,0 +1,312 @@
+//! Just enough JSON for the reports the CLI writes and the session protocol it speaks.
+
+use std::fmt::Display;
+
+/// Quote and escape a string for use as a JSON string value
+pub fn string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A parsed JSON value. Object members are kept in the order they were given.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The member of an object with the given name, if this is an object and it has one
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(member, _)| member == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// The value as a string, if it is one
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value as a non-negative whole number, if it is one
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(value) if *value >= 0.0 && value.fract() == 0.0 => Some(*value as u64),
+            _ => None,
+        }
+    }
+
+    /// The value as a boolean, if it is one
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", string(value)),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (index, (name, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", string(name), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Parse a complete JSON document. Returns a description of the problem if it is not valid.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        chars: text.char_indices().peekable(),
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some((offset, _)) => Err(format!("unexpected text at offset {}", offset)),
+    }
+}
+
+/// A recursive descent parser over the characters of a document
+struct Parser<'t> {
+    chars: std::iter::Peekable<std::str::CharIndices<'t>>,
+}
+
+impl<'t> Parser<'t> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .next_if(|(_, c)| matches!(c, ' ' | '\t' | '\n' | '\r'))
+            .is_some()
+        {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((offset, c)) => Err(format!(
+                "expected '{}' at offset {}, found '{}'",
+                expected, offset, c
+            )),
+            None => Err(format!(
+                "expected '{}', found the end of the text",
+                expected
+            )),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.chars.peek().copied() {
+            Some((_, '{')) => self.object(),
+            Some((_, '[')) => self.array(),
+            Some((_, '"')) => self.string().map(Value::String),
+            Some((_, 't')) => self.word("true", Value::Bool(true)),
+            Some((_, 'f')) => self.word("false", Value::Bool(false)),
+            Some((_, 'n')) => self.word("null", Value::Null),
+            Some((_, c)) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some((offset, c)) => Err(format!("unexpected '{}' at offset {}", c, offset)),
+            None => Err("unexpected end of text".to_string()),
+        }
+    }
+
+    fn word(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let mut text = String::new();
+        while let Some((_, c)) = self
+            .chars
+            .next_if(|(_, c)| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            text.push(c);
+        }
+        text.parse()
+            .map(Value::Number)
+            .map_err(|_| format!("invalid number '{}'", text))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(value),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, '"')) => value.push('"'),
+                    Some((_, '\\')) => value.push('\\'),
+                    Some((_, '/')) => value.push('/'),
+                    Some((_, 'b')) => value.push('\u{8}'),
+                    Some((_, 'f')) => value.push('\u{c}'),
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, 'u')) => value.push(self.unicode_escape()?),
+                    Some((offset, c)) => {
+                        return Err(format!("invalid escape '\\{}' at offset {}", c, offset))
+                    }
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some((_, c)) => value.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    /// The character for a `\uXXXX` escape, including a following low surrogate if needed
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let first = self.hex4()?;
+        let code = if (0xd800..0xdc00).contains(&first) {
+            self.expect('\\')?;
+            self.expect('u')?;
+            let second = self.hex4()?;
+            0x10000 + ((first - 0xd800) << 10) + (second.wrapping_sub(0xdc00) & 0x3ff)
+        } else {
+            first
+        };
+        char::from_u32(code).ok_or_else(|| format!("invalid unicode escape {:04x}", code))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|(_, c)| c.to_digit(16))
+                .ok_or_else(|| "invalid unicode escape".to_string())?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.chars.next_if(|(_, c)| *c == ']').is_some() {
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            if self.chars.next_if(|(_, c)| *c == ']').is_some() {
+                return Ok(Value::Array(values));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.chars.next_if(|(_, c)| *c == '}').is_some() {
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            if self.chars.next_if(|(_, c)| *c == '}').is_some() {
+                return Ok(Value::Object(members));
+            }
+            self.expect(',')?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_escaping() {
+        assert_eq!(string("plain"), "\"plain\"");
+        assert_eq!(
+            string("a \"quote\"\\ and\nnewline\u{1}"),
+            "\"a \\\"quote\\\"\\\\ and\\nnewline\\u0001\""
+        );
+    }
+
+    // Does a document survive being parsed and written out again?
+    #[test]
+    fn test_parse_round_trip() {
+        let text =
+            r#"{"id":1,"params":{"data":"a\"bé","eof":false,"cells":[0,255,-1.5]},"x":null}"#;
+
+        let value = parse(text).unwrap();
+
+        assert_eq!(value.get("id").and_then(Value::as_u64), Some(1));
+        assert_eq!(
+            value
+                .get("params")
+                .and_then(|params| params.get("data"))
+                .and_then(Value::as_str),
+            Some("a\"bé")
+        );
+        assert_eq!(parse(&value.to_string()).unwrap(), value);
+    }
+
+    // Are broken documents refused?
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("{\"a\":}").is_err());
+        assert!(parse("[1,2").is_err());
+        assert!(parse("\"unterminated").is_err());
+        assert!(parse("{} extra").is_err());
+    }
+}
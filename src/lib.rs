@@ -0,0 +1,13 @@
+//! The parts of bft that other crates can use. See [build] for running Brainfuck programs from a
+//! build script, [compile] for compiling them to other languages, [safe_write] for writing files
+//! that are never left half written, [provenance] for tracing compiled artifacts back to their
+//! source, and [frontend] for reading a program in whichever language it is written in. With the
+//! `jit` feature, [jit] runs programs as native code.
+
+pub mod build;
+pub mod compile;
+pub mod frontend;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod provenance;
+pub mod safe_write;
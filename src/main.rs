@@ -6,17 +6,111 @@
 //! size of this tape may be specified as --cells cell_count, or will default to 30,000.
 //!
 //! The virtual machine is connected to stdin and stdout
+//!
+//! With `--all`, every program in a directory is run and a report written for each.
+//!
+//! With `--dry-run`, the program is parsed and the plan for running it printed, but it is not run.
+//!
+//! With `--audit-determinism N`, the program is run N times on the same input to check that it
+//! behaves the same way each time.
+//!
+//! With `--schema NAME`, the versioned JSON Schema for one of the JSON documents bft writes is
+//! printed.
+//!
+//! With `--cached`, the output of a run whose result depends only on the program, its input file
+//! and its options is stored, and later identical runs are served from the store.
+//!
+//! The `link` subcommand combines a main program with library fragments into a single program,
+//! checking that each library follows the cell-0 convention.
+//!
+//! The `test` subcommand runs programs with their `@assert` directives checked.
+//!
+//! The `check` subcommand reports problems in a program without running it, and with `--recover`
+//! carries on past each one to report them all.
+//!
+//! The `golf` subcommand suggests shorter ways of writing parts of a program.
+//!
+//! The `map` subcommand runs one program over many input files, parsing it only once.
+//!
+//! The `generate-include` subcommand runs a program and writes its output to a file for
+//! `include_bytes!`. The same is available to build scripts as `bft::build::run_for_build`.
+//!
+//! The `session` subcommand lets another process drive the interpreter over stdio.
+//!
+//! The `daemon` subcommand keeps a program loaded and runs it for each client of a Unix socket.
+//!
+//! The `doctor` subcommand reports how bft is set up, to help track down differences between
+//! machines.
 
+mod audit;
+mod batch;
+mod cache;
 mod cli;
+#[cfg(unix)]
+mod daemon;
+mod doctor;
+mod final_state;
+mod hint;
+mod json;
+mod map;
+mod metrics;
+mod plan;
+#[cfg(feature = "profile-db")]
+mod profile;
+mod report;
+#[cfg(all(
+    feature = "sandbox",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod sandbox;
+mod schema;
+mod selftest;
+mod session;
+mod shutdown;
+mod stream;
+mod summary;
+mod tee;
+mod test_programs;
 
-use std::{io::Write, process::ExitCode};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, ErrorKind, IsTerminal, Read, Stdout, StdoutLock};
+use std::num::NonZeroU64;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use std::{fs, io::Write, process::ExitCode};
 
-use bft_interp::VirtualMachine;
-use bft_types::BfProgram;
+use bft_interp::layout::{dump_tape, recent_changes, LayoutError, TapeLayout};
+use bft_interp::raw_mode::RawMode;
+use bft_interp::{HaltReason, RecentCell, VMError, VirtualMachine};
+use bft_types::link::{link, Fragment};
+use bft_types::messages::{Catalog, Localise};
+use bft_types::{check, golf};
+use bft_types::{BfProgram, BftTypeError, CommentSyntax, LocalisedInstruction, ParseOptions};
 use clap::Parser;
 use std::io::{stdin, stdout};
 
-use cli::Args;
+use bft::build;
+#[cfg(feature = "jit")]
+use bft::compile::{CompileOptions, Tape};
+use bft::frontend;
+#[cfg(feature = "jit")]
+use bft::jit::JitProgram;
+use bft::provenance::Provenance;
+use bft::safe_write::{AtomicFile, Existing};
+#[cfg(feature = "jit")]
+use bft_interp::Arithmetic;
+#[cfg(feature = "jit")]
+use bft_types::optimize::OptLevel;
+use cache::{CacheEntry, Recorder};
+use cli::{
+    Args, CfgArgs, CheckArgs, Cli, Command, CompileArgs, DaemonArgs, GenerateIncludeArgs, GolfArgs,
+    InspectArgs, InspectTarget, LinkArgs, ProfileArgs,
+};
+use metrics::Metrics;
+use report::Reporter;
+use stream::StreamSink;
+use tee::Tee;
 
 /// Ensures the output that it writes has a newline at the end.
 /// If the program doesn't produce one, this will add it.
@@ -59,35 +153,808 @@ impl<'a, T: Write> Drop for WriterWithTrailingNewline<'a, T> {
     }
 }
 
-/// Create a [BfProgram] from the file specified, then construct a [VirtualMachine] and run it.
+/// Where the program's output goes: straight to a terminal with a newline added at the end if
+/// needed, or to stdout untouched when it isn't a terminal, or, as a filter, block-buffered with
+/// nothing added, or to the --output or --stream-to file.
+enum ProgramOutput<'a> {
+    Terminal(WriterWithTrailingNewline<'a, Stdout>),
+    Stdout(&'a mut Stdout),
+    Filter(BufWriter<StdoutLock<'a>>),
+    File {
+        file: AtomicFile,
+        /// Whether to end the file with a newline if the output doesn't
+        trailing_newline: bool,
+        last_byte: Option<u8>,
+    },
+    Stream(StreamSink<'a>),
+}
+
+impl<'a> ProgramOutput<'a> {
+    /// Write out anything still buffered, and move the --output file into place if the program
+    /// ran successfully. After a failed run the --output file is dropped, leaving any existing
+    /// file as it was.
+    fn finish(self, succeeded: bool) -> std::io::Result<()> {
+        match self {
+            ProgramOutput::Terminal(_) | ProgramOutput::Stdout(_) => Ok(()),
+            ProgramOutput::Filter(mut writer) => writer.flush(),
+            ProgramOutput::File {
+                mut file,
+                trailing_newline,
+                last_byte,
+            } if succeeded => {
+                if trailing_newline && last_byte != Some(b'\n') {
+                    writeln!(file)?;
+                }
+                file.commit()
+            }
+            ProgramOutput::File { .. } => Ok(()),
+            ProgramOutput::Stream(sink) => sink.finish().map(|_| ()),
+        }
+    }
+}
+
+impl<'a> Write for ProgramOutput<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ProgramOutput::Terminal(writer) => writer.write(buf),
+            ProgramOutput::Stdout(writer) => writer.write(buf),
+            ProgramOutput::Filter(writer) => writer.write(buf),
+            ProgramOutput::File {
+                file, last_byte, ..
+            } => {
+                let written = file.write(buf)?;
+                if let Some(last) = buf[..written].last() {
+                    *last_byte = Some(*last);
+                }
+                Ok(written)
+            }
+            ProgramOutput::Stream(sink) => sink.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ProgramOutput::Terminal(writer) => writer.flush(),
+            ProgramOutput::Stdout(writer) => writer.flush(),
+            ProgramOutput::Filter(writer) => writer.flush(),
+            ProgramOutput::File { file, .. } => file.flush(),
+            ProgramOutput::Stream(sink) => sink.flush(),
+        }
+    }
+}
+
+/// The output for the program: the --output or --stream-to file, block-buffered with --filter, or
+/// stdout, with a trailing newline only if it is a terminal
+fn program_output<'a>(
+    args: &Args,
+    terminal: &'a mut Stdout,
+    reporter: &'a Reporter,
+) -> std::io::Result<ProgramOutput<'a>> {
+    Ok(match (&args.output, &args.stream_to) {
+        (Some(path), _) => ProgramOutput::File {
+            file: AtomicFile::create(path, Existing::from_force(args.force))?,
+            trailing_newline: args.trailing_newline,
+            last_byte: None,
+        },
+        (None, Some(path)) => ProgramOutput::Stream(StreamSink::create(
+            path,
+            usize::try_from(args.stream_buffer).unwrap_or(usize::MAX),
+            args.fsync_every.and_then(NonZeroU64::new),
+            args.progress_every.and_then(NonZeroU64::new),
+            reporter,
+        )?),
+        (None, None) if args.filter => ProgramOutput::Filter(BufWriter::new(stdout().lock())),
+        (None, None) if terminal.is_terminal() => {
+            ProgramOutput::Terminal(WriterWithTrailingNewline::new(terminal))
+        }
+        (None, None) => ProgramOutput::Stdout(terminal),
+    })
+}
+
+/// Whether an error is one that a filter should stop quietly on: the reader at the other end of
+/// the pipe going away, or running out of input
+fn ends_filter(error: &VMError) -> bool {
+    match error {
+        VMError::WriteError(_, error) => error.kind() == ErrorKind::BrokenPipe,
+        VMError::ReadError(_, error) => error.kind() == ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
+
+/// Run the program given on the command line, or every program in a directory if `--all` was
+/// given.
 ///```no_run
-/// let args = cli::Args::parse();
+/// let cli = cli::Cli::parse();
 ///
-/// run_bft(&args)?;
+/// run_bft(&cli.run.unwrap(), &Reporter::new(cli.quiet, cli.verbose))?;
 ///```
-fn run_bft(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
-    let bf_program = BfProgram::from_file(&args.program)?;
+fn run_bft(args: &Args, reporter: &Reporter) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(schema) = args.schema {
+        print!("{}", schema.document());
+        return Ok(());
+    }
+
+    let mut metrics = Metrics::default();
+    let result = match (&args.program, &args.all, &args.report_dir) {
+        (_, Some(directory), Some(report_dir)) => {
+            batch::run_directory(args, directory, report_dir, reporter, &mut metrics)
+        }
+        (Some(program), _, _) => run_program(args, program, reporter, &mut metrics),
+        _ => unreachable!("clap requires a program or --all with --report-dir"),
+    };
+
+    if let Some(metrics_file) = &args.metrics_file {
+        metrics.write_textfile(metrics_file)?;
+    }
+    result
+}
+
+/// Create a [BfProgram] from the file specified, then construct a [VirtualMachine] and run it.
+fn run_program(
+    args: &Args,
+    program: &Path,
+    reporter: &Reporter,
+    metrics: &mut Metrics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let selection = frontend::select(program, args.lang);
+    reporter.verbose(format!("Front-end: {}", selection));
+    let parse_started = Instant::now();
+    let bf_program = selection
+        .frontend
+        .parse(program, &args.parse_options())
+        .inspect_err(|_| metrics.record_load_error())?;
+    reporter.verbose(format!(
+        "Parsed {} in {:.3}ms, of which analysis took {:.3}ms: {} instructions, {} loops{}",
+        program.display(),
+        parse_started.elapsed().as_secs_f64() * 1000.0,
+        bf_program.analysis_time().as_secs_f64() * 1000.0,
+        bf_program.localised_instructions().len(),
+        bf_program.loops().len(),
+        bf_program
+            .loops()
+            .max_depth()
+            .map(|depth| format!(" nested {} deep", depth + 1))
+            .unwrap_or_default()
+    ));
+    for warning in plan::overrun_warnings(args, &bf_program) {
+        reporter.info(warning);
+    }
+    if args.dry_run {
+        print!("{}", plan::describe(args, program, &bf_program));
+        return Ok(());
+    }
+    if args.stats {
+        println!("{}: {}", program.display(), bf_program.stats());
+        return Ok(());
+    }
+    if let Some(runs) = args.audit_determinism {
+        return audit::audit_determinism(args, &bf_program, runs as usize, reporter);
+    }
+    if args.jit {
+        #[cfg(feature = "jit")]
+        return run_jit(args, &bf_program, reporter);
+        #[cfg(not(feature = "jit"))]
+        reporter.info(
+            "Warning: this build of bft has no JIT (the `jit` feature), so the program is \
+             interpreted",
+        );
+    }
+    reporter.debug(format!(
+        "Tape: {} cells, {}; limits: {:?}",
+        args.cells.map(|cells| cells.get()).unwrap_or(30_000),
+        if args.extensible {
+            "extensible"
+        } else {
+            "fixed size"
+        },
+        args.limits()
+    ));
+
+    let cache_entry = match args.cached.then(|| CacheEntry::for_run(args, &bf_program)) {
+        Some(Ok(cache_entry)) => Some(cache_entry),
+        Some(Err(reason)) => {
+            reporter.info(format!("Warning: not caching this run: {}", reason));
+            None
+        }
+        None => None,
+    };
+    if let Some(output) = cache_entry
+        .as_ref()
+        .map(CacheEntry::load)
+        .transpose()?
+        .flatten()
+    {
+        reporter.verbose("Served from the cache");
+        let mut terminal = stdout();
+        let mut program_output = Tee::new(
+            program_output(args, &mut terminal, reporter)?,
+            args.tee.as_deref(),
+        )?;
+        program_output.write_all(&output)?;
+        return Ok(program_output.into_inner()?.finish(true)?);
+    }
+
+    let layout = args
+        .layout
+        .as_ref()
+        .map(TapeLayout::from_file)
+        .transpose()?;
+    let mut virtual_clock = args.virtual_clock();
+    #[cfg(feature = "profile-db")]
+    let mut loop_profile = args
+        .profile_db
+        .as_ref()
+        .map(|_| profile::LoopProfile::default());
+    let mut bf_interpreter: VirtualMachine<u8> = args.virtual_machine(&bf_program);
+    if let Some(virtual_clock) = virtual_clock.as_mut() {
+        bf_interpreter.add_observer(virtual_clock);
+    }
+    #[cfg(feature = "profile-db")]
+    if let Some(loop_profile) = loop_profile.as_mut() {
+        bf_interpreter.add_observer(loop_profile);
+    }
+    #[cfg(not(feature = "profile-db"))]
+    if args.profile_db.is_some() {
+        return Err(PROFILE_DB_UNAVAILABLE.into());
+    }
+
+    let mut input = match &cache_entry {
+        Some(cache_entry) => Box::new(Cursor::new(cache_entry.input.clone())),
+        None => program_input(args)?,
+    };
+    if args.sandbox {
+        enter_sandbox()?;
+        reporter.debug("Sandbox: seccomp filter applied");
+    }
+    // restored once the program stops
+    let raw_mode = if args.raw && stdin().is_terminal() {
+        Some(RawMode::stdin()?)
+    } else {
+        if args.raw {
+            reporter.verbose("Not using raw mode, as stdin is not a terminal");
+        }
+        None
+    };
+    let mut terminal = stdout();
+    let mut output = Recorder::new(
+        Tee::new(
+            program_output(args, &mut terminal, reporter)?,
+            args.tee.as_deref(),
+        )?,
+        cache_entry.is_some(),
+    );
+    let result = bf_interpreter.interpret(&mut input, &mut output);
+    drop(raw_mode);
+    let (output, recorded) = output.into_parts();
+    match output
+        .into_inner()
+        .and_then(|output| output.finish(result.is_ok()))
+    {
+        Err(error) if error.kind() != ErrorKind::BrokenPipe => return Err(error.into()),
+        _ => {}
+    }
+    if let (Some(cache_entry), Some(recorded), Ok(HaltReason::Completed)) =
+        (&cache_entry, recorded, &result)
+    {
+        cache_entry.store(&recorded)?;
+    }
+    reporter.verbose(format!("Run: {}", bf_interpreter.run_stats()));
+    if args.summary {
+        let summary = summary::describe(args, program, &bf_interpreter.run_stats(), &result);
+        reporter.info(summary.trim_end());
+    }
+    metrics.record_run(
+        bf_interpreter.clock(),
+        bf_interpreter.run_stats().bytes_output,
+        &result,
+    );
+    // dumps and layouts count from cell 0, which may not be at the start of a bidirectional tape
+    let origin = bf_interpreter.origin();
+    if let Some(range) = &args.dump_tape {
+        reporter.info(dump_tape(
+            &bf_interpreter.tape()[origin..],
+            bf_interpreter.head().wrapping_sub(origin),
+            range.clone(),
+            layout.as_ref(),
+        ));
+    }
+    if let Some(cell_journal) = bf_interpreter.cell_journal() {
+        // the window covers every change still in the journal
+        let window = cell_journal
+            .iter()
+            .next()
+            .map_or(0, |oldest| bf_interpreter.clock() - oldest.clock + 1);
+        reporter.info(format!(
+            "Cells changed in the last {} instructions, most recent first:",
+            window
+        ));
+        let recent: Vec<_> = cell_journal
+            .recent_cells(bf_interpreter.clock(), window)
+            .into_iter()
+            .filter_map(|recent| {
+                let cell = recent.cell.checked_sub(origin)?;
+                Some(RecentCell { cell, ..recent })
+            })
+            .collect();
+        reporter.info(recent_changes(&recent, window, layout.as_ref()));
+        reporter.debug("Every change in the journal, oldest first:");
+        for change in cell_journal.iter() {
+            reporter.debug(format!(
+                "  {}",
+                with_expansion(change, &bf_program, &change.instruction)
+            ));
+        }
+    }
+    let result = result.inspect_err(|_| {
+        if let Some(jump_history) = bf_interpreter.jump_history() {
+            reporter.info("Most recent jumps, oldest first:");
+            for jump in jump_history.iter() {
+                reporter.info(format!(
+                    "  {}",
+                    with_expansion(jump, &bf_program, &jump.instruction)
+                ));
+            }
+        }
+    });
+
+    let failed_checks = match &result {
+        Ok(halt_reason) => final_state::check(args, &bf_interpreter, *halt_reason),
+        Err(_) => Vec::new(),
+    };
+
+    #[cfg(feature = "profile-db")]
+    let run_stats = bf_interpreter.run_stats();
+
+    // the machine has the virtual clock and loop profile on loan until it is dropped
+    drop(bf_interpreter);
+    if let Some(virtual_clock) = &virtual_clock {
+        reporter.verbose(format!("Virtual clock: {}", virtual_clock));
+    }
+    #[cfg(feature = "profile-db")]
+    if let (Some(db), Some(loop_profile)) = (&args.profile_db, &loop_profile) {
+        profile::record(db, program, &bf_program, &run_stats, &result, loop_profile)?;
+        reporter.verbose(format!("Recorded in the profile database {}", db.display()));
+    }
+
+    let halt_reason = match result {
+        Err(error) if args.filter && ends_filter(&error) => return Ok(()),
+        Err(error) => {
+            let context = hint::Context {
+                args,
+                program: &bf_program,
+            };
+            return Err(hint::Hinted::attach(error, &context));
+        }
+        Ok(halt_reason) => halt_reason,
+    };
 
-    let mut bf_interpreter: VirtualMachine<u8> =
-        VirtualMachine::new(&bf_program, args.cells, args.extensible);
+    if !failed_checks.is_empty() {
+        return Err(failed_checks.join("\n").into());
+    }
+    match halt_reason {
+        HaltReason::Completed => Ok(()),
+        HaltReason::BreakpointHit {
+            program_counter, ..
+        } => {
+            let instruction = &bf_program.localised_instructions()[program_counter];
+            let stopped = format!("Program stopped early: {}", halt_reason);
+            Err(with_expansion(stopped, &bf_program, instruction).into())
+        }
+        halt_reason => Err(format!("Program stopped early: {}", halt_reason).into()),
+    }
+}
 
-    let mut input = stdin();
-    let mut output = stdout();
-    let mut output_with_newline = WriterWithTrailingNewline::new(&mut output);
-    bf_interpreter.interpret(&mut input, &mut output_with_newline)?;
+/// `text` about `instruction`, followed by the macro the instruction came from if it came from
+/// one, as its own line and column are where the macro was used
+fn with_expansion(
+    text: impl std::fmt::Display,
+    program: &BfProgram,
+    instruction: &LocalisedInstruction,
+) -> String {
+    match program.expansion(instruction) {
+        Some(expansion) => format!("{} ({})", text, expansion),
+        None => text.to_string(),
+    }
+}
+
+/// Run a program as native code, compiled with Cranelift
+#[cfg(feature = "jit")]
+fn run_jit(
+    args: &Args,
+    bf_program: &BfProgram,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if args.arithmetic != Arithmetic::Wrapping {
+        return Err("--jit only supports wrapping arithmetic".into());
+    }
+    let options = CompileOptions {
+        cell_bits: 8,
+        cells: args.cells.map_or(30_000, |cells| cells.get()),
+        tape: if args.extensible {
+            Tape::Extensible
+        } else if args.circular {
+            Tape::Circular
+        } else {
+            Tape::Fixed
+        },
+        eof_behavior: args.eof,
+        opt_level: OptLevel::Full,
+    };
+    let compile_started = Instant::now();
+    let compiled = JitProgram::new(bf_program, &options)?;
+    reporter.verbose(format!(
+        "Compiled to native code in {:.3}ms",
+        compile_started.elapsed().as_secs_f64() * 1000.0
+    ));
+
+    let mut input = program_input(args)?;
+    if args.sandbox {
+        enter_sandbox()?;
+        reporter.debug("Sandbox: seccomp filter applied");
+    }
+    let mut terminal = stdout();
+    let mut output = Tee::new(
+        program_output(args, &mut terminal, reporter)?,
+        args.tee.as_deref(),
+    )?;
+    let result = compiled.run(&mut input, &mut output);
+    match output
+        .into_inner()
+        .and_then(|output| output.finish(result.is_ok()))
+    {
+        Err(error) if error.kind() != ErrorKind::BrokenPipe => return Err(error.into()),
+        _ => {}
+    }
+    match result {
+        Err(error) if args.filter && ends_filter(&error) => Ok(()),
+        Err(error) => {
+            let context = hint::Context {
+                args,
+                program: bf_program,
+            };
+            Err(hint::Hinted::attach(error, &context))
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Open the input for the program: stdin, the `--input-str` text, the `--input` file, or the
+/// `--input` file followed by stdin if `--then-stdin` was given. When chained, the program only
+/// sees the end of its input once stdin runs out too.
+pub(crate) fn program_input(args: &Args) -> std::io::Result<Box<dyn Read>> {
+    let open = |path: &Path| {
+        File::open(path).map(BufReader::new).map_err(|error| {
+            std::io::Error::new(
+                error.kind(),
+                format!("could not open input file {}: {}", path.display(), error),
+            )
+        })
+    };
+    if let Some(text) = &args.input_str {
+        return Ok(Box::new(Cursor::new(text.clone().into_bytes())));
+    }
+    Ok(match &args.input {
+        Some(path) if args.then_stdin => Box::new(open(path)?.chain(stdin())),
+        Some(path) => Box::new(open(path)?),
+        None => Box::new(stdin()),
+    })
+}
+
+/// Restrict the process to the files it already has open
+#[cfg(all(
+    feature = "sandbox",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn enter_sandbox() -> Result<(), Box<dyn std::error::Error>> {
+    sandbox::enter().map_err(|e| format!("Could not enter the sandbox: {}", e).into())
+}
+
+/// Without sandbox support, asking for the sandbox is an error rather than silently running
+/// unprotected
+#[cfg(not(all(
+    feature = "sandbox",
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+fn enter_sandbox() -> Result<(), Box<dyn std::error::Error>> {
+    Err("--sandbox needs a Linux build of bft with the `sandbox` feature".into())
+}
+
+/// Load and verify the main program and library fragments, then write out the linked program and
+/// its provenance map.
+fn link_bft(args: &LinkArgs, reporter: &Reporter) -> Result<(), Box<dyn std::error::Error>> {
+    let main_fragment = Fragment::from_file(&args.main)?;
+    let libraries = args
+        .libraries
+        .iter()
+        .map(Fragment::from_file)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let linked = link(&main_fragment, &libraries)?;
+    reporter.verbose(format!(
+        "Linked {} library fragments onto {}",
+        libraries.len(),
+        args.main.display()
+    ));
+
+    let map_path = args.map.clone().or_else(|| {
+        args.output.as_ref().map(|output| {
+            let mut map_path = output.clone().into_os_string();
+            map_path.push(".map");
+            map_path.into()
+        })
+    });
+    // both files are checked before either is written, so neither is replaced if one can't be
+    let existing = Existing::from_force(args.force);
+    let output = args
+        .output
+        .as_ref()
+        .map(|output| AtomicFile::create(output, existing))
+        .transpose()?;
+    let map = map_path
+        .map(|map_path| AtomicFile::create(map_path, existing))
+        .transpose()?;
+
+    match output {
+        Some(mut output) => {
+            output.write_all(linked.text().as_bytes())?;
+            output.commit()?;
+        }
+        None => stdout().write_all(linked.text().as_bytes())?,
+    }
+    if let Some(mut map) = map {
+        map.write_all(linked.provenance_map().as_bytes())?;
+        map.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Print the problems found in a program, one per line as `file:line:column: message`. Fails if
+/// there are any.
+fn check_bft(
+    args: &CheckArgs,
+    catalog: &Catalog,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(&args.program)?;
+    let options = ParseOptions {
+        assertions: args.assertions,
+        comments: CommentSyntax {
+            line: args.line_comment,
+            block: args.block_comment,
+        },
+        ..ParseOptions::default()
+    };
+
+    let diagnostics = check::check(&args.program, &text, &options, args.recover);
+    for diagnostic in &diagnostics {
+        println!("{}:{}", args.program.display(), diagnostic.render(catalog));
+    }
+    match diagnostics.len() {
+        0 => {
+            reporter.info(format!("{}: no problems found", args.program.display()));
+            Ok(())
+        }
+        1 => Err("1 problem found".into()),
+        count => Err(format!("{} problems found", count).into()),
+    }
+}
+
+/// Write out the control-flow graph of a program in DOT format
+fn cfg_bft(args: &CfgArgs, reporter: &Reporter) -> Result<(), Box<dyn std::error::Error>> {
+    let bf_program = frontend::select(&args.program, args.lang)
+        .frontend
+        .parse(&args.program, &ParseOptions::default())?;
+
+    let graph = bf_program.control_flow_graph();
+    match &args.output {
+        Some(path) => {
+            let mut output = AtomicFile::create(path, Existing::from_force(args.force))?;
+            output.write_all(graph.to_dot().as_bytes())?;
+            output.commit()?;
+        }
+        None => stdout().write_all(graph.to_dot().as_bytes())?,
+    }
+    reporter.verbose(format!(
+        "{} blocks, {} edges",
+        graph.blocks().len(),
+        graph.edges().len()
+    ));
 
     Ok(())
 }
 
+/// Compile a program ahead of time for the chosen target
+fn compile_bft(args: &CompileArgs, reporter: &Reporter) -> Result<(), Box<dyn std::error::Error>> {
+    let selection = frontend::select(&args.program, args.lang);
+    let bf_program = selection
+        .frontend
+        .parse(&args.program, &args.parse.parse_options())?;
+    let options = args.compile_options();
+    let provenance = Provenance::new(
+        &args.program,
+        &bf_program,
+        selection.frontend.name(),
+        options.opt_level.to_string(),
+    );
+
+    let compiled = args
+        .target
+        .compile(&bf_program, &options, &provenance)
+        .map_err(|reason| format!("{}: {}", args.program.display(), reason))?;
+    match &args.output {
+        Some(path) => {
+            let mut output = AtomicFile::create(path, Existing::from_force(args.force))?;
+            output.write_all(&compiled)?;
+            output.commit()?;
+        }
+        None => stdout().write_all(&compiled)?,
+    }
+    reporter.verbose(format!(
+        "Compiled {} to {}, {} bytes",
+        args.program.display(),
+        args.target,
+        compiled.len()
+    ));
+
+    Ok(())
+}
+
+/// List the parts of a program that could be written in fewer bytes, and how many bytes would be
+/// saved in total.
+fn golf_bft(args: &GolfArgs, reporter: &Reporter) -> Result<(), Box<dyn std::error::Error>> {
+    let bf_program = frontend::select(&args.program, args.lang)
+        .frontend
+        .parse(&args.program, &ParseOptions::default())?;
+
+    let suggestions = golf::suggest(&bf_program);
+    for suggestion in &suggestions {
+        println!("{}", suggestion);
+    }
+    reporter.info(format!(
+        "{} suggestions, saving {} bytes",
+        suggestions.len(),
+        suggestions
+            .iter()
+            .map(golf::Suggestion::saving)
+            .sum::<usize>()
+    ));
+
+    Ok(())
+}
+
+/// Run a program and write its output to a file ready for `include_bytes!`, then print a report of
+/// what was generated.
+fn generate_include(
+    args: &GenerateIncludeArgs,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let input = args.input.as_ref().map(fs::read).transpose()?;
+    let limits = bft_interp::Limits {
+        max_instructions: args.max_instructions,
+        timeout: Some(Duration::from_millis(args.timeout_ms)),
+        ..bft_interp::Limits::default()
+    };
+
+    let generated = build::generate(
+        &args.program,
+        input.as_deref().unwrap_or_default(),
+        &args.out,
+        limits,
+        Existing::from_force(args.force),
+    )?;
+    println!("{}", generated);
+    reporter.verbose(format!(
+        "Wrote {} bytes to {}",
+        generated.bytes,
+        generated.path.display()
+    ));
+    Ok(())
+}
+
+/// Why `--profile-db` and `bft profile` fail in a build without the feature
+#[cfg(not(feature = "profile-db"))]
+const PROFILE_DB_UNAVAILABLE: &str = "profiling needs a build of bft with the `profile-db` feature";
+
+/// Summarise a profile database
+#[cfg(feature = "profile-db")]
+fn run_profile(args: &ProfileArgs) -> Result<(), Box<dyn std::error::Error>> {
+    profile::run_profile(args)
+}
+
+/// Without profile database support, asking for a report is an error
+#[cfg(not(feature = "profile-db"))]
+fn run_profile(_args: &ProfileArgs) -> Result<(), Box<dyn std::error::Error>> {
+    Err(PROFILE_DB_UNAVAILABLE.into())
+}
+
+/// Serve a program on a Unix socket
+#[cfg(unix)]
+fn run_daemon(args: &DaemonArgs, reporter: &Reporter) -> Result<(), Box<dyn std::error::Error>> {
+    daemon::run_daemon(args, reporter)
+}
+
+/// Without Unix sockets, there is nothing to serve on
+#[cfg(not(unix))]
+fn run_daemon(_args: &DaemonArgs, _reporter: &Reporter) -> Result<(), Box<dyn std::error::Error>> {
+    Err("bft daemon needs Unix sockets, which this platform does not have".into())
+}
+
+/// Print what is known about a file: for now, the provenance embedded in a compiled artifact
+fn inspect(args: &InspectArgs) -> Result<(), Box<dyn std::error::Error>> {
+    match &args.target {
+        InspectTarget::Artifact { path } => {
+            // artifacts may be binary, with the block among other data
+            let artifact = fs::read(path)?;
+            let provenance = Provenance::find(&String::from_utf8_lossy(&artifact))
+                .map_err(|reason| format!("{}: {}", path.display(), reason))?;
+            println!("{}", provenance);
+        }
+    }
+    Ok(())
+}
+
+/// The text of an error in the catalog's language, if it is one of the diagnostics with a message
+/// code. Other errors are shown as they are.
+fn localised(error: &(dyn std::error::Error + 'static), catalog: &Catalog) -> String {
+    if let Some(error) = error.downcast_ref::<BftTypeError>() {
+        error.message().render(catalog)
+    } else if let Some(hinted) = error.downcast_ref::<hint::Hinted>() {
+        hinted.render(catalog)
+    } else if let Some(error) = error.downcast_ref::<VMError>() {
+        error.message().render(catalog)
+    } else if let Some(error) = error.downcast_ref::<LayoutError>() {
+        error.message().render(catalog)
+    } else {
+        error.to_string()
+    }
+}
+
 /// Main function. Returns a success code if everything worked, or an error and prints an error message if it didn't
 fn main() -> std::process::ExitCode {
-    let args = cli::Args::parse();
+    let mut cli = Cli::parse();
+    if let Some(Command::RunBytecode(args)) = &mut cli.command {
+        args.lang = Some(frontend::Frontend::Bytecode);
+    }
+    let reporter = Reporter::new(cli.quiet, cli.verbose);
+    let catalog = match &cli.messages {
+        Some(path) => match Catalog::from_file(path) {
+            Ok(catalog) => catalog,
+            Err(error) => {
+                reporter.error(error);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Catalog::default(),
+    };
 
-    let run_result = run_bft(&args);
+    let run_result = match &cli.command {
+        Some(Command::Run(args) | Command::RunBytecode(args)) => run_bft(args, &reporter),
+        Some(Command::Link(args)) => link_bft(args, &reporter),
+        Some(Command::Test(args)) => test_programs::run_tests(args, &reporter),
+        Some(Command::Check(args)) => check_bft(args, &catalog, &reporter),
+        Some(Command::Golf(args)) => golf_bft(args, &reporter),
+        Some(Command::Cfg(args)) => cfg_bft(args, &reporter),
+        Some(Command::Compile(args)) => compile_bft(args, &reporter),
+        Some(Command::Map(args)) => map::run_map(args, &reporter),
+        Some(Command::GenerateInclude(args)) => generate_include(args, &reporter),
+        Some(Command::Session) => session::run_session(),
+        Some(Command::Daemon(args)) => run_daemon(args, &reporter),
+        Some(Command::Doctor) => doctor::run_doctor(&reporter),
+        Some(Command::Selftest(args)) => selftest::run_selftest(args, &reporter),
+        Some(Command::Inspect(args)) => inspect(args),
+        Some(Command::Profile(args)) => run_profile(args),
+        None => match &cli.run {
+            Some(args) => run_bft(args, &reporter),
+            None => unreachable!("clap requires a program when no subcommand is given"),
+        },
+    };
     match run_result {
         Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
-            println!("Error: {}", e);
+            reporter.error(localised(e.as_ref(), &catalog));
             ExitCode::FAILURE
         }
     }
@@ -141,4 +1008,44 @@ mod tests {
         let actual = output_cursor.into_inner();
         assert_eq!(expected, actual);
     }
+
+    // Is an existing --output file left alone when the program fails part way through?
+    #[test]
+    fn test_output_file_after_failure() {
+        let directory =
+            std::env::temp_dir().join(format!("bft-output-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("out.txt");
+        fs::write(&path, "old").unwrap();
+
+        let program = BfProgram::new("under.bf", "++++++++[>++++++++<-]>+.<<").unwrap();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        let mut output = ProgramOutput::File {
+            file: AtomicFile::create(&path, Existing::Overwrite).unwrap(),
+            trailing_newline: false,
+            last_byte: None,
+        };
+        let result = vm.interpret(&mut std::io::empty(), &mut output);
+        assert!(result.is_err());
+        output.finish(result.is_ok()).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    // Is --input-str given to the program, followed by the end of its input?
+    #[test]
+    fn test_input_str() {
+        use clap::Parser;
+        let cli = Cli::parse_from(["bft", "--input-str", "hi", "--eof", "zero", "echo.bf"]);
+        let args = cli.run.unwrap();
+        let program = BfProgram::new("echo.bf", ",.,.,.").unwrap();
+        let mut vm = args.virtual_machine(&program);
+
+        let mut output = Vec::new();
+        vm.interpret(&mut program_input(&args).unwrap(), &mut output)
+            .unwrap();
+        assert_eq!(output, b"hi\0");
+    }
 }
@@ -0,0 +1,159 @@
+//! `bft map`: running one program over many inputs, writing one output file for each.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use bft::frontend;
+use bft::safe_write::{self, AtomicFile, Existing};
+use bft_interp::{FlushPolicy, HaltReason, Limits, VirtualMachine};
+use bft_types::{BfProgram, ParseOptions};
+
+use crate::cli::MapArgs;
+use crate::report::Reporter;
+
+/// Parse the program once, then run it on each input in turn. The output for `inputs/x.txt` is
+/// written to `<out-dir>/x.txt.out` once the run completes, and if the run fails the reason is
+/// written to `<out-dir>/x.txt.err` instead. Existing output files are only replaced with
+/// `--force`. Fails if any input could not be processed.
+pub fn run_map(args: &MapArgs, reporter: &Reporter) -> Result<(), Box<dyn Error>> {
+    let bf_program = frontend::select(&args.program, args.lang)
+        .frontend
+        .parse(&args.program, &ParseOptions::default())?;
+
+    let mut names = HashSet::new();
+    for input in &args.inputs {
+        let name = output_name(input)?;
+        if !names.insert(name.clone()) {
+            return Err(format!(
+                "more than one input is named '{}', so their outputs would overwrite each other",
+                name
+            )
+            .into());
+        }
+    }
+    fs::create_dir_all(&args.out_dir)?;
+
+    let limits = Limits {
+        max_instructions: args.max_instructions,
+        timeout: args.timeout_ms.map(Duration::from_millis),
+        ..Limits::default()
+    };
+
+    let mut failures = Vec::new();
+    for input in &args.inputs {
+        let name = output_name(input)?;
+        let error_path = args.out_dir.join(format!("{}.err", name));
+        match run_one(args, &bf_program, limits, input, &name) {
+            Ok(()) => {
+                if error_path.exists() {
+                    fs::remove_file(&error_path)?;
+                }
+                reporter.verbose(format!("{}: ok", input.display()));
+            }
+            Err(error) => {
+                safe_write::write(&error_path, format!("{}\n", error), Existing::Overwrite)?;
+                failures.push(format!("{}: {}", input.display(), error));
+            }
+        }
+    }
+
+    for failure in &failures {
+        reporter.info(format!("FAILED {}", failure));
+    }
+    reporter.info(format!(
+        "Mapped {} inputs: {} succeeded, {} failed",
+        args.inputs.len(),
+        args.inputs.len() - failures.len(),
+        failures.len()
+    ));
+    match failures.len() {
+        0 => Ok(()),
+        failed => Err(format!("{} of {} inputs failed", failed, args.inputs.len()).into()),
+    }
+}
+
+/// The name an input's output and error files are based on
+fn output_name(input: &Path) -> Result<String, Box<dyn Error>> {
+    input
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("'{}' is not a file", input.display()).into())
+}
+
+/// Run the program over a single input, writing its output to `<out-dir>/<name>.out`. The output
+/// file is only written if the program completes.
+fn run_one(
+    args: &MapArgs,
+    bf_program: &BfProgram,
+    limits: Limits,
+    input: &Path,
+    name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut input = BufReader::new(File::open(input)?);
+    let mut output = AtomicFile::create(
+        args.out_dir.join(format!("{}.out", name)),
+        Existing::from_force(args.force),
+    )?;
+
+    let mut bf_interpreter: VirtualMachine<u8> =
+        VirtualMachine::new(bf_program, args.cells, args.extensible)
+            .with_limits(limits)
+            .with_flush_policy(FlushPolicy::Manual);
+    let halt_reason = bf_interpreter.interpret(&mut input, &mut output)?;
+
+    match halt_reason {
+        HaltReason::Completed => Ok(output.commit()?),
+        halt_reason => Err(format!("stopped early: {}", halt_reason).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Is each input run separately, with failures reported without stopping the rest?
+    #[test]
+    fn test_run_map() {
+        let directory = std::env::temp_dir().join(format!("bft-map-test-{}", std::process::id()));
+        let inputs = directory.join("inputs");
+        fs::create_dir_all(&inputs).unwrap();
+        let program = directory.join("upper.bf");
+        // echo the first two bytes, one above their value; fails if there are fewer than two
+        fs::write(&program, ",+.,+.").unwrap();
+        fs::write(inputs.join("a.txt"), "ab").unwrap();
+        fs::write(inputs.join("b.txt"), "x").unwrap();
+
+        let args = MapArgs {
+            program,
+            inputs: vec![inputs.join("a.txt"), inputs.join("b.txt")],
+            out_dir: directory.join("out"),
+            lang: None,
+            cells: None,
+            extensible: false,
+            max_instructions: None,
+            timeout_ms: None,
+            force: false,
+        };
+        let result = run_map(&args, &Reporter::new(true, 0));
+
+        let out = |name: &str| fs::read(directory.join("out").join(name));
+        assert!(result.is_err());
+        assert_eq!(out("a.txt.out").unwrap(), b"bc");
+        assert!(out("a.txt.err").is_err());
+        assert!(out("b.txt.err").is_ok());
+
+        // inputs with the same name would share an output file
+        let clashing = MapArgs {
+            inputs: vec![inputs.join("a.txt"), PathBuf::from("elsewhere/a.txt")],
+            ..args
+        };
+        assert!(run_map(&clashing, &Reporter::new(true, 0)).is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
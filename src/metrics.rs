@@ -0,0 +1,231 @@
+//! Counters describing the programs run by this process, written out in the Prometheus text format
+//! so that a node exporter's textfile collector can pick them up.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use bft::safe_write::{self, Existing};
+use bft_interp::{HaltReason, VMError};
+
+/// Running totals for everything run by this process
+#[derive(Debug, Default)]
+pub struct Metrics {
+    programs_run: u64,
+    instructions_executed: u64,
+    bytes_output: u64,
+    /// Programs that halted without an error, by [HaltReason]
+    halts: BTreeMap<&'static str, u64>,
+    /// Programs that failed, by type of error
+    errors: BTreeMap<&'static str, u64>,
+    /// How many machines the daemon keeps ready for clients, if this is the daemon
+    pool_size: Option<usize>,
+    /// Machines the daemon built: one for each place in its pool, and again when the program is
+    /// reloaded
+    machines_built: u64,
+    /// Clients the daemon served with a machine reset after serving another, rather than built
+    machines_reused: u64,
+}
+
+impl Metrics {
+    /// Count a program that could not be loaded
+    pub fn record_load_error(&mut self) {
+        self.programs_run += 1;
+        *self.errors.entry("load").or_default() += 1;
+    }
+
+    /// Count a program that was run, however it finished
+    pub fn record_run(
+        &mut self,
+        instructions: u64,
+        bytes_output: u64,
+        result: &Result<HaltReason, VMError>,
+    ) {
+        self.programs_run += 1;
+        self.instructions_executed += instructions;
+        self.bytes_output += bytes_output;
+        match result {
+            Ok(halt_reason) => *self.halts.entry(halt_label(*halt_reason)).or_default() += 1,
+            Err(error) => *self.errors.entry(error_label(error)).or_default() += 1,
+        }
+    }
+
+    /// Count the machines in the daemon's pool, so that its metrics are rendered
+    pub fn record_pool_size(&mut self, pool_size: usize) {
+        self.pool_size = Some(pool_size);
+    }
+
+    /// Count a machine the daemon built for its pool
+    pub fn record_machine_built(&mut self) {
+        self.machines_built += 1;
+    }
+
+    /// Count a client the daemon served with a machine it reset and used again
+    pub fn record_machine_reused(&mut self) {
+        self.machines_reused += 1;
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+
+        counter(
+            &mut text,
+            "bft_programs_run_total",
+            "Programs loaded or run",
+            &[(None, self.programs_run)],
+        );
+        counter(
+            &mut text,
+            "bft_instructions_executed_total",
+            "Brainfuck instructions executed",
+            &[(None, self.instructions_executed)],
+        );
+        counter(
+            &mut text,
+            "bft_bytes_output_total",
+            "Bytes output by programs",
+            &[(None, self.bytes_output)],
+        );
+        let halts: Vec<_> = self
+            .halts
+            .iter()
+            .map(|(reason, count)| (Some(("reason", *reason)), *count))
+            .collect();
+        counter(
+            &mut text,
+            "bft_program_halts_total",
+            "Programs that stopped without an error, by reason",
+            &halts,
+        );
+        let errors: Vec<_> = self
+            .errors
+            .iter()
+            .map(|(kind, count)| (Some(("type", *kind)), *count))
+            .collect();
+        counter(
+            &mut text,
+            "bft_program_errors_total",
+            "Programs that failed, by type of error",
+            &errors,
+        );
+        if let Some(pool_size) = self.pool_size {
+            gauge(
+                &mut text,
+                "bft_daemon_pool_size",
+                "Machines the daemon keeps ready for clients",
+                pool_size as u64,
+            );
+            counter(
+                &mut text,
+                "bft_daemon_machines_built_total",
+                "Machines the daemon built, for its pool or after reloading the program",
+                &[(None, self.machines_built)],
+            );
+            counter(
+                &mut text,
+                "bft_daemon_machines_reused_total",
+                "Clients served with a machine that was reset rather than built",
+                &[(None, self.machines_reused)],
+            );
+        }
+
+        text
+    }
+
+    /// Write the metrics to a file. The file is written under a temporary name and then renamed,
+    /// so a collector never sees it half written.
+    pub fn write_textfile(&self, path: &Path) -> io::Result<()> {
+        safe_write::write(path, self.render(), Existing::Overwrite)
+    }
+}
+
+/// Append a counter, with its help and type lines, and one sample per label
+fn counter(text: &mut String, name: &str, help: &str, samples: &[(Option<(&str, &str)>, u64)]) {
+    text.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} counter\n",
+        name, help, name
+    ));
+    for (label, value) in samples {
+        match label {
+            Some((label, label_value)) => text.push_str(&format!(
+                "{}{{{}=\"{}\"}} {}\n",
+                name, label, label_value, value
+            )),
+            None => text.push_str(&format!("{} {}\n", name, value)),
+        }
+    }
+}
+
+/// Append a gauge, with its help and type lines
+fn gauge(text: &mut String, name: &str, help: &str, value: u64) {
+    text.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} gauge\n{} {}\n",
+        name, help, name, name, value
+    ));
+}
+
+/// Label value for a [HaltReason]
+pub fn halt_label(halt_reason: HaltReason) -> &'static str {
+    match halt_reason {
+        HaltReason::Completed => "completed",
+        HaltReason::InstructionLimit => "instruction_limit",
+        HaltReason::Timeout => "timeout",
+        HaltReason::OutputLimit => "output_limit",
+        HaltReason::Interrupted => "interrupted",
+        HaltReason::Paused => "paused",
+        HaltReason::BreakpointHit { .. } => "breakpoint",
+        HaltReason::NeedsInput => "needs_input",
+        HaltReason::LoopIterationLimit { .. } => "loop_iteration_limit",
+    }
+}
+
+/// Label value for a [VMError]
+fn error_label(error: &VMError) -> &'static str {
+    match error {
+        VMError::HeadUnderrun(_) => "head_underrun",
+        VMError::HeadOverrun(_) => "head_overrun",
+        VMError::ReadError(..) => "read_error",
+        VMError::WriteError(..) => "write_error",
+        VMError::WriteProtected(..) => "write_protected",
+        VMError::ExtensionFailed(..) => "extension_failed",
+        VMError::AssertionFailed { .. } => "assertion_failed",
+        VMError::CellOverflow(_) => "cell_overflow",
+        VMError::CellUnderflow(_) => "cell_underflow",
+        VMError::TapeLimitExceeded(..) => "tape_limit_exceeded",
+        VMError::Aborted(_) => "aborted",
+        VMError::MemoryLimitExceeded(_) => "memory_limit_exceeded",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_types::{Instruction, LocalisedInstruction};
+
+    // Are runs added up and broken down by how they finished?
+    #[test]
+    fn test_render_metrics() {
+        let mut metrics = Metrics::default();
+        let instruction = LocalisedInstruction::new(Instruction::MoveLeft, 1, 1);
+
+        metrics.record_run(10, 2, &Ok(HaltReason::Completed));
+        metrics.record_run(5, 0, &Err(VMError::HeadUnderrun(instruction)));
+        metrics.record_load_error();
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE bft_programs_run_total counter\nbft_programs_run_total 3\n"));
+        assert!(text.contains("bft_instructions_executed_total 15\n"));
+        assert!(text.contains("bft_program_halts_total{reason=\"completed\"} 1\n"));
+        assert!(text.contains("bft_program_errors_total{type=\"head_underrun\"} 1\n"));
+        assert!(text.contains("bft_program_errors_total{type=\"load\"} 1\n"));
+        assert!(!text.contains("bft_daemon"));
+
+        metrics.record_pool_size(4);
+        metrics.record_machine_built();
+        metrics.record_machine_reused();
+        let text = metrics.render();
+        assert!(text.contains("# TYPE bft_daemon_pool_size gauge\nbft_daemon_pool_size 4\n"));
+        assert!(text.contains("bft_daemon_machines_reused_total 1\n"));
+    }
+}
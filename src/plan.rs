@@ -0,0 +1,404 @@
+//! `--dry-run`: describing how a program would be run, without running it.
+
+use std::fmt::Write;
+use std::io::{stdout, IsTerminal};
+use std::path::Path;
+
+use bft_interp::{Arithmetic, EofBehavior, FlushPolicy};
+use bft_types::optimize::OptLevel;
+use bft_types::BfProgram;
+
+use crate::cli::Args;
+use crate::frontend;
+use crate::stream::describe_size;
+
+/// Default tape length, as used by the [bft_interp::VirtualMachine]
+const DEFAULT_CELLS: usize = 30_000;
+
+/// Describe how the parsed program would be run with the given arguments: the engine, the tape,
+/// where input and output go, and the limits. Nothing is opened, written or executed.
+pub fn describe(args: &Args, path: &Path, program: &BfProgram) -> String {
+    let mut plan = String::new();
+    // writing to a String cannot fail
+    let mut line = |label: &str, value: String| {
+        let _ = writeln!(plan, "{:<12}{}", format!("{}:", label), value);
+    };
+
+    line(
+        "Program",
+        format!(
+            "{} ({} instructions{})",
+            path.display(),
+            program.localised_instructions().len(),
+            if program.has_assertions() {
+                ", with @assert directives checked"
+            } else {
+                ""
+            }
+        ),
+    );
+    line("Front-end", frontend::select(path, args.lang).to_string());
+    line("Engine", engine(args));
+    line(
+        "Passes",
+        if args.optimize != OptLevel::None {
+            format!(
+                "{}, {} runs executed in one step",
+                args.optimize,
+                program.optimize(args.optimize).fused()
+            )
+        } else {
+            "none, instructions run as parsed".to_string()
+        },
+    );
+
+    let mut tape = format!(
+        "{} cells, {}",
+        args.cells.map_or(DEFAULT_CELLS, |cells| cells.get()),
+        if args.extensible {
+            "extensible"
+        } else {
+            "fixed size"
+        }
+    );
+    if args.bidirectional {
+        tape.push_str(", growing to the left of cell 0");
+    }
+    if let Some(max_cells) = args.max_cells {
+        let _ = write!(tape, ", growing to at most {} cells", max_cells);
+    }
+    if let Some(max_memory) = args.max_memory {
+        let _ = write!(
+            tape,
+            ", with at most {} held with snapshots and traces",
+            describe_size(max_memory)
+        );
+    }
+    if args.circular {
+        tape.push_str(", circular");
+    }
+    if let Some(pre_grow) = args.pre_grow {
+        let _ = write!(tape, ", grown to {} cells and warmed up", pre_grow);
+    } else if args.warm_up {
+        tape.push_str(", warmed up");
+    }
+    match args.arithmetic {
+        Arithmetic::Wrapping => {}
+        Arithmetic::Saturating => tape.push_str(", saturating arithmetic"),
+        Arithmetic::Checked => tape.push_str(", checked arithmetic"),
+    }
+    for cells in &args.protect {
+        let _ = write!(tape, ", cells {}..{} read-only", cells.start, cells.end);
+    }
+    if let Some(layout) = &args.layout {
+        let _ = write!(tape, ", layout from {}", layout.display());
+    }
+    line("Tape", tape);
+
+    line(
+        "Input",
+        match &args.input {
+            None if args.input_str.is_some() => {
+                format!("{:?}", args.input_str.as_deref().unwrap_or_default())
+            }
+            Some(input) if args.then_stdin => format!("{}, then stdin", input.display()),
+            Some(input) => input.display().to_string(),
+            None if args.raw => "stdin, a key at a time".to_string(),
+            None => "stdin".to_string(),
+        } + match args.eof {
+            EofBehavior::Error => ", an error at EOF",
+            EofBehavior::SetZero => ", 0 at EOF",
+            EofBehavior::SetMax => ", 255 at EOF",
+            EofBehavior::LeaveUnchanged => ", cell unchanged at EOF",
+        },
+    );
+    match (args.stderr_channel, args.debug_dump) {
+        (true, true) => line(
+            "Stderr",
+            "';' writes the current cell, '#' shows the cells around the head".to_string(),
+        ),
+        (true, false) => line("Stderr", "';' writes the current cell".to_string()),
+        (false, true) => line("Stderr", "'#' shows the cells around the head".to_string()),
+        (false, false) => {}
+    }
+    line(
+        "Output",
+        format!(
+            "{}{}, flushed {}",
+            match (&args.output, &args.stream_to) {
+                (Some(path), _) => format!(
+                    "written to {}{}",
+                    path.display(),
+                    if args.trailing_newline {
+                        ", with a trailing newline added if needed"
+                    } else {
+                        ""
+                    }
+                ),
+                (None, Some(path)) => format!(
+                    "streamed to {} through a buffer of {}{}",
+                    path.display(),
+                    describe_size(args.stream_buffer),
+                    args.fsync_every
+                        .map(|every| format!(", synced every {}", describe_size(every)))
+                        .unwrap_or_default()
+                ),
+                (None, None) if args.filter => "stdout, block-buffered as a filter".to_string(),
+                (None, None) if stdout().is_terminal() => {
+                    "stdout, with a trailing newline added if needed".to_string()
+                }
+                (None, None) => "stdout".to_string(),
+            },
+            args.tee
+                .as_ref()
+                .map(|log| format!(", copied to {}", log.display()))
+                .unwrap_or_default(),
+            match args.flush_policy() {
+                FlushPolicy::EveryByte => "after every byte",
+                FlushPolicy::Line => "at newlines and before reading input",
+                FlushPolicy::Manual => "only when the buffer fills and at the end",
+            }
+        ),
+    );
+
+    let limits = args.limits();
+    let limits = [
+        limits
+            .max_instructions
+            .map(|max| format!("{} instructions", max)),
+        limits
+            .timeout
+            .map(|timeout| format!("{}ms", timeout.as_millis())),
+        limits
+            .max_output
+            .map(|max| format!("{} bytes of output", max)),
+        limits
+            .max_loop_iterations
+            .map(|max| format!("{} iterations per loop", max)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    line(
+        "Limits",
+        if limits.is_empty() {
+            "none".to_string()
+        } else {
+            limits.join(", ")
+        },
+    );
+
+    if args.virtual_clock().is_some() {
+        let costs = args.cycle_costs.unwrap_or_default();
+        let mut clock = format!(
+            "move={}, arith={}, in={}, out={}, jump={}, ext={} cycles",
+            costs.move_head,
+            costs.arithmetic,
+            costs.input,
+            costs.output,
+            costs.jump,
+            costs.extension
+        );
+        if let Some(hz) = args.clock_hz {
+            let _ = write!(clock, ", throttled to {}Hz", hz);
+        }
+        line("Clock", clock);
+    }
+    if args.sandbox {
+        line("Sandbox", "seccomp, once input is open".to_string());
+    }
+    if let Some(capacity) = args.jump_history {
+        line("Jumps", format!("last {} kept for errors", capacity));
+    }
+    if let Some(capacity) = args.tape_history {
+        line("Journal", format!("last {} cell changes shown", capacity));
+    }
+    if let Some(range) = &args.dump_tape {
+        line(
+            "Tape dump",
+            format!(
+                "cells {}..{} when the program stops",
+                range.start, range.end
+            ),
+        );
+    }
+    if let Some(metrics_file) = &args.metrics_file {
+        line("Metrics", metrics_file.display().to_string());
+    }
+    if let Some(profile_db) = &args.profile_db {
+        line(
+            "Profile",
+            format!("run and hottest loops added to {}", profile_db.display()),
+        );
+    }
+    let checks: Vec<_> = args
+        .assert_head
+        .map(|head| format!("head at cell {}", head))
+        .into_iter()
+        .chain(
+            args.assert_cell
+                .iter()
+                .map(|(cell, value)| format!("cell {} holds {}", cell, value)),
+        )
+        .chain(args.assert_halted.then(|| "ran to completion".to_string()))
+        .collect();
+    if !checks.is_empty() {
+        line(
+            "Checks",
+            format!("when the program stops: {}", checks.join(", ")),
+        );
+    }
+    if args.summary {
+        line(
+            "Summary",
+            "printed to stderr when the program stops".to_string(),
+        );
+    }
+
+    plan
+}
+
+/// Warnings for a tape that the program is certain to run off, found without running it (see
+/// [bft_types::extent]): past the last cell of a fixed-size tape, or of an extensible one that
+/// may not grow far enough, or left of cell 0 on a tape that cannot grow that way
+pub fn overrun_warnings(args: &Args, program: &BfProgram) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if args.circular {
+        return warnings;
+    }
+    let extent = program.tape_extent();
+    let cells = if args.extensible {
+        args.max_cells.map(|max_cells| max_cells.get())
+    } else {
+        Some(args.cells.map_or(DEFAULT_CELLS, |cells| cells.get()))
+    };
+    if let Some(cells) = cells.filter(|cells| extent.certain_right() >= *cells) {
+        warnings.push(format!(
+            "Warning: the program is certain to move the head past the end of the {} cell \
+             tape, as far as cell {}",
+            cells,
+            extent.certain_right()
+        ));
+    }
+    if !args.bidirectional && extent.certain_left() > 0 {
+        warnings.push(format!(
+            "Warning: the program is certain to move the head {} cells left of cell 0, off the \
+             start of the tape",
+            extent.certain_left()
+        ));
+    }
+    warnings
+}
+
+/// Describe the engine that runs the program and the cells it works with
+pub fn engine(args: &Args) -> String {
+    format!(
+        "{}, 8-bit wrapping cells",
+        if args.jit && cfg!(feature = "jit") {
+            "native code compiled with Cranelift"
+        } else {
+            "interpreter"
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    // Does the plan reflect the options given?
+    #[test]
+    fn test_describe() {
+        let cli = crate::cli::Cli::parse_from([
+            "bft",
+            "--dry-run",
+            "-c",
+            "100",
+            "-e",
+            "--timeout-ms",
+            "50",
+            "--flush",
+            "line",
+            "prog.bf",
+        ]);
+        let args = cli.run.unwrap();
+        let program = BfProgram::new("prog.bf", "+[-].").unwrap();
+
+        let plan = describe(&args, Path::new("prog.bf"), &program);
+
+        assert!(plan.contains("Program:    prog.bf (5 instructions)"));
+        assert!(plan.contains("Front-end:  bf (from the file extension)"));
+        assert!(plan.contains("Tape:       100 cells, extensible"));
+        assert!(plan.contains("Input:      stdin"));
+        assert!(plan.contains("at newlines and before reading input"));
+        assert!(plan.contains("Limits:     50ms"));
+        assert!(plan.contains("Passes:     none, instructions run as parsed"));
+
+        let cli = crate::cli::Cli::parse_from(["bft", "--dry-run", "--optimize", "prog.bf"]);
+        let runs = BfProgram::new("prog.bf", "++[->>[-]<<][>]").unwrap();
+        let plan = describe(&cli.run.unwrap(), Path::new("prog.bf"), &runs);
+        assert!(plan.contains(
+            "Passes:     clear loops, scan loops and run-length encoding, 5 runs executed in one \
+             step"
+        ));
+        let cli = crate::cli::Cli::parse_from(["bft", "--dry-run", "--optimize=1", "prog.bf"]);
+        let plan = describe(&cli.run.unwrap(), Path::new("prog.bf"), &runs);
+        assert!(plan.contains("Passes:     run-length encoding, 3 runs executed in one step"));
+
+        let cli = crate::cli::Cli::parse_from([
+            "bft",
+            "--dry-run",
+            "--stream-to",
+            "out.txt",
+            "--fsync-every",
+            "64M",
+            "prog.bf",
+        ]);
+        let plan = describe(&cli.run.unwrap(), Path::new("prog.bf"), &program);
+        assert!(plan.contains(
+            "Output:     streamed to out.txt through a buffer of 8.0 MiB, synced every 64.0 MiB, \
+             flushed only when the buffer fills and at the end"
+        ));
+
+        let cli = crate::cli::Cli::parse_from([
+            "bft",
+            "--dry-run",
+            "--output",
+            "out.txt",
+            "--trailing-newline",
+            "prog.bf",
+        ]);
+        let plan = describe(&cli.run.unwrap(), Path::new("prog.bf"), &program);
+        assert!(plan.contains(
+            "Output:     written to out.txt, with a trailing newline added if needed, flushed only \
+             when the buffer fills and at the end"
+        ));
+    }
+
+    // Are only overruns that are certain, and only on tapes that cannot take them, warned of?
+    #[test]
+    fn test_overrun_warnings() {
+        let program = BfProgram::new("prog.bf", ">>>>+[<]").unwrap();
+        let warnings = |flags: &[&str]| {
+            let cli = crate::cli::Cli::parse_from(
+                ["bft"].iter().chain(flags).chain(&["prog.bf"]).copied(),
+            );
+            overrun_warnings(&cli.run.unwrap(), &program)
+        };
+
+        assert_eq!(
+            warnings(&["-c", "4"]),
+            ["Warning: the program is certain to move the head past the end of the 4 cell tape, \
+              as far as cell 4"]
+        );
+        assert!(warnings(&["-c", "5"]).is_empty());
+        assert!(warnings(&["-c", "4", "-e"]).is_empty());
+        assert_eq!(warnings(&["-c", "2", "-e", "--max-cells", "3"]).len(), 1);
+        assert!(warnings(&["-c", "4", "--circular"]).is_empty());
+
+        let program = BfProgram::new("prog.bf", "<").unwrap();
+        let cli = crate::cli::Cli::parse_from(["bft", "prog.bf"]);
+        assert_eq!(overrun_warnings(&cli.run.unwrap(), &program).len(), 1);
+    }
+}
@@ -0,0 +1,422 @@
+//! `--profile-db`: a record of every run of a program, kept across runs so that
+//! `bft profile report` can show how it has changed: how many instructions it takes, how long, and
+//! which loops are hottest. Useful when working on the speed of a long-lived program, one change
+//! at a time.
+//!
+//! Runs are keyed by the program's fingerprint, so that each version of a program has its own
+//! history, and earlier versions at the same path are listed alongside. The database is a text
+//! file with one run per line, added to at the end, so that it can be kept under version control
+//! or read with other tools. With its tabs shown as spaces:
+//!
+//! ```text
+//! # bft profile 1
+//! 3f2a9c0d11e4b7a8  1760600000  120394  5230  13  completed  3:5=1200,7:1=300  prog.bf
+//! ```
+//!
+//! The fields, separated by tabs, are the fingerprint, the time of the run in seconds since the
+//! Unix epoch, instructions executed, microseconds taken, bytes output, how the run ended, the
+//! hottest loops as `line:column=iterations`, and the program's path, last as it may hold anything.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bft_interp::{HaltReason, Observer, RunStats, VMError};
+use bft_types::{BfProgram, Instruction, LocalisedInstruction};
+
+use crate::cli::{ProfileArgs, ProfileCommand};
+use crate::frontend;
+use crate::metrics::halt_label;
+
+/// The first line of a database, followed by the version of the format
+const HEADER: &str = "# bft profile 1";
+
+/// How many of the hottest loops are kept for each run
+const HOT_LOOPS_KEPT: usize = 5;
+
+/// Counts the iterations of each loop as a program runs, by the executions of its `]`
+#[derive(Debug, Default)]
+pub struct LoopProfile {
+    iterations: HashMap<usize, u64>,
+}
+
+impl Observer for LoopProfile {
+    fn instruction_executed(
+        &mut self,
+        _clock: u64,
+        program_counter: usize,
+        instruction: &LocalisedInstruction,
+    ) {
+        if instruction.instruction() == Instruction::ConditionalJumpBackward {
+            *self.iterations.entry(program_counter).or_default() += 1;
+        }
+    }
+}
+
+impl LoopProfile {
+    /// The loops that ran the most, hottest first, located by their `[`
+    fn hottest(&self, program: &BfProgram) -> Vec<HotLoop> {
+        let mut hot_loops: Vec<_> = self
+            .iterations
+            .iter()
+            .map(|(&close, &iterations)| {
+                // the jump target of a `]` is just after its `[`
+                let open = program.localised_instructions()[program.jump_target(close) - 1];
+                HotLoop {
+                    line_num: open.line_num(),
+                    column_num: open.column_num(),
+                    iterations,
+                }
+            })
+            .collect();
+        hot_loops.sort_by_key(|hot_loop| {
+            (
+                std::cmp::Reverse(hot_loop.iterations),
+                hot_loop.line_num,
+                hot_loop.column_num,
+            )
+        });
+        hot_loops.truncate(HOT_LOOPS_KEPT);
+        hot_loops
+    }
+}
+
+/// A loop, and how many times it went round
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HotLoop {
+    line_num: usize,
+    column_num: usize,
+    iterations: u64,
+}
+
+/// One line of the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Run {
+    fingerprint: u64,
+    timestamp: u64,
+    instructions: u64,
+    elapsed: Duration,
+    bytes_output: u64,
+    outcome: String,
+    hot_loops: Vec<HotLoop>,
+    program: PathBuf,
+}
+
+impl Run {
+    fn to_line(&self) -> String {
+        let hot_loops: Vec<_> = self
+            .hot_loops
+            .iter()
+            .map(|hot_loop| {
+                format!(
+                    "{}:{}={}",
+                    hot_loop.line_num, hot_loop.column_num, hot_loop.iterations
+                )
+            })
+            .collect();
+        format!(
+            "{:016x}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.fingerprint,
+            self.timestamp,
+            self.instructions,
+            self.elapsed.as_micros(),
+            self.bytes_output,
+            self.outcome,
+            if hot_loops.is_empty() {
+                "-".to_string()
+            } else {
+                hot_loops.join(",")
+            },
+            self.program.display()
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(8, '\t');
+        let mut field = || fields.next();
+        let fingerprint = u64::from_str_radix(field()?, 16).ok()?;
+        let timestamp = field()?.parse().ok()?;
+        let instructions = field()?.parse().ok()?;
+        let elapsed = Duration::from_micros(field()?.parse().ok()?);
+        let bytes_output = field()?.parse().ok()?;
+        let outcome = field()?.to_string();
+        let hot_loops = match field()? {
+            "-" => Vec::new(),
+            hot_loops => hot_loops
+                .split(',')
+                .map(|hot_loop| {
+                    let (location, iterations) = hot_loop.split_once('=')?;
+                    let (line_num, column_num) = location.split_once(':')?;
+                    Some(HotLoop {
+                        line_num: line_num.parse().ok()?,
+                        column_num: column_num.parse().ok()?,
+                        iterations: iterations.parse().ok()?,
+                    })
+                })
+                .collect::<Option<_>>()?,
+        };
+        Some(Self {
+            fingerprint,
+            timestamp,
+            instructions,
+            elapsed,
+            bytes_output,
+            outcome,
+            hot_loops,
+            program: PathBuf::from(field()?),
+        })
+    }
+}
+
+/// Add a finished run of `program`, loaded from `path`, to the database at `db`
+pub fn record(
+    db: &Path,
+    path: &Path,
+    program: &BfProgram,
+    stats: &RunStats,
+    result: &Result<HaltReason, VMError>,
+    loop_profile: &LoopProfile,
+) -> std::io::Result<()> {
+    let run = Run {
+        fingerprint: program.fingerprint(),
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs()),
+        instructions: stats.instructions_executed,
+        elapsed: stats.elapsed,
+        bytes_output: stats.bytes_output,
+        outcome: match result {
+            Ok(halt_reason) => halt_label(*halt_reason).to_string(),
+            Err(_) => "error".to_string(),
+        },
+        hot_loops: loop_profile.hottest(program),
+        program: path.to_path_buf(),
+    };
+
+    let mut file = OpenOptions::new().create(true).append(true).open(db)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", HEADER)?;
+    }
+    writeln!(file, "{}", run.to_line())
+}
+
+/// Read every run in the database. Lines that can't be understood are skipped.
+fn load(db: &Path) -> Result<Vec<Run>, Box<dyn Error>> {
+    let text = match fs::read_to_string(db) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            return Err(format!("no profile database at {}", db.display()).into())
+        }
+        text => text?,
+    };
+    match text.lines().next() {
+        Some(HEADER) | None => {}
+        Some(_) => return Err(format!("{} is not a bft profile database", db.display()).into()),
+    }
+    Ok(text
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(Run::from_line)
+        .collect())
+}
+
+/// `bft profile`
+pub fn run_profile(args: &ProfileArgs) -> Result<(), Box<dyn Error>> {
+    match &args.command {
+        ProfileCommand::Report { program, db } => {
+            let bf_program = frontend::select(program, None)
+                .frontend
+                .parse(program, &Default::default())?;
+            print!("{}", report(&load(db)?, program, bf_program.fingerprint()));
+        }
+    }
+    Ok(())
+}
+
+/// Describe the runs of the program with this fingerprint, and list other versions of it
+fn report(runs: &[Run], path: &Path, fingerprint: u64) -> String {
+    let mut report = String::new();
+    // writing to a String cannot fail
+    let mut line = |label: &str, value: String| {
+        let _ = writeln!(report, "{:<16}{}", format!("{}:", label), value);
+    };
+    let (these, others): (Vec<&Run>, Vec<&Run>) =
+        runs.iter().partition(|run| run.fingerprint == fingerprint);
+
+    line(
+        "Program",
+        format!("{} (fingerprint {:016x})", path.display(), fingerprint),
+    );
+    line("Runs", these.len().to_string());
+    if let (Some(first), Some(latest)) = (these.first(), these.last()) {
+        let change = |first: f64, latest: f64| {
+            if first == 0.0 {
+                String::new()
+            } else {
+                format!(
+                    " ({:+.1}% since the first run)",
+                    (latest - first) / first * 100.0
+                )
+            }
+        };
+        let best = these.iter().map(|run| run.instructions).min().unwrap_or(0);
+        line(
+            "Instructions",
+            format!(
+                "first {}, best {}, latest {}{}",
+                first.instructions,
+                best,
+                latest.instructions,
+                change(first.instructions as f64, latest.instructions as f64)
+            ),
+        );
+        let millis = |elapsed: Duration| elapsed.as_secs_f64() * 1000.0;
+        let best = these
+            .iter()
+            .map(|run| run.elapsed)
+            .min()
+            .unwrap_or_default();
+        line(
+            "Time",
+            format!(
+                "first {:.3}ms, best {:.3}ms, latest {:.3}ms{}",
+                millis(first.elapsed),
+                millis(best),
+                millis(latest.elapsed),
+                change(millis(first.elapsed), millis(latest.elapsed))
+            ),
+        );
+
+        let mut outcomes: BTreeMap<&str, usize> = BTreeMap::new();
+        for run in &these {
+            *outcomes.entry(&run.outcome).or_default() += 1;
+        }
+        let outcomes: Vec<_> = outcomes
+            .iter()
+            .map(|(outcome, count)| format!("{} {}", count, outcome))
+            .collect();
+        line("Outcomes", outcomes.join(", "));
+
+        let mut hot_loops: BTreeMap<(usize, usize), u64> = BTreeMap::new();
+        for hot_loop in these.iter().flat_map(|run| &run.hot_loops) {
+            *hot_loops
+                .entry((hot_loop.line_num, hot_loop.column_num))
+                .or_default() += hot_loop.iterations;
+        }
+        let mut hot_loops: Vec<_> = hot_loops.into_iter().collect();
+        hot_loops.sort_by_key(|(location, iterations)| (std::cmp::Reverse(*iterations), *location));
+        for (i, ((line_num, column_num), iterations)) in
+            hot_loops.iter().take(HOT_LOOPS_KEPT).enumerate()
+        {
+            line(
+                if i == 0 { "Hot loops" } else { "" },
+                format!(
+                    "line {} column {}: {} iterations over all runs",
+                    line_num, column_num, iterations
+                ),
+            );
+        }
+    }
+
+    // earlier versions of the same file, most recently run first
+    let mut versions: Vec<(u64, usize, &Run)> = Vec::new();
+    for run in others.iter().filter(|run| run.program == path) {
+        match versions
+            .iter_mut()
+            .find(|(found, ..)| *found == run.fingerprint)
+        {
+            Some((_, count, latest)) => {
+                *count += 1;
+                *latest = run;
+            }
+            None => versions.push((run.fingerprint, 1, run)),
+        }
+    }
+    versions.sort_by_key(|(_, _, latest)| std::cmp::Reverse(latest.timestamp));
+    for (i, (fingerprint, count, latest)) in versions.iter().enumerate() {
+        line(
+            if i == 0 { "Other versions" } else { "" },
+            format!(
+                "{:016x}: {} runs, latest {} instructions",
+                fingerprint, count, latest.instructions
+            ),
+        );
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_interp::VirtualMachine;
+    use std::io::{empty, sink};
+
+    // Are the hottest loops found, and does a run read back as it was written?
+    #[test]
+    fn test_loop_profile() {
+        let program = BfProgram::new("test.bf", "+++[-]\n++[>++++[-]<-]").unwrap();
+        let mut loop_profile = LoopProfile::default();
+        let mut vm: VirtualMachine<u8> = VirtualMachine::new(&program, None, false);
+        vm.add_observer(&mut loop_profile);
+        vm.interpret(&mut empty(), &mut sink()).unwrap();
+        drop(vm);
+
+        let hot_loops = loop_profile.hottest(&program);
+        let found: Vec<_> = hot_loops
+            .iter()
+            .map(|hot_loop| (hot_loop.line_num, hot_loop.column_num, hot_loop.iterations))
+            .collect();
+        assert_eq!(found, [(2, 9, 8), (1, 4, 3), (2, 3, 2)]);
+
+        let run = Run {
+            fingerprint: 0xabc,
+            timestamp: 1_760_600_000,
+            instructions: 120_394,
+            elapsed: Duration::from_micros(5230),
+            bytes_output: 13,
+            outcome: "completed".to_string(),
+            hot_loops,
+            program: PathBuf::from("dir with spaces/prog.bf"),
+        };
+        assert_eq!(Run::from_line(&run.to_line()), Some(run));
+        assert_eq!(Run::from_line("not a run"), None);
+    }
+
+    // Does the report show the trend for this version, and list the others?
+    #[test]
+    fn test_report() {
+        let run = |fingerprint, timestamp, instructions| Run {
+            fingerprint,
+            timestamp,
+            instructions,
+            elapsed: Duration::from_millis(instructions / 100),
+            bytes_output: 0,
+            outcome: "completed".to_string(),
+            hot_loops: vec![HotLoop {
+                line_num: 1,
+                column_num: 4,
+                iterations: 10,
+            }],
+            program: PathBuf::from("prog.bf"),
+        };
+        let runs = [
+            run(1, 100, 5000),
+            run(2, 200, 4000),
+            run(2, 300, 3000),
+            run(2, 400, 3500),
+        ];
+
+        let report = report(&runs, Path::new("prog.bf"), 2);
+        assert!(report.contains("Runs:           3\n"));
+        assert!(report.contains(
+            "Instructions:   first 4000, best 3000, latest 3500 (-12.5% since the first run)\n"
+        ));
+        assert!(report.contains("Outcomes:       3 completed\n"));
+        assert!(report.contains("Hot loops:      line 1 column 4: 30 iterations over all runs\n"));
+        assert!(report
+            .ends_with("Other versions: 0000000000000001: 1 runs, latest 5000 instructions\n"));
+    }
+}
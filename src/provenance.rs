@@ -0,0 +1,155 @@
+//! Provenance of compiled artifacts: which source a compiled program came from, and how it was
+//! compiled, so that an artifact passed around a team can be traced back to what produced it.
+//!
+//! A compile target writes a [Provenance] block into its output with [Provenance::header], using
+//! the target language's line comments. `bft inspect artifact` finds the block in
+//! any file, whatever the comment syntax, with [Provenance::find]:
+//!
+//! ```text
+//! // bft-provenance 1
+//! // source: examples/hello.bf
+//! // fingerprint: 3f2a9c0d11e4b7a8
+//! // toolchain: bft 0.1.0
+//! // optimisation: none
+//! // dialect: bf
+//! // end bft-provenance
+//! ```
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use bft_types::BfProgram;
+
+/// The line that starts a block, after the comment prefix, followed by the version of the format
+const START: &str = "bft-provenance";
+
+/// The version of the block's format
+const VERSION: u32 = 1;
+
+/// The line that ends a block, after the comment prefix
+const END: &str = "end bft-provenance";
+
+/// Where a compiled artifact came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// The program's source file, as given on the command line
+    pub source: PathBuf,
+    /// The [BfProgram::fingerprint] of the source
+    pub fingerprint: u64,
+    /// The bft that compiled it
+    pub toolchain: String,
+    /// The optimisation passes that were applied
+    pub optimisation: String,
+    /// The name of the front-end the source was read with, as given to `--lang`
+    pub dialect: String,
+}
+
+impl Provenance {
+    /// The provenance of `program`, read from `source` in `dialect`, compiled now by this bft
+    pub fn new(
+        source: &Path,
+        program: &BfProgram,
+        dialect: &str,
+        optimisation: impl Into<String>,
+    ) -> Self {
+        Self {
+            source: source.to_path_buf(),
+            fingerprint: program.fingerprint(),
+            toolchain: format!("bft {}", env!("CARGO_PKG_VERSION")),
+            optimisation: optimisation.into(),
+            dialect: dialect.to_string(),
+        }
+    }
+
+    /// The block to embed in an artifact, with each line commented out by `comment`, e.g. `//`
+    pub fn header(&self, comment: &str) -> String {
+        let mut header = format!("{} {} {}\n", comment, START, VERSION);
+        for line in self.to_string().lines() {
+            header.push_str(&format!("{} {}\n", comment, line));
+        }
+        header.push_str(&format!("{} {}\n", comment, END));
+        header
+    }
+
+    /// Find the block in an artifact and read it back, whatever the comment syntax
+    pub fn find(artifact: &str) -> Result<Self, String> {
+        let start = format!("{} {}", START, VERSION);
+        let mut lines = artifact.lines();
+        let prefix = lines
+            .by_ref()
+            .find_map(|line| line.trim_end().strip_suffix(start.as_str()))
+            .ok_or("no bft provenance found")?;
+
+        let mut fields = Vec::new();
+        for line in lines {
+            let line = line
+                .strip_prefix(prefix)
+                .ok_or_else(|| format!("the provenance ends early, at '{}'", line))?
+                .trim_end();
+            if line == END {
+                return Self::from_fields(&fields);
+            }
+            let (name, value) = line
+                .split_once(": ")
+                .ok_or_else(|| format!("invalid provenance line '{}'", line))?;
+            fields.push((name, value));
+        }
+        Err(format!("the provenance has no '{}' line", END))
+    }
+
+    fn from_fields(fields: &[(&str, &str)]) -> Result<Self, String> {
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(found, _)| *found == name)
+                .map(|(_, value)| value.to_string())
+                .ok_or_else(|| format!("the provenance has no {}", name))
+        };
+        let fingerprint = field("fingerprint")?;
+        Ok(Self {
+            source: PathBuf::from(field("source")?),
+            fingerprint: u64::from_str_radix(&fingerprint, 16)
+                .map_err(|_| format!("invalid fingerprint '{}'", fingerprint))?,
+            toolchain: field("toolchain")?,
+            optimisation: field("optimisation")?,
+            dialect: field("dialect")?,
+        })
+    }
+}
+
+impl Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "source: {}", self.source.display())?;
+        writeln!(f, "fingerprint: {:016x}", self.fingerprint)?;
+        writeln!(f, "toolchain: {}", self.toolchain)?;
+        writeln!(f, "optimisation: {}", self.optimisation)?;
+        write!(f, "dialect: {}", self.dialect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Is the block found again among other text, whatever the comment syntax?
+    #[test]
+    fn test_header_and_find() {
+        let program = BfProgram::new("hello.bf", "+[-].").unwrap();
+        let provenance = Provenance::new(Path::new("examples/hello.bf"), &program, "bf", "none");
+
+        for comment in ["//", ";", "#"] {
+            let artifact = format!(
+                "generated code\n{}\nmore generated code\n",
+                provenance.header(comment)
+            );
+            assert_eq!(Provenance::find(&artifact), Ok(provenance.clone()));
+        }
+        assert!(provenance
+            .header("//")
+            .starts_with("// bft-provenance 1\n// source: examples/hello.bf\n"));
+
+        assert!(Provenance::find("int main() {}\n").is_err());
+        assert!(Provenance::find("// bft-provenance 1\n// source: a.bf\n").is_err());
+        assert!(Provenance::find("// bft-provenance 1\n// source: a.bf\nint x;\n").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Central place for everything the CLI tells the user, other than the output of the program
+//! itself. All of it goes to stderr, filtered by the verbosity chosen on the command line, so that
+//! stdout only ever carries program output.
+
+use std::fmt::Display;
+
+/// How much the CLI should say about what it is doing
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Verbosity {
+    /// Only hard errors (`-q`)
+    Quiet,
+    /// Errors, warnings and short status messages
+    Normal,
+    /// Also timings and statistics (`-v`)
+    Verbose,
+    /// Also fine detail about the configuration and run (`-vv`)
+    VeryVerbose,
+}
+
+/// Writes messages to stderr according to the chosen [Verbosity]
+#[derive(Debug, Clone, Copy)]
+pub struct Reporter {
+    verbosity: Verbosity,
+}
+
+impl Reporter {
+    /// Create a reporter from the `-q` flag and the number of `-v` flags given
+    pub fn new(quiet: bool, verbose: u8) -> Self {
+        let verbosity = match (quiet, verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::VeryVerbose,
+        };
+        Self { verbosity }
+    }
+
+    /// Report an error. These are always shown.
+    pub fn error(&self, message: impl Display) {
+        eprintln!("Error: {}", message);
+    }
+
+    /// Report a status message, unless running quietly
+    pub fn info(&self, message: impl Display) {
+        if self.verbosity >= Verbosity::Normal {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Report timings and statistics, shown with `-v`
+    pub fn verbose(&self, message: impl Display) {
+        if self.verbosity >= Verbosity::Verbose {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Report fine detail, shown with `-vv`
+    pub fn debug(&self, message: impl Display) {
+        if self.verbosity >= Verbosity::VeryVerbose {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbosity_from_flags() {
+        assert_eq!(Reporter::new(true, 2).verbosity, Verbosity::Quiet);
+        assert_eq!(Reporter::new(false, 0).verbosity, Verbosity::Normal);
+        assert_eq!(Reporter::new(false, 1).verbosity, Verbosity::Verbose);
+        assert_eq!(Reporter::new(false, 5).verbosity, Verbosity::VeryVerbose);
+    }
+}
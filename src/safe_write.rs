@@ -0,0 +1,180 @@
+//! Writing files so that they are never left half written. Everything is written to a temporary
+//! file next to the destination, which is renamed over it only once it is complete, so a run that
+//! fails or is interrupted leaves either the old file or none at all.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Distinguishes the temporary files of writes happening at the same time in one process
+static NEXT_TEMPORARY: AtomicU64 = AtomicU64::new(0);
+
+/// What to do when the file to be written already exists
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Existing {
+    /// Replace it
+    #[default]
+    Overwrite,
+    /// Fail with [ErrorKind::AlreadyExists] before anything is written, unless `--force` was given
+    Refuse,
+}
+
+impl Existing {
+    /// [Existing::Overwrite] if `--force` was given, otherwise [Existing::Refuse]
+    pub fn from_force(force: bool) -> Self {
+        if force {
+            Existing::Overwrite
+        } else {
+            Existing::Refuse
+        }
+    }
+}
+
+/// A file being written under a temporary name. Once everything has been written,
+/// [AtomicFile::commit] moves it into place. If it is dropped without being committed, the
+/// temporary file is removed and the destination is left as it was.
+#[derive(Debug)]
+pub struct AtomicFile {
+    /// Only `None` while being dropped, so that the file is closed before it is removed
+    file: Option<BufWriter<File>>,
+    temporary: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Start writing the file at `path`. With [Existing::Refuse], fails at once if it exists.
+    pub fn create(path: impl AsRef<Path>, existing: Existing) -> io::Result<Self> {
+        let path = path.as_ref();
+        if existing == Existing::Refuse && path.exists() {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "{} already exists; use --force to overwrite it",
+                    path.display()
+                ),
+            ));
+        }
+
+        let name = path
+            .file_name()
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is not a file name", path.display()),
+                )
+            })?
+            .to_string_lossy();
+        let temporary = path.with_file_name(format!(
+            ".{}.{}.{}.tmp",
+            name,
+            std::process::id(),
+            NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::create(&temporary)?;
+        Ok(Self {
+            file: Some(BufWriter::new(file)),
+            temporary,
+            path: path.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    /// Finish writing, and move the file into place
+    pub fn commit(mut self) -> io::Result<()> {
+        let file = self.file();
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        fs::rename(&self.temporary, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+
+    fn file(&mut self) -> &mut BufWriter<File> {
+        self.file.as_mut().expect("file is present until dropped")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        self.file.take();
+        if !self.committed {
+            let _ = fs::remove_file(&self.temporary);
+        }
+    }
+}
+
+/// Write `contents` to the file at `path` in one go, as with [fs::write] but never leaving it
+/// half written
+pub fn write(
+    path: impl AsRef<Path>,
+    contents: impl AsRef<[u8]>,
+    existing: Existing,
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(path, existing)?;
+    file.write_all(contents.as_ref())?;
+    file.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The names of the files in `dir`
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // Does a file only appear once committed, with nothing left behind if it never is?
+    #[test]
+    fn test_commit_or_discard() {
+        let dir = std::env::temp_dir().join(format!("bft-commit-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.txt");
+
+        let mut file = AtomicFile::create(&path, Existing::Refuse).unwrap();
+        file.write_all(b"partial").unwrap();
+        assert!(!path.exists());
+        drop(file);
+        assert!(files(&dir).is_empty());
+
+        write(&path, "first", Existing::Refuse).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+        assert_eq!(files(&dir), ["out.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Is an existing file kept unless overwriting was asked for?
+    #[test]
+    fn test_existing() {
+        let dir = std::env::temp_dir().join(format!("bft-existing-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.txt");
+        fs::write(&path, "old").unwrap();
+
+        let error = write(&path, "new", Existing::Refuse).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+
+        write(&path, "new", Existing::Overwrite).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(files(&dir), ["out.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//! Versioned schemas for the JSON that bft writes, so that tools reading it know what to expect.
+//!
+//! Every JSON document bft writes carries a `schema_version` field: the batch `index.json` at its
+//! top level, and the session protocol in the result of `load`. The version only goes up when a
+//! change could break a reader, such as removing or renaming a field or changing its type. New
+//! fields may be added without a new version, so readers should ignore fields they don't know.
+//!
+//! `bft --schema NAME` prints the JSON Schema for each of them.
+
+use std::fmt::Display;
+
+/// The version of every schema below
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// The JSON documents bft writes
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Schema {
+    /// The `index.json` written by `--all`
+    Index,
+    /// The results of the `bft session` JSON-RPC methods
+    Session,
+}
+
+impl Schema {
+    /// Every schema, in the order they are listed in help
+    pub const ALL: [Schema; 2] = [Schema::Index, Schema::Session];
+
+    /// The name given to `--schema`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Schema::Index => "index",
+            Schema::Session => "session",
+        }
+    }
+
+    /// The JSON Schema describing the document
+    pub fn document(&self) -> &'static str {
+        match self {
+            Schema::Index => INDEX_SCHEMA,
+            Schema::Session => SESSION_SCHEMA,
+        }
+    }
+}
+
+impl Display for Schema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Parse the name of a schema given on the command line
+pub fn parse_schema(value: &str) -> Result<Schema, String> {
+    Schema::ALL
+        .into_iter()
+        .find(|schema| schema.name() == value)
+        .ok_or_else(|| {
+            let names: Vec<_> = Schema::ALL.iter().map(Schema::name).collect();
+            format!("expected one of {}, got '{}'", names.join(", "), value)
+        })
+}
+
+const INDEX_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "$id": "bft:index:1",
+  "title": "bft --all report index",
+  "type": "object",
+  "required": ["schema_version", "directory", "programs"],
+  "properties": {
+    "schema_version": {"const": 1},
+    "directory": {"type": "string"},
+    "programs": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["program", "status", "halt_reason", "error", "instructions", "output_bytes", "elapsed_ms"],
+        "properties": {
+          "program": {"type": "string"},
+          "status": {"enum": ["completed", "stopped", "failed"]},
+          "halt_reason": {"type": ["string", "null"]},
+          "error": {"type": ["string", "null"]},
+          "instructions": {"type": "integer", "minimum": 0},
+          "output_bytes": {"type": "integer", "minimum": 0},
+          "elapsed_ms": {"type": "number", "minimum": 0}
+        }
+      }
+    }
+  }
+}
+"#;
+
+const SESSION_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "$id": "bft:session:1",
+  "title": "bft session JSON-RPC results, by method",
+  "$defs": {
+    "load": {
+      "type": "object",
+      "required": ["schema_version", "instructions"],
+      "properties": {
+        "schema_version": {"const": 1},
+        "instructions": {"type": "integer", "minimum": 0}
+      }
+    },
+    "input": {
+      "type": "object",
+      "required": ["buffered"],
+      "properties": {
+        "buffered": {"type": "integer", "minimum": 0}
+      }
+    },
+    "step": {
+      "type": "object",
+      "required": ["executed", "halt_reason", "error", "clock", "head"],
+      "properties": {
+        "executed": {"type": "integer", "minimum": 0},
+        "halt_reason": {"type": ["string", "null"]},
+        "error": {"type": ["string", "null"]},
+        "clock": {"type": "integer", "minimum": 0},
+        "head": {"type": "integer", "minimum": 0}
+      }
+    },
+    "tape": {
+      "type": "object",
+      "required": ["head", "cells"],
+      "properties": {
+        "head": {"type": "integer", "minimum": 0},
+        "cells": {"type": "array", "items": {"type": "integer", "minimum": 0}}
+      }
+    },
+    "output": {
+      "type": "object",
+      "required": ["data", "bytes"],
+      "properties": {
+        "data": {"type": "string"},
+        "bytes": {"type": "array", "items": {"type": "integer", "minimum": 0, "maximum": 255}}
+      }
+    },
+    "shutdown": {"type": "null"},
+    "error": {
+      "type": "object",
+      "required": ["code", "message"],
+      "properties": {
+        "code": {"type": "integer"},
+        "message": {"type": "string"}
+      }
+    }
+  }
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{self, Value};
+
+    // Is every schema valid JSON, at the current version, and found by its name?
+    #[test]
+    fn test_schemas() {
+        for schema in Schema::ALL {
+            let document = json::parse(schema.document()).unwrap();
+            let id = document.get("$id").and_then(Value::as_str).unwrap();
+            assert_eq!(id, format!("bft:{}:{}", schema.name(), SCHEMA_VERSION));
+            assert_eq!(parse_schema(schema.name()), Ok(schema));
+        }
+        assert!(parse_schema("trace").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! `bft selftest`: a built-in suite of programs, run with the tape, cell and EOF options given on
+//! the command line, for finding out what those options actually do.
+//!
+//! The suite has two parts. Well-known programs are run and their output checked, showing whether
+//! ordinary programs still work under the options. Small probes are then run to find out how the
+//! options behave at the edges: what happens below zero and past the largest value of a cell, at
+//! the end of input, and at each end of the tape. Probes don't pass or fail; they describe.
+
+use std::error::Error;
+use std::io::Cursor;
+use std::num::NonZeroUsize;
+
+use bft_interp::{HaltReason, Limits, VMError, VirtualMachine};
+use bft_types::BfProgram;
+
+use crate::cli::SelftestArgs;
+use crate::doctor::{SELF_TEST_OUTPUT, SELF_TEST_PROGRAM};
+use crate::report::Reporter;
+
+/// The most instructions any program in the suite may execute, so that options which stop a
+/// program from finishing don't leave the suite running forever
+const MAX_INSTRUCTIONS: u64 = 1_000_000;
+
+/// The tape size when --cells is not given
+const DEFAULT_CELLS: usize = 30_000;
+
+/// A well-known program, and the output it should give
+struct Check {
+    name: &'static str,
+    source: &'static str,
+    input: &'static [u8],
+    /// What the output must start with. Programs such as rot13 carry on past the end of their
+    /// input in ways that depend on the EOF behaviour, so what comes after is not checked.
+    expected: &'static [u8],
+}
+
+/// ROT13 as written by Daniel B. Cristofani, reading until EOF
+const ROT13: &str = "
+-,+[
+  -[
+    >>++++[>++++++++<-]
+    <+<-[
+      >+>+>-[>>>]
+      <[[>+<-]>>+>]
+      <<<<<-
+    ]
+  ]>>>[-]+
+  >--[-[<->+++[-]]]<[
+    ++++++++++++<[
+      >-[>+>>]
+      >[+[<+>-]>+>>]
+      <<<<<-
+    ]
+    >>[<+>-]
+    >[
+      -[
+        -<<[-]>>
+      ]<<[<<->>-]>>
+    ]<<[<<+>>-]
+  ]
+  <[-]
+  <.[-]
+  <-,+
+]";
+
+/// The part of a quine that prints program text: builds each instruction's character in a cell,
+/// prints it, and clears the cell for the next
+const INSTRUCTION_PRINTER: &str = "
+++++++++[>+++++<-]>+++.[-]<
+++++++++[>+++++<-]>+++++.[-]<
+++++++++[>+++++++<-]>++++.[-]<
+++++++++[>+++++++<-]>++++++.[-]<
+++++++++[>+++++++++++<-]>+++.[-]<
+++++++++[>+++++++++++<-]>+++++.[-]<
+++++++++[>+++++<-]>++++++.[-]<
+++++++++[>+++++<-]>++++.[-]<";
+
+const CHECKS: &[Check] = &[
+    Check {
+        name: "hello world",
+        source: SELF_TEST_PROGRAM,
+        input: b"",
+        expected: SELF_TEST_OUTPUT,
+    },
+    Check {
+        name: "rot13",
+        source: ROT13,
+        input: b"Hello, World!",
+        expected: b"Uryyb, Jbeyq!",
+    },
+    Check {
+        name: "quine fragment",
+        source: INSTRUCTION_PRINTER,
+        input: b"",
+        expected: b"+-<>[].,",
+    },
+];
+
+/// A small program that shows how the options behave, and how to describe what it did
+struct Probe {
+    name: &'static str,
+    source: &'static str,
+    describe: fn(&Run) -> String,
+}
+
+const PROBES: &[Probe] = &[
+    Probe {
+        name: "0 - 1",
+        source: "-",
+        describe: |run| match &run.result {
+            Ok(_) => format!("gives {}", run.current_cell()),
+            Err(VMError::CellUnderflow(_)) => "is an error".to_string(),
+            Err(error) => format!("fails unexpectedly: {}", error),
+        },
+    },
+    Probe {
+        name: "255 + 1",
+        // 8 * 8 * 4 = 256
+        source: "++++++++[>++++++++<-]>[<++++>-]<",
+        describe: |run| match &run.result {
+            Ok(_) => format!("gives {}", run.current_cell()),
+            Err(VMError::CellOverflow(_)) => "is an error".to_string(),
+            Err(error) => format!("fails unexpectedly: {}", error),
+        },
+    },
+    Probe {
+        name: "EOF",
+        source: "+++,",
+        describe: |run| match &run.result {
+            Ok(_) if run.current_cell() == 3 => "leaves the cell unchanged".to_string(),
+            Ok(_) => format!("sets the cell to {}", run.current_cell()),
+            Err(VMError::ReadError(..)) => "is an error".to_string(),
+            Err(error) => format!("fails unexpectedly: {}", error),
+        },
+    },
+    Probe {
+        name: "left of cell 0",
+        source: "<",
+        describe: |run| match &run.result {
+            Ok(_) if run.vm.head_position() < 0 => "the tape grows to the left".to_string(),
+            Ok(_) => format!("wraps round to cell {}", run.vm.head_position()),
+            Err(VMError::HeadUnderrun(_)) => "is an error".to_string(),
+            Err(error) => format!("fails unexpectedly: {}", error),
+        },
+    },
+    Probe {
+        name: "right of the last cell",
+        // marks every cell until the head runs off the end or comes back round to cell 0
+        source: "+[>+]",
+        describe: |run| match &run.result {
+            Ok(_) if run.vm.tape().len() > run.initial_cells => {
+                format!("the tape grows beyond its {} cells", run.initial_cells)
+            }
+            Ok(_) => format!("wraps round to cell 0 after {} cells", run.initial_cells),
+            Err(VMError::HeadOverrun(_)) => {
+                format!("is an error after {} cells", run.initial_cells)
+            }
+            Err(error) => format!("fails unexpectedly: {}", error),
+        },
+    },
+];
+
+/// A program from the suite that has been run
+struct Run<'a> {
+    vm: VirtualMachine<'a, u8>,
+    result: Result<HaltReason, VMError>,
+    output: Vec<u8>,
+    initial_cells: usize,
+}
+
+impl Run<'_> {
+    /// The value of the cell the head finished on
+    fn current_cell(&self) -> u8 {
+        self.vm.tape()[self.vm.head()]
+    }
+}
+
+/// Run the suite, print what it found, and fail if any of the well-known programs did not give
+/// the output they should
+pub fn run_selftest(args: &SelftestArgs, reporter: &Reporter) -> Result<(), Box<dyn Error>> {
+    println!("Programs:");
+    let mut failed = 0;
+    for check in CHECKS {
+        match run_check(args, check) {
+            Ok(clock) => {
+                println!("  {}: passed", check.name);
+                reporter.debug(format!("{}: {} instructions", check.name, clock));
+            }
+            Err(error) => {
+                println!("  {}: FAILED: {}", check.name, error);
+                failed += 1;
+            }
+        }
+    }
+
+    println!();
+    println!("Semantics:");
+    for (name, description) in semantics(args)? {
+        println!("  {}: {}", name, description);
+    }
+
+    if failed > 0 {
+        return Err(format!("{} of {} programs failed", failed, CHECKS.len()).into());
+    }
+    Ok(())
+}
+
+/// Run a program from the suite with the options given, to completion or until it fails
+fn run<'a>(args: &SelftestArgs, program: &'a BfProgram, input: &[u8]) -> Run<'a> {
+    let initial_cells = args.cells.map_or(DEFAULT_CELLS, NonZeroUsize::get);
+    let mut vm = args.virtual_machine(program).with_limits(Limits {
+        // always enough for the right-hand probe to reach the end of the tape
+        max_instructions: Some(MAX_INSTRUCTIONS.max(3 * (initial_cells as u64 + 2))),
+        ..Limits::default()
+    });
+    let mut output = Vec::new();
+    let result = vm.interpret(&mut Cursor::new(input), &mut output);
+    Run {
+        vm,
+        result,
+        output,
+        initial_cells,
+    }
+}
+
+/// Run a well-known program and check its output. Returns the number of instructions executed.
+fn run_check(args: &SelftestArgs, check: &Check) -> Result<u64, String> {
+    let program = BfProgram::new(check.name, check.source).map_err(|e| e.to_string())?;
+    let run = run(args, &program, check.input);
+    if run.output.starts_with(check.expected) {
+        return Ok(run.vm.clock());
+    }
+    match run.result {
+        Ok(HaltReason::Completed) => Err(format!(
+            "expected output {:?}, got {:?}",
+            String::from_utf8_lossy(check.expected),
+            String::from_utf8_lossy(&run.output)
+        )),
+        Ok(halt_reason) => Err(format!("stopped early: {}", halt_reason)),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// Run every probe, giving each one's name and what it found
+fn semantics(args: &SelftestArgs) -> Result<Vec<(&'static str, String)>, Box<dyn Error>> {
+    PROBES
+        .iter()
+        .map(|probe| {
+            let program = BfProgram::new(probe.name, probe.source)?;
+            let run = run(args, &program, b"");
+            Ok((probe.name, (probe.describe)(&run)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bft_interp::{Arithmetic, EofBehavior};
+
+    // Do the well-known programs pass with the default options?
+    #[test]
+    fn test_checks_pass() {
+        for check in CHECKS {
+            assert!(run_check(&SelftestArgs::default(), check).is_ok());
+        }
+
+        let args = SelftestArgs {
+            arithmetic: Arithmetic::Checked,
+            ..SelftestArgs::default()
+        };
+        assert!(run_check(&args, &CHECKS[1]).is_err());
+    }
+
+    // Do the probes describe the options they were run with?
+    #[test]
+    fn test_semantics() {
+        let args = SelftestArgs {
+            cells: NonZeroUsize::new(100),
+            ..SelftestArgs::default()
+        };
+        let found: Vec<_> = semantics(&args)
+            .unwrap()
+            .into_iter()
+            .map(|(_, description)| description)
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "gives 255",
+                "gives 0",
+                "is an error",
+                "is an error",
+                "is an error after 100 cells"
+            ]
+        );
+
+        let args = SelftestArgs {
+            cells: NonZeroUsize::new(100),
+            circular: true,
+            arithmetic: Arithmetic::Saturating,
+            eof: EofBehavior::SetZero,
+            ..SelftestArgs::default()
+        };
+        let found: Vec<_> = semantics(&args)
+            .unwrap()
+            .into_iter()
+            .map(|(_, description)| description)
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "gives 0",
+                "gives 255",
+                "sets the cell to 0",
+                "wraps round to cell 99",
+                "wraps round to cell 0 after 100 cells"
+            ]
+        );
+    }
+}
@@ -0,0 +1,427 @@
+//! `bft session`: controlling the interpreter from another process with JSON-RPC 2.0 over stdio.
+//!
+//! Each request and response is a single line of JSON. The methods are:
+//!
+//! - `load` `{"source": "...", "name"?: "...", "cells"?: n, "extensible"?: bool}`: parse a program
+//!   and start a fresh machine for it, replacing any previous one. The result gives the
+//!   `schema_version` of the protocol (see `bft --schema session`).
+//! - `input` `{"data": "...", "eof"?: bool}`: add bytes to the program's input. Once `eof` has been
+//!   given, the program sees the end of its input when the buffered bytes run out; until then, it
+//!   pauses with `needs_input`.
+//! - `step` `{"count"?: n}`: run up to `count` instructions (1 by default).
+//! - `tape` `{"start"?: n, "end"?: n}`: read a range of cells (the first 16 by default).
+//! - `output` `{}`: take the output produced since the last call.
+//! - `shutdown` `{}`: end the session.
+//!
+//! Requests without an `id` are notifications, and are carried out without a response.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, stdin, stdout, BufRead, ErrorKind, Read, Write};
+use std::num::NonZeroUsize;
+
+use bft_interp::{Limits, VirtualMachine};
+use bft_types::BfProgram;
+
+use crate::json::{self, Value};
+use crate::metrics::halt_label;
+use crate::schema::SCHEMA_VERSION;
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const NO_PROGRAM: i64 = -32000;
+
+/// Cells returned by `tape` when no end is given
+const DEFAULT_TAPE_RANGE: usize = 16;
+
+/// A request read from the client
+struct Request {
+    /// `None` for a notification, which gets no response
+    id: Option<Value>,
+    method: String,
+    params: Value,
+}
+
+/// A program to run, as given to `load`
+struct Load {
+    program: BfProgram,
+    cells: Option<NonZeroUsize>,
+    extensible: bool,
+}
+
+/// The program's input: bytes given by the client so far, and whether there will be any more
+#[derive(Debug, Default)]
+struct SessionInput {
+    buffered: VecDeque<u8>,
+    eof: bool,
+}
+
+impl Read for SessionInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered.is_empty() && !self.eof {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        self.buffered.read(buf)
+    }
+}
+
+/// Serve a session on stdin and stdout until the client shuts it down or closes stdin
+pub fn run_session() -> Result<(), Box<dyn Error>> {
+    serve(stdin().lock(), &mut stdout().lock())?;
+    Ok(())
+}
+
+/// Serve a session, reading requests from `requests` and writing responses to `responses`
+fn serve(mut requests: impl BufRead, responses: &mut impl Write) -> io::Result<()> {
+    let mut pending = None;
+    loop {
+        let request = match pending.take() {
+            Some(request) => request,
+            None => match next_request(&mut requests, responses)? {
+                Some(request) => request,
+                None => return Ok(()),
+            },
+        };
+
+        match request.method.as_str() {
+            "load" => match load(&request.params) {
+                Ok(load) => {
+                    respond(
+                        responses,
+                        &request.id,
+                        object(vec![
+                            ("schema_version", number(SCHEMA_VERSION)),
+                            (
+                                "instructions",
+                                number(load.program.localised_instructions().len() as u64),
+                            ),
+                        ]),
+                    )?;
+                    pending = run_loaded(&load, &mut requests, responses)?;
+                }
+                Err(message) => respond_error(responses, &request.id, INVALID_PARAMS, &message)?,
+            },
+            "shutdown" => return respond(responses, &request.id, Value::Null),
+            "input" | "step" | "tape" | "output" => {
+                respond_error(responses, &request.id, NO_PROGRAM, "no program loaded")?
+            }
+            method => respond_error(
+                responses,
+                &request.id,
+                METHOD_NOT_FOUND,
+                &format!("unknown method '{}'", method),
+            )?,
+        }
+    }
+}
+
+/// Handle requests for a loaded program until one needs a new program or ends the session, and
+/// hand that request back. Returns `None` if the client goes away.
+fn run_loaded(
+    load: &Load,
+    requests: &mut impl BufRead,
+    responses: &mut impl Write,
+) -> io::Result<Option<Request>> {
+    let mut bf_interpreter: VirtualMachine<u8> =
+        VirtualMachine::new(&load.program, load.cells, load.extensible);
+    let mut input = SessionInput::default();
+    let mut output = Vec::new();
+
+    while let Some(request) = next_request(requests, responses)? {
+        let params = &request.params;
+        let result = match request.method.as_str() {
+            "load" | "shutdown" => return Ok(Some(request)),
+            "input" => match params.get("data").and_then(Value::as_str) {
+                Some(data) => {
+                    input.buffered.extend(data.as_bytes());
+                    input.eof |= params.get("eof").and_then(Value::as_bool) == Some(true);
+                    Ok(object(vec![(
+                        "buffered",
+                        number(input.buffered.len() as u64),
+                    )]))
+                }
+                None => Err((INVALID_PARAMS, "expected a string 'data'".to_string())),
+            },
+            "step" => {
+                let count = params.get("count").and_then(Value::as_u64).unwrap_or(1);
+                let clock = bf_interpreter.clock();
+                bf_interpreter = bf_interpreter.with_limits(Limits {
+                    max_instructions: Some(count),
+                    ..Limits::default()
+                });
+                let (halt_reason, error) = match bf_interpreter.interpret(&mut input, &mut output) {
+                    Ok(halt_reason) => (Value::String(halt_label(halt_reason).into()), None),
+                    Err(error) => (Value::Null, Some(Value::String(error.to_string()))),
+                };
+                Ok(object(vec![
+                    ("executed", number(bf_interpreter.clock() - clock)),
+                    ("halt_reason", halt_reason),
+                    ("error", error.unwrap_or(Value::Null)),
+                    ("clock", number(bf_interpreter.clock())),
+                    ("head", number(bf_interpreter.head() as u64)),
+                ]))
+            }
+            "tape" => {
+                let tape = bf_interpreter.tape();
+                let start = params.get("start").and_then(Value::as_u64).unwrap_or(0) as usize;
+                let end = params
+                    .get("end")
+                    .and_then(Value::as_u64)
+                    .map_or(start.saturating_add(DEFAULT_TAPE_RANGE), |end| end as usize);
+                let cells = tape
+                    .iter()
+                    .take(end)
+                    .skip(start)
+                    .map(|cell| number(*cell as u64))
+                    .collect();
+                Ok(object(vec![
+                    ("head", number(bf_interpreter.head() as u64)),
+                    ("cells", Value::Array(cells)),
+                ]))
+            }
+            "output" => {
+                let data = std::mem::take(&mut output);
+                Ok(object(vec![
+                    ("data", Value::String(String::from_utf8_lossy(&data).into())),
+                    (
+                        "bytes",
+                        Value::Array(data.into_iter().map(|byte| number(byte as u64)).collect()),
+                    ),
+                ]))
+            }
+            method => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        };
+
+        match result {
+            Ok(result) => respond(responses, &request.id, result)?,
+            Err((code, message)) => respond_error(responses, &request.id, code, &message)?,
+        }
+    }
+
+    Ok(None)
+}
+
+/// Parse the parameters of a `load` request
+fn load(params: &Value) -> Result<Load, String> {
+    let source = params
+        .get("source")
+        .and_then(Value::as_str)
+        .ok_or("expected a string 'source'")?;
+    let name = params
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or("session.bf");
+
+    Ok(Load {
+        program: BfProgram::new(name, source).map_err(|error| error.to_string())?,
+        cells: params
+            .get("cells")
+            .and_then(Value::as_u64)
+            .and_then(|cells| NonZeroUsize::new(cells as usize)),
+        extensible: params.get("extensible").and_then(Value::as_bool) == Some(true),
+    })
+}
+
+/// Read the next request, answering any lines that are not valid requests with an error. Returns
+/// `None` once there are no more lines.
+fn next_request(
+    requests: &mut impl BufRead,
+    responses: &mut impl Write,
+) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if requests.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message = match json::parse(&line) {
+            Ok(message) => message,
+            Err(error) => {
+                respond_error(responses, &Some(Value::Null), PARSE_ERROR, &error)?;
+                continue;
+            }
+        };
+        let id = message.get("id").cloned();
+        match message.get("method").and_then(Value::as_str) {
+            Some(method) => {
+                return Ok(Some(Request {
+                    method: method.to_string(),
+                    params: message
+                        .get("params")
+                        .cloned()
+                        .unwrap_or(Value::Object(Vec::new())),
+                    id,
+                }))
+            }
+            None => respond_error(
+                responses,
+                &Some(id.unwrap_or(Value::Null)),
+                INVALID_REQUEST,
+                "expected a 'method'",
+            )?,
+        }
+    }
+}
+
+/// Build an object from name/value pairs
+fn object(members: Vec<(&str, Value)>) -> Value {
+    Value::Object(
+        members
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    )
+}
+
+/// A JSON number from a count or index
+fn number(value: u64) -> Value {
+    Value::Number(value as f64)
+}
+
+/// Send the result of the request with `id`, unless it was a notification
+fn respond(responses: &mut impl Write, id: &Option<Value>, result: Value) -> io::Result<()> {
+    let Some(id) = id else {
+        return Ok(());
+    };
+    let response = object(vec![
+        ("jsonrpc", Value::String("2.0".into())),
+        ("id", id.clone()),
+        ("result", result),
+    ]);
+    writeln!(responses, "{}", response)?;
+    responses.flush()
+}
+
+/// Send the error the request with `id` failed with, unless it was a notification
+fn respond_error(
+    responses: &mut impl Write,
+    id: &Option<Value>,
+    code: i64,
+    message: &str,
+) -> io::Result<()> {
+    let Some(id) = id else {
+        return Ok(());
+    };
+    let error = object(vec![
+        ("code", Value::Number(code as f64)),
+        ("message", Value::String(message.into())),
+    ]);
+    let response = object(vec![
+        ("jsonrpc", Value::String("2.0".into())),
+        ("id", id.clone()),
+        ("error", error),
+    ]);
+    writeln!(responses, "{}", response)?;
+    responses.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Run a session over the given requests and parse each response
+    fn session(requests: &str) -> Vec<Value> {
+        let mut responses = Vec::new();
+        serve(Cursor::new(requests), &mut responses).unwrap();
+        String::from_utf8(responses)
+            .unwrap()
+            .lines()
+            .map(|line| json::parse(line).unwrap())
+            .collect()
+    }
+
+    // Can a program be loaded, fed input in pieces, stepped and inspected?
+    #[test]
+    fn test_session() {
+        let responses = session(concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"load","params":{"source":",[.,]"}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"step","params":{"count":10}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":3,"method":"input","params":{"data":"hi","eof":true}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":4,"method":"step","params":{"count":100}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":5,"method":"output"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":6,"method":"tape","params":{"end":2}}"#,
+            "\n",
+        ));
+
+        let result = |index: usize, name: &str| {
+            responses[index]
+                .get("result")
+                .and_then(|result| result.get(name))
+                .cloned()
+        };
+        assert_eq!(result(0, "instructions"), Some(Value::Number(5.0)));
+        assert_eq!(
+            result(0, "schema_version"),
+            Some(Value::Number(SCHEMA_VERSION as f64))
+        );
+        assert_eq!(
+            result(1, "halt_reason"),
+            Some(Value::String("needs_input".into()))
+        );
+        // the program runs off the end of its input, which is an error for ','
+        assert!(matches!(result(3, "error"), Some(Value::String(_))));
+        assert_eq!(result(4, "data"), Some(Value::String("hi".into())));
+        assert_eq!(
+            result(5, "cells"),
+            Some(Value::Array(vec![Value::Number(105.0), Value::Number(0.0)]))
+        );
+    }
+
+    // Are requests that cannot be handled answered with errors?
+    #[test]
+    fn test_session_errors() {
+        let responses =
+            session("not json\n{\"id\":1,\"method\":\"step\"}\n{\"id\":2,\"method\":\"fly\"}\n");
+
+        let code = |index: usize| {
+            responses[index]
+                .get("error")
+                .and_then(|error| error.get("code"))
+                .cloned()
+        };
+        assert_eq!(code(0), Some(Value::Number(PARSE_ERROR as f64)));
+        assert_eq!(code(1), Some(Value::Number(NO_PROGRAM as f64)));
+        assert_eq!(code(2), Some(Value::Number(METHOD_NOT_FOUND as f64)));
+    }
+
+    // Are notifications carried out without a response, and is a tape range from the far end of
+    // the tape cut short rather than overflowing?
+    #[test]
+    fn test_session_notifications() {
+        let responses = session(concat!(
+            r#"{"jsonrpc":"2.0","method":"load","params":{"source":"+"}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","method":"step"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","method":"fly"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":1,"method":"tape","params":{"end":1}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"tape","params":{"start":18446744073709551615}}"#,
+            "\n",
+        ));
+
+        assert_eq!(responses.len(), 2);
+        let cells = |index: usize| {
+            responses[index]
+                .get("result")
+                .and_then(|result| result.get("cells"))
+                .cloned()
+        };
+        assert_eq!(cells(0), Some(Value::Array(vec![Value::Number(1.0)])));
+        assert_eq!(cells(1), Some(Value::Array(Vec::new())));
+    }
+}
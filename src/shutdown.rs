@@ -0,0 +1,161 @@
+//! Shutting down cleanly when asked to with SIGTERM, as service managers such as systemd and
+//! Kubernetes do, rather than being killed part way through writing a report.
+//!
+//! Once SIGTERM arrives, no new programs are started. The programs already running are given a
+//! grace period to finish, after which they are cancelled through their [CancelToken]s and
+//! reported as interrupted. Without [listen_for_sigterm], SIGTERM kills the process as usual.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use bft_interp::CancelToken;
+
+/// How long the running program has to finish after SIGTERM unless --grace-ms is given
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(10);
+
+/// How often the drain thread checks whether shutdown has been requested
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Set by the signal handler
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" {
+    fn signal(signum: std::ffi::c_int, handler: usize) -> usize;
+}
+
+#[cfg(unix)]
+const SIGTERM: std::ffi::c_int = 15;
+
+#[cfg(unix)]
+extern "C" fn on_sigterm(_signum: std::ffi::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Catch SIGTERM from now on, recording it for [requested] instead of exiting. Does nothing on
+/// platforms without signals.
+pub fn listen_for_sigterm() {
+    #[cfg(unix)]
+    // SAFETY: the handler only stores to an atomic, which is safe to do from a signal handler
+    unsafe {
+        signal(
+            SIGTERM,
+            on_sigterm as extern "C" fn(std::ffi::c_int) as usize,
+        );
+    }
+}
+
+/// Whether shutdown has been requested
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Cancels the running programs once the grace period after a shutdown request has passed.
+/// Stops watching when dropped.
+pub struct Drain {
+    /// The tokens of the programs running now
+    running: Arc<Mutex<Vec<CancelToken>>>,
+    finished: Arc<AtomicBool>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl Drain {
+    /// Start watching for a shutdown request, allowing `grace` for the running program to finish.
+    /// Shutdown is requested once `stop` returns true, which for SIGTERM is [requested].
+    pub fn new(grace: Duration, stop: impl Fn() -> bool + Send + 'static) -> Self {
+        let running: Arc<Mutex<Vec<CancelToken>>> = Arc::default();
+        let finished = Arc::new(AtomicBool::new(false));
+        let watcher = {
+            let running = Arc::clone(&running);
+            let finished = Arc::clone(&finished);
+            thread::spawn(move || watch(grace, stop, &running, &finished))
+        };
+        Self {
+            running,
+            finished,
+            watcher: Some(watcher),
+        }
+    }
+
+    /// Record the token of the program that is about to run, replacing the last one
+    pub fn running(&self, cancel_token: CancelToken) {
+        self.running_all([cancel_token]);
+    }
+
+    /// Record the tokens of every program running now, for when several run at once
+    pub fn running_all(&self, cancel_tokens: impl IntoIterator<Item = CancelToken>) {
+        *self
+            .running
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = cancel_tokens.into_iter().collect();
+    }
+}
+
+impl Drop for Drain {
+    fn drop(&mut self) {
+        self.finished.store(true, Ordering::SeqCst);
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+    }
+}
+
+/// Wait for `stop` to request shutdown, then for the grace period, then cancel whatever is running
+fn watch(
+    grace: Duration,
+    stop: impl Fn() -> bool,
+    running: &Mutex<Vec<CancelToken>>,
+    finished: &AtomicBool,
+) {
+    let mut deadline = None;
+    while !finished.load(Ordering::SeqCst) {
+        match deadline {
+            None if stop() => deadline = Some(Instant::now() + grace),
+            Some(deadline) if Instant::now() >= deadline => {
+                for cancel_token in running
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .iter()
+                {
+                    cancel_token.cancel();
+                }
+                return;
+            }
+            _ => {}
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Is the running program cancelled once the grace period after a request has passed?
+    #[test]
+    fn test_drain() {
+        let cancel_token = CancelToken::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        let drain = {
+            let stop = Arc::clone(&stop);
+            Drain::new(Duration::from_millis(50), move || {
+                stop.load(Ordering::SeqCst)
+            })
+        };
+        drain.running(cancel_token.clone());
+
+        stop.store(true, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(10));
+        assert!(!cancel_token.is_cancelled());
+        let started = Instant::now();
+        while !cancel_token.is_cancelled() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert!(cancel_token.is_cancelled());
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        drop(drain);
+        assert!(!requested());
+    }
+}
@@ -0,0 +1,159 @@
+//! `--stream-to`: writing the program's output straight to a file, for programs that produce far
+//! more of it than is sensible to pass through stdout. Output goes through one large buffer rather
+//! than the terminal path, nothing is added at the end, and the file can be synced to disk as it
+//! grows so that a crash loses at most the last stretch. `--max-output` sets the budget: the
+//! program is stopped before it writes more than that many bytes.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroU64;
+use std::path::Path;
+
+use crate::report::Reporter;
+
+/// Writes output to a file, syncing it and reporting progress every so many bytes
+pub struct StreamSink<'a> {
+    writer: BufWriter<File>,
+    written: u64,
+    fsync_every: Option<NonZeroU64>,
+    /// The total written at the last sync
+    synced: u64,
+    progress_every: Option<NonZeroU64>,
+    /// The total written at the last progress report
+    reported: u64,
+    reporter: &'a Reporter,
+}
+
+impl<'a> StreamSink<'a> {
+    /// Create or truncate the file at `path`. Unlike the files bft writes itself, this one is
+    /// written in place rather than renamed into place when complete, so that whatever a long run
+    /// has produced so far is kept if it is stopped.
+    pub fn create(
+        path: &Path,
+        buffer_size: usize,
+        fsync_every: Option<NonZeroU64>,
+        progress_every: Option<NonZeroU64>,
+        reporter: &'a Reporter,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::with_capacity(buffer_size, File::create(path)?),
+            written: 0,
+            fsync_every,
+            synced: 0,
+            progress_every,
+            reported: 0,
+            reporter,
+        })
+    }
+
+    /// Write out what is still buffered and sync the file, returning the number of bytes written
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.sync()?;
+        self.reporter.verbose(format!(
+            "Streamed {} to the output file",
+            describe_size(self.written)
+        ));
+        Ok(self.written)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.synced = self.written;
+        Ok(())
+    }
+}
+
+impl Write for StreamSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.written += written as u64;
+
+        if let Some(every) = self.fsync_every {
+            if self.written - self.synced >= every.get() {
+                self.sync()?;
+            }
+        }
+        if let Some(every) = self.progress_every {
+            if self.written - self.reported >= every.get() {
+                self.reported = self.written - self.written % every.get();
+                self.reporter
+                    .info(format!("Streamed {}", describe_size(self.written)));
+            }
+        }
+        Ok(written)
+    }
+
+    /// Only flushes the buffer into the file. The file itself is synced at the intervals asked
+    /// for and at the end.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A number of bytes, in MiB to one decimal place once there is at least 1 MiB
+pub fn describe_size(bytes: u64) -> String {
+    if bytes < 1 << 20 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / f64::from(1 << 20))
+    }
+}
+
+/// Parse a size in bytes, optionally followed by K, M or G for multiples of 1024
+pub fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("expected a size like 4096, 64K, 8M or 1G, got '{}'", value);
+
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    number.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Are sizes read with and without a suffix, nonsense refused, and sizes described readably?
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("4096"), Ok(4096));
+        assert_eq!(parse_byte_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_byte_size("8m"), Ok(8 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1G"), Ok(1024 * 1024 * 1024));
+        assert!(parse_byte_size("G").is_err());
+        assert!(parse_byte_size("1T").is_err());
+        assert!(parse_byte_size("99999999999G").is_err());
+
+        assert_eq!(describe_size(1000), "1000 bytes");
+        assert_eq!(describe_size(3 << 19), "1.5 MiB");
+    }
+
+    // Does everything written end up in the file, through a buffer smaller than the output?
+    #[test]
+    fn test_stream() {
+        let path = std::env::temp_dir().join(format!("bft-stream-test-{}", std::process::id()));
+        let reporter = Reporter::new(true, 0);
+        let mut sink = StreamSink::create(
+            &path,
+            16,
+            NonZeroU64::new(100),
+            NonZeroU64::new(1),
+            &reporter,
+        )
+        .unwrap();
+        for _ in 0..100 {
+            sink.write_all(b"0123456789").unwrap();
+        }
+        assert_eq!(sink.synced, 1000);
+        assert_eq!(sink.reported, 1000);
+        assert_eq!(sink.finish().unwrap(), 1000);
+        assert_eq!(fs::read(&path).unwrap(), b"0123456789".repeat(100));
+        fs::remove_file(&path).unwrap();
+    }
+}
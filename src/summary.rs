@@ -0,0 +1,91 @@
+//! `--summary`: a short table describing a run once the program has stopped, for feedback
+//! without the detail of `-v` or the metrics file.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use bft_interp::{HaltReason, RunStats, VMError};
+
+use crate::cli::Args;
+use crate::plan;
+
+/// Describe a finished run as an aligned table: the program, the engine, how much it did, how long
+/// it took, how much of the tape it used and why it stopped
+pub fn describe(
+    args: &Args,
+    path: &Path,
+    stats: &RunStats,
+    result: &Result<HaltReason, VMError>,
+) -> String {
+    let mut summary = String::new();
+    // writing to a String cannot fail
+    let mut line = |label: &str, value: String| {
+        let _ = writeln!(summary, "{:<12}{}", format!("{}:", label), value);
+    };
+
+    line("Program", path.display().to_string());
+    line("Engine", plan::engine(args));
+    line(
+        "Executed",
+        format!(
+            "{} instructions, {} bytes in, {} bytes out",
+            stats.instructions_executed, stats.bytes_input, stats.bytes_output
+        ),
+    );
+    line(
+        "Time",
+        format!("{:.3}ms", stats.elapsed.as_secs_f64() * 1000.0),
+    );
+    line(
+        "Peak tape",
+        format!(
+            "head reached cell {} of {}",
+            stats.peak_head, stats.tape_len
+        ),
+    );
+    line(
+        "Halted",
+        match result {
+            Ok(HaltReason::Completed) => "completed".to_string(),
+            Ok(halt_reason) => format!("stopped early: {}", halt_reason),
+            Err(error) => format!("failed: {}", error),
+        },
+    );
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::time::Duration;
+
+    // Does the summary line up its values and say why the program stopped?
+    #[test]
+    fn test_describe() {
+        let cli = crate::cli::Cli::parse_from(["bft", "--summary", "prog.bf"]);
+        let args = cli.run.unwrap();
+        let stats = RunStats {
+            instructions_executed: 1234,
+            elapsed: Duration::from_micros(1500),
+            tape_len: 30_000,
+            peak_head: 7,
+            bytes_output: 13,
+            ..RunStats::default()
+        };
+
+        let summary = describe(
+            &args,
+            Path::new("prog.bf"),
+            &stats,
+            &Ok(HaltReason::InstructionLimit),
+        );
+
+        assert!(summary.starts_with("Program:    prog.bf\n"));
+        assert!(summary.contains("Executed:   1234 instructions, 0 bytes in, 13 bytes out"));
+        assert!(summary.contains("Time:       1.500ms"));
+        assert!(summary.contains("Peak tape:  head reached cell 7 of 30000"));
+        assert!(summary.ends_with("Halted:     stopped early: instruction limit reached\n"));
+    }
+}
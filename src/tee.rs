@@ -0,0 +1,88 @@
+//! `--tee`: keeping a copy of everything a program writes in a log file, while it is shown as
+//! usual, so a long interactive session can be looked back over.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A writer that passes everything on, appending a copy to a log file if there is one
+pub struct Tee<W: Write> {
+    inner: W,
+    log: Option<BufWriter<File>>,
+}
+
+impl<W: Write> Tee<W> {
+    /// Wrap a writer, appending a copy of its output to the file at `log` if given. The file is
+    /// created if it doesn't exist.
+    pub fn new(inner: W, log: Option<&Path>) -> io::Result<Self> {
+        let log = log
+            .map(|path| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|error| {
+                        io::Error::new(
+                            error.kind(),
+                            format!("could not open log file {}: {}", path.display(), error),
+                        )
+                    })
+            })
+            .transpose()?
+            .map(BufWriter::new);
+        Ok(Self { inner, log })
+    }
+
+    /// The wrapped writer, once everything has been written to the log
+    pub fn into_inner(mut self) -> io::Result<W> {
+        if let Some(log) = &mut self.log {
+            log.flush()?;
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(log) = &mut self.log {
+            log.write_all(&buf[..written])?;
+        }
+        Ok(written)
+    }
+
+    /// Flushes the log too, so that it keeps up with what has been shown
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        match &mut self.log {
+            Some(log) => log.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Is the output passed on and appended to the log, keeping what the log already held?
+    #[test]
+    fn test_tee() {
+        let log = std::env::temp_dir().join(format!("bft-tee-test-{}.log", std::process::id()));
+        fs::write(&log, "earlier\n").unwrap();
+
+        let mut tee = Tee::new(Vec::new(), Some(&log)).unwrap();
+        tee.write_all(b"abc").unwrap();
+        tee.flush().unwrap();
+        assert_eq!(fs::read_to_string(&log).unwrap(), "earlier\nabc");
+        tee.write_all(b"def").unwrap();
+        assert_eq!(tee.into_inner().unwrap(), b"abcdef");
+        assert_eq!(fs::read_to_string(&log).unwrap(), "earlier\nabcdef");
+
+        let mut tee = Tee::new(Vec::new(), None).unwrap();
+        tee.write_all(b"abc").unwrap();
+        assert_eq!(tee.into_inner().unwrap(), b"abc");
+        fs::remove_file(log).unwrap();
+    }
+}
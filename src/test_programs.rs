@@ -0,0 +1,64 @@
+//! `bft test`: running programs as tests of themselves, using their `@assert` directives.
+
+use std::error::Error;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::Duration;
+
+use bft::frontend;
+use bft_interp::{HaltReason, Limits, VirtualMachine};
+use bft_types::ParseOptions;
+
+use crate::cli::TestArgs;
+use crate::report::Reporter;
+
+/// Run each program with no input and its assertions checked. Each program passes if it completes
+/// without an error. Fails if any program does not pass.
+pub fn run_tests(args: &TestArgs, reporter: &Reporter) -> Result<(), Box<dyn Error>> {
+    let limits = Limits {
+        timeout: Some(Duration::from_millis(args.timeout_ms)),
+        ..Limits::default()
+    };
+
+    let mut failures = 0;
+    for program in &args.programs {
+        match run_test(program, limits) {
+            Ok(()) => println!("{} ... ok", program.display()),
+            Err(error) => {
+                failures += 1;
+                println!("{} ... FAILED: {}", program.display(), error);
+            }
+        }
+    }
+
+    reporter.info(format!(
+        "{} passed, {} failed",
+        args.programs.len() - failures,
+        failures
+    ));
+    match failures {
+        0 => Ok(()),
+        failures => Err(format!("{} of {} tests failed", failures, args.programs.len()).into()),
+    }
+}
+
+/// Run a single program, returning why it failed if it did
+fn run_test(program: &Path, limits: Limits) -> Result<(), Box<dyn Error>> {
+    let options = ParseOptions {
+        assertions: true,
+        ..ParseOptions::default()
+    };
+    let bf_program = frontend::select(program, None)
+        .frontend
+        .parse(program, &options)?;
+    if !bf_program.has_assertions() {
+        return Err("no @assert directives found".into());
+    }
+
+    let mut bf_interpreter: VirtualMachine<u8> =
+        VirtualMachine::new(&bf_program, None, false).with_limits(limits);
+    match bf_interpreter.interpret(&mut Cursor::new([]), &mut Vec::new())? {
+        HaltReason::Completed => Ok(()),
+        halt_reason => Err(format!("stopped early: {}", halt_reason).into()),
+    }
+}